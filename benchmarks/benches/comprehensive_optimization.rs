@@ -58,6 +58,7 @@ fn benchmark_end_to_end_pipeline(c: &mut Criterion) {
                     max_batch_delay: Duration::from_millis(1),
                     buffer_capacity: 100,
                     adaptive: false,
+                    ..Default::default()
                 });
 
                 let mut results = Vec::new();
@@ -137,6 +138,7 @@ fn benchmark_adaptive_batching(c: &mut Criterion) {
                     max_batch_delay: Duration::from_millis(1),
                     buffer_capacity: 200,
                     adaptive: false,
+                    ..Default::default()
                 });
 
                 let mut batch_count = 0;
@@ -163,6 +165,7 @@ fn benchmark_adaptive_batching(c: &mut Criterion) {
                     max_batch_delay: Duration::from_millis(1),
                     buffer_capacity: 200,
                     adaptive: true,
+                    ..Default::default()
                 });
 
                 let mut batch_count = 0;
@@ -209,6 +212,7 @@ fn benchmark_throughput_comparison(c: &mut Criterion) {
                 max_batch_delay: Duration::from_millis(1),
                 buffer_capacity: 1000,
                 adaptive: true,
+                ..Default::default()
             });
 
             let mut total_bytes = 0;
@@ -53,6 +53,7 @@ fn benchmark_peak_consensus_throughput(c: &mut Criterion) {
                 max_batch_delay: Duration::from_micros(100), // Very low latency
                 buffer_capacity: 10000,
                 adaptive: true,
+                ..Default::default()
             });
 
             let mut committed_batches = 0;
@@ -71,6 +72,7 @@ fn benchmark_peak_consensus_throughput(c: &mut Criterion) {
                             batch_id: BatchId::new(),
                             decision: StateValue::V1,
                             batch: Some(batch.clone()),
+                            config_hash: 0,
                         }),
                     );
 
@@ -105,6 +107,7 @@ fn benchmark_peak_consensus_throughput(c: &mut Criterion) {
                     max_batch_delay: Duration::from_micros(50),
                     buffer_capacity: 1000,
                     adaptive: true,
+                    ..Default::default()
                 });
 
                 let mut node_committed = 0;
@@ -122,6 +125,7 @@ fn benchmark_peak_consensus_throughput(c: &mut Criterion) {
                                 batch_id: BatchId::new(),
                                 decision: StateValue::V1,
                                 batch: Some(batch),
+                                config_hash: 0,
                             }),
                         );
 
@@ -160,6 +164,7 @@ fn benchmark_theoretical_limits(c: &mut Criterion) {
                         batch_id: BatchId::new(),
                         decision: StateValue::V1,
                         batch: Some(batch),
+                        config_hash: 0,
                     }),
                 );
 
@@ -0,0 +1,490 @@
+//! Subcommands working on a [`FileSystemPersistence`] data directory.
+
+use rabia_core::persistence::{EngineState, PersistenceLayer};
+use rabia_core::{PhaseId, RabiaError, Result};
+use rabia_persistence::{FileSystemPersistence, Generation};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// A node's data directory, opened without modifying it.
+///
+/// Only [`import`](Self::import) and [`reset`](Self::reset) write to it.
+pub struct DataDir {
+    path: PathBuf,
+    persistence: FileSystemPersistence,
+}
+
+/// A generation read from disk and decoded.
+pub struct LoadedState {
+    pub generation: Generation,
+    pub bytes: Vec<u8>,
+    pub state: EngineState,
+}
+
+impl DataDir {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let persistence = FileSystemPersistence::open_existing(&path).await?;
+        Ok(Self { path, persistence })
+    }
+
+    /// Load the generation for `phase`, or the newest one that verifies.
+    ///
+    /// Unlike starting a node, corrupt generations are skipped but not moved
+    /// aside. So are generations that verify but do not decode.
+    pub async fn load(&self, phase: Option<PhaseId>) -> Result<LoadedState> {
+        let generations = self.persistence.list_generations().await?;
+        let mut first_error = None;
+        for generation in generations {
+            if phase.is_some_and(|phase| phase != generation.phase) {
+                continue;
+            }
+            let loaded = self
+                .persistence
+                .load_generation(generation.phase)
+                .await
+                .and_then(|bytes| EngineState::from_bytes(&bytes).map(|state| (bytes, state)));
+            match loaded {
+                Ok((bytes, state)) => {
+                    return Ok(LoadedState {
+                        generation,
+                        bytes,
+                        state,
+                    });
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        Err(first_error.unwrap_or_else(|| match phase {
+            Some(phase) => RabiaError::persistence(format!(
+                "No state generation for phase {} in {}",
+                phase,
+                self.path.display()
+            )),
+            None => {
+                RabiaError::persistence(format!("No persisted state in {}", self.path.display()))
+            }
+        }))
+    }
+
+    /// Describe the stored generations, the selected state and the decision
+    /// log entries that would be replayed on top of it.
+    pub async fn inspect(&self, phase: Option<PhaseId>) -> Result<String> {
+        let mut report = String::new();
+        writeln!(report, "Data directory: {}", self.path.display()).unwrap();
+
+        if self.persistence.has_legacy_state() {
+            writeln!(
+                report,
+                "Legacy state.dat: present, migrated when the node next starts"
+            )
+            .unwrap();
+        }
+
+        let generations = self.persistence.list_generations().await?;
+        writeln!(report, "Generations: {}", generations.len()).unwrap();
+        for generation in &generations {
+            let loaded = self
+                .persistence
+                .load_generation(generation.phase)
+                .await
+                .and_then(|bytes| EngineState::from_bytes(&bytes));
+            let status = match loaded {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            };
+            writeln!(
+                report,
+                "  phase {:>10}  {:>10} bytes  {}",
+                generation.phase.value(),
+                generation.size,
+                status
+            )
+            .unwrap();
+        }
+        if generations.is_empty() {
+            return Ok(report);
+        }
+
+        let loaded = self.load(phase).await?;
+        describe_state(&mut report, &loaded);
+
+        // Unlike loading them for replay, reading leaves a torn record in place
+        let pending = self
+            .persistence
+            .read_decisions(loaded.state.last_committed_phase)
+            .await?;
+        match (pending.first(), pending.last()) {
+            (Some((first, _)), Some((last, _))) => writeln!(
+                report,
+                "Decision log: {} entries to replay, phases {} to {}",
+                pending.len(),
+                first.value(),
+                last.value()
+            ),
+            _ => writeln!(report, "Decision log: nothing to replay"),
+        }
+        .unwrap();
+        Ok(report)
+    }
+
+    /// Copy a generation, as stored, to `file`.
+    pub async fn export(&self, phase: Option<PhaseId>, file: &Path) -> Result<String> {
+        let loaded = self.load(phase).await?;
+        tokio::fs::write(file, &loaded.bytes).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to write {}: {}", file.display(), e))
+        })?;
+        Ok(format!(
+            "Exported state at phase {} ({} bytes) to {}",
+            loaded.generation.phase.value(),
+            loaded.bytes.len(),
+            file.display()
+        ))
+    }
+
+    /// Replace everything in the data directory with the state in `file`.
+    ///
+    /// The state must decode and its snapshot must verify. It is stored in the
+    /// current format after the existing generations, the decision log and
+    /// its truncation record have been moved aside, so a node starts from it
+    /// without looking for commits the old log dropped.
+    pub async fn import(&self, file: &Path) -> Result<String> {
+        let bytes = tokio::fs::read(file).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to read {}: {}", file.display(), e))
+        })?;
+        let state = EngineState::from_bytes(&bytes)?;
+        verify_snapshots(&state)?;
+
+        let persistence = self.open_for_writing().await?;
+        persistence.reset().await?;
+        persistence.save_state(&state.to_bytes()?).await?;
+        Ok(format!(
+            "Imported state at phase {} from {}",
+            state.last_committed_phase.value(),
+            file.display()
+        ))
+    }
+
+    /// Move aside every generation, the decision log and its truncation record.
+    pub async fn reset(&self) -> Result<String> {
+        let persistence = self.open_for_writing().await?;
+        let generations = persistence.list_generations().await?.len();
+        persistence.reset().await?;
+        Ok(format!(
+            "Moved aside {} generations and the decision log in {}",
+            generations,
+            self.path.display()
+        ))
+    }
+
+    /// Open the directory as a node would, migrating a legacy `state.dat`
+    /// so that it is moved aside along with everything else.
+    async fn open_for_writing(&self) -> Result<FileSystemPersistence> {
+        FileSystemPersistence::new(&self.path).await
+    }
+}
+
+fn describe_state(report: &mut String, loaded: &LoadedState) {
+    let state = &loaded.state;
+    let format = match EngineState::format_version(&loaded.bytes) {
+        Some(version) => format!("binary v{}", version),
+        None => "legacy JSON".to_string(),
+    };
+    writeln!(report, "State (phase {}):", loaded.generation.phase.value()).unwrap();
+    writeln!(report, "  format:               {}", format).unwrap();
+    writeln!(
+        report,
+        "  current phase:        {}",
+        state.current_phase.value()
+    )
+    .unwrap();
+    writeln!(
+        report,
+        "  last committed phase: {}",
+        state.last_committed_phase.value()
+    )
+    .unwrap();
+    writeln!(report, "  sessions:             {}", state.sessions.len()).unwrap();
+    writeln!(
+        report,
+        "  committed batches:    {}",
+        state.committed_batches.len()
+    )
+    .unwrap();
+
+    match &state.snapshot {
+        Some(snapshot) => writeln!(
+            report,
+            "  snapshot:             version {}, {} bytes, checksum {:#010x} ({})",
+            snapshot.version,
+            snapshot.data.len(),
+            snapshot.checksum,
+            verified(snapshot.verify_checksum())
+        ),
+        None => writeln!(report, "  snapshot:             none"),
+    }
+    .unwrap();
+
+    writeln!(report, "  deltas:               {}", state.deltas.len()).unwrap();
+    let mut version = state.snapshot.as_ref().map(|snapshot| snapshot.version);
+    for delta in &state.deltas {
+        let chained = version.is_some_and(|version| delta.check_applies_to(version).is_ok());
+        writeln!(
+            report,
+            "    {} -> {}, {} bytes, checksum {:#010x} ({}{})",
+            delta.base_version,
+            delta.version,
+            delta.data.len(),
+            delta.checksum,
+            verified(delta.verify_checksum()),
+            if chained { "" } else { ", breaks the chain" }
+        )
+        .unwrap();
+        version = Some(delta.version);
+    }
+}
+
+fn verified(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "CHECKSUM MISMATCH"
+    }
+}
+
+/// Fail unless the snapshot and every delta verify and the deltas chain.
+fn verify_snapshots(state: &EngineState) -> Result<()> {
+    let Some(snapshot) = &state.snapshot else {
+        if state.deltas.is_empty() {
+            return Ok(());
+        }
+        return Err(RabiaError::StateCorruption {
+            details: "State has snapshot deltas but no snapshot".to_string(),
+        });
+    };
+    if !snapshot.verify_checksum() {
+        return Err(RabiaError::ChecksumMismatch {
+            expected: snapshot.checksum,
+            actual: crc32fast::hash(&snapshot.data),
+        });
+    }
+
+    let mut version = snapshot.version;
+    for delta in &state.deltas {
+        delta.check_applies_to(version)?;
+        version = delta.version;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rabia_core::state_machine::Snapshot;
+    use tempfile::TempDir;
+
+    fn state_at(phase: u64, data: &str) -> EngineState {
+        EngineState::new(
+            PhaseId::new(phase + 1),
+            PhaseId::new(phase),
+            Some(Snapshot::new(phase, data.as_bytes().to_vec())),
+        )
+    }
+
+    async fn data_dir_with(states: &[EngineState]) -> (TempDir, DataDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        for state in states {
+            persistence
+                .save_state(&state.to_bytes().unwrap())
+                .await
+                .unwrap();
+        }
+        let data_dir = DataDir::open(temp_dir.path()).await.unwrap();
+        (temp_dir, data_dir)
+    }
+
+    #[tokio::test]
+    async fn test_inspect_reports_state() {
+        let (_temp_dir, data_dir) = data_dir_with(&[state_at(4, "{}"), state_at(5, "{}")]).await;
+
+        let report = data_dir.inspect(None).await.unwrap();
+        assert!(report.contains("Generations: 2"));
+        assert!(report.contains("State (phase 5)"));
+        assert!(report.contains("last committed phase: 5"));
+        assert!(report.contains("binary v3"));
+        assert!(report.contains("committed batches:    0"));
+        assert!(report.contains("(ok)"));
+        assert!(report.contains("Decision log: nothing to replay"));
+
+        let report = data_dir.inspect(Some(PhaseId::new(4))).await.unwrap();
+        assert!(report.contains("State (phase 4)"));
+    }
+
+    #[tokio::test]
+    async fn test_export_import_and_reset() {
+        let (temp_dir, data_dir) = data_dir_with(&[state_at(9, "{\"a\":[49]}")]).await;
+        let file = temp_dir.path().join("export.bin");
+        data_dir.export(None, &file).await.unwrap();
+
+        let (_other_dir, other) = data_dir_with(&[state_at(2, "{}")]).await;
+        other.import(&file).await.unwrap();
+        let loaded = other.load(None).await.unwrap();
+        assert_eq!(loaded.state.last_committed_phase, PhaseId::new(9));
+        let snapshot = loaded.state.snapshot.unwrap();
+        assert_eq!(snapshot.version, 9);
+        assert_eq!(snapshot.data.as_ref(), b"{\"a\":[49]}");
+
+        other.reset().await.unwrap();
+        assert!(other.load(None).await.is_err());
+    }
+
+    /// Start a lone node on the directory and wait until it runs.
+    async fn start_node(path: &Path) -> Result<()> {
+        use rabia_core::{network::ClusterConfig, state_machine::InMemoryStateMachine, NodeId};
+        use rabia_engine::{EngineStatus, RabiaConfig, RabiaEngine};
+
+        let node_id = NodeId::new();
+        let persistence = FileSystemPersistence::new(path).await?;
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let handle = RabiaEngine::new(
+            node_id,
+            RabiaConfig::default(),
+            ClusterConfig::new(node_id, [node_id].into()),
+            InMemoryStateMachine::new(),
+            rabia_testing::InMemoryNetwork::new(node_id),
+            persistence,
+            cmd_rx,
+        )
+        .spawn();
+        let status = handle.wait_for_status(EngineStatus::Running).await;
+        let result = handle.shutdown().await;
+        assert_eq!(status, EngineStatus::Running, "{:?}", result);
+        result
+    }
+
+    #[tokio::test]
+    async fn test_node_starts_after_import_and_reset() {
+        let (temp_dir, data_dir) = data_dir_with(&[state_at(9, "{\"a\":[49]}")]).await;
+        let file = temp_dir.path().join("export.bin");
+        data_dir.export(None, &file).await.unwrap();
+
+        // A directory whose log was truncated past the imported state
+        let (other_dir, _) = data_dir_with(&[state_at(10, "{}"), state_at(20, "{}")]).await;
+        let persistence = FileSystemPersistence::new(other_dir.path()).await.unwrap();
+        let batch = rabia_core::CommandBatch::new(vec![rabia_core::Command::new("SET a 1")]);
+        for phase in 1..=22 {
+            persistence
+                .append_decision(PhaseId::new(phase), &batch)
+                .await
+                .unwrap();
+        }
+        persistence
+            .truncate_decisions(PhaseId::new(20))
+            .await
+            .unwrap();
+        assert_eq!(
+            persistence.truncated_through().await.unwrap(),
+            PhaseId::new(10)
+        );
+
+        let other = DataDir::open(other_dir.path()).await.unwrap();
+        other.import(&file).await.unwrap();
+        start_node(other_dir.path()).await.unwrap();
+
+        other.reset().await.unwrap();
+        start_node(other_dir.path()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_corrupt_snapshot() {
+        let (temp_dir, data_dir) = data_dir_with(&[state_at(3, "{}")]).await;
+        let mut state = state_at(7, "{}");
+        state.snapshot.as_mut().unwrap().checksum ^= 1;
+        let file = temp_dir.path().join("corrupt.bin");
+        std::fs::write(&file, state.to_bytes().unwrap()).unwrap();
+
+        assert!(data_dir.import(&file).await.is_err());
+        // The existing state is left alone
+        let loaded = data_dir.load(None).await.unwrap();
+        assert_eq!(loaded.state.last_committed_phase, PhaseId::new(3));
+    }
+
+    #[tokio::test]
+    async fn test_load_skips_generation_that_does_not_decode() {
+        let (_temp_dir, data_dir) = data_dir_with(&[state_at(4, "{}")]).await;
+        // Verifies as a generation, but is no engine state
+        data_dir
+            .persistence
+            .save_state_through(PhaseId::new(6), b"not an engine state")
+            .await
+            .unwrap();
+
+        let loaded = data_dir.load(None).await.unwrap();
+        assert_eq!(loaded.generation.phase, PhaseId::new(4));
+        assert!(data_dir.load(Some(PhaseId::new(6))).await.is_err());
+        let report = data_dir.inspect(None).await.unwrap();
+        assert!(report.contains("State (phase 4)"));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_leaves_the_directory_untouched() {
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        persistence
+            .save_state(&state_at(1, "{}").to_bytes().unwrap())
+            .await
+            .unwrap();
+        let batch = rabia_core::CommandBatch::new(vec![rabia_core::Command::new("SET a 1")]);
+        for phase in 2..=3 {
+            persistence
+                .append_decision(PhaseId::new(phase), &batch)
+                .await
+                .unwrap();
+        }
+        // Tear the last log record
+        let log = persistence.decision_log_path();
+        let len = std::fs::metadata(&log).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        // And leave a state.dat from an older version
+        let legacy = temp_dir.path().join("state.dat");
+        std::fs::write(&legacy, state_at(0, "{}").to_bytes().unwrap()).unwrap();
+
+        let data_dir = DataDir::open(temp_dir.path()).await.unwrap();
+        let report = data_dir.inspect(None).await.unwrap();
+        assert!(report.contains("Legacy state.dat: present"));
+        assert!(report.contains("Generations: 1"));
+        assert!(report.contains("Decision log: 1 entries to replay, phases 2 to 2"));
+
+        assert_eq!(std::fs::metadata(&log).unwrap().len(), len - 1);
+        assert!(legacy.exists());
+    }
+
+    #[tokio::test]
+    async fn test_reset_moves_legacy_state_aside() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = temp_dir.path().join("state.dat");
+        std::fs::write(&legacy, state_at(3, "{}").to_bytes().unwrap()).unwrap();
+
+        let data_dir = DataDir::open(temp_dir.path()).await.unwrap();
+        data_dir.reset().await.unwrap();
+        assert!(!legacy.exists());
+        assert!(data_dir.load(None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_open_missing_directory_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = temp_dir.path().join("missing");
+        assert!(DataDir::open(&missing).await.is_err());
+        assert!(!missing.exists());
+    }
+}
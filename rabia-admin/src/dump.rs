@@ -0,0 +1,170 @@
+//! Decoding snapshot payloads of known state machines into JSON.
+
+use rabia_core::persistence::EngineState;
+use rabia_core::state_machine::{InMemoryStateMachine, IncrementalSnapshot, StateMachine};
+use rabia_core::{RabiaError, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// State machines whose snapshot payload the tool can decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownStateMachine {
+    /// `rabia_core::state_machine::InMemoryStateMachine`
+    InMemory,
+    /// `BankingSMR` from the banking example
+    Banking,
+    /// `CounterSMR` from the counter example
+    Counter,
+    /// `KVStoreSMR` from the key-value store example
+    KvStore,
+}
+
+impl KnownStateMachine {
+    pub const NAMES: &'static [&'static str] = &["in-memory", "banking", "counter", "kvstore"];
+
+    /// The snapshot in `state`, with its deltas applied, as JSON.
+    pub async fn dump(self, state: &EngineState) -> Result<Value> {
+        let snapshot = state
+            .snapshot
+            .as_ref()
+            .ok_or_else(|| RabiaError::state_machine("Persisted state has no snapshot"))?;
+        if !snapshot.verify_checksum() {
+            return Err(RabiaError::ChecksumMismatch {
+                expected: snapshot.checksum,
+                actual: crc32fast::hash(&snapshot.data),
+            });
+        }
+
+        match self {
+            KnownStateMachine::InMemory => {
+                let mut sm = InMemoryStateMachine::new();
+                sm.restore_snapshot(snapshot).await?;
+                for delta in &state.deltas {
+                    sm.apply_delta(delta).await?;
+                }
+                let mut entries: Vec<_> = sm.get_state().await.into_iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                let object = entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        // Show text values as text and anything else as bytes
+                        let value = match std::str::from_utf8(&value) {
+                            Ok(text) => Value::String(text.to_string()),
+                            Err(_) => Value::from(value.to_vec()),
+                        };
+                        (key, value)
+                    })
+                    .collect();
+                Ok(Value::Object(object))
+            }
+            KnownStateMachine::Banking => {
+                decode_bincode::<rabia_banking_example::BankingState>(state, &snapshot.data)
+            }
+            KnownStateMachine::Counter => {
+                decode_bincode::<rabia_counter_example::CounterState>(state, &snapshot.data)
+            }
+            KnownStateMachine::KvStore => {
+                decode_bincode::<rabia_kvstore_example::KVStoreState>(state, &snapshot.data)
+            }
+        }
+    }
+}
+
+impl FromStr for KnownStateMachine {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "in-memory" => Ok(KnownStateMachine::InMemory),
+            "banking" => Ok(KnownStateMachine::Banking),
+            "counter" => Ok(KnownStateMachine::Counter),
+            "kvstore" => Ok(KnownStateMachine::KvStore),
+            _ => Err(format!(
+                "unknown state machine '{}', expected one of: {}",
+                name,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Decode a state serialized with `rabia_core::smr::StateMachine::serialize_state`.
+fn decode_bincode<T: DeserializeOwned + Serialize>(
+    state: &EngineState,
+    data: &[u8],
+) -> Result<Value> {
+    if !state.deltas.is_empty() {
+        return Err(RabiaError::state_machine(
+            "Persisted state has snapshot deltas, which this state machine cannot produce",
+        ));
+    }
+    let decoded: T = bincode::deserialize(data).map_err(|e| {
+        RabiaError::serialization(format!(
+            "Snapshot is not a {} state: {}",
+            std::any::type_name::<T>(),
+            e
+        ))
+    })?;
+    Ok(serde_json::to_value(decoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rabia_core::{state_machine::Snapshot, Command, PhaseId};
+
+    fn engine_state(snapshot: Snapshot) -> EngineState {
+        EngineState::new(PhaseId::new(3), PhaseId::new(2), Some(snapshot))
+    }
+
+    #[tokio::test]
+    async fn test_dump_in_memory_applies_deltas() {
+        let mut sm = InMemoryStateMachine::new();
+        sm.apply_command(&Command::new("SET a 1")).await.unwrap();
+        let snapshot = sm.create_snapshot().await.unwrap();
+        sm.clear_dirty();
+        sm.apply_command(&Command::new("SET b 2")).await.unwrap();
+        let delta = sm.create_delta().await.unwrap();
+
+        let state = engine_state(snapshot).with_deltas(vec![delta]);
+        let dumped = KnownStateMachine::InMemory.dump(&state).await.unwrap();
+        assert_eq!(dumped, serde_json::json!({ "a": "1", "b": "2" }));
+    }
+
+    #[tokio::test]
+    async fn test_dump_counter_state() {
+        let counter = rabia_counter_example::CounterState {
+            value: 42,
+            operation_count: 7,
+        };
+        let snapshot = Snapshot::new(1, bincode::serialize(&counter).unwrap());
+
+        let dumped = KnownStateMachine::Counter
+            .dump(&engine_state(snapshot))
+            .await
+            .unwrap();
+        assert_eq!(dumped["value"], 42);
+        assert_eq!(dumped["operation_count"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_dump_rejects_bad_checksum() {
+        let mut snapshot = Snapshot::new(1, b"{}".to_vec());
+        snapshot.checksum ^= 1;
+
+        let result = KnownStateMachine::InMemory
+            .dump(&engine_state(snapshot))
+            .await;
+        assert!(matches!(result, Err(RabiaError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_parse_names() {
+        for name in KnownStateMachine::NAMES {
+            assert!(name.parse::<KnownStateMachine>().is_ok());
+        }
+        assert!("postgres".parse::<KnownStateMachine>().is_err());
+    }
+}
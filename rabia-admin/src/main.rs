@@ -0,0 +1,231 @@
+//! Offline inspection and repair of a Rabia node's persisted state.
+//!
+//! Works on the data directory of a stopped node using
+//! [`FileSystemPersistence`](rabia_persistence::FileSystemPersistence):
+//!
+//! - `inspect` lists the stored generations and decodes the newest valid one
+//! - `dump` prints the snapshot payload of a known state machine as JSON
+//! - `export` copies a generation to a file
+//! - `import` replaces the persisted state with an exported one
+//! - `reset` moves all persisted state aside so the node starts empty
+//!
+//! Nothing is deleted: `import` and `reset` rename the files they replace.
+
+mod data_dir;
+mod dump;
+
+use data_dir::DataDir;
+use dump::KnownStateMachine;
+use rabia_core::PhaseId;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: rabia-admin <command> <data-dir> [options]
+
+Commands:
+  inspect <data-dir> [--phase N]                      Show stored generations and the decoded state
+  dump <data-dir> --state-machine NAME [--phase N]    Print the snapshot payload as JSON
+  export <data-dir> <file> [--phase N]                Copy a state generation to <file>
+  import <data-dir> <file>                            Replace the persisted state with <file>
+  reset <data-dir> --yes                              Move all persisted state aside
+
+Without --phase, the newest generation that verifies is used.
+State machines: ";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Inspect {
+        data_dir: PathBuf,
+        phase: Option<PhaseId>,
+    },
+    Dump {
+        data_dir: PathBuf,
+        state_machine: KnownStateMachine,
+        phase: Option<PhaseId>,
+    },
+    Export {
+        data_dir: PathBuf,
+        file: PathBuf,
+        phase: Option<PhaseId>,
+    },
+    Import {
+        data_dir: PathBuf,
+        file: PathBuf,
+    },
+    Reset {
+        data_dir: PathBuf,
+    },
+}
+
+impl Command {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let (command, rest) = args.split_first().ok_or("missing command")?;
+
+        let mut positional = Vec::new();
+        let mut phase = None;
+        let mut state_machine = None;
+        let mut confirmed = false;
+        let mut rest = rest.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--phase" => {
+                    let value = rest.next().ok_or("--phase needs a value")?;
+                    let value = value
+                        .parse()
+                        .map_err(|_| format!("invalid phase '{}'", value))?;
+                    phase = Some(PhaseId::new(value));
+                }
+                "--state-machine" => {
+                    let value = rest.next().ok_or("--state-machine needs a value")?;
+                    state_machine = Some(value.parse::<KnownStateMachine>()?);
+                }
+                "--yes" => confirmed = true,
+                option if option.starts_with("--") => {
+                    return Err(format!("unknown option '{}'", option))
+                }
+                _ => positional.push(PathBuf::from(arg)),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let data_dir = positional.next().ok_or("missing data directory")?;
+        let command = match command.as_str() {
+            "inspect" => Command::Inspect { data_dir, phase },
+            "dump" => Command::Dump {
+                data_dir,
+                state_machine: state_machine.ok_or("dump needs --state-machine")?,
+                phase,
+            },
+            "export" => Command::Export {
+                data_dir,
+                file: positional.next().ok_or("missing export file")?,
+                phase,
+            },
+            "import" => Command::Import {
+                data_dir,
+                file: positional.next().ok_or("missing import file")?,
+            },
+            "reset" if confirmed => Command::Reset { data_dir },
+            "reset" => return Err("reset moves all persisted state aside; pass --yes".into()),
+            other => return Err(format!("unknown command '{}'", other)),
+        };
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument '{}'", extra.display()));
+        }
+        Ok(command)
+    }
+
+    async fn run(self) -> rabia_core::Result<String> {
+        match self {
+            Command::Inspect { data_dir, phase } => {
+                DataDir::open(data_dir).await?.inspect(phase).await
+            }
+            Command::Dump {
+                data_dir,
+                state_machine,
+                phase,
+            } => {
+                let loaded = DataDir::open(data_dir).await?.load(phase).await?;
+                let value = state_machine.dump(&loaded.state).await?;
+                Ok(serde_json::to_string_pretty(&value)?)
+            }
+            Command::Export {
+                data_dir,
+                file,
+                phase,
+            } => DataDir::open(data_dir).await?.export(phase, &file).await,
+            Command::Import { data_dir, file } => {
+                DataDir::open(data_dir).await?.import(&file).await
+            }
+            Command::Reset { data_dir } => DataDir::open(data_dir).await?.reset().await,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}{}", USAGE, KnownStateMachine::NAMES.join(", "));
+        return ExitCode::SUCCESS;
+    }
+
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!(
+                "error: {}\n\n{}{}",
+                e,
+                USAGE,
+                KnownStateMachine::NAMES.join(", ")
+            );
+            return ExitCode::from(2);
+        }
+    };
+    match command.run().await {
+        Ok(output) => {
+            println!("{}", output.trim_end());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse("inspect /data --phase 7").unwrap(),
+            Command::Inspect {
+                data_dir: "/data".into(),
+                phase: Some(PhaseId::new(7)),
+            }
+        );
+        assert_eq!(
+            parse("dump /data --state-machine counter").unwrap(),
+            Command::Dump {
+                data_dir: "/data".into(),
+                state_machine: KnownStateMachine::Counter,
+                phase: None,
+            }
+        );
+        assert_eq!(
+            parse("export /data state.bin").unwrap(),
+            Command::Export {
+                data_dir: "/data".into(),
+                file: "state.bin".into(),
+                phase: None,
+            }
+        );
+        assert_eq!(
+            parse("reset /data --yes").unwrap(),
+            Command::Reset {
+                data_dir: "/data".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_arguments() {
+        assert!(parse("").is_err());
+        assert!(parse("reset /data").is_err());
+        assert!(parse("dump /data").is_err());
+        assert!(parse("import /data").is_err());
+        assert!(parse("inspect /data --phase x").is_err());
+        assert!(parse("inspect /data extra").is_err());
+        assert!(parse("inspect /data --force").is_err());
+        assert!(parse("repair /data").is_err());
+    }
+}
@@ -1,14 +1,21 @@
+use crate::validation::RejectionReason;
 use crate::{Command, CommandBatch, RabiaError, Result};
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::time::timeout;
 
+/// Default byte budget per batch, well inside the 16 MB transport frame limit
+pub const DEFAULT_MAX_BATCH_BYTES: usize = 8 * 1024 * 1024;
+
 /// Configuration for batching behavior
 #[derive(Debug, Clone)]
 pub struct BatchConfig {
     /// Maximum number of commands per batch
     pub max_batch_size: usize,
+    /// Maximum total `Command.data` bytes per batch; a larger single command
+    /// is rejected
+    pub max_batch_bytes: usize,
     /// Maximum time to wait before flushing a partial batch
     pub max_batch_delay: Duration,
     /// Buffer size for incoming commands
@@ -21,6 +28,7 @@ impl Default for BatchConfig {
     fn default() -> Self {
         Self {
             max_batch_size: 100,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
             max_batch_delay: Duration::from_millis(10),
             buffer_capacity: 1000,
             adaptive: true,
@@ -37,6 +45,17 @@ pub struct BatchStats {
     pub commands_dropped: usize,
     pub flush_timeouts: usize,
     pub adaptive_adjustments: usize,
+    /// Total `Command.data` bytes across all batches
+    pub total_bytes: usize,
+    pub average_batch_bytes: f64,
+    /// Bytes of the most recent batch as a fraction of the byte budget
+    pub last_byte_fill_ratio: f64,
+    /// Average bytes per batch as a fraction of the byte budget
+    pub average_byte_fill_ratio: f64,
+    /// Batches cut short because the next command did not fit the byte budget
+    pub byte_limited_flushes: usize,
+    /// Commands rejected for exceeding the byte budget on their own
+    pub commands_oversized: usize,
 }
 
 impl BatchStats {
@@ -45,12 +64,61 @@ impl BatchStats {
         self.total_batches += 1;
         self.average_batch_size = self.total_commands as f64 / self.total_batches as f64;
     }
+
+    /// Record the byte size of a batch already counted by [`record_batch`](Self::record_batch).
+    pub fn record_batch_bytes(&mut self, batch_bytes: usize, max_batch_bytes: usize) {
+        self.total_bytes += batch_bytes;
+        self.average_batch_bytes = self.total_bytes as f64 / self.total_batches.max(1) as f64;
+        let budget = max_batch_bytes.max(1) as f64;
+        self.last_byte_fill_ratio = batch_bytes as f64 / budget;
+        self.average_byte_fill_ratio = self.average_batch_bytes / budget;
+    }
+}
+
+/// Reject a command that could never fit in a batch of `max_batch_bytes`.
+fn check_command_bytes(command: &Command, max_batch_bytes: usize) -> Result<()> {
+    if command.data.len() > max_batch_bytes {
+        return Err(RabiaError::ValidationFailed {
+            reason: RejectionReason::CommandTooLarge,
+            detail: format!(
+                "Command size {} exceeds batch byte budget {}",
+                command.data.len(),
+                max_batch_bytes
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Drain the longest prefix of `buffer` within both limits, always at least
+/// one command. Returns the commands, their total bytes, and whether the byte
+/// budget cut the batch short.
+fn take_batch(
+    buffer: &mut VecDeque<Command>,
+    max_commands: usize,
+    max_bytes: usize,
+) -> (Vec<Command>, usize, bool) {
+    let mut count = 0;
+    let mut bytes = 0;
+    let mut byte_limited = false;
+    for command in buffer.iter().take(max_commands.max(1)) {
+        let size = command.data.len();
+        if count > 0 && bytes + size > max_bytes {
+            byte_limited = true;
+            break;
+        }
+        count += 1;
+        bytes += size;
+    }
+    (buffer.drain(..count).collect(), bytes, byte_limited)
 }
 
 /// A high-performance command batcher that groups commands for efficient processing
 pub struct CommandBatcher {
     config: BatchConfig,
     buffer: VecDeque<Command>,
+    /// Total `Command.data` bytes in `buffer`
+    buffered_bytes: usize,
     stats: BatchStats,
     last_flush: Instant,
     adaptive_batch_size: usize,
@@ -63,6 +131,7 @@ impl CommandBatcher {
         Self {
             config,
             buffer: VecDeque::with_capacity(buffer_capacity),
+            buffered_bytes: 0,
             stats: BatchStats::default(),
             last_flush: Instant::now(),
             adaptive_batch_size,
@@ -70,17 +139,37 @@ impl CommandBatcher {
     }
 
     /// Add a command to the batch buffer
+    ///
+    /// A command larger than `max_batch_bytes` is rejected with
+    /// `ValidationFailed { reason: CommandTooLarge }`.
     pub fn add_command(&mut self, command: Command) -> Result<Option<CommandBatch>> {
+        if let Err(e) = check_command_bytes(&command, self.config.max_batch_bytes) {
+            self.stats.commands_oversized += 1;
+            return Err(e);
+        }
+
         // Check if buffer is full
         if self.buffer.len() >= self.config.buffer_capacity {
             self.stats.commands_dropped += 1;
-            return Err(RabiaError::internal("Command buffer overflow"));
+            return Err(RabiaError::would_block("add command to full batch buffer"));
         }
 
-        self.buffer.push_back(command);
+        // Flush what is buffered if this command would overflow the byte budget
+        if !self.buffer.is_empty()
+            && self.buffered_bytes + command.data.len() > self.config.max_batch_bytes
+        {
+            let batch = self.flush_batch();
+            self.stats.byte_limited_flushes += 1;
+            self.push(command);
+            return Ok(Some(batch));
+        }
+
+        self.push(command);
 
         // Check if we should flush based on size
-        if self.buffer.len() >= self.current_batch_size() {
+        if self.buffer.len() >= self.current_batch_size()
+            || self.buffered_bytes >= self.config.max_batch_bytes
+        {
             return Ok(Some(self.flush_batch()));
         }
 
@@ -118,17 +207,34 @@ impl CommandBatcher {
         self.buffer.len()
     }
 
+    /// Get total `Command.data` bytes currently buffered
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
     /// Check if buffer is empty
     pub fn is_empty(&self) -> bool {
         self.buffer.is_empty()
     }
 
+    fn push(&mut self, command: Command) {
+        self.buffered_bytes += command.data.len();
+        self.buffer.push_back(command);
+    }
+
     fn flush_batch(&mut self) -> CommandBatch {
-        let batch_size = self.current_batch_size().min(self.buffer.len());
-        let commands: Vec<Command> = self.buffer.drain(..batch_size).collect();
+        let max_commands = self.current_batch_size();
+        let (commands, bytes, byte_limited) =
+            take_batch(&mut self.buffer, max_commands, self.config.max_batch_bytes);
+        self.buffered_bytes -= bytes;
 
         let batch = CommandBatch::new(commands);
         self.stats.record_batch(batch.commands.len());
+        self.stats
+            .record_batch_bytes(bytes, self.config.max_batch_bytes);
+        if byte_limited {
+            self.stats.byte_limited_flushes += 1;
+        }
         self.last_flush = Instant::now();
 
         // Adaptive batching: adjust batch size based on performance
@@ -168,6 +274,7 @@ impl CommandBatcher {
 /// Async command batcher for concurrent scenarios
 pub struct AsyncCommandBatcher {
     command_tx: mpsc::UnboundedSender<Command>,
+    max_batch_bytes: usize,
     batch_rx: mpsc::UnboundedReceiver<CommandBatch>,
     #[allow(dead_code)]
     stats_tx: mpsc::UnboundedSender<BatchStats>,
@@ -224,6 +331,7 @@ impl AsyncCommandBatcher {
 
         Self {
             command_tx,
+            max_batch_bytes: config.max_batch_bytes,
             batch_rx,
             stats_tx,
             _task_handle: task_handle,
@@ -231,7 +339,10 @@ impl AsyncCommandBatcher {
     }
 
     /// Add a command for batching
+    ///
+    /// Oversized commands are rejected here rather than dropped by the task.
     pub fn add_command(&self, command: Command) -> Result<()> {
+        check_command_bytes(&command, self.max_batch_bytes)?;
         self.command_tx
             .send(command)
             .map_err(|_| RabiaError::internal("Batcher task has stopped"))
@@ -258,6 +369,229 @@ impl AsyncCommandBatcher {
     }
 }
 
+/// Configuration for [`AdaptiveBatcher`]
+#[derive(Debug, Clone)]
+pub struct AdaptiveBatchConfig {
+    /// Commit latency the controller tries to stay under
+    pub target_latency: Duration,
+    pub min_batch_size: usize,
+    pub max_batch_size: usize,
+    /// Maximum total `Command.data` bytes per batch
+    pub max_batch_bytes: usize,
+    pub min_batch_delay: Duration,
+    pub max_batch_delay: Duration,
+    /// Batches awaiting commit at which new batches are held back
+    pub max_in_flight: usize,
+    /// Weight of the newest latency sample in the moving average, in `(0, 1]`
+    pub smoothing: f64,
+    /// Maximum number of commands waiting to be batched
+    pub buffer_capacity: usize,
+}
+
+impl Default for AdaptiveBatchConfig {
+    fn default() -> Self {
+        Self {
+            target_latency: Duration::from_millis(50),
+            min_batch_size: 1,
+            max_batch_size: 1000,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            min_batch_delay: Duration::from_millis(1),
+            max_batch_delay: Duration::from_millis(20),
+            max_in_flight: 4,
+            smoothing: 0.2,
+            buffer_capacity: 10_000,
+        }
+    }
+}
+
+/// Batcher whose size and delay follow observed commit latency.
+///
+/// The caller submits each batch returned by [`next_batch`](Self::next_batch)
+/// to the engine and reports the outcome with [`record_commit`](Self::record_commit)
+/// or [`record_failure`](Self::record_failure). While the smoothed commit
+/// latency is above `target_latency` the batch size and delay shrink; while it
+/// is well below and commands are queueing, batches grow to move more commands
+/// per phase. At most `max_in_flight` batches are outstanding, so under load
+/// commands accumulate into larger batches instead of more phases.
+pub struct AdaptiveBatcher {
+    config: AdaptiveBatchConfig,
+    buffer: VecDeque<Command>,
+    buffered_bytes: usize,
+    /// When the oldest buffered command arrived
+    oldest: Option<Instant>,
+    batch_size: usize,
+    batch_delay: Duration,
+    in_flight: usize,
+    latency_estimate: Option<Duration>,
+    stats: BatchStats,
+}
+
+impl AdaptiveBatcher {
+    pub fn new(config: AdaptiveBatchConfig) -> Self {
+        // Start latency-optimal and let load grow the batches
+        let batch_size = config.min_batch_size.max(1);
+        let batch_delay = config.min_batch_delay;
+        Self {
+            buffer: VecDeque::new(),
+            buffered_bytes: 0,
+            oldest: None,
+            batch_size,
+            batch_delay,
+            in_flight: 0,
+            latency_estimate: None,
+            stats: BatchStats::default(),
+            config,
+        }
+    }
+
+    /// Queue a command; fails with `WouldBlock` when the buffer is full, or
+    /// `ValidationFailed` if the command alone exceeds `max_batch_bytes`.
+    pub fn add_command(&mut self, command: Command) -> Result<()> {
+        if let Err(e) = check_command_bytes(&command, self.config.max_batch_bytes) {
+            self.stats.commands_oversized += 1;
+            return Err(e);
+        }
+        if self.buffer.len() >= self.config.buffer_capacity {
+            self.stats.commands_dropped += 1;
+            return Err(RabiaError::would_block(
+                "add command to full adaptive batch buffer",
+            ));
+        }
+        if self.buffer.is_empty() {
+            self.oldest = Some(Instant::now());
+        }
+        self.buffered_bytes += command.data.len();
+        self.buffer.push_back(command);
+        Ok(())
+    }
+
+    /// Take the next batch to submit, if one is due at `now`.
+    ///
+    /// A batch is due when enough commands are buffered or the oldest has
+    /// waited for the current batch delay, and fewer than `max_in_flight`
+    /// batches are outstanding. The returned batch counts as in flight.
+    pub fn next_batch(&mut self, now: Instant) -> Option<CommandBatch> {
+        if self.buffer.is_empty() || self.in_flight >= self.config.max_in_flight {
+            return None;
+        }
+
+        let full = self.buffer.len() >= self.batch_size
+            || self.buffered_bytes >= self.config.max_batch_bytes;
+        let waited = self
+            .oldest
+            .is_some_and(|oldest| now.duration_since(oldest) >= self.batch_delay);
+        if !full && !waited {
+            return None;
+        }
+        if !full {
+            self.stats.flush_timeouts += 1;
+        }
+
+        let (commands, bytes, byte_limited) = take_batch(
+            &mut self.buffer,
+            self.batch_size,
+            self.config.max_batch_bytes,
+        );
+        self.buffered_bytes -= bytes;
+        self.oldest = if self.buffer.is_empty() {
+            None
+        } else {
+            Some(now)
+        };
+        self.in_flight += 1;
+        self.stats.record_batch(commands.len());
+        self.stats
+            .record_batch_bytes(bytes, self.config.max_batch_bytes);
+        if byte_limited {
+            self.stats.byte_limited_flushes += 1;
+        }
+        Some(CommandBatch::new(commands))
+    }
+
+    /// When a partial batch becomes due, for scheduling a wake-up.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.in_flight >= self.config.max_in_flight {
+            return None;
+        }
+        self.oldest.map(|oldest| oldest + self.batch_delay)
+    }
+
+    /// Report that a submitted batch committed after `latency`.
+    pub fn record_commit(&mut self, latency: Duration) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        let estimate = match self.latency_estimate {
+            Some(previous) => {
+                previous.mul_f64(1.0 - self.config.smoothing)
+                    + latency.mul_f64(self.config.smoothing)
+            }
+            None => latency,
+        };
+        self.latency_estimate = Some(estimate);
+        self.adjust(estimate);
+    }
+
+    /// Report that a submitted batch failed; latency is not sampled.
+    pub fn record_failure(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+
+    fn adjust(&mut self, latency: Duration) {
+        let target = self.config.target_latency;
+        let (size, delay) = if latency > target {
+            // Over the SLO: smaller batches and less waiting
+            (self.batch_size * 3 / 4, self.batch_delay / 2)
+        } else if latency < target.mul_f64(0.75) {
+            // Headroom: wait up to a share of it, and grow if commands are queueing
+            let backlog = self.buffer.len() >= self.batch_size
+                || self.in_flight + 1 >= self.config.max_in_flight;
+            let size = if backlog {
+                self.batch_size + (self.batch_size / 8).max(1)
+            } else {
+                self.batch_size
+            };
+            (size, (target - latency) / 4)
+        } else {
+            return;
+        };
+
+        let size = size.clamp(
+            self.config.min_batch_size.max(1),
+            self.config.max_batch_size,
+        );
+        let delay = delay.clamp(self.config.min_batch_delay, self.config.max_batch_delay);
+        if size != self.batch_size || delay != self.batch_delay {
+            self.batch_size = size;
+            self.batch_delay = delay;
+            self.stats.adaptive_adjustments += 1;
+        }
+    }
+
+    pub fn current_batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn current_batch_delay(&self) -> Duration {
+        self.batch_delay
+    }
+
+    /// Smoothed commit latency, once a commit has been observed
+    pub fn latency_estimate(&self) -> Option<Duration> {
+        self.latency_estimate
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn stats(&self) -> &BatchStats {
+        &self.stats
+    }
+}
+
 /// Batch processor that applies multiple commands efficiently
 pub struct BatchProcessor {
     /// Optional command transformation function
@@ -333,6 +667,7 @@ mod tests {
     fn test_command_batcher_basic() {
         let config = BatchConfig {
             max_batch_size: 3,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
             max_batch_delay: Duration::from_millis(100),
             buffer_capacity: 10,
             adaptive: false,
@@ -381,6 +716,7 @@ mod tests {
     async fn test_async_command_batcher() {
         let config = BatchConfig {
             max_batch_size: 2,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
             max_batch_delay: Duration::from_millis(50),
             buffer_capacity: 10,
             adaptive: false,
@@ -405,6 +741,7 @@ mod tests {
     async fn test_async_batcher_timeout() {
         let config = BatchConfig {
             max_batch_size: 10,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
             max_batch_delay: Duration::from_millis(50),
             buffer_capacity: 10,
             adaptive: false,
@@ -425,6 +762,172 @@ mod tests {
         assert_eq!(batch.commands.len(), 1);
     }
 
+    #[test]
+    fn test_batches_split_by_byte_budget() {
+        let config = BatchConfig {
+            max_batch_size: 100,
+            max_batch_bytes: 10,
+            max_batch_delay: Duration::from_secs(10),
+            buffer_capacity: 100,
+            adaptive: false,
+        };
+        let mut batcher = CommandBatcher::new(config);
+
+        assert!(batcher.add_command(Command::new("aaaa")).unwrap().is_none());
+        assert!(batcher.add_command(Command::new("bbbb")).unwrap().is_none());
+        assert_eq!(batcher.buffered_bytes(), 8);
+
+        // The next command would overflow the budget, so the buffer goes out first
+        let batch = batcher.add_command(Command::new("cccc")).unwrap().unwrap();
+        assert_eq!(batch.commands.len(), 2);
+        assert_eq!(batcher.buffered_bytes(), 4);
+
+        // Reaching the budget exactly flushes immediately
+        let batch = batcher
+            .add_command(Command::new("dddddd"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.commands.len(), 2);
+        assert!(batcher.is_empty());
+
+        let stats = batcher.stats();
+        assert_eq!(stats.total_bytes, 18);
+        assert_eq!(stats.byte_limited_flushes, 1);
+        assert_eq!(stats.last_byte_fill_ratio, 1.0);
+        assert_eq!(stats.average_byte_fill_ratio, 0.9);
+    }
+
+    #[test]
+    fn test_oversized_command_is_rejected() {
+        let config = BatchConfig {
+            max_batch_bytes: 4,
+            ..Default::default()
+        };
+        let mut batcher = CommandBatcher::new(config.clone());
+        let err = batcher.add_command(Command::new("too big")).unwrap_err();
+        assert!(matches!(
+            err,
+            RabiaError::ValidationFailed {
+                reason: RejectionReason::CommandTooLarge,
+                ..
+            }
+        ));
+        assert_eq!(batcher.stats().commands_oversized, 1);
+        assert!(batcher.is_empty());
+
+        let mut adaptive = AdaptiveBatcher::new(AdaptiveBatchConfig {
+            max_batch_bytes: 4,
+            ..Default::default()
+        });
+        assert!(adaptive.add_command(Command::new("too big")).is_err());
+        assert_eq!(adaptive.buffer_len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_async_batcher_rejects_oversized_command() {
+        let batcher = AsyncCommandBatcher::new(BatchConfig {
+            max_batch_bytes: 4,
+            ..Default::default()
+        });
+        assert!(batcher.add_command(Command::new("too big")).is_err());
+        assert!(batcher.add_command(Command::new("ok")).is_ok());
+    }
+
+    #[test]
+    fn test_adaptive_batcher_respects_byte_budget() {
+        let mut batcher = AdaptiveBatcher::new(AdaptiveBatchConfig {
+            min_batch_size: 10,
+            max_batch_bytes: 8,
+            ..adaptive_config()
+        });
+        for data in ["aaaa", "bbbb", "cccc"] {
+            batcher.add_command(Command::new(data)).unwrap();
+        }
+
+        let batch = batcher.next_batch(Instant::now()).unwrap();
+        assert_eq!(batch.commands.len(), 2);
+        assert_eq!(batcher.stats().byte_limited_flushes, 1);
+        assert_eq!(batcher.buffer_len(), 1);
+    }
+
+    fn adaptive_config() -> AdaptiveBatchConfig {
+        AdaptiveBatchConfig {
+            target_latency: Duration::from_millis(40),
+            min_batch_size: 1,
+            max_batch_size: 64,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            min_batch_delay: Duration::ZERO,
+            max_batch_delay: Duration::from_millis(10),
+            max_in_flight: 2,
+            smoothing: 1.0,
+            buffer_capacity: 1000,
+        }
+    }
+
+    #[test]
+    fn test_adaptive_batcher_holds_batches_at_in_flight_limit() {
+        let mut batcher = AdaptiveBatcher::new(adaptive_config());
+        for i in 0..5 {
+            batcher
+                .add_command(Command::new(format!("SET k{}", i)))
+                .unwrap();
+        }
+
+        let now = Instant::now();
+        assert!(batcher.next_batch(now).is_some());
+        assert!(batcher.next_batch(now).is_some());
+        // Pipeline is full; remaining commands wait for a commit
+        assert!(batcher.next_batch(now).is_none());
+        assert_eq!(batcher.in_flight(), 2);
+
+        batcher.record_commit(Duration::from_millis(5));
+        assert!(batcher.next_batch(now).is_some());
+    }
+
+    #[test]
+    fn test_adaptive_batcher_grows_under_load_and_shrinks_over_slo() {
+        let mut batcher = AdaptiveBatcher::new(adaptive_config());
+        for i in 0..500 {
+            batcher
+                .add_command(Command::new(format!("SET k{}", i)))
+                .unwrap();
+        }
+
+        // Fast commits with a backlog grow the batch size
+        for _ in 0..20 {
+            let now = Instant::now();
+            while batcher.next_batch(now).is_some() {}
+            batcher.record_commit(Duration::from_millis(5));
+        }
+        let grown = batcher.current_batch_size();
+        let relaxed_delay = batcher.current_batch_delay();
+        assert!(grown > 1);
+
+        // Slow commits shrink it again
+        for _ in 0..3 {
+            batcher.next_batch(Instant::now());
+            batcher.record_commit(Duration::from_millis(100));
+        }
+        assert!(batcher.current_batch_size() < grown);
+        assert!(batcher.current_batch_delay() < relaxed_delay);
+        assert!(batcher.stats().adaptive_adjustments > 0);
+    }
+
+    #[test]
+    fn test_adaptive_batcher_flushes_partial_batch_after_delay() {
+        let mut config = adaptive_config();
+        config.min_batch_size = 10;
+        config.min_batch_delay = Duration::from_millis(5);
+        let mut batcher = AdaptiveBatcher::new(config);
+        batcher.add_command(Command::new("SET a 1")).unwrap();
+
+        let start = Instant::now();
+        assert!(batcher.next_batch(start).is_none());
+        let deadline = batcher.next_deadline().unwrap();
+        let batch = batcher.next_batch(deadline).unwrap();
+        assert_eq!(batch.commands.len(), 1);
+    }
+
     #[tokio::test]
     async fn test_batch_processor() {
         let processor = BatchProcessor::new().with_parallel(false);
@@ -2,7 +2,11 @@
 //!
 //! Comprehensive error handling for the Rabia consensus protocol.
 
+use crate::validation::RejectionReason;
 use crate::{BatchId, NodeId, PhaseId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
 use thiserror::Error;
 
 /// Error types that can occur during Rabia consensus operations.
@@ -21,6 +25,14 @@ use thiserror::Error;
 /// - **Resource Errors**: Missing nodes, phases, or batches
 /// - **Integrity Errors**: Checksum mismatches and corruption
 /// - **Timeout Errors**: Operations that exceed time limits
+/// - **Request Errors**: Rejected input, shutdown and backpressure
+/// - **Configuration Errors**: Invalid cluster configuration and key material
+///
+/// Every variant has a stable numeric [`ErrorCode`], and errors serialize with
+/// a `kind` tag so they can be returned to clients and matched on there. I/O
+/// and serialization errors keep their underlying error as their
+/// [`source`](std::error::Error::source) locally; a deserialized copy keeps
+/// the message and, for I/O errors, the [`std::io::ErrorKind`].
 ///
 /// # Examples
 ///
@@ -32,7 +44,8 @@ use thiserror::Error;
 ///     println!("This error can be retried");
 /// }
 /// ```
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum RabiaError {
     /// Network communication failure between nodes
     #[error("Network error: {message}")]
@@ -82,23 +95,216 @@ pub enum RabiaError {
     #[error("Partial write detected: {details}")]
     PartialWrite { details: String },
 
+    /// Stored data uses a format this version cannot read, e.g. one written
+    /// by a newer version; it is left in place
+    #[error("Unsupported storage format: {details}")]
+    UnsupportedFormat { details: String },
+
     /// Operation exceeded its timeout limit
     #[error("Timeout occurred: {operation}")]
     Timeout { operation: String },
 
-    /// JSON serialization/deserialization failure
+    /// Local replica lags the cluster by more than the allowed bound
+    #[error(
+        "Replica is stale: committed phase {local_committed}, cluster at {cluster_committed} ({lag_ms} ms behind)"
+    )]
+    Stale {
+        local_committed: PhaseId,
+        cluster_committed: PhaseId,
+        lag_ms: u64,
+    },
+
+    /// Message or batch rejected by validation
+    #[error("Validation failed ({reason}): {detail}")]
+    ValidationFailed {
+        reason: RejectionReason,
+        detail: String,
+    },
+
+    /// Sender is not a member of the current cluster configuration
+    #[error("Node {node_id} is not a member of the cluster")]
+    NotMember { node_id: NodeId },
+
+    /// The engine is shutting down or has stopped
+    #[error("Engine is shutting down")]
+    ShuttingDown,
+
+    /// Operation cannot proceed without blocking, e.g. a buffer is full
+    #[error("Operation would block: {operation}")]
+    WouldBlock { operation: String },
+
+    /// The engine let go of a request without answering it, so whether it
+    /// was applied is unknown
+    #[error("Outcome unknown: {detail}")]
+    OutcomeUnknown { detail: String },
+
+    /// Serialization or deserialization failure
     #[error("Serialization error: {0}")]
-    Serialization(#[from] serde_json::Error),
+    Serialization(#[source] SerializationError),
 
     /// File system or network I/O failure
     #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(#[source] IoError),
+
+    /// Invalid cluster or node configuration
+    #[error("Configuration error: {message}")]
+    Configuration { message: String },
+
+    /// Missing, unreadable or invalid key material
+    #[error("Key error: {message}")]
+    Key { message: String },
+
+    /// A background task panicked or was cancelled
+    #[error("Task {task} failed: {message}")]
+    TaskFailed { task: String, message: String },
 
     /// Unexpected internal error
     #[error("Internal error: {message}")]
     Internal { message: String },
 }
 
+/// An I/O error carried by [`RabiaError::Io`].
+///
+/// Keeps the original [`std::io::Error`] as its source. Serialized copies
+/// keep the message and kind but not the source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IoError {
+    /// Name of the [`std::io::ErrorKind`], e.g. `"NotFound"`
+    io_kind: String,
+    message: String,
+    #[serde(skip)]
+    source: Option<Arc<std::io::Error>>,
+}
+
+impl IoError {
+    pub fn kind(&self) -> std::io::ErrorKind {
+        match &self.source {
+            Some(source) => source.kind(),
+            None => io_kind_from_name(&self.io_kind),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<std::io::Error> for IoError {
+    fn from(error: std::io::Error) -> Self {
+        Self {
+            io_kind: format!("{:?}", error.kind()),
+            message: error.to_string(),
+            source: Some(Arc::new(error)),
+        }
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind() && self.message == other.message
+    }
+}
+
+impl Eq for IoError {}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for IoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// Map the `Debug` name of an [`std::io::ErrorKind`] back to the kind.
+fn io_kind_from_name(name: &str) -> std::io::ErrorKind {
+    use std::io::ErrorKind;
+    [
+        ErrorKind::NotFound,
+        ErrorKind::PermissionDenied,
+        ErrorKind::ConnectionRefused,
+        ErrorKind::ConnectionReset,
+        ErrorKind::ConnectionAborted,
+        ErrorKind::NotConnected,
+        ErrorKind::AddrInUse,
+        ErrorKind::AddrNotAvailable,
+        ErrorKind::BrokenPipe,
+        ErrorKind::AlreadyExists,
+        ErrorKind::WouldBlock,
+        ErrorKind::InvalidInput,
+        ErrorKind::InvalidData,
+        ErrorKind::TimedOut,
+        ErrorKind::WriteZero,
+        ErrorKind::Interrupted,
+        ErrorKind::Unsupported,
+        ErrorKind::UnexpectedEof,
+        ErrorKind::OutOfMemory,
+    ]
+    .into_iter()
+    .find(|kind| format!("{:?}", kind) == name)
+    .unwrap_or(ErrorKind::Other)
+}
+
+/// A serialization error carried by [`RabiaError::Serialization`].
+///
+/// Keeps the original error, e.g. a [`serde_json::Error`], as its source
+/// when there is one. Serialized copies keep only the message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerializationError {
+    message: String,
+    #[serde(skip)]
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+}
+
+impl SerializationError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<serde_json::Error> for SerializationError {
+    fn from(error: serde_json::Error) -> Self {
+        Self {
+            message: error.to_string(),
+            source: Some(Arc::new(error)),
+        }
+    }
+}
+
+impl PartialEq for SerializationError {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+}
+
+impl Eq for SerializationError {}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SerializationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+
 /// Type alias for Results in the Rabia consensus system.
 ///
 /// This type alias provides a convenient way to return results that may
@@ -217,8 +423,93 @@ impl RabiaError {
     /// let error = RabiaError::serialization("Invalid JSON");
     /// ```
     pub fn serialization(message: impl Into<String>) -> Self {
-        Self::Internal {
-            message: format!("Serialization error: {}", message.into()),
+        Self::Serialization(SerializationError::new(message))
+    }
+
+    /// Creates a new configuration error with the given message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::RabiaError;
+    ///
+    /// let error = RabiaError::configuration("Duplicate node name 'alpha'");
+    /// ```
+    pub fn configuration(message: impl Into<String>) -> Self {
+        Self::Configuration {
+            message: message.into(),
+        }
+    }
+
+    /// Creates a new key error with the given message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::RabiaError;
+    ///
+    /// let error = RabiaError::key("Key file must contain 32 bytes");
+    /// ```
+    pub fn key(message: impl Into<String>) -> Self {
+        Self::Key {
+            message: message.into(),
+        }
+    }
+
+    /// Creates a new would-block error for the given operation.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::RabiaError;
+    ///
+    /// let error = RabiaError::would_block("add command to full buffer");
+    /// assert!(error.is_retryable());
+    /// ```
+    pub fn would_block(operation: impl Into<String>) -> Self {
+        Self::WouldBlock {
+            operation: operation.into(),
+        }
+    }
+
+    /// Stable numeric code identifying the kind of error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::{ErrorCode, RabiaError};
+    ///
+    /// assert_eq!(RabiaError::ShuttingDown.code(), ErrorCode::ShuttingDown);
+    /// assert_eq!(ErrorCode::ShuttingDown.as_u16(), 102);
+    /// ```
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ValidationFailed { .. } => ErrorCode::ValidationFailed,
+            Self::NotMember { .. } => ErrorCode::NotMember,
+            Self::ShuttingDown => ErrorCode::ShuttingDown,
+            Self::WouldBlock { .. } => ErrorCode::WouldBlock,
+            Self::OutcomeUnknown { .. } => ErrorCode::OutcomeUnknown,
+            Self::Stale { .. } => ErrorCode::Stale,
+            Self::QuorumNotAvailable { .. } => ErrorCode::QuorumNotAvailable,
+            Self::Timeout { .. } => ErrorCode::Timeout,
+            Self::NodeNotFound { .. } => ErrorCode::NodeNotFound,
+            Self::PhaseNotFound { .. } => ErrorCode::PhaseNotFound,
+            Self::BatchNotFound { .. } => ErrorCode::BatchNotFound,
+            Self::Consensus { .. } => ErrorCode::Consensus,
+            Self::InvalidStateTransition { .. } => ErrorCode::InvalidStateTransition,
+            Self::StateMachine { .. } => ErrorCode::StateMachine,
+            Self::Persistence { .. } => ErrorCode::Persistence,
+            Self::ChecksumMismatch { .. } => ErrorCode::ChecksumMismatch,
+            Self::StateCorruption { .. } => ErrorCode::StateCorruption,
+            Self::PartialWrite { .. } => ErrorCode::PartialWrite,
+            Self::UnsupportedFormat { .. } => ErrorCode::UnsupportedFormat,
+            Self::Network { .. } => ErrorCode::Network,
+            Self::Serialization(_) => ErrorCode::Serialization,
+            Self::Io(_) => ErrorCode::Io,
+            Self::Configuration { .. } => ErrorCode::Configuration,
+            Self::Key { .. } => ErrorCode::Key,
+            Self::TaskFailed { .. } => ErrorCode::TaskFailed,
+            Self::Internal { .. } => ErrorCode::Internal,
         }
     }
 
@@ -249,7 +540,186 @@ impl RabiaError {
     pub fn is_retryable(&self) -> bool {
         matches!(
             self,
-            Self::Network { .. } | Self::Timeout { .. } | Self::QuorumNotAvailable { .. }
+            Self::Network { .. }
+                | Self::Timeout { .. }
+                | Self::QuorumNotAvailable { .. }
+                | Self::Stale { .. }
+                | Self::WouldBlock { .. }
         )
     }
 }
+
+impl From<serde_json::Error> for RabiaError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Serialization(error.into())
+    }
+}
+
+impl From<std::io::Error> for RabiaError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error.into())
+    }
+}
+
+/// Stable numeric error codes.
+///
+/// Codes never change meaning once assigned. They are grouped by range:
+/// 1xx request errors, 2xx missing resources, 3xx protocol and state machine
+/// errors, 4xx storage and integrity errors, 5xx transport and encoding
+/// errors, 6xx configuration errors, and 9xx internal errors. Serialized as
+/// the bare number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u16", try_from = "u16")]
+#[repr(u16)]
+pub enum ErrorCode {
+    ValidationFailed = 100,
+    NotMember = 101,
+    ShuttingDown = 102,
+    WouldBlock = 103,
+    Stale = 104,
+    QuorumNotAvailable = 105,
+    Timeout = 106,
+    OutcomeUnknown = 107,
+    NodeNotFound = 200,
+    PhaseNotFound = 201,
+    BatchNotFound = 202,
+    Consensus = 300,
+    InvalidStateTransition = 301,
+    StateMachine = 302,
+    Persistence = 400,
+    ChecksumMismatch = 401,
+    StateCorruption = 402,
+    PartialWrite = 403,
+    UnsupportedFormat = 404,
+    Network = 500,
+    Serialization = 501,
+    Io = 502,
+    Configuration = 600,
+    Key = 601,
+    Internal = 900,
+    TaskFailed = 901,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 26] = [
+        ErrorCode::ValidationFailed,
+        ErrorCode::NotMember,
+        ErrorCode::ShuttingDown,
+        ErrorCode::WouldBlock,
+        ErrorCode::Stale,
+        ErrorCode::QuorumNotAvailable,
+        ErrorCode::Timeout,
+        ErrorCode::OutcomeUnknown,
+        ErrorCode::NodeNotFound,
+        ErrorCode::PhaseNotFound,
+        ErrorCode::BatchNotFound,
+        ErrorCode::Consensus,
+        ErrorCode::InvalidStateTransition,
+        ErrorCode::StateMachine,
+        ErrorCode::Persistence,
+        ErrorCode::ChecksumMismatch,
+        ErrorCode::StateCorruption,
+        ErrorCode::PartialWrite,
+        ErrorCode::UnsupportedFormat,
+        ErrorCode::Network,
+        ErrorCode::Serialization,
+        ErrorCode::Io,
+        ErrorCode::Configuration,
+        ErrorCode::Key,
+        ErrorCode::Internal,
+        ErrorCode::TaskFailed,
+    ];
+
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_u16(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_u16() == code)
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        code.as_u16()
+    }
+}
+
+impl TryFrom<u16> for ErrorCode {
+    type Error = String;
+
+    fn try_from(code: u16) -> std::result::Result<Self, Self::Error> {
+        Self::from_u16(code).ok_or_else(|| format!("unknown error code {}", code))
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:03}", self.as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_roundtrip() {
+        for code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::from_u16(code.as_u16()), Some(code));
+        }
+        assert_eq!(ErrorCode::from_u16(999), None);
+        assert_eq!(serde_json::to_string(&ErrorCode::NotMember).unwrap(), "101");
+    }
+
+    #[test]
+    fn test_errors_serialize_with_kind_tag() {
+        let error = RabiaError::ValidationFailed {
+            reason: RejectionReason::BatchTooLarge,
+            detail: "3 commands, limit 2".to_string(),
+        };
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["kind"], "validation_failed");
+        assert_eq!(json["reason"], "batch_too_large");
+
+        let decoded: RabiaError = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded, error);
+        assert_eq!(decoded.code(), ErrorCode::ValidationFailed);
+
+        let shutting_down = serde_json::to_string(&RabiaError::ShuttingDown).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RabiaError>(&shutting_down).unwrap(),
+            RabiaError::ShuttingDown
+        );
+    }
+
+    #[test]
+    fn test_io_and_serialization_errors_keep_their_source() {
+        use std::error::Error as _;
+
+        let io: RabiaError =
+            std::io::Error::new(std::io::ErrorKind::NotFound, "no such file").into();
+        let RabiaError::Io(inner) = &io else {
+            panic!("expected an I/O error, got {:?}", io);
+        };
+        assert_eq!(inner.kind(), std::io::ErrorKind::NotFound);
+        assert!(io.source().and_then(|source| source.source()).is_some());
+
+        // Clients see the kind and message, without the source
+        let decoded: RabiaError =
+            serde_json::from_str(&serde_json::to_string(&io).unwrap()).unwrap();
+        assert_eq!(decoded, io);
+        let RabiaError::Io(inner) = &decoded else {
+            panic!("expected an I/O error, got {:?}", decoded);
+        };
+        assert_eq!(inner.kind(), std::io::ErrorKind::NotFound);
+        assert!(decoded
+            .source()
+            .and_then(|source| source.source())
+            .is_none());
+
+        let json: RabiaError = serde_json::from_str::<u32>("x").unwrap_err().into();
+        assert_eq!(json.code(), ErrorCode::Serialization);
+        assert!(json.source().and_then(|source| source.source()).is_some());
+    }
+}
@@ -0,0 +1,292 @@
+//! # Message Freshness
+//!
+//! Logical-clock freshness checks that do not depend on synchronized clocks.
+//!
+//! Every node stamps its outgoing messages with a [`MessageSequence`]: an
+//! incarnation that grows each time the node starts and a counter that
+//! increases with each message. Nodes with durable storage keep the
+//! incarnation as a counter in their data directory (see
+//! [`PersistenceLayer::next_incarnation`](crate::persistence::PersistenceLayer::next_incarnation)),
+//! so a clock stepped backwards cannot make a restart look like a replay;
+//! without one, the startup time is used. Receivers keep a sliding window of recently seen numbers per
+//! peer in a [`SequenceTracker`] and reject duplicates and messages that fall
+//! behind the window. A higher incarnation means the peer restarted, so its
+//! window starts over; messages from a lower incarnation, or without a
+//! sequence once the peer has sent sequenced ones, are replays and rejected.
+//! Staleness at the protocol level is handled separately by rejecting votes
+//! for phases below the compaction watermark.
+
+use crate::validation::{Rejection, RejectionReason};
+use crate::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of most recent sequence numbers tracked per peer.
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// Position of a message in its sender's stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageSequence {
+    /// Grows every time the sender restarts
+    pub incarnation: u64,
+    /// Per-incarnation message counter, starting at 1
+    pub number: u64,
+}
+
+/// Stamps outgoing messages with increasing sequence numbers.
+#[derive(Debug)]
+pub struct SequenceGenerator {
+    incarnation: u64,
+    next: AtomicU64,
+}
+
+/// Last incarnation handed out in this process
+static LAST_INCARNATION: AtomicU64 = AtomicU64::new(0);
+
+impl SequenceGenerator {
+    /// Start a new incarnation from the current time, later than any earlier
+    /// incarnation of this process.
+    ///
+    /// Only use this when there is no durable incarnation counter: a clock
+    /// stepped backwards across a restart yields a lower incarnation, and
+    /// peers drop the node's messages as replays.
+    pub fn new() -> Self {
+        let now = clock_incarnation();
+        let previous = LAST_INCARNATION
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or_default();
+        Self::with_incarnation(now.max(previous + 1))
+    }
+
+    pub fn with_incarnation(incarnation: u64) -> Self {
+        Self {
+            incarnation,
+            next: AtomicU64::new(1),
+        }
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    pub fn next_sequence(&self) -> MessageSequence {
+        MessageSequence {
+            incarnation: self.incarnation,
+            number: self.next.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// The current time in microseconds, as a starting incarnation.
+///
+/// Durable incarnation counters start from this value, so they stay above
+/// the clock-based incarnations a node may have used before.
+pub fn clock_incarnation() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+impl Default for SequenceGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PeerWindow {
+    incarnation: u64,
+    highest: u64,
+    /// Bit `i` is set when `highest - i` has been seen
+    seen: u64,
+}
+
+impl PeerWindow {
+    fn start(sequence: MessageSequence) -> Self {
+        Self {
+            incarnation: sequence.incarnation,
+            highest: sequence.number,
+            seen: 1,
+        }
+    }
+
+    fn observe(&mut self, number: u64) -> std::result::Result<(), RejectionReason> {
+        if number > self.highest {
+            let shift = number - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW {
+                1
+            } else {
+                (self.seen << shift) | 1
+            };
+            self.highest = number;
+            return Ok(());
+        }
+
+        let offset = self.highest - number;
+        if offset >= REPLAY_WINDOW {
+            return Err(RejectionReason::StaleSequence);
+        }
+
+        let bit = 1u64 << offset;
+        if self.seen & bit != 0 {
+            return Err(RejectionReason::DuplicateMessage);
+        }
+        self.seen |= bit;
+        Ok(())
+    }
+}
+
+/// Per-peer anti-replay windows over received message sequences.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    peers: Mutex<HashMap<NodeId, PeerWindow>>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `sequence` from `from`, rejecting duplicates, numbers that
+    /// fall behind the peer's window and incarnations older than its current
+    /// one.
+    pub fn observe(
+        &self,
+        from: NodeId,
+        sequence: MessageSequence,
+    ) -> std::result::Result<(), Rejection> {
+        let mut peers = self.peers.lock().unwrap();
+        let window = match peers.get_mut(&from) {
+            Some(window) if window.incarnation == sequence.incarnation => window,
+            Some(window) if window.incarnation > sequence.incarnation => {
+                return Err(Rejection::new(
+                    RejectionReason::StaleSequence,
+                    format!(
+                        "Incarnation {} from {} is older than its current incarnation {}",
+                        sequence.incarnation, from, window.incarnation
+                    ),
+                ));
+            }
+            // First message from this peer, or it restarted and counts from 1 again
+            _ => {
+                peers.insert(from, PeerWindow::start(sequence));
+                return Ok(());
+            }
+        };
+
+        window.observe(sequence.number).map_err(|reason| {
+            Rejection::new(
+                reason,
+                format!(
+                    "Sequence {} from {} is outside the replay window (highest {})",
+                    sequence.number, from, window.highest
+                ),
+            )
+        })
+    }
+
+    /// Check a message that carries no sequence. Once a peer has sent
+    /// sequenced messages, an unsequenced one can only be a replay or forgery.
+    pub fn observe_unsequenced(&self, from: NodeId) -> std::result::Result<(), Rejection> {
+        if self.peers.lock().unwrap().contains_key(&from) {
+            return Err(Rejection::new(
+                RejectionReason::MissingSequence,
+                format!("Message from {} has no sequence number", from),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Forget a peer, e.g. after it leaves the cluster.
+    pub fn remove_peer(&self, node_id: &NodeId) {
+        self.peers.lock().unwrap().remove(node_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seq(incarnation: u64, number: u64) -> MessageSequence {
+        MessageSequence {
+            incarnation,
+            number,
+        }
+    }
+
+    #[test]
+    fn test_generator_is_monotonic() {
+        let generator = SequenceGenerator::with_incarnation(7);
+        let first = generator.next_sequence();
+        let second = generator.next_sequence();
+        assert_eq!(first.incarnation, 7);
+        assert!(second.number > first.number);
+    }
+
+    #[test]
+    fn test_duplicates_rejected_reordering_tolerated() {
+        let tracker = SequenceTracker::new();
+        let peer = NodeId::new();
+
+        assert!(tracker.observe(peer, seq(1, 1)).is_ok());
+        assert!(tracker.observe(peer, seq(1, 3)).is_ok());
+        // Late but within the window
+        assert!(tracker.observe(peer, seq(1, 2)).is_ok());
+
+        let duplicate = tracker.observe(peer, seq(1, 3)).unwrap_err();
+        assert_eq!(duplicate.reason, RejectionReason::DuplicateMessage);
+    }
+
+    #[test]
+    fn test_messages_behind_window_are_stale() {
+        let tracker = SequenceTracker::new();
+        let peer = NodeId::new();
+
+        assert!(tracker.observe(peer, seq(1, 1)).is_ok());
+        assert!(tracker.observe(peer, seq(1, 1 + REPLAY_WINDOW)).is_ok());
+
+        let stale = tracker.observe(peer, seq(1, 1)).unwrap_err();
+        assert_eq!(stale.reason, RejectionReason::StaleSequence);
+    }
+
+    #[test]
+    fn test_new_incarnation_resets_window() {
+        let tracker = SequenceTracker::new();
+        let peer = NodeId::new();
+
+        assert!(tracker.observe(peer, seq(1, 100)).is_ok());
+        // Restarted peer counts from 1 again
+        assert!(tracker.observe(peer, seq(2, 1)).is_ok());
+        assert!(tracker.observe(peer, seq(2, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_older_incarnation_and_unsequenced_replays_rejected() {
+        let tracker = SequenceTracker::new();
+        let peer = NodeId::new();
+
+        assert!(tracker.observe_unsequenced(peer).is_ok());
+        assert!(tracker.observe(peer, seq(5, 1)).is_ok());
+
+        let replay = tracker.observe(peer, seq(4, 200)).unwrap_err();
+        assert_eq!(replay.reason, RejectionReason::StaleSequence);
+        // The current window is untouched
+        assert!(tracker.observe(peer, seq(5, 2)).is_ok());
+
+        let unsequenced = tracker.observe_unsequenced(peer).unwrap_err();
+        assert_eq!(unsequenced.reason, RejectionReason::MissingSequence);
+    }
+
+    #[test]
+    fn test_incarnations_increase() {
+        let first = SequenceGenerator::new();
+        let second = SequenceGenerator::new();
+        assert!(second.incarnation() > first.incarnation());
+    }
+}
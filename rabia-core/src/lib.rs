@@ -11,10 +11,16 @@
 //! - **Operation Types**: Core types for SMR operations, batching, and results
 //! - **Consensus Messages**: Protocol messages for coordinating operation ordering
 //! - **Node Management**: Types like NodeId, BatchId, PhaseId for cluster coordination
+//! - **Node Registry**: Stable member names, indices and IDs from a cluster config file
 //! - **Error Handling**: Comprehensive error types and recovery mechanisms
 //! - **Serialization**: High-performance binary serialization for SMR operations
+//! - **Wire Format**: Versioned, zero-copy binary encoding of protocol messages
+//! - **Compact Votes**: Member-indexed vote vectors and fixed-size tallies
 //! - **Memory Management**: Optimized memory pools for reduced allocations
 //! - **Validation**: Operation and state validation utilities
+//! - **Message Freshness**: Per-peer sequence numbers instead of wall-clock checks
+//! - **Message Signing**: Optional HMAC or Ed25519 signatures on protocol messages
+//! - **Client Sessions**: Deduplication of retried commands for exactly-once semantics
 //!
 //! ## Implementing State Machines with the Rabia Protocol
 //!
@@ -94,15 +100,21 @@
 
 pub mod batching;
 pub mod error;
+pub mod freshness;
 pub mod memory_pool;
 pub mod messages;
 pub mod network;
 pub mod persistence;
+pub mod registry;
 pub mod serialization;
+pub mod sessions;
+pub mod signing;
 pub mod smr;
 pub mod state_machine;
 pub mod types;
 pub mod validation;
+pub mod votes;
+pub mod wire;
 
 // Re-export commonly used types for convenience
 pub use error::*;
@@ -1,15 +1,31 @@
-use crate::state_machine::Snapshot;
+use crate::freshness::MessageSequence;
+use crate::sessions::{CommittedBatches, SessionTable};
+use crate::state_machine::{DeltaSnapshot, Snapshot};
+use crate::votes::{MemberIndex, VoteTally, VoteVector};
 use crate::{BatchId, CommandBatch, NodeId, PhaseId, StateValue};
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProtocolMessage {
     pub id: uuid::Uuid,
     pub from: NodeId,
     pub to: Option<NodeId>, // None for broadcast
+    /// Sender's wall-clock time; informational only unless clock skew checks are enabled
     pub timestamp: u64,
     pub message_type: MessageType,
+    /// Position in the sender's message stream, used to reject replays
+    #[serde(default)]
+    pub sequence: Option<MessageSequence>,
+    /// HMAC or Ed25519 signature over the other fields, when signing is enabled
+    #[serde(default)]
+    pub signature: Option<Bytes>,
+    /// The bytes the signature covers, as received, when the message was
+    /// decoded from a signed [`wire`](crate::wire) frame. Verification uses
+    /// these rather than re-encoding, so fields added by a newer sender and
+    /// unknown here are still covered.
+    #[serde(skip)]
+    pub signed_bytes: Option<Bytes>,
 }
 
 impl ProtocolMessage {
@@ -23,9 +39,17 @@ impl ProtocolMessage {
                 .unwrap()
                 .as_millis() as u64,
             message_type,
+            sequence: None,
+            signature: None,
+            signed_bytes: None,
         }
     }
 
+    pub fn with_sequence(mut self, sequence: MessageSequence) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
     pub fn propose(from: NodeId, proposal: ProposeMessage) -> Self {
         Self::new(from, None, MessageType::Propose(proposal))
     }
@@ -90,7 +114,8 @@ pub struct VoteRound2Message {
     pub batch_id: BatchId,
     pub vote: StateValue,
     pub voter_id: NodeId,
-    pub round1_votes: HashMap<NodeId, StateValue>,
+    /// Round 1 votes that justify this vote, indexed by cluster member
+    pub round1_votes: VoteVector,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -99,12 +124,20 @@ pub struct DecisionMessage {
     pub batch_id: BatchId,
     pub decision: StateValue,
     pub batch: Option<CommandBatch>,
+    /// [`MemberIndex::config_hash`](crate::votes::MemberIndex::config_hash)
+    /// of the configuration the batch was decided under
+    pub config_hash: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncRequestMessage {
     pub requester_phase: PhaseId,
     pub requester_state_version: u64,
+    /// Version of the requester's state machine, if it can apply deltas; a
+    /// responder whose delta chain passes through it ships only the deltas
+    /// taken since
+    #[serde(default)]
+    pub requester_snapshot_version: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,6 +147,16 @@ pub struct SyncResponseMessage {
     pub state_snapshot: Option<Snapshot>,
     pub pending_batches: Vec<(BatchId, CommandBatch)>,
     pub committed_phases: Vec<(PhaseId, BatchId, StateValue)>,
+    /// Client session table matching `state_snapshot`
+    #[serde(default)]
+    pub sessions: Option<SessionTable>,
+    /// Deltas to apply on top of `state_snapshot`, oldest first, or on top
+    /// of the requester's state when there is no snapshot
+    #[serde(default)]
+    pub state_deltas: Vec<DeltaSnapshot>,
+    /// Recently committed batches matching `sessions`
+    #[serde(default)]
+    pub committed_batches: Option<CommittedBatches>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,17 +178,44 @@ pub struct QuorumNotificationMessage {
     pub active_nodes: Vec<NodeId>,
 }
 
+/// Outcome of recording a vote in a phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteRecord {
+    /// First vote from this voter in the round
+    Recorded,
+    /// Same vote as already recorded
+    Duplicate,
+    /// Different vote from one already recorded; the first vote is kept
+    Conflict { previous: StateValue },
+    /// The voter has no index in the member index, or the round's votes are
+    /// indexed by another configuration; nothing is recorded
+    Unindexed,
+}
+
+/// Votes and outcome of one phase on this node.
+///
+/// Votes are kept one per cluster member in [`VoteVector`]s indexed by the
+/// engine's [`MemberIndex`], and the first vote recorded in a round adopts
+/// that index. When membership changes, [`reindex`](Self::reindex) moves the
+/// votes to the new index.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PhaseData {
     pub phase_id: PhaseId,
     pub batch_id: Option<BatchId>,
     pub proposed_value: Option<StateValue>,
-    pub round1_votes: HashMap<NodeId, StateValue>,
-    pub round2_votes: HashMap<NodeId, StateValue>,
+    pub round1_votes: VoteVector,
+    pub round2_votes: VoteVector,
+    /// Counts of `round1_votes` per value
+    pub round1_tally: VoteTally,
+    /// Counts of `round2_votes` per value
+    pub round2_tally: VoteTally,
     pub decision: Option<StateValue>,
     pub batch: Option<CommandBatch>,
     pub timestamp: u64,
     pub is_committed: bool,
+    /// Another batch than `batch_id` was proposed or voted on in this phase
+    #[serde(default)]
+    pub contested: bool,
 }
 
 impl PhaseData {
@@ -154,8 +224,10 @@ impl PhaseData {
             phase_id,
             batch_id: None,
             proposed_value: None,
-            round1_votes: HashMap::new(),
-            round2_votes: HashMap::new(),
+            round1_votes: VoteVector::default(),
+            round2_votes: VoteVector::default(),
+            round1_tally: VoteTally::default(),
+            round2_tally: VoteTally::default(),
             decision: None,
             batch: None,
             timestamp: std::time::SystemTime::now()
@@ -163,55 +235,74 @@ impl PhaseData {
                 .unwrap()
                 .as_millis() as u64,
             is_committed: false,
+            contested: false,
         }
     }
 
-    pub fn add_round1_vote(&mut self, voter: NodeId, vote: StateValue) {
-        self.round1_votes.insert(voter, vote);
+    pub fn add_round1_vote(
+        &mut self,
+        members: &MemberIndex,
+        voter: NodeId,
+        vote: StateValue,
+    ) -> VoteRecord {
+        record_vote(
+            &mut self.round1_votes,
+            &mut self.round1_tally,
+            members,
+            voter,
+            vote,
+        )
     }
 
-    pub fn add_round2_vote(&mut self, voter: NodeId, vote: StateValue) {
-        self.round2_votes.insert(voter, vote);
+    pub fn add_round2_vote(
+        &mut self,
+        members: &MemberIndex,
+        voter: NodeId,
+        vote: StateValue,
+    ) -> VoteRecord {
+        record_vote(
+            &mut self.round2_votes,
+            &mut self.round2_tally,
+            members,
+            voter,
+            vote,
+        )
     }
 
-    pub fn has_round1_majority(&self, quorum_size: usize) -> Option<StateValue> {
-        self.count_votes(&self.round1_votes, quorum_size)
+    /// `voter`'s round 1 vote, if recorded under `members`.
+    pub fn round1_vote(&self, members: &MemberIndex, voter: &NodeId) -> Option<StateValue> {
+        cast_vote(&self.round1_votes, members, voter)
     }
 
-    pub fn has_round2_majority(&self, quorum_size: usize) -> Option<StateValue> {
-        self.count_votes(&self.round2_votes, quorum_size)
+    /// `voter`'s round 2 vote, if recorded under `members`.
+    pub fn round2_vote(&self, members: &MemberIndex, voter: &NodeId) -> Option<StateValue> {
+        cast_vote(&self.round2_votes, members, voter)
     }
 
-    fn count_votes(
-        &self,
-        votes: &HashMap<NodeId, StateValue>,
-        quorum_size: usize,
-    ) -> Option<StateValue> {
-        let mut v0_count = 0;
-        let mut v1_count = 0;
-        let mut vq_count = 0;
-
-        for vote in votes.values() {
-            match vote {
-                StateValue::V0 => v0_count += 1,
-                StateValue::V1 => v1_count += 1,
-                StateValue::VQuestion => vq_count += 1,
+    /// Move votes indexed by `from` to `to` after a membership change.
+    /// Votes of removed members are dropped from the votes and tallies.
+    pub fn reindex(&mut self, from: &MemberIndex, to: &MemberIndex) {
+        for (votes, tally) in [
+            (&mut self.round1_votes, &mut self.round1_tally),
+            (&mut self.round2_votes, &mut self.round2_tally),
+        ] {
+            if votes.is_indexed_by(from) && !votes.is_empty() {
+                *votes = votes.reindex(from, to);
+                *tally = votes.tally();
             }
         }
+    }
 
-        if v0_count >= quorum_size {
-            Some(StateValue::V0)
-        } else if v1_count >= quorum_size {
-            Some(StateValue::V1)
-        } else if vq_count >= quorum_size {
-            Some(StateValue::VQuestion)
-        } else {
-            None
-        }
+    pub fn has_round1_majority(&self, quorum_size: usize) -> Option<StateValue> {
+        self.round1_tally.majority(quorum_size)
+    }
+
+    pub fn has_round2_majority(&self, quorum_size: usize) -> Option<StateValue> {
+        self.round2_tally.majority(quorum_size)
     }
 
     pub fn total_votes(&self) -> usize {
-        self.round1_votes.len().max(self.round2_votes.len())
+        self.round1_tally.total().max(self.round2_tally.total())
     }
 
     pub fn set_decision(&mut self, decision: StateValue) {
@@ -222,6 +313,41 @@ impl PhaseData {
     }
 }
 
+fn record_vote(
+    votes: &mut VoteVector,
+    tally: &mut VoteTally,
+    members: &MemberIndex,
+    voter: NodeId,
+    vote: StateValue,
+) -> VoteRecord {
+    if !votes.is_indexed_by(members) {
+        if !votes.is_empty() {
+            return VoteRecord::Unindexed;
+        }
+        *votes = VoteVector::new(members);
+    }
+    let Some(index) = members.index_of(&voter) else {
+        return VoteRecord::Unindexed;
+    };
+
+    match votes.get(index) {
+        None => {
+            votes.set(index, vote);
+            tally.add(vote);
+            VoteRecord::Recorded
+        }
+        Some(previous) if previous == vote => VoteRecord::Duplicate,
+        Some(previous) => VoteRecord::Conflict { previous },
+    }
+}
+
+fn cast_vote(votes: &VoteVector, members: &MemberIndex, voter: &NodeId) -> Option<StateValue> {
+    if !votes.is_indexed_by(members) {
+        return None;
+    }
+    votes.get(members.index_of(voter)?)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PendingBatch {
     pub batch: CommandBatch,
@@ -255,3 +381,62 @@ impl PendingBatch {
         now.saturating_sub(self.received_timestamp)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicting_vote_keeps_first() {
+        let voter = NodeId::new();
+        let members = MemberIndex::new([voter, NodeId::new()]);
+        let mut phase = PhaseData::new(PhaseId::new(1));
+
+        assert_eq!(
+            phase.add_round1_vote(&members, voter, StateValue::V1),
+            VoteRecord::Recorded
+        );
+        assert_eq!(
+            phase.add_round1_vote(&members, voter, StateValue::V1),
+            VoteRecord::Duplicate
+        );
+        assert_eq!(
+            phase.add_round1_vote(&members, voter, StateValue::V0),
+            VoteRecord::Conflict {
+                previous: StateValue::V1
+            }
+        );
+        assert_eq!(phase.round1_vote(&members, &voter), Some(StateValue::V1));
+        assert_eq!(phase.round1_tally.total(), 1);
+
+        // Rounds are tracked independently
+        assert_eq!(
+            phase.add_round2_vote(&members, voter, StateValue::V0),
+            VoteRecord::Recorded
+        );
+        assert_eq!(
+            phase.add_round2_vote(&members, NodeId::new(), StateValue::V0),
+            VoteRecord::Unindexed
+        );
+    }
+
+    #[test]
+    fn test_reindex_keeps_votes_of_remaining_members() {
+        let (kept, removed, added) = (NodeId::new(), NodeId::new(), NodeId::new());
+        let before = MemberIndex::new([kept, removed]);
+        let after = MemberIndex::new([kept, added]);
+        let mut phase = PhaseData::new(PhaseId::new(1));
+        phase.add_round1_vote(&before, kept, StateValue::V1);
+        phase.add_round1_vote(&before, removed, StateValue::V0);
+
+        phase.reindex(&before, &after);
+        assert_eq!(phase.round1_vote(&after, &kept), Some(StateValue::V1));
+        assert_eq!(phase.round1_vote(&after, &removed), None);
+        assert_eq!(phase.round1_tally.total(), 1);
+        assert_eq!(
+            phase.add_round1_vote(&after, added, StateValue::V1),
+            VoteRecord::Recorded
+        );
+        assert_eq!(phase.has_round1_majority(2), Some(StateValue::V1));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::messages::ProtocolMessage;
+use crate::votes::MemberIndex;
 use crate::{NodeId, Result};
 use async_trait::async_trait;
 use std::collections::HashSet;
@@ -31,6 +32,11 @@ impl ClusterConfig {
     pub fn total_nodes(&self) -> usize {
         self.all_nodes.len()
     }
+
+    /// Member indices used for compact vote encoding
+    pub fn member_index(&self) -> MemberIndex {
+        MemberIndex::new(self.all_nodes.iter().copied())
+    }
 }
 
 #[async_trait]
@@ -39,6 +45,12 @@ pub trait NetworkTransport: Send + Sync {
 
     async fn broadcast(&self, message: ProtocolMessage, exclude: Option<NodeId>) -> Result<()>;
 
+    /// Wait for the next inbound message.
+    ///
+    /// Implementations should block until a message arrives rather than
+    /// returning an error when idle; the engine awaits this directly alongside
+    /// its command channel and timers. The returned future must be
+    /// cancel-safe: dropping it before completion must not lose a message.
     async fn receive(&mut self) -> Result<(NodeId, ProtocolMessage)>;
 
     async fn get_connected_nodes(&self) -> Result<HashSet<NodeId>>;
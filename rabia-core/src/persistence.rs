@@ -1,16 +1,58 @@
-use crate::state_machine::Snapshot;
-use crate::{PhaseId, Result};
+use crate::sessions::{CommittedBatches, SessionTable};
+use crate::state_machine::{DeltaSnapshot, Snapshot};
+use crate::{CommandBatch, PhaseId, RabiaError, Result};
 use async_trait::async_trait;
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Magic bytes at the start of a binary-encoded [`EngineState`]
+const ENGINE_STATE_MAGIC: &[u8; 4] = b"RBES";
+/// Current binary [`EngineState`] format version
+pub const ENGINE_STATE_FORMAT_VERSION: u8 = 3;
+/// Offset of `last_committed_phase`: magic, version, `current_phase`
+const LAST_COMMITTED_OFFSET: usize = 4 + 1 + 8;
+
+/// A saved state with the delta records appended to it, oldest first.
+pub type StateChain = (Vec<u8>, Vec<Vec<u8>>);
 
 /// Simple state structure for Rabia engine persistence.
 ///
 /// This contains only the essential state information needed for Rabia consensus.
+///
+/// [`to_bytes`](Self::to_bytes) produces a versioned binary encoding, all
+/// integers big-endian:
+///
+/// ```text
+/// "RBES" | version: u8 | current_phase: u64 | last_committed_phase: u64
+///        | sessions_len: u32 | sessions (bincode)
+///        | has_snapshot: u8 [| snapshot version: u64 | checksum: u32 | data_len: u64 | data]
+///        | deltas_len: u32 | deltas_len * (base_version: u64 | version: u64 | checksum: u32
+///                                          | data_len: u64 | data)
+///        | committed_len: u32 | committed batches (bincode)
+/// ```
+///
+/// Snapshot and delta data are written as raw bytes. Version 1 has no
+/// deltas and versions before 3 have no committed batches. Readers ignore bytes after the last field they know.
+/// [`from_bytes`](Self::from_bytes) also reads the legacy JSON encoding.
+///
+/// A state without a snapshot can also serve as a delta record, appended to
+/// a saved state with [`PersistenceLayer::append_state_delta`] and merged
+/// back with [`chain`](Self::chain).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EngineState {
     pub current_phase: PhaseId,
     pub last_committed_phase: PhaseId,
     pub snapshot: Option<Snapshot>,
+    /// Client session table, persisted alongside the snapshot it belongs to
+    #[serde(default)]
+    pub sessions: SessionTable,
+    /// Deltas to apply on top of `snapshot`, oldest first
+    #[serde(default)]
+    pub deltas: Vec<DeltaSnapshot>,
+    /// Recently committed batches, persisted like the session table
+    #[serde(default)]
+    pub committed_batches: CommittedBatches,
 }
 
 impl EngineState {
@@ -23,29 +65,257 @@ impl EngineState {
             current_phase,
             last_committed_phase,
             snapshot,
+            sessions: SessionTable::default(),
+            deltas: Vec::new(),
+            committed_batches: CommittedBatches::default(),
+        }
+    }
+
+    /// Attach the client session table to this state.
+    pub fn with_sessions(mut self, sessions: SessionTable) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Attach the recently committed batches to this state.
+    pub fn with_committed_batches(mut self, committed_batches: CommittedBatches) -> Self {
+        self.committed_batches = committed_batches;
+        self
+    }
+
+    /// Attach the delta chain taken on top of the snapshot.
+    pub fn with_deltas(mut self, deltas: Vec<DeltaSnapshot>) -> Self {
+        self.deltas = deltas;
+        self
+    }
+
+    /// Continue this state with a later delta record: its phases and
+    /// sessions and committed batches replace ours, and its deltas extend
+    /// the chain.
+    pub fn chain(&mut self, record: EngineState) -> Result<()> {
+        if record.snapshot.is_some() {
+            return Err(RabiaError::StateCorruption {
+                details: "Delta record carries a full snapshot".to_string(),
+            });
         }
+        self.current_phase = record.current_phase;
+        self.last_committed_phase = record.last_committed_phase;
+        self.sessions = record.sessions;
+        self.committed_batches = record.committed_batches;
+        self.deltas.extend(record.deltas);
+        Ok(())
+    }
+
+    /// Decode a saved state and the delta records appended to it, oldest
+    /// first, into one state.
+    pub fn from_chain(state: &[u8], records: &[Vec<u8>]) -> Result<Self> {
+        let mut engine_state = Self::from_bytes(state)?;
+        for record in records {
+            engine_state.chain(Self::from_bytes(record)?)?;
+        }
+        Ok(engine_state)
     }
 
     /// Serialize the engine state to bytes for persistence.
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
-        serde_json::to_vec(self).map_err(|e| {
-            crate::RabiaError::serialization(format!("Failed to serialize engine state: {}", e))
-        })
+        let snapshot_len = self.snapshot.as_ref().map_or(0, |s| s.data.len());
+        let deltas_len: usize = self.deltas.iter().map(|d| 28 + d.data.len()).sum();
+        let mut buffer = Vec::with_capacity(64 + snapshot_len + deltas_len);
+        self.write_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Stream the binary encoding to `writer`, copying snapshot data as is.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let sessions = bincode::serialize(&self.sessions).map_err(|e| {
+            RabiaError::serialization(format!("Failed to serialize session table: {}", e))
+        })?;
+        let committed_batches = bincode::serialize(&self.committed_batches).map_err(|e| {
+            RabiaError::serialization(format!("Failed to serialize committed batches: {}", e))
+        })?;
+
+        writer.write_all(ENGINE_STATE_MAGIC)?;
+        writer.write_all(&[ENGINE_STATE_FORMAT_VERSION])?;
+        writer.write_all(&self.current_phase.value().to_be_bytes())?;
+        writer.write_all(&self.last_committed_phase.value().to_be_bytes())?;
+        writer.write_all(&(sessions.len() as u32).to_be_bytes())?;
+        writer.write_all(&sessions)?;
+        match &self.snapshot {
+            Some(snapshot) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&snapshot.version.to_be_bytes())?;
+                writer.write_all(&snapshot.checksum.to_be_bytes())?;
+                writer.write_all(&(snapshot.data.len() as u64).to_be_bytes())?;
+                writer.write_all(&snapshot.data)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        writer.write_all(&(self.deltas.len() as u32).to_be_bytes())?;
+        for delta in &self.deltas {
+            writer.write_all(&delta.base_version.to_be_bytes())?;
+            writer.write_all(&delta.version.to_be_bytes())?;
+            writer.write_all(&delta.checksum.to_be_bytes())?;
+            writer.write_all(&(delta.data.len() as u64).to_be_bytes())?;
+            writer.write_all(&delta.data)?;
+        }
+        writer.write_all(&(committed_batches.len() as u32).to_be_bytes())?;
+        writer.write_all(&committed_batches)?;
+        Ok(())
     }
 
-    /// Deserialize engine state from bytes.
+    /// Deserialize engine state from bytes in either the binary or the
+    /// legacy JSON encoding.
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.starts_with(ENGINE_STATE_MAGIC) {
+            return Self::decode_binary(data);
+        }
         serde_json::from_slice(data).map_err(|e| {
-            crate::RabiaError::serialization(format!("Failed to deserialize engine state: {}", e))
+            RabiaError::serialization(format!("Failed to deserialize engine state: {}", e))
         })
     }
+
+    /// Binary format version of encoded state, or `None` for the legacy JSON
+    /// encoding.
+    pub fn format_version(data: &[u8]) -> Option<u8> {
+        if data.starts_with(ENGINE_STATE_MAGIC) {
+            data.get(ENGINE_STATE_MAGIC.len()).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Check whether `data` uses the legacy JSON encoding.
+    pub fn is_legacy_json(data: &[u8]) -> bool {
+        !data.starts_with(ENGINE_STATE_MAGIC)
+    }
+
+    fn decode_binary(data: &[u8]) -> Result<Self> {
+        let mut reader = StateReader { data, position: 0 };
+        reader.take(ENGINE_STATE_MAGIC.len())?;
+        let version = reader.u8()?;
+        if version > ENGINE_STATE_FORMAT_VERSION {
+            return Err(RabiaError::serialization(format!(
+                "Unsupported engine state format version {}",
+                version
+            )));
+        }
+
+        let current_phase = PhaseId::new(reader.u64()?);
+        let last_committed_phase = PhaseId::new(reader.u64()?);
+        let sessions_len = reader.u32()? as usize;
+        let sessions = bincode::deserialize(reader.take(sessions_len)?).map_err(|e| {
+            RabiaError::serialization(format!("Failed to deserialize session table: {}", e))
+        })?;
+        let snapshot = match reader.u8()? {
+            0 => None,
+            1 => {
+                let version = reader.u64()?;
+                let checksum = reader.u32()?;
+                let len = reader.u64()? as usize;
+                let data = Bytes::copy_from_slice(reader.take(len)?);
+                Some(Snapshot {
+                    version,
+                    data,
+                    checksum,
+                })
+            }
+            flag => {
+                return Err(RabiaError::serialization(format!(
+                    "Invalid snapshot flag {} in engine state",
+                    flag
+                )))
+            }
+        };
+
+        let mut deltas = Vec::new();
+        if version >= 2 {
+            for _ in 0..reader.u32()? {
+                let base_version = reader.u64()?;
+                let version = reader.u64()?;
+                let checksum = reader.u32()?;
+                let len = reader.u64()? as usize;
+                deltas.push(DeltaSnapshot {
+                    base_version,
+                    version,
+                    data: Bytes::copy_from_slice(reader.take(len)?),
+                    checksum,
+                });
+            }
+        }
+
+        let committed_batches = if version >= 3 {
+            let committed_len = reader.u32()? as usize;
+            bincode::deserialize(reader.take(committed_len)?).map_err(|e| {
+                RabiaError::serialization(format!("Failed to deserialize committed batches: {}", e))
+            })?
+        } else {
+            CommittedBatches::default()
+        };
+
+        Ok(Self {
+            current_phase,
+            last_committed_phase,
+            snapshot,
+            sessions,
+            deltas,
+            committed_batches,
+        })
+    }
+
+    /// Read `last_committed_phase` from serialized state without building the
+    /// snapshot, or `None` if `data` is not an engine state.
+    pub fn peek_last_committed_phase(data: &[u8]) -> Option<PhaseId> {
+        if data.starts_with(ENGINE_STATE_MAGIC) {
+            let bytes = data.get(LAST_COMMITTED_OFFSET..LAST_COMMITTED_OFFSET + 8)?;
+            return Some(PhaseId::new(u64::from_be_bytes(bytes.try_into().ok()?)));
+        }
+
+        #[derive(Deserialize)]
+        struct Committed {
+            last_committed_phase: PhaseId,
+        }
+        serde_json::from_slice::<Committed>(data)
+            .ok()
+            .map(|committed| committed.last_committed_phase)
+    }
+}
+
+/// Bounds-checked cursor over a binary engine state.
+struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| RabiaError::serialization("Truncated engine state"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
 }
 
 /// Simplified persistence layer for Rabia consensus protocol.
 ///
-/// Rabia only needs to persist a single state value - the current SMR (State Machine Replication) state.
-/// This simplified interface removes the complexity of WAL, checkpoints, and versioning that was
-/// unnecessary for Rabia's consensus requirements.
+/// Rabia only needs to persist a single state value - the current SMR (State Machine Replication) state -
+/// plus a log of the batches committed since that state was saved. The engine snapshots according to
+/// its snapshot policy and appends every other commit to the decision log, which it replays on restart.
 #[async_trait]
 pub trait PersistenceLayer: Send + Sync {
     /// Save the current state to persistent storage.
@@ -58,11 +328,257 @@ pub trait PersistenceLayer: Send + Sync {
     /// * `Err(RabiaError)` if the save operation failed
     async fn save_state(&self, state: &[u8]) -> Result<()>;
 
+    /// Save state that covers the commits through the given phase.
+    ///
+    /// For wrappers that store the state in a form the backend cannot read
+    /// the phase from, e.g. encrypted. Backends that keep states by phase
+    /// should override it; the default saves as [`save_state`](Self::save_state).
+    async fn save_state_through(&self, _phase: PhaseId, state: &[u8]) -> Result<()> {
+        self.save_state(state).await
+    }
+
     /// Load the current state from persistent storage.
     ///
+    /// This is the state as passed to [`save_state`](Self::save_state),
+    /// without the delta records appended to it since.
+    ///
     /// # Returns
     /// * `Ok(Some(state))` if state was found and loaded successfully  
     /// * `Ok(None)` if no state exists (first startup)
     /// * `Err(RabiaError)` if the load operation failed
     async fn load_state(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Append a delta record to the last saved state.
+    ///
+    /// The record is an encoded [`EngineState`] without a snapshot, holding
+    /// the deltas taken since the previous save and the engine metadata at
+    /// this point, so it costs a fraction of saving the whole state again.
+    /// It must be durable when this returns, and the next
+    /// [`save_state`](Self::save_state) drops it.
+    ///
+    /// The default merges the record into the saved state and saves the
+    /// result, which only works for backends storing plain engine states;
+    /// backends should override it to store the record on its own.
+    async fn append_state_delta(&self, record: &[u8]) -> Result<()> {
+        let Some(state) = self.load_state().await? else {
+            return Err(RabiaError::persistence(
+                "Cannot append a delta record without a saved state",
+            ));
+        };
+        let mut engine_state = EngineState::from_bytes(&state)?;
+        engine_state.chain(EngineState::from_bytes(record)?)?;
+        self.save_state(&engine_state.to_bytes()?).await
+    }
+
+    /// Load the current state together with the delta records appended to
+    /// it, oldest first. See [`EngineState::from_chain`].
+    async fn load_state_chain(&self) -> Result<Option<StateChain>> {
+        Ok(self.load_state().await?.map(|state| (state, Vec::new())))
+    }
+
+    /// Whether this backend keeps a decision log.
+    ///
+    /// Backends overriding the decision log methods below return `true`. The
+    /// default `false` makes the engine save the state on every commit
+    /// instead of logging it, and the log methods do nothing.
+    fn has_decision_log(&self) -> bool {
+        false
+    }
+
+    /// Append a committed batch to the decision log.
+    ///
+    /// The entry must be durable when this returns. Logging a phase again
+    /// replaces its entry.
+    async fn append_decision(&self, _phase: PhaseId, _batch: &CommandBatch) -> Result<()> {
+        Ok(())
+    }
+
+    /// Append several committed batches to the decision log at once.
+    ///
+    /// All entries must be durable when this returns. Backends should
+    /// override this to cover the whole group with a single sync; the
+    /// default appends the entries one by one.
+    async fn append_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        for (phase, batch) in entries {
+            self.append_decision(*phase, batch).await?;
+        }
+        Ok(())
+    }
+
+    /// Load logged batches with a phase greater than `after`, in phase order.
+    async fn load_decisions(&self, _after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        Ok(Vec::new())
+    }
+
+    /// Drop log entries up to and including `through`, once a saved state
+    /// covers them.
+    async fn truncate_decisions(&self, _through: PhaseId) -> Result<()> {
+        Ok(())
+    }
+
+    /// The highest phase whose log entries may have been dropped by
+    /// [`truncate_decisions`](Self::truncate_decisions).
+    ///
+    /// A loaded state older than this cannot be brought up to date from the
+    /// log. The default returns phase 0, for backends whose saved state always
+    /// covers the entries they drop.
+    async fn truncated_through(&self) -> Result<PhaseId> {
+        Ok(PhaseId::default())
+    }
+
+    /// Durably advance this node's incarnation counter and return the new
+    /// value, which stamps the node's outgoing message sequences.
+    ///
+    /// Backends that survive restarts must return a value greater than any
+    /// they returned before, without relying on the wall clock. The default
+    /// returns `None`, and the engine falls back to the startup time.
+    async fn next_incarnation(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::SessionConfig;
+    use crate::{BatchId, ClientId, Command};
+
+    fn sample_state() -> EngineState {
+        let mut sessions = SessionTable::new();
+        let command = Command::with_session("SET a 1", ClientId::new(), 1);
+        sessions.record(
+            command.session.unwrap(),
+            PhaseId::new(6),
+            Bytes::from_static(b"OK"),
+            &SessionConfig::default(),
+        );
+        EngineState::new(
+            PhaseId::new(7),
+            PhaseId::new(6),
+            Some(Snapshot::new(3, [0u8, 1, 2, 255].repeat(1000))),
+        )
+        .with_sessions(sessions)
+        .with_committed_batches([(BatchId::new(), PhaseId::new(6))].into_iter().collect())
+    }
+
+    /// Length of the committed batches at the end of `state`'s encoding.
+    fn committed_trailer_len(state: &EngineState) -> usize {
+        4 + bincode::serialize(&state.committed_batches).unwrap().len()
+    }
+
+    fn assert_same(a: &EngineState, b: &EngineState) {
+        assert_eq!(a.current_phase, b.current_phase);
+        assert_eq!(a.last_committed_phase, b.last_committed_phase);
+        assert_eq!(a.sessions, b.sessions);
+        let (sa, sb) = (a.snapshot.as_ref().unwrap(), b.snapshot.as_ref().unwrap());
+        assert_eq!(
+            (sa.version, sa.checksum, &sa.data),
+            (sb.version, sb.checksum, &sb.data)
+        );
+        assert_eq!(a.deltas, b.deltas);
+        assert_eq!(a.committed_batches, b.committed_batches);
+    }
+
+    #[test]
+    fn test_binary_roundtrip_with_deltas() {
+        let state = sample_state().with_deltas(vec![
+            DeltaSnapshot::new(3, 5, b"first".to_vec()),
+            DeltaSnapshot::new(5, 6, b"second".to_vec()),
+        ]);
+        let bytes = state.to_bytes().unwrap();
+        assert_same(&EngineState::from_bytes(&bytes).unwrap(), &state);
+
+        // Version 2 states end after the deltas and carry no committed batches
+        let mut v2 = bytes.clone();
+        v2.truncate(v2.len() - committed_trailer_len(&state));
+        v2[4] = 2;
+        let decoded = EngineState::from_bytes(&v2).unwrap();
+        assert_eq!(decoded.deltas, state.deltas);
+        assert!(decoded.committed_batches.is_empty());
+
+        // Version 1 states end after the snapshot and carry no deltas
+        let mut v1 = sample_state().to_bytes().unwrap();
+        v1.truncate(v1.len() - committed_trailer_len(&state) - 4);
+        v1[4] = 1;
+        assert!(EngineState::from_bytes(&v1).unwrap().deltas.is_empty());
+    }
+
+    #[test]
+    fn test_binary_roundtrip_keeps_snapshot_bytes_raw() {
+        let state = sample_state();
+        let bytes = state.to_bytes().unwrap();
+
+        assert!(!EngineState::is_legacy_json(&bytes));
+        assert_eq!(
+            EngineState::format_version(&bytes),
+            Some(ENGINE_STATE_FORMAT_VERSION)
+        );
+        // Snapshot data appears verbatim, followed by an empty delta list
+        let (head, tail) = bytes.split_at(bytes.len() - committed_trailer_len(&state) - 4);
+        assert!(head.ends_with(&state.snapshot.as_ref().unwrap().data));
+        assert_eq!(tail[..4], 0u32.to_be_bytes());
+        assert_same(&EngineState::from_bytes(&bytes).unwrap(), &state);
+        assert_eq!(
+            EngineState::peek_last_committed_phase(&bytes),
+            Some(PhaseId::new(6))
+        );
+    }
+
+    #[test]
+    fn test_reads_legacy_json() {
+        let state = sample_state();
+        let json = serde_json::to_vec(&state).unwrap();
+
+        assert!(EngineState::is_legacy_json(&json));
+        assert_eq!(EngineState::format_version(&json), None);
+        assert!(json.len() > state.to_bytes().unwrap().len() * 2);
+        assert_same(&EngineState::from_bytes(&json).unwrap(), &state);
+        assert_eq!(
+            EngineState::peek_last_committed_phase(&json),
+            Some(PhaseId::new(6))
+        );
+    }
+
+    #[test]
+    fn test_chain_applies_delta_records_in_order() {
+        let base = sample_state().with_deltas(vec![DeltaSnapshot::new(3, 5, b"first".to_vec())]);
+        let first = EngineState::new(PhaseId::new(9), PhaseId::new(8), None)
+            .with_deltas(vec![DeltaSnapshot::new(5, 6, b"second".to_vec())]);
+        let second = EngineState::new(PhaseId::new(10), PhaseId::new(10), None);
+
+        let chained = EngineState::from_chain(
+            &base.to_bytes().unwrap(),
+            &[first.to_bytes().unwrap(), second.to_bytes().unwrap()],
+        )
+        .unwrap();
+        assert_eq!(chained.current_phase, PhaseId::new(10));
+        assert_eq!(chained.last_committed_phase, PhaseId::new(10));
+        assert!(chained.sessions.is_empty());
+        assert_eq!(
+            chained.deltas,
+            vec![
+                DeltaSnapshot::new(3, 5, b"first".to_vec()),
+                DeltaSnapshot::new(5, 6, b"second".to_vec()),
+            ]
+        );
+
+        // A record with a snapshot would silently replace the base
+        let mut state = sample_state();
+        assert!(state.chain(sample_state()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_truncated_and_future_versions() {
+        let bytes = sample_state().to_bytes().unwrap();
+        assert!(EngineState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut future = bytes.clone();
+        future[4] = ENGINE_STATE_FORMAT_VERSION + 1;
+        assert!(EngineState::from_bytes(&future).is_err());
+
+        // Fields appended by a later writer are ignored
+        let mut extended = bytes;
+        extended.extend_from_slice(b"future field");
+        assert!(EngineState::from_bytes(&extended).is_ok());
+    }
 }
@@ -0,0 +1,311 @@
+//! # Node Registry
+//!
+//! Stable names and small indices for cluster members.
+//!
+//! A [`NodeRegistry`] is built from a cluster configuration file listing each
+//! member's name, and optionally its index, ID and address:
+//!
+//! ```toml
+//! [[nodes]]
+//! name = "alpha"
+//! address = "10.0.0.1:7000"
+//!
+//! [[nodes]]
+//! name = "beta"
+//! index = 1
+//! address = "10.0.0.2:7000"
+//! ```
+//!
+//! Indices default to the position in the file. Unless an explicit `id` is
+//! given, each member gets a [`NodeId::stable`] ID derived from its index and
+//! name, so IDs are the same on every node and across restarts, and sort in
+//! index order. Leader selection and member indices therefore follow the
+//! order of the file.
+//!
+//! `NodeId`'s `Display` always prints the UUID. Names are looked up
+//! explicitly with [`NodeId::display_name`] or [`NodeId::display_with`]; an
+//! engine configured with a registry uses them in its tracing span, log lines
+//! and statistics.
+
+use crate::network::ClusterConfig;
+use crate::{NodeId, RabiaError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use uuid::Uuid;
+
+/// One member in a cluster configuration file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeConfig {
+    pub name: String,
+    /// Defaults to the member's position in the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// Defaults to an ID derived from the index and name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// Contents of a cluster configuration file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryConfig {
+    #[serde(default)]
+    pub nodes: Vec<NodeConfig>,
+}
+
+/// A registered cluster member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeEntry {
+    pub name: String,
+    pub index: u32,
+    pub id: NodeId,
+    pub address: Option<String>,
+}
+
+/// Lookup table between member names, indices and node IDs.
+#[derive(Debug, Clone, Default)]
+pub struct NodeRegistry {
+    /// Members sorted by index
+    entries: Vec<NodeEntry>,
+    by_name: HashMap<String, usize>,
+    by_id: HashMap<NodeId, usize>,
+}
+
+impl NodeRegistry {
+    pub fn from_config(config: RegistryConfig) -> Result<Self> {
+        let mut entries = Vec::with_capacity(config.nodes.len());
+        for (position, node) in config.nodes.into_iter().enumerate() {
+            if node.name.trim().is_empty() {
+                return Err(RabiaError::configuration(format!(
+                    "Node at position {} has an empty name",
+                    position
+                )));
+            }
+            let index = node.index.unwrap_or(position as u32);
+            let id = node
+                .id
+                .map(NodeId)
+                .unwrap_or_else(|| NodeId::stable(index, &node.name));
+            entries.push(NodeEntry {
+                name: node.name,
+                index,
+                id,
+                address: node.address,
+            });
+        }
+        entries.sort_by_key(|entry| entry.index);
+
+        let mut by_name = HashMap::new();
+        let mut by_id = HashMap::new();
+        let mut indices = HashSet::new();
+        for (position, entry) in entries.iter().enumerate() {
+            if by_name.insert(entry.name.clone(), position).is_some() {
+                return Err(RabiaError::configuration(format!(
+                    "Duplicate node name '{}'",
+                    entry.name
+                )));
+            }
+            if !indices.insert(entry.index) {
+                return Err(RabiaError::configuration(format!(
+                    "Duplicate node index {}",
+                    entry.index
+                )));
+            }
+            if by_id.insert(entry.id, position).is_some() {
+                return Err(RabiaError::configuration(format!(
+                    "Duplicate node id {}",
+                    entry.id.0
+                )));
+            }
+        }
+
+        Ok(Self {
+            entries,
+            by_name,
+            by_id,
+        })
+    }
+
+    /// Parse a cluster configuration in TOML.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: RegistryConfig = toml::from_str(text).map_err(|e| {
+            RabiaError::configuration(format!("Invalid cluster configuration: {}", e))
+        })?;
+        Self::from_config(config)
+    }
+
+    /// Read a cluster configuration file in TOML.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            RabiaError::configuration(format!(
+                "Cannot read cluster configuration {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::from_toml(&text)
+    }
+
+    /// Configuration that rebuilds this registry, with every field explicit.
+    pub fn to_config(&self) -> RegistryConfig {
+        RegistryConfig {
+            nodes: self
+                .entries
+                .iter()
+                .map(|entry| NodeConfig {
+                    name: entry.name.clone(),
+                    index: Some(entry.index),
+                    id: Some(entry.id.0),
+                    address: entry.address.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(&self.to_config()).map_err(|e| {
+            RabiaError::serialization(format!("Cannot encode cluster configuration: {}", e))
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NodeEntry> {
+        self.by_name
+            .get(name)
+            .map(|position| &self.entries[*position])
+    }
+
+    pub fn by_index(&self, index: u32) -> Option<&NodeEntry> {
+        self.entries
+            .binary_search_by_key(&index, |entry| entry.index)
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    pub fn by_id(&self, node_id: &NodeId) -> Option<&NodeEntry> {
+        self.by_id
+            .get(node_id)
+            .map(|position| &self.entries[*position])
+    }
+
+    pub fn node_id(&self, name: &str) -> Option<NodeId> {
+        self.get(name).map(|entry| entry.id)
+    }
+
+    pub fn name_of(&self, node_id: &NodeId) -> Option<&str> {
+        self.by_id(node_id).map(|entry| entry.name.as_str())
+    }
+
+    /// Members in index order.
+    pub fn iter(&self) -> impl Iterator<Item = &NodeEntry> {
+        self.entries.iter()
+    }
+
+    pub fn node_ids(&self) -> HashSet<NodeId> {
+        self.entries.iter().map(|entry| entry.id).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Cluster configuration of all registered members, seen from `local_name`.
+    pub fn cluster_config(&self, local_name: &str) -> Result<ClusterConfig> {
+        let local = self.node_id(local_name).ok_or_else(|| {
+            RabiaError::configuration(format!("Node '{}' is not in the registry", local_name))
+        })?;
+        Ok(ClusterConfig::new(local, self.node_ids()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER: &str = r#"
+        [[nodes]]
+        name = "alpha"
+        address = "10.0.0.1:7000"
+
+        [[nodes]]
+        name = "beta"
+
+        [[nodes]]
+        name = "gamma"
+        index = 7
+    "#;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let registry = NodeRegistry::from_toml(CLUSTER).unwrap();
+        assert_eq!(registry.len(), 3);
+
+        let alpha = registry.get("alpha").unwrap();
+        assert_eq!(alpha.index, 0);
+        assert_eq!(alpha.address.as_deref(), Some("10.0.0.1:7000"));
+        assert_eq!(registry.by_index(7).unwrap().name, "gamma");
+        assert_eq!(registry.name_of(&alpha.id), Some("alpha"));
+        assert!(registry.by_index(2).is_none());
+    }
+
+    #[test]
+    fn test_ids_are_stable_and_sort_by_index() {
+        let first = NodeRegistry::from_toml(CLUSTER).unwrap();
+        let second = NodeRegistry::from_toml(CLUSTER).unwrap();
+        assert_eq!(first.node_id("beta"), second.node_id("beta"));
+
+        let mut ids: Vec<NodeId> = first.node_ids().into_iter().collect();
+        ids.sort();
+        let names: Vec<&str> = ids.iter().map(|id| first.name_of(id).unwrap()).collect();
+        assert_eq!(names, vec!["alpha", "beta", "gamma"]);
+    }
+
+    #[test]
+    fn test_rejects_duplicates() {
+        let duplicate_name = r#"
+            [[nodes]]
+            name = "alpha"
+            [[nodes]]
+            name = "alpha"
+        "#;
+        assert!(NodeRegistry::from_toml(duplicate_name).is_err());
+
+        let duplicate_index = r#"
+            [[nodes]]
+            name = "alpha"
+            index = 1
+            [[nodes]]
+            name = "beta"
+        "#;
+        assert!(NodeRegistry::from_toml(duplicate_index).is_err());
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let registry = NodeRegistry::from_toml(CLUSTER).unwrap();
+        let reloaded = NodeRegistry::from_toml(&registry.to_toml().unwrap()).unwrap();
+        assert_eq!(
+            registry.iter().collect::<Vec<_>>(),
+            reloaded.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_display_name_uses_registry() {
+        let registry = NodeRegistry::from_toml(CLUSTER).unwrap();
+        let node_id = registry.node_id("beta").unwrap();
+        assert_eq!(node_id.display_name(&registry), "beta");
+        // Display is unaffected by any registry
+        assert_eq!(node_id.to_string(), node_id.0.to_string());
+
+        // Unregistered nodes print their UUID
+        let other = NodeId::new();
+        assert_eq!(other.display_name(&registry), other.0.to_string());
+    }
+}
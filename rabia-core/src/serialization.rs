@@ -185,7 +185,7 @@ fn estimate_message_size(message: &ProtocolMessage) -> usize {
             64 + batch_size
         }
         MessageType::VoteRound1(_) => 32,
-        MessageType::VoteRound2(vote) => 32 + vote.round1_votes.len() * 16,
+        MessageType::VoteRound2(vote) => 32 + vote.round1_votes.as_packed().len(),
         MessageType::Decision(decision) => {
             let batch_size = decision
                 .batch
@@ -0,0 +1,430 @@
+//! # Client Sessions
+//!
+//! Deduplication of retried commands for exactly-once semantics.
+//!
+//! The [`SessionTable`] is part of the replicated state: every replica updates
+//! it while applying committed batches in phase order, so all replicas agree on
+//! which commands have been applied and which responses are cached. It is
+//! persisted and shipped during sync together with the state machine snapshot.
+//!
+//! [`CommittedBatches`] does the same for whole batches, so a batch that is
+//! proposed again after it committed is not applied twice.
+
+use crate::{BatchId, ClientId, Command, PhaseId, SessionInfo};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// Limits applied to the session table.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Number of most recent responses cached per client
+    pub response_window: usize,
+    /// Maximum number of tracked clients before the least recently active is evicted
+    pub max_sessions: usize,
+    /// Maximum number of committed batches remembered before those of the
+    /// oldest phases are evicted
+    pub max_committed_batches: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            response_window: 64,
+            max_sessions: 10_000,
+            max_committed_batches: 4096,
+        }
+    }
+}
+
+/// Outcome of checking a command against the session table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionCheck {
+    /// Command has no session or has not been applied yet
+    Apply,
+    /// Command was already applied; this is its original response
+    Duplicate(Bytes),
+    /// Command is older than the client's response window, so whether it was
+    /// applied can no longer be told
+    Expired,
+}
+
+/// Per-client deduplication state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionEntry {
+    /// Highest sequence number applied for this client
+    pub last_sequence: u64,
+    /// Phase in which this client last had a command applied
+    pub last_active_phase: PhaseId,
+    /// Cached responses keyed by sequence number
+    pub responses: BTreeMap<u64, Bytes>,
+}
+
+impl SessionEntry {
+    /// Whether every applied sequence from `sequence` up still has its
+    /// response cached. Responses are evicted lowest sequence first, and only
+    /// once the window is full.
+    fn in_window(&self, sequence: u64, config: &SessionConfig) -> bool {
+        self.responses.len() < config.response_window
+            || self
+                .responses
+                .first_key_value()
+                .is_some_and(|(oldest, _)| sequence > *oldest)
+    }
+}
+
+/// Replicated table of client sessions and their cached responses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTable {
+    sessions: HashMap<ClientId, SessionEntry>,
+}
+
+impl SessionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether `command` should be applied or answered from the cache.
+    ///
+    /// Commands may commit out of sequence order, e.g. when a client pipelines
+    /// them. A sequence below the highest applied one is still applied if it
+    /// falls inside the response window without a cached response, since
+    /// every sequence applied in the window has one.
+    pub fn check(&self, command: &Command, config: &SessionConfig) -> SessionCheck {
+        let Some(session) = command.session else {
+            return SessionCheck::Apply;
+        };
+        let Some(entry) = self.sessions.get(&session.client_id) else {
+            return SessionCheck::Apply;
+        };
+
+        if let Some(response) = entry.responses.get(&session.sequence) {
+            return SessionCheck::Duplicate(response.clone());
+        }
+        if session.sequence > entry.last_sequence || entry.in_window(session.sequence, config) {
+            SessionCheck::Apply
+        } else {
+            SessionCheck::Expired
+        }
+    }
+
+    /// Record the response of a freshly applied session command.
+    pub fn record(
+        &mut self,
+        session: SessionInfo,
+        phase_id: PhaseId,
+        response: Bytes,
+        config: &SessionConfig,
+    ) {
+        let entry = self.sessions.entry(session.client_id).or_default();
+        entry.last_sequence = entry.last_sequence.max(session.sequence);
+        entry.last_active_phase = phase_id;
+        entry.responses.insert(session.sequence, response);
+
+        while entry.responses.len() > config.response_window {
+            entry.responses.pop_first();
+        }
+
+        if self.sessions.len() > config.max_sessions {
+            self.evict_least_recent(config.max_sessions);
+        }
+    }
+
+    /// Get the session entry of a client.
+    pub fn get(&self, client_id: &ClientId) -> Option<&SessionEntry> {
+        self.sessions.get(client_id)
+    }
+
+    /// Number of tracked client sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Iterate over client sessions in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&ClientId, &SessionEntry)> {
+        self.sessions.iter()
+    }
+
+    fn evict_least_recent(&mut self, max_sessions: usize) {
+        // Order by phase then client id so every replica evicts the same sessions
+        let mut by_activity: Vec<(PhaseId, ClientId)> = self
+            .sessions
+            .iter()
+            .map(|(client_id, entry)| (entry.last_active_phase, *client_id))
+            .collect();
+        by_activity.sort();
+
+        let excess = self.sessions.len().saturating_sub(max_sessions);
+        for (_, client_id) in by_activity.into_iter().take(excess) {
+            self.sessions.remove(&client_id);
+        }
+    }
+}
+
+impl FromIterator<(ClientId, SessionEntry)> for SessionTable {
+    fn from_iter<I: IntoIterator<Item = (ClientId, SessionEntry)>>(iter: I) -> Self {
+        Self {
+            sessions: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Replicated record of the batches committed in the latest phases.
+///
+/// A batch can be proposed again after it committed, e.g. by a follower that
+/// reclaimed it from the leader. Replicas skip a batch found here instead of
+/// applying it a second time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "HashMap<BatchId, PhaseId>", into = "HashMap<BatchId, PhaseId>")]
+pub struct CommittedBatches {
+    batches: HashMap<BatchId, PhaseId>,
+    /// The same batches ordered for eviction
+    by_phase: BTreeSet<(PhaseId, Uuid)>,
+}
+
+impl CommittedBatches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether a batch has committed.
+    pub fn contains(&self, batch_id: &BatchId) -> bool {
+        self.batches.contains_key(batch_id)
+    }
+
+    /// Phase in which a batch committed.
+    pub fn get(&self, batch_id: &BatchId) -> Option<PhaseId> {
+        self.batches.get(batch_id).copied()
+    }
+
+    /// Record a batch committed in `phase_id`.
+    ///
+    /// Only the batches of the latest phases are kept, ordered by phase then
+    /// batch id, so replicas keep the same batches whichever order they
+    /// apply decisions in.
+    pub fn record(&mut self, batch_id: BatchId, phase_id: PhaseId, config: &SessionConfig) {
+        if let Some(previous) = self.batches.insert(batch_id, phase_id) {
+            self.by_phase.remove(&(previous, batch_id.0));
+        }
+        self.by_phase.insert((phase_id, batch_id.0));
+
+        while self.batches.len() > config.max_committed_batches {
+            let Some((_, oldest)) = self.by_phase.pop_first() else {
+                break;
+            };
+            self.batches.remove(&BatchId(oldest));
+        }
+    }
+
+    /// Number of remembered batches.
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Iterate over batches and their phases in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&BatchId, &PhaseId)> {
+        self.batches.iter()
+    }
+}
+
+impl FromIterator<(BatchId, PhaseId)> for CommittedBatches {
+    fn from_iter<I: IntoIterator<Item = (BatchId, PhaseId)>>(iter: I) -> Self {
+        let batches: HashMap<BatchId, PhaseId> = iter.into_iter().collect();
+        let by_phase = batches
+            .iter()
+            .map(|(batch_id, phase_id)| (*phase_id, batch_id.0))
+            .collect();
+        Self { batches, by_phase }
+    }
+}
+
+impl From<HashMap<BatchId, PhaseId>> for CommittedBatches {
+    fn from(batches: HashMap<BatchId, PhaseId>) -> Self {
+        batches.into_iter().collect()
+    }
+}
+
+impl From<CommittedBatches> for HashMap<BatchId, PhaseId> {
+    fn from(committed: CommittedBatches) -> Self {
+        committed.batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_without_session_always_apply() {
+        let table = SessionTable::new();
+        assert_eq!(
+            table.check(&Command::new("SET a 1"), &SessionConfig::default()),
+            SessionCheck::Apply
+        );
+    }
+
+    #[test]
+    fn test_duplicate_returns_cached_response() {
+        let config = SessionConfig::default();
+        let mut table = SessionTable::new();
+        let client = ClientId::new();
+
+        let cmd = Command::with_session("SET a 1", client, 1);
+        assert_eq!(table.check(&cmd, &config), SessionCheck::Apply);
+        table.record(
+            cmd.session.unwrap(),
+            PhaseId::new(1),
+            Bytes::from("OK"),
+            &config,
+        );
+
+        let retry = Command::with_session("SET a 1", client, 1);
+        assert_eq!(
+            table.check(&retry, &config),
+            SessionCheck::Duplicate(Bytes::from("OK"))
+        );
+
+        let next = Command::with_session("SET a 2", client, 2);
+        assert_eq!(table.check(&next, &config), SessionCheck::Apply);
+    }
+
+    #[test]
+    fn test_response_window_expires_old_responses() {
+        let config = SessionConfig {
+            response_window: 2,
+            max_sessions: 10,
+            ..SessionConfig::default()
+        };
+        let mut table = SessionTable::new();
+        let client = ClientId::new();
+
+        for sequence in 1..=3 {
+            let session = SessionInfo {
+                client_id: client,
+                sequence,
+            };
+            table.record(session, PhaseId::new(sequence), Bytes::from("OK"), &config);
+        }
+
+        let oldest = Command::with_session("SET a 1", client, 1);
+        assert_eq!(table.check(&oldest, &config), SessionCheck::Expired);
+        let newest = Command::with_session("SET a 1", client, 3);
+        assert!(matches!(
+            table.check(&newest, &config),
+            SessionCheck::Duplicate(_)
+        ));
+    }
+
+    #[test]
+    fn test_out_of_order_sequence_inside_window_is_applied() {
+        let config = SessionConfig {
+            response_window: 2,
+            max_sessions: 10,
+            ..SessionConfig::default()
+        };
+        let mut table = SessionTable::new();
+        let client = ClientId::new();
+        let record = |table: &mut SessionTable, sequence: u64| {
+            let session = SessionInfo {
+                client_id: client,
+                sequence,
+            };
+            table.record(session, PhaseId::new(sequence), Bytes::from("OK"), &config);
+        };
+
+        // Sequence 5 commits before 4
+        record(&mut table, 3);
+        record(&mut table, 5);
+        let late = Command::with_session("SET a 4", client, 4);
+        assert_eq!(table.check(&late, &config), SessionCheck::Apply);
+
+        // Once 4 is applied, 3 leaves the window and can no longer be told apart
+        record(&mut table, 4);
+        assert!(matches!(
+            table.check(&late, &config),
+            SessionCheck::Duplicate(_)
+        ));
+        let oldest = Command::with_session("SET a 3", client, 3);
+        assert_eq!(table.check(&oldest, &config), SessionCheck::Expired);
+    }
+
+    #[test]
+    fn test_least_recent_sessions_are_evicted() {
+        let config = SessionConfig {
+            response_window: 4,
+            max_sessions: 2,
+            ..SessionConfig::default()
+        };
+        let mut table = SessionTable::new();
+        let clients: Vec<ClientId> = (0..3).map(|_| ClientId::new()).collect();
+
+        for (i, client) in clients.iter().enumerate() {
+            let session = SessionInfo {
+                client_id: *client,
+                sequence: 1,
+            };
+            table.record(session, PhaseId::new(i as u64), Bytes::from("OK"), &config);
+        }
+
+        assert_eq!(table.len(), 2);
+        assert!(table.get(&clients[0]).is_none());
+        assert!(table.get(&clients[2]).is_some());
+    }
+
+    #[test]
+    fn test_session_table_roundtrip() {
+        let mut table = SessionTable::new();
+        let session = SessionInfo {
+            client_id: ClientId::new(),
+            sequence: 7,
+        };
+        table.record(
+            session,
+            PhaseId::new(3),
+            Bytes::from("value"),
+            &SessionConfig::default(),
+        );
+
+        let bytes = serde_json::to_vec(&table).unwrap();
+        let restored: SessionTable = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(restored, table);
+    }
+
+    #[test]
+    fn test_committed_batches_keep_latest_phases() {
+        let config = SessionConfig {
+            max_committed_batches: 2,
+            ..SessionConfig::default()
+        };
+        let batches: Vec<BatchId> = (0..3).map(|_| BatchId::new()).collect();
+
+        // Applied out of phase order, the oldest phase is still the one evicted
+        let mut committed = CommittedBatches::new();
+        for phase in [2, 3, 1] {
+            committed.record(batches[phase - 1], PhaseId::new(phase as u64), &config);
+        }
+
+        assert_eq!(committed.len(), 2);
+        assert!(!committed.contains(&batches[0]));
+        assert_eq!(committed.get(&batches[2]), Some(PhaseId::new(3)));
+    }
+
+    #[test]
+    fn test_committed_batches_roundtrip() {
+        let mut committed = CommittedBatches::new();
+        committed.record(BatchId::new(), PhaseId::new(3), &SessionConfig::default());
+
+        let bytes = bincode::serialize(&committed).unwrap();
+        let restored: CommittedBatches = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(restored, committed);
+    }
+}
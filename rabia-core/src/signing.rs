@@ -0,0 +1,399 @@
+//! # Message Signing
+//!
+//! Optional end-to-end integrity for [`ProtocolMessage`]s, independent of
+//! transport security, so that relayed decisions and sync responses cannot be
+//! forged by an intermediary.
+//!
+//! Two schemes are supported:
+//!
+//! - **HMAC-SHA256** with a key shared by all nodes
+//! - **Ed25519** with a private key per node and the public key of every peer,
+//!   so a signature also proves which node produced the message
+//!
+//! Keys are read from hex-encoded files named in a [`SigningConfig`].
+//! Signatures cover the [`wire`](crate::wire) encoding of the message without
+//! its signature. That encoding writes maps in sorted order, so it does not
+//! depend on map iteration order. Received messages are verified against the
+//! frame as it arrived ([`ProtocolMessage::signed_bytes`]), not a local
+//! re-encoding, so a signature from a newer node that appends fields this
+//! node does not know still verifies.
+
+use crate::messages::ProtocolMessage;
+use crate::validation::{Rejection, RejectionReason};
+use crate::{wire, NodeId, RabiaError, Result};
+use bytes::Bytes;
+use ed25519_dalek::{Signer, Verifier};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Prefix mixed into every signature so it cannot be reused in another context
+const SIGNING_DOMAIN: &[u8] = b"rabia-protocol-message-v1";
+
+/// Where to find signing keys.
+#[derive(Debug, Clone)]
+pub enum SigningConfig {
+    /// HMAC-SHA256 with a key shared by all nodes
+    Hmac { key_file: PathBuf },
+    /// Ed25519 with this node's private key and every peer's public key
+    Ed25519 {
+        private_key_file: PathBuf,
+        public_key_files: HashMap<NodeId, PathBuf>,
+    },
+}
+
+impl SigningConfig {
+    /// Read the configured key files.
+    pub fn load(&self) -> Result<MessageSigner> {
+        match self {
+            SigningConfig::Hmac { key_file } => MessageSigner::hmac(read_key_file(key_file)?),
+            SigningConfig::Ed25519 {
+                private_key_file,
+                public_key_files,
+            } => {
+                let private_key = read_key32(private_key_file)?;
+                let mut public_keys = HashMap::new();
+                for (node_id, path) in public_key_files {
+                    public_keys.insert(*node_id, read_key32(path)?);
+                }
+                MessageSigner::ed25519(private_key, public_keys)
+            }
+        }
+    }
+}
+
+enum Keys {
+    Hmac(Vec<u8>),
+    Ed25519 {
+        signing_key: Box<ed25519_dalek::SigningKey>,
+        verifying_keys: HashMap<NodeId, ed25519_dalek::VerifyingKey>,
+    },
+}
+
+/// Signs outgoing messages and verifies incoming ones.
+pub struct MessageSigner {
+    keys: Keys,
+}
+
+impl fmt::Debug for MessageSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print key material
+        match &self.keys {
+            Keys::Hmac(_) => f
+                .debug_struct("MessageSigner")
+                .field("scheme", &"hmac-sha256")
+                .finish(),
+            Keys::Ed25519 { verifying_keys, .. } => f
+                .debug_struct("MessageSigner")
+                .field("scheme", &"ed25519")
+                .field("known_peers", &verifying_keys.len())
+                .finish(),
+        }
+    }
+}
+
+impl MessageSigner {
+    /// Create an HMAC-SHA256 signer from a shared key.
+    pub fn hmac(key: impl Into<Vec<u8>>) -> Result<Self> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(RabiaError::key("HMAC signing key cannot be empty"));
+        }
+        Ok(Self {
+            keys: Keys::Hmac(key),
+        })
+    }
+
+    /// Create an Ed25519 signer from this node's 32-byte private key and the
+    /// public keys of its peers.
+    pub fn ed25519(private_key: [u8; 32], public_keys: HashMap<NodeId, [u8; 32]>) -> Result<Self> {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&private_key);
+        let mut verifying_keys = HashMap::new();
+        for (node_id, bytes) in public_keys {
+            let key = ed25519_dalek::VerifyingKey::from_bytes(&bytes).map_err(|e| {
+                RabiaError::key(format!("Invalid public key for node {}: {}", node_id, e))
+            })?;
+            verifying_keys.insert(node_id, key);
+        }
+        Ok(Self {
+            keys: Keys::Ed25519 {
+                signing_key: Box::new(signing_key),
+                verifying_keys,
+            },
+        })
+    }
+
+    /// Public key matching an Ed25519 private key, for distributing to peers.
+    pub fn ed25519_public_key(private_key: &[u8; 32]) -> [u8; 32] {
+        ed25519_dalek::SigningKey::from_bytes(private_key)
+            .verifying_key()
+            .to_bytes()
+    }
+
+    /// Sign `message`, replacing any existing signature.
+    pub fn sign(&self, mut message: ProtocolMessage) -> Result<ProtocolMessage> {
+        message.signed_bytes = None;
+        let payload = signing_payload(&message)?;
+        let signature = match &self.keys {
+            Keys::Hmac(key) => {
+                let mut mac = new_hmac(key);
+                mac.update(&payload);
+                Bytes::copy_from_slice(&mac.finalize().into_bytes())
+            }
+            Keys::Ed25519 { signing_key, .. } => {
+                Bytes::copy_from_slice(&signing_key.sign(&payload).to_bytes())
+            }
+        };
+        message.signature = Some(signature);
+        Ok(message)
+    }
+
+    /// Check that `message` carries a valid signature from its sender.
+    pub fn verify(&self, message: &ProtocolMessage) -> std::result::Result<(), Rejection> {
+        let Some(signature) = &message.signature else {
+            return Err(Rejection::new(
+                RejectionReason::MissingSignature,
+                format!("Message from {} is not signed", message.from),
+            ));
+        };
+        let invalid = |detail: String| Rejection::new(RejectionReason::InvalidSignature, detail);
+
+        let payload = signing_payload(message)
+            .map_err(|e| invalid(format!("Cannot encode message for verification: {}", e)))?;
+
+        match &self.keys {
+            Keys::Hmac(key) => {
+                let mut mac = new_hmac(key);
+                mac.update(&payload);
+                mac.verify_slice(signature)
+                    .map_err(|_| invalid(format!("Bad HMAC on message from {}", message.from)))
+            }
+            Keys::Ed25519 { verifying_keys, .. } => {
+                let key = verifying_keys.get(&message.from).ok_or_else(|| {
+                    invalid(format!("No public key known for node {}", message.from))
+                })?;
+                let signature = ed25519_dalek::Signature::from_slice(signature)
+                    .map_err(|e| invalid(format!("Malformed signature: {}", e)))?;
+                key.verify(&payload, &signature).map_err(|_| {
+                    invalid(format!(
+                        "Bad Ed25519 signature on message from {}",
+                        message.from
+                    ))
+                })
+            }
+        }
+    }
+}
+
+/// The message's wire encoding without its signature: the bytes received
+/// when it was decoded from a frame, otherwise the canonical local encoding
+/// (maps are written in sorted order, so every node produces the same bytes).
+fn signing_payload(message: &ProtocolMessage) -> Result<Vec<u8>> {
+    let encoded = match &message.signed_bytes {
+        Some(received) => received.clone(),
+        None => wire::encode_unsigned(message)?,
+    };
+    let mut payload = Vec::with_capacity(SIGNING_DOMAIN.len() + encoded.len());
+    payload.extend_from_slice(SIGNING_DOMAIN);
+    payload.extend_from_slice(&encoded);
+    Ok(payload)
+}
+
+fn new_hmac(key: &[u8]) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    HmacSha256::new_from_slice(key).expect("HMAC accepts any key length")
+}
+
+fn read_key_file(path: &Path) -> Result<Vec<u8>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| RabiaError::key(format!("Cannot read key file {}: {}", path.display(), e)))?;
+    decode_hex(text.trim())
+        .map_err(|e| RabiaError::key(format!("Invalid key file {}: {}", path.display(), e)))
+}
+
+/// Read a hex-encoded 32-byte key from `path`.
+pub fn read_key32(path: &Path) -> Result<[u8; 32]> {
+    let key = read_key_file(path)?;
+    key.as_slice().try_into().map_err(|_| {
+        RabiaError::key(format!(
+            "Key file {} must contain 32 bytes, found {}",
+            path.display(),
+            key.len()
+        ))
+    })
+}
+
+fn decode_hex(text: &str) -> std::result::Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err("hex key has an odd number of digits".to_string());
+    }
+    text.as_bytes()
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let digit = |byte: u8| (byte as char).to_digit(16);
+            match (digit(pair[0]), digit(pair[1])) {
+                (Some(high), Some(low)) => Ok((high * 16 + low) as u8),
+                _ => Err(format!("invalid hex digits at offset {}", i * 2)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{DecisionMessage, HeartBeatMessage, MessageType};
+    use crate::{BatchId, PhaseId, StateValue};
+
+    fn decision(from: NodeId) -> ProtocolMessage {
+        ProtocolMessage::decision(
+            from,
+            DecisionMessage {
+                phase_id: PhaseId::new(3),
+                batch_id: BatchId::new(),
+                decision: StateValue::V1,
+                batch: None,
+                config_hash: 0,
+            },
+        )
+    }
+
+    #[test]
+    fn test_hmac_sign_and_verify() {
+        let signer = MessageSigner::hmac(b"shared secret".to_vec()).unwrap();
+        let message = signer.sign(decision(NodeId::new())).unwrap();
+        assert!(signer.verify(&message).is_ok());
+
+        let other = MessageSigner::hmac(b"another secret".to_vec()).unwrap();
+        let rejection = other.verify(&message).unwrap_err();
+        assert_eq!(rejection.reason, RejectionReason::InvalidSignature);
+    }
+
+    #[test]
+    fn test_tampered_message_fails_verification() {
+        let signer = MessageSigner::hmac(b"shared secret".to_vec()).unwrap();
+        let mut message = signer.sign(decision(NodeId::new())).unwrap();
+
+        if let MessageType::Decision(decision) = &mut message.message_type {
+            decision.decision = StateValue::V0;
+        }
+        assert!(signer.verify(&message).is_err());
+    }
+
+    /// `frame` as a newer sender would encode it, with `extra` appended to
+    /// the payload record.
+    fn with_newer_payload_field(frame: &[u8], extra: &[u8]) -> Bytes {
+        let bump = |frame: &mut Vec<u8>, at: usize| {
+            let len = u32::from_be_bytes(frame[at..at + 4].try_into().unwrap());
+            frame[at..at + 4].copy_from_slice(&(len + extra.len() as u32).to_be_bytes());
+        };
+        let mut frame = frame.to_vec();
+        let envelope_len = u32::from_be_bytes(frame[8..12].try_into().unwrap()) as usize;
+        bump(&mut frame, 4);
+        bump(&mut frame, 12 + envelope_len);
+        frame.extend_from_slice(extra);
+        Bytes::from(frame)
+    }
+
+    #[test]
+    fn test_received_frame_with_unknown_fields_verifies() {
+        let key = b"shared secret".to_vec();
+        let signer = MessageSigner::hmac(key.clone()).unwrap();
+        let mut message = decision(NodeId::new());
+        let extra = [0xab; 6];
+
+        // A newer sender signs the payload field this version does not know
+        let unsigned = with_newer_payload_field(&wire::encode_unsigned(&message).unwrap(), &extra);
+        let mut mac = new_hmac(&key);
+        mac.update(SIGNING_DOMAIN);
+        mac.update(&unsigned);
+        message.signature = Some(Bytes::copy_from_slice(&mac.finalize().into_bytes()));
+        let frame = with_newer_payload_field(&wire::encode(&message).unwrap(), &extra);
+
+        let received = wire::decode(frame.clone()).unwrap();
+        assert_eq!(received.signed_bytes, Some(unsigned));
+        assert!(signer.verify(&received).is_ok());
+
+        // Re-encoding locally drops the unknown field and breaks the signature
+        let mut reencoded = received.clone();
+        reencoded.signed_bytes = None;
+        assert!(signer.verify(&reencoded).is_err());
+
+        // The unknown field is still covered
+        let mut tampered = frame.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = wire::decode(Bytes::from(tampered)).unwrap();
+        assert!(signer.verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_message_is_rejected() {
+        let signer = MessageSigner::hmac(b"shared secret".to_vec()).unwrap();
+        let rejection = signer.verify(&decision(NodeId::new())).unwrap_err();
+        assert_eq!(rejection.reason, RejectionReason::MissingSignature);
+    }
+
+    #[test]
+    fn test_ed25519_binds_signature_to_sender() {
+        let node_a = NodeId::new();
+        let node_b = NodeId::new();
+        let key_a = [1u8; 32];
+        let key_b = [2u8; 32];
+        let public_keys: HashMap<NodeId, [u8; 32]> = [
+            (node_a, MessageSigner::ed25519_public_key(&key_a)),
+            (node_b, MessageSigner::ed25519_public_key(&key_b)),
+        ]
+        .into_iter()
+        .collect();
+
+        let signer_a = MessageSigner::ed25519(key_a, public_keys.clone()).unwrap();
+        let signer_b = MessageSigner::ed25519(key_b, public_keys).unwrap();
+
+        let message = signer_a.sign(decision(node_a)).unwrap();
+        assert!(signer_b.verify(&message).is_ok());
+
+        // Node A cannot produce a message that verifies as coming from B
+        let forged = signer_a.sign(decision(node_b)).unwrap();
+        assert!(signer_b.verify(&forged).is_err());
+    }
+
+    #[test]
+    fn test_load_hmac_key_from_file() {
+        let path = std::env::temp_dir().join(format!("rabia-hmac-{}.key", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "00112233445566778899aabbccddeeff\n").unwrap();
+
+        let signer = SigningConfig::Hmac {
+            key_file: path.clone(),
+        }
+        .load()
+        .unwrap();
+        let message = ProtocolMessage::new(
+            NodeId::new(),
+            None,
+            MessageType::HeartBeat(HeartBeatMessage {
+                current_phase: PhaseId::new(1),
+                last_committed_phase: PhaseId::new(0),
+                active: true,
+            }),
+        );
+        let message = signer.sign(message).unwrap();
+        assert!(signer.verify(&message).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(SigningConfig::Hmac { key_file: path }.load().is_err());
+    }
+
+    #[test]
+    fn test_decode_hex_rejects_non_ascii() {
+        assert_eq!(decode_hex("0aFf").unwrap(), vec![0x0a, 0xff]);
+        // Two bytes long, but a single character
+        assert!(decode_hex("é").is_err());
+        assert!(decode_hex("0é0").is_err());
+        assert!(decode_hex("0g").is_err());
+    }
+}
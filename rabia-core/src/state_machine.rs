@@ -1,7 +1,8 @@
-use crate::{Command, Result};
+use crate::{Command, RabiaError, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Snapshot {
@@ -26,6 +27,79 @@ impl Snapshot {
     }
 }
 
+/// Changes since an earlier snapshot or delta.
+///
+/// Deltas form a chain on top of a full [`Snapshot`]: `base_version` is the
+/// version the delta applies to and `version` the one it produces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaSnapshot {
+    pub base_version: u64,
+    pub version: u64,
+    pub data: Bytes,
+    pub checksum: u32,
+}
+
+impl DeltaSnapshot {
+    pub fn new(base_version: u64, version: u64, data: impl Into<Bytes>) -> Self {
+        let data = data.into();
+        let checksum = crc32fast::hash(&data);
+        Self {
+            base_version,
+            version,
+            data,
+            checksum,
+        }
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        crc32fast::hash(&self.data) == self.checksum
+    }
+
+    /// Check that this delta is intact and applies on top of `version`.
+    pub fn check_applies_to(&self, version: u64) -> Result<()> {
+        if !self.verify_checksum() {
+            return Err(RabiaError::ChecksumMismatch {
+                expected: self.checksum,
+                actual: crc32fast::hash(&self.data),
+            });
+        }
+        if self.base_version != version {
+            return Err(RabiaError::state_machine(format!(
+                "Delta from version {} cannot apply to version {}",
+                self.base_version, version
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Optional extension for state machines that can snapshot incrementally.
+///
+/// The state machine tracks the keys or pages it changes and captures only
+/// those in a [`DeltaSnapshot`], chained to the last full snapshot or delta.
+/// It is exposed to the engine through [`StateMachine::incremental`].
+#[async_trait]
+pub trait IncrementalSnapshot: Send + Sync {
+    /// Number of keys or pages changed since the last snapshot or delta.
+    fn dirty_count(&self) -> usize;
+
+    /// Version of the current state, which the next delta is taken up to.
+    ///
+    /// Replicas that applied the same commands are at the same version, so
+    /// a delta chain can be applied on any replica at its base version.
+    fn version(&self) -> u64;
+
+    /// Capture the changes since the last snapshot or delta, and track
+    /// changes from here on.
+    async fn create_delta(&mut self) -> Result<DeltaSnapshot>;
+
+    /// Apply a delta taken on top of the current state.
+    async fn apply_delta(&mut self, delta: &DeltaSnapshot) -> Result<()>;
+
+    /// Forget tracked changes once a full snapshot covers them.
+    fn clear_dirty(&mut self);
+}
+
 #[async_trait]
 pub trait StateMachine: Send + Sync {
     type State: Clone + Send + Sync;
@@ -49,19 +123,41 @@ pub trait StateMachine: Send + Sync {
     fn is_deterministic(&self) -> bool {
         true
     }
+
+    /// Incremental snapshot support, for state machines that implement
+    /// [`IncrementalSnapshot`].
+    fn incremental(&mut self) -> Option<&mut dyn IncrementalSnapshot> {
+        None
+    }
+
+    /// A copy to take full snapshots from in the background, for state
+    /// machines cheap enough to clone. Without one, full snapshots are taken
+    /// inline and hold up commits while they run.
+    fn snapshot_source(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct InMemoryStateMachine {
-    pub state: std::collections::HashMap<String, Bytes>,
+    pub state: HashMap<String, Bytes>,
     pub version: u64,
+    /// Keys changed since `dirty_since`
+    dirty: HashSet<String>,
+    /// Version of the last snapshot or delta
+    dirty_since: u64,
 }
 
 impl InMemoryStateMachine {
     pub fn new() -> Self {
         Self {
-            state: std::collections::HashMap::new(),
+            state: HashMap::new(),
             version: 0,
+            dirty: HashSet::new(),
+            dirty_since: 0,
         }
     }
 }
@@ -74,7 +170,7 @@ impl Default for InMemoryStateMachine {
 
 #[async_trait]
 impl StateMachine for InMemoryStateMachine {
-    type State = std::collections::HashMap<String, Bytes>;
+    type State = HashMap<String, Bytes>;
 
     async fn apply_command(&mut self, command: &Command) -> Result<Bytes> {
         let command_str = String::from_utf8_lossy(&command.data);
@@ -91,7 +187,8 @@ impl StateMachine for InMemoryStateMachine {
             "SET" if parts.len() == 3 => {
                 let key = parts[1].clone();
                 let value = Bytes::from(parts[2].clone());
-                self.state.insert(key, value.clone());
+                self.state.insert(key.clone(), value);
+                self.dirty.insert(key);
                 self.version += 1;
                 Ok(Bytes::from("OK"))
             }
@@ -106,6 +203,7 @@ impl StateMachine for InMemoryStateMachine {
                 let key = &parts[1];
                 match self.state.remove(key) {
                     Some(_) => {
+                        self.dirty.insert(key.clone());
                         self.version += 1;
                         Ok(Bytes::from("OK"))
                     }
@@ -131,10 +229,96 @@ impl StateMachine for InMemoryStateMachine {
 
         self.state = serde_json::from_slice(&snapshot.data)?;
         self.version = snapshot.version;
+        self.clear_dirty();
         Ok(())
     }
 
     async fn get_state(&self) -> Self::State {
         self.state.clone()
     }
+
+    fn snapshot_source(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+
+    fn incremental(&mut self) -> Option<&mut dyn IncrementalSnapshot> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl IncrementalSnapshot for InMemoryStateMachine {
+    fn dirty_count(&self) -> usize {
+        self.dirty.len()
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    async fn create_delta(&mut self) -> Result<DeltaSnapshot> {
+        // Deleted keys are recorded as `None`
+        let changes: HashMap<&String, Option<&Bytes>> = self
+            .dirty
+            .iter()
+            .map(|key| (key, self.state.get(key)))
+            .collect();
+        let delta = DeltaSnapshot::new(
+            self.dirty_since,
+            self.version,
+            serde_json::to_vec(&changes)?,
+        );
+        self.clear_dirty();
+        Ok(delta)
+    }
+
+    async fn apply_delta(&mut self, delta: &DeltaSnapshot) -> Result<()> {
+        delta.check_applies_to(self.version)?;
+
+        let changes: HashMap<String, Option<Bytes>> = serde_json::from_slice(&delta.data)?;
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.state.insert(key, value),
+                None => self.state.remove(&key),
+            };
+        }
+        self.version = delta.version;
+        self.clear_dirty();
+        Ok(())
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty_since = self.version;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_delta_chain() {
+        let mut sm = InMemoryStateMachine::new();
+        sm.apply_command(&Command::new("SET a 1")).await.unwrap();
+        let snapshot = sm.create_snapshot().await.unwrap();
+        sm.clear_dirty();
+
+        sm.apply_command(&Command::new("SET b 2")).await.unwrap();
+        sm.apply_command(&Command::new("DEL a")).await.unwrap();
+        sm.apply_command(&Command::new("GET b")).await.unwrap();
+        assert_eq!(sm.dirty_count(), 2);
+        let delta = sm.create_delta().await.unwrap();
+        assert_eq!((delta.base_version, delta.version), (1, 3));
+        assert_eq!(sm.dirty_count(), 0);
+
+        let mut replica = InMemoryStateMachine::new();
+        replica.restore_snapshot(&snapshot).await.unwrap();
+        replica.apply_delta(&delta).await.unwrap();
+        assert_eq!(replica.state, sm.state);
+        assert_eq!(replica.version, 3);
+
+        // Applying the same delta again breaks the chain
+        assert!(replica.apply_delta(&delta).await.is_err());
+    }
 }
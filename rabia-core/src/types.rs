@@ -37,6 +37,85 @@ impl NodeId {
     pub fn new() -> Self {
         Self(Uuid::new_v4())
     }
+
+    /// Creates the stable identifier of the cluster member at `index` named `name`.
+    ///
+    /// The same index and name always give the same ID, and IDs built this way
+    /// sort by index. See [`NodeRegistry`](crate::registry::NodeRegistry).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::NodeId;
+    ///
+    /// assert_eq!(NodeId::stable(0, "alpha"), NodeId::stable(0, "alpha"));
+    /// assert!(NodeId::stable(0, "zulu") < NodeId::stable(1, "alpha"));
+    /// ```
+    pub fn stable(index: u32, name: &str) -> Self {
+        use sha2::{Digest, Sha256};
+
+        let digest = Sha256::digest(name.as_bytes());
+        let mut bytes = [0u8; 16];
+        bytes[..4].copy_from_slice(&index.to_be_bytes());
+        bytes[4..].copy_from_slice(&digest[..12]);
+        Self(uuid::Builder::from_custom_bytes(bytes).into_uuid())
+    }
+
+    /// The member's name in `registry`, or its UUID if it is not registered.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::{registry::NodeRegistry, NodeId};
+    ///
+    /// let registry = NodeRegistry::from_toml("[[nodes]]\nname = \"alpha\"").unwrap();
+    /// let alpha = registry.node_id("alpha").unwrap();
+    /// assert_eq!(alpha.display_name(&registry), "alpha");
+    /// ```
+    pub fn display_name(&self, registry: &crate::registry::NodeRegistry) -> String {
+        self.display_with(Some(registry)).to_string()
+    }
+
+    /// Display as the member name when `registry` knows this node, and as the
+    /// UUID otherwise, without allocating; meant for log lines.
+    ///
+    /// ```rust
+    /// use rabia_core::{registry::NodeRegistry, NodeId};
+    ///
+    /// let registry = NodeRegistry::from_toml("[[nodes]]\nname = \"alpha\"").unwrap();
+    /// let alpha = registry.node_id("alpha").unwrap();
+    /// assert_eq!(format!("{}", alpha.display_with(Some(&registry))), "alpha");
+    /// assert_eq!(format!("{}", alpha.display_with(None)), alpha.to_string());
+    /// ```
+    pub fn display_with<'a>(
+        &self,
+        registry: Option<&'a crate::registry::NodeRegistry>,
+    ) -> NodeDisplay<'a> {
+        NodeDisplay {
+            node_id: *self,
+            registry,
+        }
+    }
+}
+
+/// A [`NodeId`] displayed through an optional registry; see
+/// [`NodeId::display_with`].
+#[derive(Debug, Clone, Copy)]
+pub struct NodeDisplay<'a> {
+    node_id: NodeId,
+    registry: Option<&'a crate::registry::NodeRegistry>,
+}
+
+impl fmt::Display for NodeDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+            .registry
+            .and_then(|registry| registry.name_of(&self.node_id))
+        {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.node_id),
+        }
+    }
 }
 
 impl Default for NodeId {
@@ -119,6 +198,7 @@ impl From<i32> for NodeId {
 }
 
 impl fmt::Display for NodeId {
+    /// Prints the UUID; see [`NodeId::display_name`] for member names.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
@@ -160,7 +240,9 @@ impl fmt::Display for ConsensusState {
 /// let phase2 = phase1.next();
 /// assert!(phase2 > phase1);
 /// ```
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct PhaseId(pub u64);
 
 impl PhaseId {
@@ -303,11 +385,61 @@ impl fmt::Display for StateValue {
     }
 }
 
+/// Unique identifier for a client session.
+///
+/// Clients that want exactly-once semantics tag their commands with a
+/// `ClientId` and a per-client sequence number (see [`SessionInfo`]), so a
+/// retried command can be recognised and answered from the session table
+/// instead of being applied a second time.
+///
+/// # Examples
+///
+/// ```rust
+/// use rabia_core::ClientId;
+///
+/// let client_id = ClientId::new();
+/// assert_ne!(client_id, ClientId::new());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ClientId(pub Uuid);
+
+impl ClientId {
+    /// Creates a new random client identifier.
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for ClientId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Client session identity attached to a command.
+///
+/// Sequence numbers must increase monotonically per client. A command whose
+/// sequence number has already been applied is treated as a retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Client that issued the command
+    pub client_id: ClientId,
+    /// Per-client sequence number of the command
+    pub sequence: u64,
+}
+
 /// A command to be executed by the state machine.
 ///
 /// Commands represent individual operations that can be applied to the
 /// distributed state machine. Each command has a unique identifier and
 /// contains arbitrary data that will be interpreted by the state machine.
+/// Commands may optionally carry a [`SessionInfo`] for exactly-once semantics.
 ///
 /// # Examples
 ///
@@ -323,6 +455,9 @@ pub struct Command {
     pub id: Uuid,
     /// Command data to be executed by the state machine
     pub data: bytes::Bytes,
+    /// Client session used to deduplicate retries, if any
+    #[serde(default)]
+    pub session: Option<SessionInfo>,
 }
 
 impl Command {
@@ -346,6 +481,31 @@ impl Command {
         Self {
             id: Uuid::new_v4(),
             data: data.into(),
+            session: None,
+        }
+    }
+
+    /// Creates a new command belonging to a client session.
+    ///
+    /// Retrying the same `(client_id, sequence)` pair returns the original
+    /// result instead of applying the command again.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rabia_core::{ClientId, Command};
+    ///
+    /// let client_id = ClientId::new();
+    /// let cmd = Command::with_session("SET key value", client_id, 1);
+    /// assert_eq!(cmd.session.unwrap().sequence, 1);
+    /// ```
+    pub fn with_session(data: impl Into<bytes::Bytes>, client_id: ClientId, sequence: u64) -> Self {
+        Self {
+            session: Some(SessionInfo {
+                client_id,
+                sequence,
+            }),
+            ..Self::new(data)
         }
     }
 }
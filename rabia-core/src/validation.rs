@@ -1,5 +1,21 @@
-use crate::messages::ProtocolMessage;
+//! # Validation
+//!
+//! Structural validation of protocol messages and command batches.
+//!
+//! [`MessageValidator`] applies the limits of a [`ValidationConfig`], runs any
+//! application-defined [`ValidationRule`]s and counts rejections by
+//! [`RejectionReason`]. The [`Validator`] trait remains as a shorthand that
+//! validates against the default configuration.
+
+use crate::freshness::SequenceTracker;
+use crate::messages::{MessageType, ProtocolMessage};
+use crate::signing::MessageSigner;
 use crate::{BatchId, CommandBatch, NodeId, PhaseId, RabiaError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub trait Validator {
@@ -10,7 +26,11 @@ pub trait Validator {
 pub struct ValidationConfig {
     pub max_batch_size: usize,
     pub max_command_size: usize,
-    pub max_clock_skew_ms: u64,
+    /// Reject messages and batches whose wall-clock timestamp is further than
+    /// this from local time. Disabled by default so correctness does not
+    /// depend on synchronized clocks; freshness comes from sequence numbers
+    /// and the compaction watermark instead.
+    pub max_clock_skew_ms: Option<u64>,
     pub min_phase_id: u64,
     pub max_phase_id: u64,
 }
@@ -20,186 +40,527 @@ impl Default for ValidationConfig {
         Self {
             max_batch_size: 1000,
             max_command_size: 1024 * 1024, // 1MB
-            max_clock_skew_ms: 60_000,     // 1 minute
+            max_clock_skew_ms: None,
             min_phase_id: 0,
             max_phase_id: u64::MAX,
         }
     }
 }
 
-impl Validator for ProtocolMessage {
-    fn validate(&self) -> Result<()> {
-        // Validate timestamp
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+/// Why a message or batch was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// `ProtocolMessage.from` differs from the node the message arrived from
+    SourceMismatch,
+    /// Vote cast on behalf of a node other than its sender
+    VoterMismatch,
+    /// Sender is not a member of the current cluster configuration
+    NotMember,
+    /// Message or batch timestamp is outside the allowed clock skew
+    ClockSkew,
+    /// Message repeats a sequence number already received from its sender
+    DuplicateMessage,
+    /// Message sequence number is behind the sender's replay window, or from
+    /// an incarnation older than the sender's current one
+    StaleSequence,
+    /// Message without a sequence number from a sender that sends sequenced ones
+    MissingSequence,
+    /// Message refers to a phase that has already been compacted away
+    BelowWatermark,
+    /// Signing is enabled but the message carries no signature
+    MissingSignature,
+    /// Message signature does not match its contents or sender
+    InvalidSignature,
+    /// Phase ID outside `[min_phase_id, max_phase_id]`
+    PhaseOutOfRange,
+    /// Phases reported in an order that cannot happen, e.g. committed > current
+    InvalidPhaseOrder,
+    /// Round 2 vote without the round 1 votes that justify it
+    MissingRound1Votes,
+    /// Round 1 votes indexed by a different cluster configuration
+    ConfigMismatch,
+    /// Batch without commands
+    EmptyBatch,
+    /// Batch with more than `max_batch_size` commands
+    BatchTooLarge,
+    /// Command without data
+    EmptyCommand,
+    /// Command larger than `max_command_size` bytes
+    CommandTooLarge,
+    /// Rejected by the application-defined rule with this name
+    Custom(String),
+}
+
+impl RejectionReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            RejectionReason::SourceMismatch => "source_mismatch",
+            RejectionReason::VoterMismatch => "voter_mismatch",
+            RejectionReason::NotMember => "not_member",
+            RejectionReason::ClockSkew => "clock_skew",
+            RejectionReason::DuplicateMessage => "duplicate_message",
+            RejectionReason::StaleSequence => "stale_sequence",
+            RejectionReason::MissingSequence => "missing_sequence",
+            RejectionReason::BelowWatermark => "below_watermark",
+            RejectionReason::MissingSignature => "missing_signature",
+            RejectionReason::InvalidSignature => "invalid_signature",
+            RejectionReason::PhaseOutOfRange => "phase_out_of_range",
+            RejectionReason::InvalidPhaseOrder => "invalid_phase_order",
+            RejectionReason::MissingRound1Votes => "missing_round1_votes",
+            RejectionReason::ConfigMismatch => "config_mismatch",
+            RejectionReason::EmptyBatch => "empty_batch",
+            RejectionReason::BatchTooLarge => "batch_too_large",
+            RejectionReason::EmptyCommand => "empty_command",
+            RejectionReason::CommandTooLarge => "command_too_large",
+            RejectionReason::Custom(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A failed validation check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub reason: RejectionReason,
+    pub detail: String,
+}
+
+impl Rejection {
+    pub fn new(reason: RejectionReason, detail: impl Into<String>) -> Self {
+        Self {
+            reason,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.detail, self.reason)
+    }
+}
+
+impl From<Rejection> for RabiaError {
+    fn from(rejection: Rejection) -> Self {
+        RabiaError::ValidationFailed {
+            reason: rejection.reason,
+            detail: rejection.detail,
+        }
+    }
+}
+
+/// Application-defined validation hook.
+///
+/// Rules run after the built-in checks have passed. Returning an error rejects
+/// the message or batch and counts it under [`RejectionReason::Custom`] with
+/// the rule's name.
+pub trait ValidationRule: Send + Sync {
+    /// Name used as the rejection reason
+    fn name(&self) -> &str;
+
+    fn validate_message(
+        &self,
+        _from: NodeId,
+        _message: &ProtocolMessage,
+    ) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    fn validate_batch(&self, _batch: &CommandBatch) -> std::result::Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Validates inbound messages and client batches against a [`ValidationConfig`].
+#[derive(Default)]
+pub struct MessageValidator {
+    config: ValidationConfig,
+    rules: Vec<Arc<dyn ValidationRule>>,
+    rejections: Mutex<HashMap<RejectionReason, u64>>,
+    sequences: SequenceTracker,
+    /// Lowest phase still retained; messages for earlier phases are stale
+    compaction_watermark: AtomicU64,
+    /// Verifies message signatures once signing is enabled
+    signer: OnceLock<Arc<MessageSigner>>,
+}
+
+impl fmt::Debug for MessageValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<&str> = self.rules.iter().map(|rule| rule.name()).collect();
+        f.debug_struct("MessageValidator")
+            .field("config", &self.config)
+            .field("rules", &rules)
+            .field("compaction_watermark", &self.compaction_watermark())
+            .field("signer", &self.signer.get())
+            .finish()
+    }
+}
+
+impl MessageValidator {
+    pub fn new(config: ValidationConfig) -> Self {
+        Self {
+            config,
+            rules: Vec::new(),
+            rejections: Mutex::new(HashMap::new()),
+            sequences: SequenceTracker::new(),
+            compaction_watermark: AtomicU64::new(0),
+            signer: OnceLock::new(),
+        }
+    }
+
+    /// Add an application-defined rule.
+    pub fn with_rule(mut self, rule: Arc<dyn ValidationRule>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Require every message to carry a valid signature.
+    pub fn with_signer(self, signer: Arc<MessageSigner>) -> Self {
+        self.set_signer(signer);
+        self
+    }
+
+    /// Enable signature verification on a shared validator. Returns `false`
+    /// if a signer was already set; the first one stays in effect.
+    pub fn set_signer(&self, signer: Arc<MessageSigner>) -> bool {
+        self.signer.set(signer).is_ok()
+    }
+
+    pub fn config(&self) -> &ValidationConfig {
+        &self.config
+    }
+
+    /// Validate a message received from `from`, counting any rejection.
+    ///
+    /// Sequenced messages that pass are recorded, so a replay of the same
+    /// message is rejected.
+    pub fn validate_message(&self, from: NodeId, message: &ProtocolMessage) -> Result<()> {
+        self.check_message(from, message)
+            .and_then(|()| match message.sequence {
+                Some(sequence) => self.sequences.observe(from, sequence),
+                None => self.sequences.observe_unsequenced(from),
+            })
+            .map_err(|rejection| self.reject(rejection))
+    }
+
+    /// Raise the compaction watermark; it never moves backwards.
+    pub fn set_compaction_watermark(&self, phase_id: PhaseId) {
+        self.compaction_watermark
+            .fetch_max(phase_id.value(), Ordering::AcqRel);
+    }
+
+    pub fn compaction_watermark(&self) -> PhaseId {
+        PhaseId::new(self.compaction_watermark.load(Ordering::Acquire))
+    }
 
-        let config = ValidationConfig::default();
+    /// Forget the sequence window of a node that left the cluster.
+    pub fn remove_peer(&self, node_id: &NodeId) {
+        self.sequences.remove_peer(node_id);
+    }
 
-        if self.timestamp > now + config.max_clock_skew_ms {
-            return Err(RabiaError::internal(format!(
-                "Message timestamp {} is too far in the future (current: {})",
-                self.timestamp, now
-            )));
+    /// Validate a client batch, counting any rejection.
+    pub fn validate_batch(&self, batch: &CommandBatch) -> Result<()> {
+        self.check_batch(batch)
+            .map_err(|rejection| self.reject(rejection))
+    }
+
+    /// Rejections so far, by reason.
+    pub fn rejection_counts(&self) -> HashMap<RejectionReason, u64> {
+        self.rejections.lock().unwrap().clone()
+    }
+
+    /// Total number of rejections so far.
+    pub fn total_rejections(&self) -> u64 {
+        self.rejections.lock().unwrap().values().sum()
+    }
+
+    /// Run the checks without counting the outcome.
+    pub fn check_message(
+        &self,
+        from: NodeId,
+        message: &ProtocolMessage,
+    ) -> std::result::Result<(), Rejection> {
+        if message.from != from {
+            return Err(Rejection::new(
+                RejectionReason::SourceMismatch,
+                format!(
+                    "Message claims to be from {} but was received from {}",
+                    message.from, from
+                ),
+            ));
         }
 
-        if now.saturating_sub(self.timestamp) > config.max_clock_skew_ms * 10 {
-            return Err(RabiaError::internal(format!(
-                "Message timestamp {} is too old (current: {})",
-                self.timestamp, now
-            )));
+        if let Some(signer) = self.signer.get() {
+            signer.verify(message)?;
         }
 
-        // Validate message type specific fields
-        match &self.message_type {
-            crate::messages::MessageType::Propose(propose) => {
-                validate_phase_id(&propose.phase_id)?;
-                validate_batch_id(&propose.batch_id)?;
+        self.check_timestamp(message.timestamp, "Message")?;
+
+        match &message.message_type {
+            MessageType::Propose(propose) => {
+                self.check_phase_id(&propose.phase_id)?;
+                self.check_watermark(&propose.phase_id)?;
+                check_batch_id(&propose.batch_id)?;
                 if let Some(batch) = &propose.batch {
-                    batch.validate()?;
+                    self.check_batch(batch)?;
                 }
             }
-            crate::messages::MessageType::VoteRound1(vote) => {
-                validate_phase_id(&vote.phase_id)?;
-                validate_batch_id(&vote.batch_id)?;
-                validate_node_id(&vote.voter_id)?;
+            MessageType::VoteRound1(vote) => {
+                self.check_phase_id(&vote.phase_id)?;
+                self.check_watermark(&vote.phase_id)?;
+                check_batch_id(&vote.batch_id)?;
+                check_voter(&vote.voter_id, from)?;
             }
-            crate::messages::MessageType::VoteRound2(vote) => {
-                validate_phase_id(&vote.phase_id)?;
-                validate_batch_id(&vote.batch_id)?;
-                validate_node_id(&vote.voter_id)?;
+            MessageType::VoteRound2(vote) => {
+                self.check_phase_id(&vote.phase_id)?;
+                self.check_watermark(&vote.phase_id)?;
+                check_batch_id(&vote.batch_id)?;
+                check_voter(&vote.voter_id, from)?;
 
-                // Validate round1_votes mapping
                 if vote.round1_votes.is_empty() {
-                    return Err(RabiaError::internal(
-                        "Round 2 vote must include round 1 votes".to_string(),
+                    return Err(Rejection::new(
+                        RejectionReason::MissingRound1Votes,
+                        "Round 2 vote must include round 1 votes",
                     ));
                 }
             }
-            crate::messages::MessageType::Decision(decision) => {
-                validate_phase_id(&decision.phase_id)?;
-                validate_batch_id(&decision.batch_id)?;
+            MessageType::Decision(decision) => {
+                self.check_phase_id(&decision.phase_id)?;
+                self.check_watermark(&decision.phase_id)?;
+                check_batch_id(&decision.batch_id)?;
                 if let Some(batch) = &decision.batch {
-                    batch.validate()?;
+                    self.check_batch(batch)?;
                 }
             }
-            crate::messages::MessageType::SyncRequest(request) => {
-                validate_phase_id(&request.requester_phase)?;
+            MessageType::SyncRequest(request) => {
+                self.check_phase_id(&request.requester_phase)?;
             }
-            crate::messages::MessageType::SyncResponse(response) => {
-                validate_phase_id(&response.responder_phase)?;
+            MessageType::SyncResponse(response) => {
+                self.check_phase_id(&response.responder_phase)?;
 
-                // Validate pending batches
                 for (batch_id, batch) in &response.pending_batches {
-                    validate_batch_id(batch_id)?;
-                    batch.validate()?;
+                    check_batch_id(batch_id)?;
+                    self.check_batch(batch)?;
                 }
             }
-            crate::messages::MessageType::NewBatch(new_batch) => {
-                new_batch.batch.validate()?;
-                validate_node_id(&new_batch.originator)?;
+            MessageType::NewBatch(new_batch) => {
+                self.check_batch(&new_batch.batch)?;
+                check_node_id(&new_batch.originator)?;
             }
-            crate::messages::MessageType::HeartBeat(heartbeat) => {
-                validate_phase_id(&heartbeat.current_phase)?;
-                validate_phase_id(&heartbeat.last_committed_phase)?;
+            MessageType::HeartBeat(heartbeat) => {
+                self.check_phase_id(&heartbeat.current_phase)?;
+                self.check_phase_id(&heartbeat.last_committed_phase)?;
 
-                // Committed phase should not be greater than current phase
                 if heartbeat.last_committed_phase > heartbeat.current_phase {
-                    return Err(RabiaError::InvalidStateTransition {
-                        from: format!("committed={}", heartbeat.last_committed_phase),
-                        to: format!("current={}", heartbeat.current_phase),
-                    });
+                    return Err(Rejection::new(
+                        RejectionReason::InvalidPhaseOrder,
+                        format!(
+                            "Committed phase {} is ahead of current phase {}",
+                            heartbeat.last_committed_phase, heartbeat.current_phase
+                        ),
+                    ));
                 }
             }
-            crate::messages::MessageType::QuorumNotification(notification) => {
+            MessageType::QuorumNotification(notification) => {
                 for node_id in &notification.active_nodes {
-                    validate_node_id(node_id)?;
+                    check_node_id(node_id)?;
                 }
             }
         }
 
+        for rule in &self.rules {
+            rule.validate_message(from, message).map_err(|detail| {
+                Rejection::new(RejectionReason::Custom(rule.name().to_string()), detail)
+            })?;
+        }
+
         Ok(())
     }
-}
 
-impl Validator for CommandBatch {
-    fn validate(&self) -> Result<()> {
-        let config = ValidationConfig::default();
-
-        // Validate batch size
-        if self.commands.len() > config.max_batch_size {
-            return Err(RabiaError::internal(format!(
-                "Batch size {} exceeds maximum {}",
-                self.commands.len(),
-                config.max_batch_size
-            )));
+    /// Run the batch checks without counting the outcome.
+    pub fn check_batch(&self, batch: &CommandBatch) -> std::result::Result<(), Rejection> {
+        if batch.commands.len() > self.config.max_batch_size {
+            return Err(Rejection::new(
+                RejectionReason::BatchTooLarge,
+                format!(
+                    "Batch size {} exceeds maximum {}",
+                    batch.commands.len(),
+                    self.config.max_batch_size
+                ),
+            ));
         }
 
-        if self.commands.is_empty() {
-            return Err(RabiaError::internal("Batch cannot be empty".to_string()));
+        if batch.commands.is_empty() {
+            return Err(Rejection::new(
+                RejectionReason::EmptyBatch,
+                "Batch cannot be empty",
+            ));
         }
 
-        // Validate individual commands
-        for command in &self.commands {
-            if command.data.len() > config.max_command_size {
-                return Err(RabiaError::internal(format!(
-                    "Command size {} exceeds maximum {}",
-                    command.data.len(),
-                    config.max_command_size
-                )));
+        for command in &batch.commands {
+            if command.data.len() > self.config.max_command_size {
+                return Err(Rejection::new(
+                    RejectionReason::CommandTooLarge,
+                    format!(
+                        "Command size {} exceeds maximum {}",
+                        command.data.len(),
+                        self.config.max_command_size
+                    ),
+                ));
             }
 
-            // Basic command validation
             if command.data.is_empty() {
-                return Err(RabiaError::internal(
-                    "Command data cannot be empty".to_string(),
+                return Err(Rejection::new(
+                    RejectionReason::EmptyCommand,
+                    "Command data cannot be empty",
+                ));
+            }
+        }
+
+        if let Some(max_skew) = self.config.max_clock_skew_ms {
+            if batch.timestamp > now_millis() + max_skew {
+                return Err(Rejection::new(
+                    RejectionReason::ClockSkew,
+                    format!(
+                        "Batch timestamp {} is too far in the future",
+                        batch.timestamp
+                    ),
                 ));
             }
         }
 
-        // Validate checksum
-        let _calculated_checksum = self.checksum();
-        // In a real implementation, we would compare against a stored checksum
+        for rule in &self.rules {
+            rule.validate_batch(batch).map_err(|detail| {
+                Rejection::new(RejectionReason::Custom(rule.name().to_string()), detail)
+            })?;
+        }
 
-        // Validate timestamp
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+        Ok(())
+    }
+
+    fn check_timestamp(&self, timestamp: u64, what: &str) -> std::result::Result<(), Rejection> {
+        let Some(max_skew) = self.config.max_clock_skew_ms else {
+            return Ok(());
+        };
+        let now = now_millis();
+
+        if timestamp > now + max_skew {
+            return Err(Rejection::new(
+                RejectionReason::ClockSkew,
+                format!(
+                    "{} timestamp {} is too far in the future (current: {})",
+                    what, timestamp, now
+                ),
+            ));
+        }
+
+        if now.saturating_sub(timestamp) > max_skew * 10 {
+            return Err(Rejection::new(
+                RejectionReason::ClockSkew,
+                format!(
+                    "{} timestamp {} is too old (current: {})",
+                    what, timestamp, now
+                ),
+            ));
+        }
 
-        if self.timestamp > now + config.max_clock_skew_ms {
-            return Err(RabiaError::internal(format!(
-                "Batch timestamp {} is too far in the future",
-                self.timestamp
-            )));
+        Ok(())
+    }
+
+    fn check_watermark(&self, phase_id: &PhaseId) -> std::result::Result<(), Rejection> {
+        let watermark = self.compaction_watermark();
+        if *phase_id < watermark {
+            return Err(Rejection::new(
+                RejectionReason::BelowWatermark,
+                format!(
+                    "Phase {} is below the compaction watermark {}",
+                    phase_id, watermark
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_phase_id(&self, phase_id: &PhaseId) -> std::result::Result<(), Rejection> {
+        let value = phase_id.value();
+
+        if value < self.config.min_phase_id || value > self.config.max_phase_id {
+            return Err(Rejection::new(
+                RejectionReason::PhaseOutOfRange,
+                format!(
+                    "Phase ID {} is out of valid range [{}, {}]",
+                    value, self.config.min_phase_id, self.config.max_phase_id
+                ),
+            ));
         }
 
         Ok(())
     }
+
+    /// Count a rejection decided outside the validator, e.g. by membership checks.
+    pub fn reject(&self, rejection: Rejection) -> RabiaError {
+        self.count_rejection(&rejection.reason);
+        rejection.into()
+    }
+
+    /// Count a rejection that is reported with a more specific error.
+    pub fn count_rejection(&self, reason: &RejectionReason) {
+        *self
+            .rejections
+            .lock()
+            .unwrap()
+            .entry(reason.clone())
+            .or_insert(0) += 1;
+    }
 }
 
-fn validate_phase_id(phase_id: &PhaseId) -> Result<()> {
-    let config = ValidationConfig::default();
-    let value = phase_id.value();
+impl Validator for ProtocolMessage {
+    fn validate(&self) -> Result<()> {
+        MessageValidator::default()
+            .check_message(self.from, self)
+            .map_err(Into::into)
+    }
+}
 
-    if value < config.min_phase_id || value > config.max_phase_id {
-        return Err(RabiaError::internal(format!(
-            "Phase ID {} is out of valid range [{}, {}]",
-            value, config.min_phase_id, config.max_phase_id
-        )));
+impl Validator for CommandBatch {
+    fn validate(&self) -> Result<()> {
+        MessageValidator::default()
+            .check_batch(self)
+            .map_err(Into::into)
     }
+}
 
-    Ok(())
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
 }
 
-fn validate_batch_id(_batch_id: &BatchId) -> Result<()> {
+fn check_batch_id(_batch_id: &BatchId) -> std::result::Result<(), Rejection> {
     // BatchId is a UUID, so basic validation is that it's not nil
     // Additional validation could include checking against known batches
     Ok(())
 }
 
-fn validate_node_id(_node_id: &NodeId) -> Result<()> {
+fn check_voter(voter_id: &NodeId, from: NodeId) -> std::result::Result<(), Rejection> {
+    // Votes only count for the node that sent them
+    if *voter_id != from {
+        return Err(Rejection::new(
+            RejectionReason::VoterMismatch,
+            format!("Vote for {} was sent by {}", voter_id, from),
+        ));
+    }
+    Ok(())
+}
+
+fn check_node_id(_node_id: &NodeId) -> std::result::Result<(), Rejection> {
     // NodeId is a UUID, so basic validation is that it's not nil
     // Additional validation could include checking against authorized nodes
     Ok(())
@@ -228,6 +589,7 @@ pub fn validate_message_sequence(previous_phase: PhaseId, current_phase: PhaseId
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::HeartBeatMessage;
     use crate::Command;
 
     #[test]
@@ -253,4 +615,184 @@ mod tests {
         assert!(validate_message_sequence(phase1, phase2).is_ok());
         assert!(validate_message_sequence(phase2, phase3).is_err());
     }
+
+    #[test]
+    fn test_configured_limits_are_applied() {
+        let validator = MessageValidator::new(ValidationConfig {
+            max_batch_size: 2,
+            max_command_size: 8,
+            ..Default::default()
+        });
+
+        let batch = CommandBatch::new(vec![Command::new("a"), Command::new("b")]);
+        assert!(validator.validate_batch(&batch).is_ok());
+
+        let too_many = CommandBatch::new(vec![Command::new("a"); 3]);
+        assert!(validator.validate_batch(&too_many).is_err());
+
+        let too_big = CommandBatch::new(vec![Command::new("SET key1 value1")]);
+        assert!(validator.validate_batch(&too_big).is_err());
+
+        let counts = validator.rejection_counts();
+        assert_eq!(counts.get(&RejectionReason::BatchTooLarge), Some(&1));
+        assert_eq!(counts.get(&RejectionReason::CommandTooLarge), Some(&1));
+        assert_eq!(validator.total_rejections(), 2);
+    }
+
+    #[test]
+    fn test_source_mismatch_is_rejected() {
+        let validator = MessageValidator::default();
+        let message = ProtocolMessage::new(
+            NodeId::new(),
+            None,
+            MessageType::HeartBeat(HeartBeatMessage {
+                current_phase: PhaseId::new(1),
+                last_committed_phase: PhaseId::new(0),
+                active: true,
+            }),
+        );
+
+        assert!(validator.validate_message(message.from, &message).is_ok());
+        assert!(validator.validate_message(NodeId::new(), &message).is_err());
+        assert_eq!(
+            validator
+                .rejection_counts()
+                .get(&RejectionReason::SourceMismatch),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn test_vote_for_another_node_is_rejected() {
+        use crate::messages::VoteRound1Message;
+
+        let validator = MessageValidator::default();
+        let sender = NodeId::new();
+        let vote = |voter_id| {
+            ProtocolMessage::new(
+                sender,
+                None,
+                MessageType::VoteRound1(VoteRound1Message {
+                    phase_id: PhaseId::new(1),
+                    batch_id: BatchId::new(),
+                    vote: crate::StateValue::V1,
+                    voter_id,
+                }),
+            )
+        };
+
+        assert!(validator.validate_message(sender, &vote(sender)).is_ok());
+        assert!(validator
+            .validate_message(sender, &vote(NodeId::new()))
+            .is_err());
+        assert_eq!(
+            validator
+                .rejection_counts()
+                .get(&RejectionReason::VoterMismatch),
+            Some(&1)
+        );
+    }
+
+    fn round1_vote(sender: NodeId, phase: u64) -> ProtocolMessage {
+        ProtocolMessage::new(
+            sender,
+            None,
+            MessageType::VoteRound1(crate::messages::VoteRound1Message {
+                phase_id: PhaseId::new(phase),
+                batch_id: BatchId::new(),
+                vote: crate::StateValue::V1,
+                voter_id: sender,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_skewed_clock_is_accepted_by_default() {
+        let sender = NodeId::new();
+        let mut message = round1_vote(sender, 1);
+        message.timestamp = 0;
+
+        assert!(MessageValidator::default()
+            .validate_message(sender, &message)
+            .is_ok());
+
+        let strict = MessageValidator::new(ValidationConfig {
+            max_clock_skew_ms: Some(60_000),
+            ..Default::default()
+        });
+        assert!(strict.validate_message(sender, &message).is_err());
+    }
+
+    #[test]
+    fn test_votes_below_watermark_are_rejected() {
+        let validator = MessageValidator::default();
+        let sender = NodeId::new();
+        validator.set_compaction_watermark(PhaseId::new(10));
+        validator.set_compaction_watermark(PhaseId::new(5));
+        assert_eq!(validator.compaction_watermark(), PhaseId::new(10));
+
+        assert!(validator
+            .validate_message(sender, &round1_vote(sender, 9))
+            .is_err());
+        assert!(validator
+            .validate_message(sender, &round1_vote(sender, 10))
+            .is_ok());
+        assert_eq!(
+            validator
+                .rejection_counts()
+                .get(&RejectionReason::BelowWatermark),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn test_replayed_message_is_rejected() {
+        use crate::freshness::SequenceGenerator;
+
+        let validator = MessageValidator::default();
+        let sender = NodeId::new();
+        let sequences = SequenceGenerator::new();
+        let message = round1_vote(sender, 1).with_sequence(sequences.next_sequence());
+
+        assert!(validator.validate_message(sender, &message).is_ok());
+        assert!(validator.validate_message(sender, &message).is_err());
+        assert_eq!(
+            validator
+                .rejection_counts()
+                .get(&RejectionReason::DuplicateMessage),
+            Some(&1)
+        );
+    }
+
+    struct NoDeletes;
+
+    impl ValidationRule for NoDeletes {
+        fn name(&self) -> &str {
+            "no_deletes"
+        }
+
+        fn validate_batch(&self, batch: &CommandBatch) -> std::result::Result<(), String> {
+            if batch.commands.iter().any(|c| c.data.starts_with(b"DEL")) {
+                return Err("DEL commands are not allowed".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_custom_rule_rejections_are_counted_by_name() {
+        let validator = MessageValidator::default().with_rule(Arc::new(NoDeletes));
+
+        let batch = CommandBatch::new(vec![Command::new("SET a 1")]);
+        assert!(validator.validate_batch(&batch).is_ok());
+
+        let batch = CommandBatch::new(vec![Command::new("DEL a")]);
+        assert!(validator.validate_batch(&batch).is_err());
+        assert_eq!(
+            validator
+                .rejection_counts()
+                .get(&RejectionReason::Custom("no_deletes".to_string())),
+            Some(&1)
+        );
+    }
 }
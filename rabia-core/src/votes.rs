@@ -0,0 +1,355 @@
+//! # Compact Votes
+//!
+//! Fixed-size representations of votes, indexed by cluster member instead of
+//! by 16-byte node ID.
+//!
+//! Every node derives the same [`MemberIndex`] from its cluster configuration
+//! by sorting the member IDs. A [`VoteVector`] then stores one vote per member
+//! in two bits, so the round 1 votes carried by a round 2 message take a couple
+//! of bytes in a five-node cluster. Each vector carries a hash of the
+//! configuration it was indexed with, because the same index names a different
+//! node in another configuration. A [`VoteTally`] keeps running counts per
+//! value so majority checks do not walk the votes.
+
+use crate::{NodeId, StateValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Dense, deterministic indices for the members of a cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemberIndex {
+    /// Sorted member IDs; a node's index is its position
+    members: Vec<NodeId>,
+    config_hash: u32,
+}
+
+impl MemberIndex {
+    pub fn new(nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let mut members: Vec<NodeId> = nodes.into_iter().collect();
+        members.sort();
+        members.dedup();
+
+        let mut hasher = crc32fast::Hasher::new();
+        for member in &members {
+            hasher.update(member.0.as_bytes());
+        }
+        Self {
+            members,
+            config_hash: hasher.finalize(),
+        }
+    }
+
+    /// Also hash `settings`, values every member must share because they
+    /// change what replicated state a commit produces. Members configured
+    /// with other values then reject each other's votes and decisions.
+    pub fn with_settings(mut self, settings: &[u64]) -> Self {
+        let mut hasher = crc32fast::Hasher::new_with_initial(self.config_hash);
+        for setting in settings {
+            hasher.update(&setting.to_le_bytes());
+        }
+        self.config_hash = hasher.finalize();
+        self
+    }
+
+    /// Hash of the member set and shared settings, the same on every node
+    /// with this configuration.
+    pub fn config_hash(&self) -> u32 {
+        self.config_hash
+    }
+
+    pub fn index_of(&self, node_id: &NodeId) -> Option<usize> {
+        self.members.binary_search(node_id).ok()
+    }
+
+    pub fn node_at(&self, index: usize) -> Option<NodeId> {
+        self.members.get(index).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+const BITS_PER_VOTE: usize = 2;
+const VOTES_PER_BYTE: usize = 8 / BITS_PER_VOTE;
+
+fn vote_code(vote: StateValue) -> u8 {
+    match vote {
+        StateValue::V0 => 1,
+        StateValue::V1 => 2,
+        StateValue::VQuestion => 3,
+    }
+}
+
+fn code_vote(code: u8) -> Option<StateValue> {
+    match code {
+        1 => Some(StateValue::V0),
+        2 => Some(StateValue::V1),
+        3 => Some(StateValue::VQuestion),
+        _ => None,
+    }
+}
+
+/// Votes of cluster members packed two bits per member index.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteVector {
+    /// [`MemberIndex::config_hash`] of the configuration the indices refer to
+    #[serde(default)]
+    config_hash: u32,
+    packed: Vec<u8>,
+}
+
+impl VoteVector {
+    /// An empty vector indexed by `members`.
+    pub fn new(members: &MemberIndex) -> Self {
+        Self {
+            config_hash: members.config_hash(),
+            packed: Vec::new(),
+        }
+    }
+
+    /// Pack a vote map; votes from nodes outside `members` are dropped.
+    pub fn from_votes(votes: &HashMap<NodeId, StateValue>, members: &MemberIndex) -> Self {
+        let mut vector = Self::new(members);
+        for (node_id, vote) in votes {
+            if let Some(index) = members.index_of(node_id) {
+                vector.set(index, *vote);
+            }
+        }
+        vector
+    }
+
+    /// Rebuild packed bytes received from the network.
+    pub fn from_packed(config_hash: u32, packed: Vec<u8>) -> Self {
+        Self {
+            config_hash,
+            packed,
+        }
+    }
+
+    pub fn config_hash(&self) -> u32 {
+        self.config_hash
+    }
+
+    /// Whether the indices refer to `members`.
+    pub fn is_indexed_by(&self, members: &MemberIndex) -> bool {
+        self.config_hash == members.config_hash()
+    }
+
+    /// Packed representation, two bits per member.
+    pub fn as_packed(&self) -> &[u8] {
+        &self.packed
+    }
+
+    pub fn set(&mut self, index: usize, vote: StateValue) {
+        let byte = index / VOTES_PER_BYTE;
+        if byte >= self.packed.len() {
+            self.packed.resize(byte + 1, 0);
+        }
+        let shift = (index % VOTES_PER_BYTE) * BITS_PER_VOTE;
+        self.packed[byte] = (self.packed[byte] & !(0b11 << shift)) | (vote_code(vote) << shift);
+    }
+
+    pub fn get(&self, index: usize) -> Option<StateValue> {
+        let byte = self.packed.get(index / VOTES_PER_BYTE)?;
+        code_vote((byte >> ((index % VOTES_PER_BYTE) * BITS_PER_VOTE)) & 0b11)
+    }
+
+    /// Cast votes as `(member index, vote)` pairs in index order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, StateValue)> + '_ {
+        (0..self.packed.len() * VOTES_PER_BYTE)
+            .filter_map(|index| self.get(index).map(|vote| (index, vote)))
+    }
+
+    /// Expand back to a vote map, or `None` when the vector was indexed by a
+    /// different configuration.
+    pub fn to_votes(&self, members: &MemberIndex) -> Option<HashMap<NodeId, StateValue>> {
+        if !self.is_indexed_by(members) {
+            return None;
+        }
+        Some(
+            self.iter()
+                .filter_map(|(index, vote)| members.node_at(index).map(|node_id| (node_id, vote)))
+                .collect(),
+        )
+    }
+
+    /// Number of members that cast a vote.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packed.iter().all(|byte| *byte == 0)
+    }
+
+    pub fn tally(&self) -> VoteTally {
+        let mut tally = VoteTally::default();
+        for (_, vote) in self.iter() {
+            tally.add(vote);
+        }
+        tally
+    }
+
+    /// Re-index votes cast under `from` by `to`, dropping votes of members
+    /// `to` no longer has.
+    pub fn reindex(&self, from: &MemberIndex, to: &MemberIndex) -> Self {
+        let mut vector = Self::new(to);
+        for (index, vote) in self.iter() {
+            if let Some(index) = from
+                .node_at(index)
+                .and_then(|node_id| to.index_of(&node_id))
+            {
+                vector.set(index, vote);
+            }
+        }
+        vector
+    }
+}
+
+/// Running count of votes per value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteTally {
+    /// Counts for V0, V1 and VQuestion
+    counts: [usize; 3],
+}
+
+impl VoteTally {
+    fn slot(vote: StateValue) -> usize {
+        match vote {
+            StateValue::V0 => 0,
+            StateValue::V1 => 1,
+            StateValue::VQuestion => 2,
+        }
+    }
+
+    pub fn add(&mut self, vote: StateValue) {
+        self.counts[Self::slot(vote)] += 1;
+    }
+
+    pub fn count(&self, vote: StateValue) -> usize {
+        self.counts[Self::slot(vote)]
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Value with at least `quorum_size` votes, checked V0, V1, then VQuestion.
+    pub fn majority(&self, quorum_size: usize) -> Option<StateValue> {
+        [StateValue::V0, StateValue::V1, StateValue::VQuestion]
+            .into_iter()
+            .find(|vote| self.count(*vote) >= quorum_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_member_index_is_order_independent() {
+        let nodes: Vec<NodeId> = (0..5).map(|_| NodeId::new()).collect();
+        let forward = MemberIndex::new(nodes.iter().copied());
+        let backward = MemberIndex::new(nodes.iter().rev().copied());
+
+        assert_eq!(forward, backward);
+        for node_id in &nodes {
+            let index = forward.index_of(node_id).unwrap();
+            assert_eq!(forward.node_at(index), Some(*node_id));
+        }
+    }
+
+    /// Node IDs that sort in the order given
+    fn fixed_nodes(count: u128) -> Vec<NodeId> {
+        (1..=count)
+            .map(|n| NodeId(uuid::Uuid::from_u128(n)))
+            .collect()
+    }
+
+    #[test]
+    fn test_vote_vector_roundtrip() {
+        let nodes = fixed_nodes(5);
+        let members = MemberIndex::new(nodes.iter().copied());
+        let votes: HashMap<NodeId, StateValue> = [
+            (nodes[0], StateValue::V1),
+            (nodes[2], StateValue::V0),
+            (nodes[4], StateValue::VQuestion),
+        ]
+        .into_iter()
+        .collect();
+
+        let vector = VoteVector::from_votes(&votes, &members);
+        // Index 4 is the first vote in the second byte
+        assert_eq!(vector.as_packed().len(), 2);
+        assert_eq!(vector.len(), 3);
+        assert_eq!(vector.to_votes(&members), Some(votes));
+    }
+
+    #[test]
+    fn test_vote_vector_rejects_other_configuration() {
+        let nodes = fixed_nodes(4);
+        let old_members = MemberIndex::new(nodes[..3].iter().copied());
+        let new_members = MemberIndex::new(nodes.iter().copied());
+        assert_ne!(old_members.config_hash(), new_members.config_hash());
+        assert_eq!(
+            MemberIndex::new(nodes.iter().rev().copied()).config_hash(),
+            new_members.config_hash()
+        );
+
+        let votes: HashMap<NodeId, StateValue> = [(nodes[0], StateValue::V1)].into_iter().collect();
+        let vector = VoteVector::from_votes(&votes, &old_members);
+        assert!(vector.is_indexed_by(&old_members));
+        assert_eq!(vector.to_votes(&new_members), None);
+    }
+
+    #[test]
+    fn test_vote_vector_overwrite_and_non_members() {
+        let members = MemberIndex::new([NodeId::new()]);
+        let mut votes = HashMap::new();
+        votes.insert(NodeId::new(), StateValue::V1);
+        assert!(VoteVector::from_votes(&votes, &members).is_empty());
+
+        let mut vector = VoteVector::new(&members);
+        vector.set(5, StateValue::V0);
+        vector.set(5, StateValue::V1);
+        assert_eq!(vector.get(5), Some(StateValue::V1));
+        assert_eq!(vector.get(4), None);
+        assert_eq!(vector.get(100), None);
+    }
+
+    #[test]
+    fn test_tally_majority() {
+        let mut tally = VoteTally::default();
+        tally.add(StateValue::V1);
+        tally.add(StateValue::V1);
+        tally.add(StateValue::V0);
+
+        assert_eq!(tally.total(), 3);
+        assert_eq!(tally.majority(2), Some(StateValue::V1));
+        assert_eq!(tally.majority(3), None);
+    }
+
+    #[test]
+    fn test_settings_change_the_config_hash() {
+        let nodes = [NodeId::new(), NodeId::new()];
+        let members = MemberIndex::new(nodes.iter().copied());
+        let with_settings = members.clone().with_settings(&[64, 10_000]);
+
+        assert_ne!(with_settings.config_hash(), members.config_hash());
+        assert_eq!(
+            with_settings.config_hash(),
+            MemberIndex::new(nodes.iter().rev().copied())
+                .with_settings(&[64, 10_000])
+                .config_hash()
+        );
+        assert_ne!(
+            with_settings.config_hash(),
+            members.with_settings(&[1, 10_000]).config_hash()
+        );
+    }
+}
@@ -0,0 +1,900 @@
+//! # Wire Format
+//!
+//! Compact, explicitly versioned binary encoding of [`ProtocolMessage`] for
+//! network transport.
+//!
+//! Every frame starts with a fixed 8-byte [`WireHeader`]:
+//!
+//! | bytes | field                                   |
+//! |-------|-----------------------------------------|
+//! | 0..2  | magic `"RB"`                            |
+//! | 2     | wire format version                     |
+//! | 3     | message type ([`WireMessageType`])      |
+//! | 4..8  | body length, big-endian `u32`           |
+//!
+//! The body holds two records: the envelope (id, sender, recipient, timestamp,
+//! sequence, signature) followed by the payload for the message type. A record
+//! is a `u32` length followed by its fields, and every nested struct (batches,
+//! commands, snapshots, session entries) is a record of its own.
+//!
+//! ## Compatibility
+//!
+//! New fields are only ever appended to the end of a record. Decoders skip
+//! bytes left in a record after the fields they know, and fill fields missing
+//! from the end of a record with defaults, so nodes one version apart can
+//! exchange messages. The header version is bumped only for incompatible
+//! changes; frames outside `MIN_WIRE_VERSION..=WIRE_VERSION` are rejected.
+//!
+//! Decoding takes a [`Bytes`] frame and slices command data, snapshot data and
+//! cached responses out of it without copying.
+
+use crate::freshness::MessageSequence;
+use crate::messages::{
+    DecisionMessage, HeartBeatMessage, MessageType, NewBatchMessage, ProposeMessage,
+    ProtocolMessage, QuorumNotificationMessage, SyncRequestMessage, SyncResponseMessage,
+    VoteRound1Message, VoteRound2Message,
+};
+use crate::sessions::{CommittedBatches, SessionEntry, SessionTable};
+use crate::state_machine::{DeltaSnapshot, Snapshot};
+use crate::votes::VoteVector;
+use crate::{
+    BatchId, ClientId, Command, CommandBatch, NodeId, PhaseId, RabiaError, Result, SessionInfo,
+    StateValue,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Current wire format version, written in every header.
+pub const WIRE_VERSION: u8 = 1;
+
+/// Oldest wire format version this node can decode.
+pub const MIN_WIRE_VERSION: u8 = 1;
+
+/// Size of the fixed frame header in bytes.
+pub const HEADER_LEN: usize = 8;
+
+const MAGIC: [u8; 2] = *b"RB";
+
+/// Message type code carried in the frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum WireMessageType {
+    Propose = 1,
+    VoteRound1 = 2,
+    VoteRound2 = 3,
+    Decision = 4,
+    SyncRequest = 5,
+    SyncResponse = 6,
+    NewBatch = 7,
+    HeartBeat = 8,
+    QuorumNotification = 9,
+}
+
+impl WireMessageType {
+    pub fn of(message_type: &MessageType) -> Self {
+        match message_type {
+            MessageType::Propose(_) => Self::Propose,
+            MessageType::VoteRound1(_) => Self::VoteRound1,
+            MessageType::VoteRound2(_) => Self::VoteRound2,
+            MessageType::Decision(_) => Self::Decision,
+            MessageType::SyncRequest(_) => Self::SyncRequest,
+            MessageType::SyncResponse(_) => Self::SyncResponse,
+            MessageType::NewBatch(_) => Self::NewBatch,
+            MessageType::HeartBeat(_) => Self::HeartBeat,
+            MessageType::QuorumNotification(_) => Self::QuorumNotification,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Result<Self> {
+        Ok(match code {
+            1 => Self::Propose,
+            2 => Self::VoteRound1,
+            3 => Self::VoteRound2,
+            4 => Self::Decision,
+            5 => Self::SyncRequest,
+            6 => Self::SyncResponse,
+            7 => Self::NewBatch,
+            8 => Self::HeartBeat,
+            9 => Self::QuorumNotification,
+            other => {
+                return Err(RabiaError::serialization(format!(
+                    "Unknown wire message type {}",
+                    other
+                )))
+            }
+        })
+    }
+}
+
+/// Fixed header at the start of every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WireHeader {
+    pub version: u8,
+    pub message_type: WireMessageType,
+    pub body_len: u32,
+}
+
+impl WireHeader {
+    /// Parse and check the header at the start of `frame`.
+    pub fn parse(frame: &[u8]) -> Result<Self> {
+        if frame.len() < HEADER_LEN {
+            return Err(RabiaError::serialization(format!(
+                "Frame of {} bytes is shorter than the wire header",
+                frame.len()
+            )));
+        }
+        if frame[0..2] != MAGIC {
+            return Err(RabiaError::serialization(
+                "Frame does not start with wire magic",
+            ));
+        }
+
+        let version = frame[2];
+        if !(MIN_WIRE_VERSION..=WIRE_VERSION).contains(&version) {
+            return Err(RabiaError::serialization(format!(
+                "Unsupported wire version {} (supported {}..={})",
+                version, MIN_WIRE_VERSION, WIRE_VERSION
+            )));
+        }
+
+        Ok(Self {
+            version,
+            message_type: WireMessageType::from_code(frame[3])?,
+            body_len: u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]),
+        })
+    }
+}
+
+/// Encode a message into a single frame.
+pub fn encode(message: &ProtocolMessage) -> Result<Bytes> {
+    encode_frame(message, true)
+}
+
+/// Encode a message as [`encode`] does but without its signature: the bytes
+/// a signature covers.
+pub fn encode_unsigned(message: &ProtocolMessage) -> Result<Bytes> {
+    encode_frame(message, false)
+}
+
+fn encode_frame(message: &ProtocolMessage, with_signature: bool) -> Result<Bytes> {
+    let mut writer = Writer {
+        buf: BytesMut::with_capacity(256),
+    };
+    writer.buf.put_slice(&MAGIC);
+    writer.buf.put_u8(WIRE_VERSION);
+    writer
+        .buf
+        .put_u8(WireMessageType::of(&message.message_type) as u8);
+    writer.buf.put_u32(0);
+
+    writer.record(|w| write_envelope(w, message, with_signature));
+    writer.record(|w| write_payload(w, &message.message_type));
+
+    // Nested record lengths are truncated if the body overflows, so check the whole
+    let body_len = u32::try_from(writer.buf.len() - HEADER_LEN)
+        .map_err(|_| RabiaError::serialization("Message is too large for the wire format"))?;
+    writer.buf[4..HEADER_LEN].copy_from_slice(&body_len.to_be_bytes());
+    Ok(writer.buf.freeze())
+}
+
+/// Decode a frame produced by [`encode`], by this or an adjacent version.
+///
+/// Payload data in the result shares memory with `frame`.
+pub fn decode(mut frame: Bytes) -> Result<ProtocolMessage> {
+    let header = WireHeader::parse(&frame)?;
+    let received = frame.clone();
+    frame.advance(HEADER_LEN);
+
+    let body_len = header.body_len as usize;
+    if frame.len() < body_len {
+        return Err(RabiaError::serialization(format!(
+            "Frame body truncated: expected {} bytes, found {}",
+            body_len,
+            frame.len()
+        )));
+    }
+    let mut body = Reader {
+        buf: frame.split_to(body_len),
+    };
+
+    let mut envelope = body.record()?;
+    let mut payload = body.record()?;
+
+    let id = envelope.uuid()?;
+    let from = NodeId(envelope.uuid()?);
+    let to = envelope.option(|r| Ok(NodeId(r.uuid()?)))?;
+    let timestamp = envelope.u64()?;
+    let sequence = envelope.option(|r| {
+        Ok(MessageSequence {
+            incarnation: r.u64()?,
+            number: r.u64()?,
+        })
+    })?;
+    // Frame offset of the signature field, from the envelope's end
+    let envelope_end = ENVELOPE_START + 4 + envelope_len(&received);
+    let signature_start = envelope_end - envelope.buf.remaining();
+    let signature = envelope.trailing(|r| r.option(Reader::bytes))?.flatten();
+    let signed_bytes = signature.as_ref().map(|_| {
+        let signature_end = envelope_end - envelope.buf.remaining();
+        unsigned_frame(
+            &received[..HEADER_LEN + body_len],
+            signature_start..signature_end,
+        )
+    });
+
+    Ok(ProtocolMessage {
+        id,
+        from,
+        to,
+        timestamp,
+        message_type: read_payload(&mut payload, header.message_type)?,
+        sequence,
+        signature,
+        signed_bytes,
+    })
+}
+
+/// Frame offset of the envelope record
+const ENVELOPE_START: usize = HEADER_LEN;
+
+/// Length of the envelope record of a frame whose body has been checked.
+fn envelope_len(frame: &[u8]) -> usize {
+    u32::from_be_bytes(
+        frame[ENVELOPE_START..ENVELOPE_START + 4]
+            .try_into()
+            .unwrap(),
+    ) as usize
+}
+
+/// The bytes a sender signed: `frame` as received, with the signature field
+/// at `signature` replaced by an empty option.
+///
+/// Working on the received bytes rather than re-encoding the decoded message
+/// keeps fields this version does not know, which a newer sender signed too.
+fn unsigned_frame(frame: &[u8], signature: std::ops::Range<usize>) -> Bytes {
+    let removed = (signature.len() - 1) as u32;
+    let mut unsigned = BytesMut::with_capacity(frame.len() - removed as usize);
+    unsigned.put_slice(&frame[..signature.start]);
+    unsigned.put_u8(0);
+    unsigned.put_slice(&frame[signature.end..]);
+
+    let body_len = u32::from_be_bytes(unsigned[4..HEADER_LEN].try_into().unwrap()) - removed;
+    unsigned[4..HEADER_LEN].copy_from_slice(&body_len.to_be_bytes());
+    let envelope_len = envelope_len(&unsigned) as u32 - removed;
+    unsigned[ENVELOPE_START..ENVELOPE_START + 4].copy_from_slice(&envelope_len.to_be_bytes());
+    unsigned.freeze()
+}
+
+fn write_envelope(w: &mut Writer, message: &ProtocolMessage, with_signature: bool) {
+    w.uuid(&message.id);
+    w.uuid(&message.from.0);
+    w.option(&message.to, |w, to| w.uuid(&to.0));
+    w.u64(message.timestamp);
+    w.option(&message.sequence, |w, sequence| {
+        w.u64(sequence.incarnation);
+        w.u64(sequence.number);
+    });
+    let signature = message.signature.as_ref().filter(|_| with_signature);
+    w.option(&signature, |w, signature| w.bytes(signature));
+}
+
+fn write_payload(w: &mut Writer, message_type: &MessageType) {
+    match message_type {
+        MessageType::Propose(m) => {
+            w.u64(m.phase_id.value());
+            w.uuid(&m.batch_id.0);
+            w.state_value(m.value);
+            w.option(&m.batch, write_batch);
+        }
+        MessageType::VoteRound1(m) => {
+            w.u64(m.phase_id.value());
+            w.uuid(&m.batch_id.0);
+            w.state_value(m.vote);
+            w.uuid(&m.voter_id.0);
+        }
+        MessageType::VoteRound2(m) => {
+            w.u64(m.phase_id.value());
+            w.uuid(&m.batch_id.0);
+            w.state_value(m.vote);
+            w.uuid(&m.voter_id.0);
+            w.bytes(m.round1_votes.as_packed());
+            w.u32(m.round1_votes.config_hash());
+        }
+        MessageType::Decision(m) => {
+            w.u64(m.phase_id.value());
+            w.uuid(&m.batch_id.0);
+            w.state_value(m.decision);
+            w.option(&m.batch, write_batch);
+            w.u32(m.config_hash);
+        }
+        MessageType::SyncRequest(m) => {
+            w.u64(m.requester_phase.value());
+            w.u64(m.requester_state_version);
+            w.option(&m.requester_snapshot_version, |w, version| w.u64(*version));
+        }
+        MessageType::SyncResponse(m) => {
+            w.u64(m.responder_phase.value());
+            w.u64(m.responder_state_version);
+            w.option(&m.state_snapshot, |w, snapshot| {
+                w.record(|w| {
+                    w.u64(snapshot.version);
+                    w.bytes(&snapshot.data);
+                    w.u32(snapshot.checksum);
+                })
+            });
+            w.seq(&m.pending_batches, |w, (batch_id, batch)| {
+                w.uuid(&batch_id.0);
+                write_batch(w, batch);
+            });
+            w.seq(&m.committed_phases, |w, (phase_id, batch_id, value)| {
+                w.u64(phase_id.value());
+                w.uuid(&batch_id.0);
+                w.state_value(*value);
+            });
+            w.option(&m.sessions, write_sessions);
+            w.seq(&m.state_deltas, |w, delta| {
+                w.record(|w| {
+                    w.u64(delta.base_version);
+                    w.u64(delta.version);
+                    w.bytes(&delta.data);
+                    w.u32(delta.checksum);
+                })
+            });
+            w.option(&m.committed_batches, write_committed_batches);
+        }
+        MessageType::NewBatch(m) => {
+            write_batch(w, &m.batch);
+            w.uuid(&m.originator.0);
+        }
+        MessageType::HeartBeat(m) => {
+            w.u64(m.current_phase.value());
+            w.u64(m.last_committed_phase.value());
+            w.bool(m.active);
+        }
+        MessageType::QuorumNotification(m) => {
+            w.bool(m.has_quorum);
+            w.seq(&m.active_nodes, |w, node_id| w.uuid(&node_id.0));
+        }
+    }
+}
+
+fn read_payload(r: &mut Reader, message_type: WireMessageType) -> Result<MessageType> {
+    Ok(match message_type {
+        WireMessageType::Propose => MessageType::Propose(ProposeMessage {
+            phase_id: PhaseId::new(r.u64()?),
+            batch_id: BatchId(r.uuid()?),
+            value: r.state_value()?,
+            batch: r.option(read_batch)?,
+        }),
+        WireMessageType::VoteRound1 => MessageType::VoteRound1(VoteRound1Message {
+            phase_id: PhaseId::new(r.u64()?),
+            batch_id: BatchId(r.uuid()?),
+            vote: r.state_value()?,
+            voter_id: NodeId(r.uuid()?),
+        }),
+        WireMessageType::VoteRound2 => MessageType::VoteRound2(VoteRound2Message {
+            phase_id: PhaseId::new(r.u64()?),
+            batch_id: BatchId(r.uuid()?),
+            vote: r.state_value()?,
+            voter_id: NodeId(r.uuid()?),
+            round1_votes: {
+                let packed = r.bytes()?.to_vec();
+                // Older senders omit the hash, and their votes are rejected
+                let config_hash = r.trailing(Reader::u32)?.unwrap_or_default();
+                VoteVector::from_packed(config_hash, packed)
+            },
+        }),
+        WireMessageType::Decision => MessageType::Decision(DecisionMessage {
+            phase_id: PhaseId::new(r.u64()?),
+            batch_id: BatchId(r.uuid()?),
+            decision: r.state_value()?,
+            batch: r.option(read_batch)?,
+            // Older senders omit the hash, and their decisions are rejected
+            config_hash: r.trailing(Reader::u32)?.unwrap_or_default(),
+        }),
+        WireMessageType::SyncRequest => MessageType::SyncRequest(SyncRequestMessage {
+            requester_phase: PhaseId::new(r.u64()?),
+            requester_state_version: r.u64()?,
+            requester_snapshot_version: r.trailing(|r| r.option(Reader::u64))?.flatten(),
+        }),
+        WireMessageType::SyncResponse => MessageType::SyncResponse(SyncResponseMessage {
+            responder_phase: PhaseId::new(r.u64()?),
+            responder_state_version: r.u64()?,
+            state_snapshot: r.option(|r| {
+                let mut record = r.record()?;
+                Ok(Snapshot {
+                    version: record.u64()?,
+                    data: record.bytes()?,
+                    checksum: record.u32()?,
+                })
+            })?,
+            pending_batches: r.seq(|r| Ok((BatchId(r.uuid()?), read_batch(r)?)))?,
+            committed_phases: r
+                .seq(|r| Ok((PhaseId::new(r.u64()?), BatchId(r.uuid()?), r.state_value()?)))?,
+            sessions: r.trailing(|r| r.option(read_sessions))?.flatten(),
+            state_deltas: r
+                .trailing(|r| {
+                    r.seq(|r| {
+                        let mut record = r.record()?;
+                        Ok(DeltaSnapshot {
+                            base_version: record.u64()?,
+                            version: record.u64()?,
+                            data: record.bytes()?,
+                            checksum: record.u32()?,
+                        })
+                    })
+                })?
+                .unwrap_or_default(),
+            committed_batches: r.trailing(|r| r.option(read_committed_batches))?.flatten(),
+        }),
+        WireMessageType::NewBatch => MessageType::NewBatch(NewBatchMessage {
+            batch: read_batch(r)?,
+            originator: NodeId(r.uuid()?),
+        }),
+        WireMessageType::HeartBeat => MessageType::HeartBeat(HeartBeatMessage {
+            current_phase: PhaseId::new(r.u64()?),
+            last_committed_phase: PhaseId::new(r.u64()?),
+            active: r.bool()?,
+        }),
+        WireMessageType::QuorumNotification => {
+            MessageType::QuorumNotification(QuorumNotificationMessage {
+                has_quorum: r.bool()?,
+                active_nodes: r.seq(|r| Ok(NodeId(r.uuid()?)))?,
+            })
+        }
+    })
+}
+
+fn write_batch(w: &mut Writer, batch: &CommandBatch) {
+    w.record(|w| {
+        w.uuid(&batch.id.0);
+        w.u64(batch.timestamp);
+        w.seq(&batch.commands, |w, command| {
+            w.record(|w| {
+                w.uuid(&command.id);
+                w.bytes(&command.data);
+                w.option(&command.session, |w, session| {
+                    w.uuid(&session.client_id.0);
+                    w.u64(session.sequence);
+                });
+            })
+        });
+    })
+}
+
+fn read_batch(r: &mut Reader) -> Result<CommandBatch> {
+    let mut record = r.record()?;
+    Ok(CommandBatch {
+        id: BatchId(record.uuid()?),
+        timestamp: record.u64()?,
+        commands: record.seq(|r| {
+            let mut record = r.record()?;
+            Ok(Command {
+                id: record.uuid()?,
+                data: record.bytes()?,
+                session: record.option(|r| {
+                    Ok(SessionInfo {
+                        client_id: ClientId(r.uuid()?),
+                        sequence: r.u64()?,
+                    })
+                })?,
+            })
+        })?,
+    })
+}
+
+fn write_sessions(w: &mut Writer, sessions: &SessionTable) {
+    let mut entries: Vec<_> = sessions.iter().collect();
+    entries.sort_by_key(|(client_id, _)| **client_id);
+    w.seq(&entries, |w, (client_id, entry)| {
+        w.record(|w| {
+            w.uuid(&client_id.0);
+            w.u64(entry.last_sequence);
+            w.u64(entry.last_active_phase.value());
+            let responses: Vec<_> = entry.responses.iter().collect();
+            w.seq(&responses, |w, (sequence, response)| {
+                w.u64(**sequence);
+                w.bytes(response);
+            });
+        })
+    });
+}
+
+fn read_sessions(r: &mut Reader) -> Result<SessionTable> {
+    let entries = r.seq(|r| {
+        let mut record = r.record()?;
+        let client_id = ClientId(record.uuid()?);
+        let entry = SessionEntry {
+            last_sequence: record.u64()?,
+            last_active_phase: PhaseId::new(record.u64()?),
+            responses: record
+                .seq(|r| Ok((r.u64()?, r.bytes()?)))?
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+        };
+        Ok((client_id, entry))
+    })?;
+    Ok(entries.into_iter().collect())
+}
+
+fn write_committed_batches(w: &mut Writer, committed: &CommittedBatches) {
+    let mut entries: Vec<_> = committed.iter().collect();
+    entries.sort_by_key(|(batch_id, phase_id)| (**phase_id, batch_id.0));
+    w.seq(&entries, |w, (batch_id, phase_id)| {
+        w.uuid(&batch_id.0);
+        w.u64(phase_id.value());
+    });
+}
+
+fn read_committed_batches(r: &mut Reader) -> Result<CommittedBatches> {
+    let entries = r.seq(|r| Ok((BatchId(r.uuid()?), PhaseId::new(r.u64()?))))?;
+    Ok(entries.into_iter().collect())
+}
+
+struct Writer {
+    buf: BytesMut,
+}
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.buf.put_u32(value);
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.buf.put_u64(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.buf.put_u8(value as u8);
+    }
+
+    fn uuid(&mut self, value: &Uuid) {
+        self.buf.put_slice(value.as_bytes());
+    }
+
+    fn state_value(&mut self, value: StateValue) {
+        self.buf.put_u8(match value {
+            StateValue::V0 => 0,
+            StateValue::V1 => 1,
+            StateValue::VQuestion => 2,
+        });
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.buf.put_u32(value.len() as u32);
+        self.buf.put_slice(value);
+    }
+
+    fn option<T>(&mut self, value: &Option<T>, write: impl FnOnce(&mut Self, &T)) {
+        match value {
+            Some(value) => {
+                self.buf.put_u8(1);
+                write(self, value);
+            }
+            None => self.buf.put_u8(0),
+        }
+    }
+
+    fn seq<T>(&mut self, items: &[T], mut write: impl FnMut(&mut Self, &T)) {
+        self.buf.put_u32(items.len() as u32);
+        for item in items {
+            write(self, item);
+        }
+    }
+
+    /// Write a length-prefixed record that decoders can extend and skip
+    fn record(&mut self, write: impl FnOnce(&mut Self)) {
+        let start = self.buf.len();
+        self.buf.put_u32(0);
+        write(self);
+        let len = (self.buf.len() - start - 4) as u32;
+        self.buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+}
+
+struct Reader {
+    buf: Bytes,
+}
+
+impl Reader {
+    fn need(&self, len: usize) -> Result<()> {
+        if self.buf.remaining() < len {
+            return Err(RabiaError::serialization(format!(
+                "Unexpected end of wire data: needed {} bytes, {} left",
+                len,
+                self.buf.remaining()
+            )));
+        }
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        self.need(1)?;
+        Ok(self.buf.get_u8())
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.need(4)?;
+        Ok(self.buf.get_u32())
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        self.need(8)?;
+        Ok(self.buf.get_u64())
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn uuid(&mut self) -> Result<Uuid> {
+        self.need(16)?;
+        let mut bytes = [0u8; 16];
+        self.buf.copy_to_slice(&mut bytes);
+        Ok(Uuid::from_bytes(bytes))
+    }
+
+    fn state_value(&mut self) -> Result<StateValue> {
+        match self.u8()? {
+            0 => Ok(StateValue::V0),
+            1 => Ok(StateValue::V1),
+            2 => Ok(StateValue::VQuestion),
+            other => Err(RabiaError::serialization(format!(
+                "Invalid state value {}",
+                other
+            ))),
+        }
+    }
+
+    /// Length-prefixed bytes, sliced out of the frame without copying
+    fn bytes(&mut self) -> Result<Bytes> {
+        let len = self.u32()? as usize;
+        self.need(len)?;
+        Ok(self.buf.split_to(len))
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            other => Err(RabiaError::serialization(format!(
+                "Invalid option tag {}",
+                other
+            ))),
+        }
+    }
+
+    fn seq<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let count = self.u32()? as usize;
+        // Every item takes at least one byte, which bounds a forged count
+        let mut items = Vec::with_capacity(count.min(self.buf.remaining()));
+        for _ in 0..count {
+            items.push(read(self)?);
+        }
+        Ok(items)
+    }
+
+    /// Read a record; bytes after the fields the caller reads are ignored
+    fn record(&mut self) -> Result<Reader> {
+        let len = self.u32()? as usize;
+        self.need(len)?;
+        Ok(Reader {
+            buf: self.buf.split_to(len),
+        })
+    }
+
+    /// Read a field that older encoders may have left off the end of a record
+    fn trailing<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        if self.buf.has_remaining() {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::votes::MemberIndex;
+
+    fn propose_message() -> ProtocolMessage {
+        ProtocolMessage::propose(
+            NodeId::new(),
+            ProposeMessage {
+                phase_id: PhaseId::new(7),
+                batch_id: BatchId::new(),
+                value: StateValue::V1,
+                batch: Some(CommandBatch::new(vec![
+                    Command::new("SET key1 value1"),
+                    Command::with_session("SET key2 value2", ClientId::new(), 3),
+                ])),
+            },
+        )
+        .with_sequence(MessageSequence {
+            incarnation: 42,
+            number: 9,
+        })
+    }
+
+    #[test]
+    fn test_roundtrip_preserves_message() {
+        let message = propose_message();
+        let decoded = decode(encode(&message).unwrap()).unwrap();
+
+        assert_eq!(decoded.id, message.id);
+        assert_eq!(decoded.from, message.from);
+        assert_eq!(decoded.timestamp, message.timestamp);
+        assert_eq!(decoded.sequence, message.sequence);
+        match (decoded.message_type, message.message_type) {
+            (MessageType::Propose(decoded), MessageType::Propose(original)) => {
+                assert_eq!(decoded.phase_id, original.phase_id);
+                assert_eq!(decoded.batch, original.batch);
+            }
+            _ => panic!("Expected a propose message"),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_sync_response_with_sessions() {
+        let mut sessions = SessionTable::new();
+        sessions.record(
+            SessionInfo {
+                client_id: ClientId::new(),
+                sequence: 1,
+            },
+            PhaseId::new(4),
+            Bytes::from_static(b"OK"),
+            &Default::default(),
+        );
+        let batch = CommandBatch::new(vec![Command::new("GET a")]);
+        let committed_batches: CommittedBatches =
+            [(batch.id, PhaseId::new(4))].into_iter().collect();
+        let message = ProtocolMessage::sync_response(
+            NodeId::new(),
+            NodeId::new(),
+            SyncResponseMessage {
+                responder_phase: PhaseId::new(5),
+                responder_state_version: 12,
+                state_snapshot: Some(Snapshot::new(12, b"state".to_vec())),
+                pending_batches: vec![(batch.id, batch)],
+                committed_phases: vec![(PhaseId::new(4), BatchId::new(), StateValue::V1)],
+                sessions: Some(sessions.clone()),
+                state_deltas: vec![DeltaSnapshot::new(12, 14, b"delta".to_vec())],
+                committed_batches: Some(committed_batches.clone()),
+            },
+        );
+
+        let decoded = decode(encode(&message).unwrap()).unwrap();
+        let MessageType::SyncResponse(response) = decoded.message_type else {
+            panic!("Expected a sync response");
+        };
+        assert_eq!(response.sessions, Some(sessions));
+        assert_eq!(response.committed_batches, Some(committed_batches));
+        assert_eq!(response.state_snapshot.unwrap().data, &b"state"[..]);
+        assert_eq!(response.pending_batches.len(), 1);
+        assert_eq!(response.committed_phases.len(), 1);
+        assert_eq!(
+            response.state_deltas,
+            vec![DeltaSnapshot::new(12, 14, b"delta".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_sync_request_with_snapshot_version() {
+        let request = |requester_snapshot_version| {
+            ProtocolMessage::sync_request(
+                NodeId::new(),
+                NodeId::new(),
+                SyncRequestMessage {
+                    requester_phase: PhaseId::new(5),
+                    requester_state_version: 3,
+                    requester_snapshot_version,
+                },
+            )
+        };
+
+        for version in [Some(12), None] {
+            let decoded = decode(encode(&request(version)).unwrap()).unwrap();
+            let MessageType::SyncRequest(decoded) = decoded.message_type else {
+                panic!("Expected a sync request");
+            };
+            assert_eq!(decoded.requester_snapshot_version, version);
+        }
+    }
+
+    #[test]
+    fn test_round2_votes_are_compact() {
+        let nodes: Vec<NodeId> = (0..5).map(|_| NodeId::new()).collect();
+        let members = MemberIndex::new(nodes.iter().copied());
+        let mut round1_votes = VoteVector::new(&members);
+        for node_id in &nodes {
+            round1_votes.set(members.index_of(node_id).unwrap(), StateValue::V1);
+        }
+        let message = ProtocolMessage::vote_round2(
+            nodes[0],
+            nodes[1],
+            VoteRound2Message {
+                phase_id: PhaseId::new(2),
+                batch_id: BatchId::new(),
+                vote: StateValue::V1,
+                voter_id: nodes[0],
+                round1_votes: round1_votes.clone(),
+            },
+        );
+
+        let frame = encode(&message).unwrap();
+        assert!(frame.len() < 128);
+        let MessageType::VoteRound2(vote) = decode(frame).unwrap().message_type else {
+            panic!("Expected a round 2 vote");
+        };
+        assert_eq!(vote.round1_votes, round1_votes);
+        assert_eq!(vote.round1_votes.to_votes(&members).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_command_data_is_not_copied() {
+        let frame = encode(&propose_message()).unwrap();
+        let decoded = decode(frame.clone()).unwrap();
+
+        let MessageType::Propose(propose) = decoded.message_type else {
+            panic!("Expected a propose message");
+        };
+        let data = &propose.batch.unwrap().commands[0].data;
+        let frame_range = frame.as_ptr() as usize..frame.as_ptr() as usize + frame.len();
+        assert!(frame_range.contains(&(data.as_ptr() as usize)));
+    }
+
+    #[test]
+    fn test_unknown_trailing_fields_are_skipped() {
+        let message = ProtocolMessage::new(
+            NodeId::new(),
+            None,
+            MessageType::HeartBeat(HeartBeatMessage {
+                current_phase: PhaseId::new(3),
+                last_committed_phase: PhaseId::new(2),
+                active: true,
+            }),
+        );
+
+        // Simulate a newer node that appended a field to each record
+        let mut w = Writer {
+            buf: BytesMut::new(),
+        };
+        w.buf.put_slice(&MAGIC);
+        w.buf.put_u8(WIRE_VERSION);
+        w.buf.put_u8(WireMessageType::HeartBeat as u8);
+        w.buf.put_u32(0);
+        w.record(|w| {
+            write_envelope(w, &message, true);
+            w.u64(0xdead_beef);
+        });
+        w.record(|w| {
+            write_payload(w, &message.message_type);
+            w.bytes(b"future field");
+        });
+        let body_len = (w.buf.len() - HEADER_LEN) as u32;
+        w.buf[4..HEADER_LEN].copy_from_slice(&body_len.to_be_bytes());
+
+        let decoded = decode(w.buf.freeze()).unwrap();
+        assert_eq!(decoded.id, message.id);
+        let MessageType::HeartBeat(heartbeat) = decoded.message_type else {
+            panic!("Expected a heartbeat");
+        };
+        assert_eq!(heartbeat.current_phase, PhaseId::new(3));
+        assert!(heartbeat.active);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_version_and_truncation() {
+        let frame = encode(&propose_message()).unwrap();
+
+        let mut newer = frame.to_vec();
+        newer[2] = WIRE_VERSION + 1;
+        assert!(decode(Bytes::from(newer)).is_err());
+
+        let truncated = frame.slice(..frame.len() - 1);
+        assert!(decode(truncated).is_err());
+    }
+}
@@ -1,6 +1,74 @@
 use crate::network::TcpNetworkConfig;
+use rabia_core::{
+    registry::NodeRegistry, sessions::SessionConfig, signing::SigningConfig,
+    validation::ValidationConfig,
+};
+use std::sync::Arc;
 use std::time::Duration;
 
+/// When the engine saves a full state snapshot.
+///
+/// Commits between snapshots are appended to the persistence layer's
+/// decision log and replayed on restart. A snapshot is taken once any of the
+/// set thresholds is reached; with none set, only on shutdown. It is written
+/// in the background, and taken from the state machine's
+/// [`snapshot_source`](rabia_core::state_machine::StateMachine::snapshot_source)
+/// off the commit path if it has one. State machines that support
+/// incremental snapshots write deltas between full snapshots instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPolicy {
+    /// Committed phases since the last snapshot
+    pub every_phases: Option<u64>,
+    /// Time since the last snapshot, checked when a phase commits
+    pub every_interval: Option<Duration>,
+    /// Command bytes logged since the last snapshot
+    pub every_log_bytes: Option<usize>,
+    /// Deltas taken on top of a full snapshot before the next full one, for
+    /// state machines that support incremental snapshots; 0 disables deltas
+    pub max_deltas: usize,
+}
+
+impl Default for SnapshotPolicy {
+    fn default() -> Self {
+        Self {
+            every_phases: Some(1000),
+            every_interval: Some(Duration::from_secs(60)),
+            every_log_bytes: Some(64 * 1024 * 1024),
+            max_deltas: 8,
+        }
+    }
+}
+
+impl SnapshotPolicy {
+    /// Snapshot after every committed phase.
+    pub fn every_commit() -> Self {
+        Self {
+            every_phases: Some(1),
+            every_interval: None,
+            every_log_bytes: None,
+            ..Self::default()
+        }
+    }
+
+    /// Snapshot only on shutdown, relying on the decision log otherwise.
+    pub fn on_shutdown_only() -> Self {
+        Self {
+            every_phases: None,
+            every_interval: None,
+            every_log_bytes: None,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a snapshot is due given what was committed since the last one.
+    pub fn is_due(&self, phases: u64, log_bytes: usize, elapsed: Duration) -> bool {
+        phases > 0
+            && (self.every_phases.is_some_and(|every| phases >= every)
+                || self.every_interval.is_some_and(|every| elapsed >= every)
+                || self.every_log_bytes.is_some_and(|every| log_bytes >= every))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RabiaConfig {
     pub phase_timeout: Duration,
@@ -15,6 +83,25 @@ pub struct RabiaConfig {
     pub backoff_base: Duration,
     pub backoff_max: Duration,
     pub network_config: TcpNetworkConfig,
+    /// Client session limits; every member must use the same ones, since the
+    /// session table is replicated state
+    pub session_config: SessionConfig,
+    /// Limits applied to inbound messages and client batches
+    pub validation_config: ValidationConfig,
+    /// Forward client batches to the deterministic leader instead of proposing locally
+    pub leader_assisted: bool,
+    /// Silence after which the leader is suspected and the engine falls back to leaderless mode
+    pub leader_suspect_timeout: Duration,
+    /// Maximum time a graceful shutdown waits for in-flight phases to decide
+    pub shutdown_drain_timeout: Duration,
+    /// Sign outgoing messages and require valid signatures on inbound ones
+    pub signing: Option<SigningConfig>,
+    /// When to snapshot instead of only logging a commit
+    pub snapshot_policy: SnapshotPolicy,
+    /// Most commits the persistence worker makes durable with one write
+    pub group_commit_max: usize,
+    /// Member names used in the engine's tracing span and statistics
+    pub registry: Option<Arc<NodeRegistry>>,
 }
 
 impl Default for RabiaConfig {
@@ -32,6 +119,15 @@ impl Default for RabiaConfig {
             backoff_base: Duration::from_millis(100),
             backoff_max: Duration::from_secs(10),
             network_config: TcpNetworkConfig::default(),
+            session_config: SessionConfig::default(),
+            validation_config: ValidationConfig::default(),
+            leader_assisted: false,
+            leader_suspect_timeout: Duration::from_millis(3000),
+            shutdown_drain_timeout: Duration::from_secs(5),
+            signing: None,
+            snapshot_policy: SnapshotPolicy::default(),
+            group_commit_max: 256,
+            registry: None,
         }
     }
 }
@@ -70,4 +166,60 @@ impl RabiaConfig {
         self.network_config = config;
         self
     }
+
+    pub fn with_session_config(mut self, config: SessionConfig) -> Self {
+        self.session_config = config;
+        self
+    }
+
+    /// Settings every member must share, hashed into the configuration hash
+    /// carried by votes and decisions so a member configured differently is
+    /// rejected instead of diverging.
+    pub fn replicated_settings(&self) -> Vec<u64> {
+        vec![
+            self.session_config.response_window as u64,
+            self.session_config.max_sessions as u64,
+            self.session_config.max_committed_batches as u64,
+        ]
+    }
+
+    pub fn with_validation_config(mut self, config: ValidationConfig) -> Self {
+        self.validation_config = config;
+        self
+    }
+
+    pub fn with_signing(mut self, signing: SigningConfig) -> Self {
+        self.signing = Some(signing);
+        self
+    }
+
+    pub fn with_leader_assisted(mut self, enabled: bool) -> Self {
+        self.leader_assisted = enabled;
+        self
+    }
+
+    pub fn with_leader_suspect_timeout(mut self, timeout: Duration) -> Self {
+        self.leader_suspect_timeout = timeout;
+        self
+    }
+
+    pub fn with_shutdown_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_drain_timeout = timeout;
+        self
+    }
+
+    pub fn with_snapshot_policy(mut self, policy: SnapshotPolicy) -> Self {
+        self.snapshot_policy = policy;
+        self
+    }
+
+    pub fn with_group_commit_max(mut self, max: usize) -> Self {
+        self.group_commit_max = max;
+        self
+    }
+
+    pub fn with_registry(mut self, registry: NodeRegistry) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
 }
@@ -1,25 +1,35 @@
 use rand::Rng;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::time::{interval, timeout};
-use tracing::{debug, error, info, warn};
+use std::time::Instant;
+use tokio::time::interval;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use rabia_core::{
+    freshness::SequenceGenerator,
     messages::{
-        DecisionMessage, HeartBeatMessage, MessageType, NewBatchMessage, ProposeMessage,
-        ProtocolMessage, SyncRequestMessage, SyncResponseMessage, VoteRound1Message,
+        DecisionMessage, HeartBeatMessage, MessageType, NewBatchMessage, PhaseData, ProposeMessage,
+        ProtocolMessage, SyncRequestMessage, SyncResponseMessage, VoteRecord, VoteRound1Message,
         VoteRound2Message,
     },
     network::{ClusterConfig, NetworkEventHandler, NetworkTransport},
     persistence::PersistenceLayer,
-    state_machine::StateMachine,
-    BatchId, CommandBatch, NodeId, PhaseId, RabiaError, Result, StateValue, Validator,
+    sessions::{CommittedBatches, SessionCheck, SessionTable},
+    signing::MessageSigner,
+    state_machine::{DeltaSnapshot, Snapshot, StateMachine},
+    validation::{MessageValidator, RejectionReason},
+    votes::{MemberIndex, VoteTally},
+    BatchId, Command, CommandBatch, NodeDisplay, NodeId, PhaseId, RabiaError, Result, StateValue,
 };
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
 
 use crate::{
-    network::TcpNetwork, CommandRequest, EngineCommand, EngineCommandReceiver, EngineState,
-    LeaderSelector, RabiaConfig,
+    network::TcpNetwork,
+    persist::{CommitRecord, DurableAck, PersistenceWorker, Reply},
+    CommandRequest, CommandResults, EngineCommand, EngineCommandReceiver, EngineEvent,
+    EngineHandle, EngineState, EngineStatus, FailureDetector, LeaderSelector, LocalReader,
+    RabiaConfig, EVENT_CHANNEL_CAPACITY,
 };
 
 pub struct RabiaEngine<SM, NT, PL>
@@ -30,7 +40,6 @@ where
 {
     node_id: NodeId,
     config: RabiaConfig,
-    #[allow(dead_code)]
     cluster_config: ClusterConfig,
     state_machine: Arc<tokio::sync::Mutex<SM>>,
     network: Arc<tokio::sync::Mutex<NT>>,
@@ -39,6 +48,57 @@ where
     command_rx: EngineCommandReceiver,
     rng: rand::rngs::StdRng,
     leader_selector: LeaderSelector,
+    failure_detector: FailureDetector,
+    validator: Arc<MessageValidator>,
+    sequencer: SequenceGenerator,
+    /// Member indices for compact vote encoding, kept in step with `cluster_config`
+    member_index: MemberIndex,
+    /// Signs outgoing messages when `RabiaConfig::signing` is set
+    signer: Option<Arc<MessageSigner>>,
+    sessions: SessionTable,
+    pending_responses: HashMap<BatchId, oneshot::Sender<Result<CommandResults>>>,
+    /// Batches handed to the leader that this node has not seen commit yet
+    forwarded_batches: HashSet<BatchId>,
+    /// Batches this node has proposed that have not committed yet
+    proposed_batches: HashSet<BatchId>,
+    /// Batches waiting to be proposed on the next heartbeat tick with quorum
+    queued_batches: VecDeque<BatchId>,
+    /// A queued batch lost a phase other batches were proposed in, so the
+    /// next ticks hold the queue back at random
+    contended: bool,
+    /// Recently committed batches, replicated like `sessions`, so a batch
+    /// committed again is not applied twice
+    committed_batches: CommittedBatches,
+    /// Phases up to this one are covered by restored or synced state, so
+    /// late decisions for them are not applied again
+    restored_through: PhaseId,
+    /// Set while the restored state misses commits that the decision log no
+    /// longer holds; sync requests are repeated until a sync restores them
+    sync_pending: bool,
+    status_tx: watch::Sender<EngineStatus>,
+    events_tx: broadcast::Sender<EngineEvent>,
+    /// Commands from an [`EngineHandle`], present once the engine is spawned
+    control_rx: Option<EngineCommandReceiver>,
+    /// Set when a graceful shutdown has been requested
+    drain_deadline: Option<Instant>,
+    /// Snapshot started by the snapshot policy, written in the background
+    snapshot_task: Option<JoinHandle<Result<Option<Snapshot>>>>,
+    /// Last full snapshot, which deltas chain to; `None` while one is being taken
+    snapshot_base: Option<Snapshot>,
+    /// Deltas taken on top of `snapshot_base`, oldest first
+    snapshot_deltas: Vec<DeltaSnapshot>,
+    /// How much of the chain is in persistence; `None` until `snapshot_base`
+    /// itself is saved
+    saved_chain: Option<SavedChain>,
+    /// Phases logged since the last snapshot was taken
+    logged_phases: u64,
+    /// Command bytes logged since the last snapshot was taken
+    logged_bytes: usize,
+    last_snapshot: Instant,
+    /// Writes commits to the decision log in groups, started by `initialize`
+    persist_worker: Option<PersistenceWorker>,
+    /// Acknowledgements from `persist_worker`
+    durable_rx: Option<mpsc::UnboundedReceiver<DurableAck>>,
 }
 
 impl<SM, NT, PL> RabiaEngine<SM, NT, PL>
@@ -70,10 +130,54 @@ where
             state_machine: Arc::new(tokio::sync::Mutex::new(state_machine)),
             network: Arc::new(tokio::sync::Mutex::new(network)),
             persistence: Arc::new(persistence),
-            engine_state: Arc::new(EngineState::new(cluster_config.quorum_size)),
+            engine_state: Arc::new(
+                EngineState::new(cluster_config.quorum_size).with_registry(config.registry.clone()),
+            ),
             command_rx,
             rng,
             leader_selector,
+            failure_detector: FailureDetector::new(config.leader_suspect_timeout),
+            validator: Arc::new(MessageValidator::new(config.validation_config.clone())),
+            sequencer: SequenceGenerator::new(),
+            member_index: cluster_config
+                .member_index()
+                .with_settings(&config.replicated_settings()),
+            signer: None,
+            sessions: SessionTable::new(),
+            pending_responses: HashMap::new(),
+            forwarded_batches: HashSet::new(),
+            proposed_batches: HashSet::new(),
+            queued_batches: VecDeque::new(),
+            contended: false,
+            committed_batches: CommittedBatches::new(),
+            restored_through: PhaseId::default(),
+            sync_pending: false,
+            status_tx: watch::channel(EngineStatus::Starting).0,
+            events_tx: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            control_rx: None,
+            drain_deadline: None,
+            snapshot_task: None,
+            snapshot_base: None,
+            snapshot_deltas: Vec::new(),
+            saved_chain: None,
+            logged_phases: 0,
+            logged_bytes: 0,
+            last_snapshot: Instant::now(),
+            persist_worker: None,
+            durable_rx: None,
+        }
+    }
+
+    /// `node_id` for log lines: its member name when a registry is
+    /// configured, its UUID otherwise.
+    fn name(&self, node_id: NodeId) -> NodeDisplay<'_> {
+        node_id.display_with(self.config.registry.as_deref())
+    }
+
+    fn leader_name(&self, leader: Option<NodeId>) -> String {
+        match leader {
+            Some(leader) => self.name(leader).to_string(),
+            None => "none".to_string(),
         }
     }
 }
@@ -95,25 +199,15 @@ where
         // Create TCP network from configuration
         let network = TcpNetwork::new(node_id, config.network_config.clone()).await?;
 
-        let rng = match config.randomization_seed {
-            Some(seed) => rand::SeedableRng::seed_from_u64(seed),
-            None => rand::SeedableRng::from_entropy(),
-        };
-
-        let leader_selector = LeaderSelector::with_cluster(cluster_config.all_nodes.clone());
-
-        Ok(Self {
+        Ok(Self::new(
             node_id,
-            config: config.clone(),
-            cluster_config: cluster_config.clone(),
-            state_machine: Arc::new(tokio::sync::Mutex::new(state_machine)),
-            network: Arc::new(tokio::sync::Mutex::new(network)),
-            persistence: Arc::new(persistence),
-            engine_state: Arc::new(EngineState::new(cluster_config.quorum_size)),
+            config,
+            cluster_config,
+            state_machine,
+            network,
+            persistence,
             command_rx,
-            rng,
-            leader_selector,
-        })
+        ))
     }
 }
 
@@ -138,73 +232,374 @@ where
         self.leader_selector.get_leadership_info()
     }
 
+    /// Get a handle for serving local, possibly stale, reads.
+    ///
+    /// The handle stays valid after the engine is moved into [`run`](Self::run).
+    pub fn local_reader(&self) -> LocalReader<SM> {
+        LocalReader::new(self.state_machine.clone(), self.engine_state.clone())
+    }
+
+    /// Replace the message validator, e.g. to add application-defined rules.
+    ///
+    /// By default the engine validates with `RabiaConfig::validation_config`.
+    pub fn with_validator(mut self, validator: MessageValidator) -> Self {
+        self.validator = Arc::new(validator);
+        self
+    }
+
+    /// Validator applied to inbound messages, with its rejection counters
+    pub fn validator(&self) -> Arc<MessageValidator> {
+        self.validator.clone()
+    }
+
+    /// Subscribe to engine events such as detected equivocation
+    pub fn subscribe_events(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Current lifecycle status of the engine
+    pub fn status(&self) -> EngineStatus {
+        *self.status_tx.borrow()
+    }
+
+    /// Run the engine on the Tokio runtime and return a handle to control it.
+    ///
+    /// The command channel passed at construction keeps working alongside the
+    /// handle.
+    pub fn spawn(mut self) -> EngineHandle {
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        self.control_rx = Some(control_rx);
+
+        let status_rx = self.status_tx.subscribe();
+        let engine_state = self.engine_state.clone();
+        let validator = self.validator.clone();
+        let events_tx = self.events_tx.clone();
+        let join_handle = tokio::spawn(self.run());
+
+        EngineHandle::new(
+            control_tx,
+            status_rx,
+            engine_state,
+            validator,
+            events_tx,
+            join_handle,
+        )
+    }
+
     /// Update cluster membership and determine new leader
     pub fn update_cluster_membership(&mut self, nodes: HashSet<NodeId>) -> Option<NodeId> {
+        for node_id in self.leader_selector.get_cluster_view().to_vec() {
+            if !nodes.contains(&node_id) {
+                self.failure_detector.remove_node(node_id);
+                self.validator.remove_peer(&node_id);
+            }
+        }
+
+        self.cluster_config.all_nodes = nodes.clone();
+        let member_index = self
+            .cluster_config
+            .member_index()
+            .with_settings(&self.config.replicated_settings());
+        let previous_index = std::mem::replace(&mut self.member_index, member_index);
+        self.engine_state
+            .reindex_phases(&previous_index, &self.member_index);
         let new_leader = self.leader_selector.update_cluster_view(nodes.clone());
 
         // Update the engine state's active nodes as well
         self.engine_state.update_active_nodes(nodes);
 
         if let Some(leader) = new_leader {
-            info!("Leadership changed to node {}", leader);
+            info!("Leadership changed to node {}", self.name(leader));
         }
 
         new_leader
     }
 
-    /// Save the current engine state to persistence.
-    async fn save_state(&self) -> Result<()> {
-        let current_phase = self.engine_state.current_phase();
-        let last_committed_phase = self.engine_state.last_committed_phase();
+    /// Save the current engine state to persistence, waiting for any
+    /// background snapshot first so the two never race.
+    async fn save_state(&mut self) -> Result<()> {
+        self.wait_for_snapshot().await;
+        let job = self.prepare_snapshot().await?;
+        let covered = job.last_committed_phase;
+        match job.write(self.persistence.as_ref()).await {
+            Ok(snapshot) => self.snapshot_saved(snapshot),
+            Err(e) => {
+                self.saved_chain = None;
+                return Err(e);
+            }
+        }
+        self.engine_state.mark_durable(covered);
+        Ok(())
+    }
 
-        // Get state machine snapshot
-        let snapshot = {
-            let sm = self.state_machine.lock().await;
-            Some(sm.create_snapshot().await?)
+    /// Hand a committed batch to the persistence worker, which answers the
+    /// client once the decision log entry is durable, and start a background
+    /// snapshot when the snapshot policy calls for one.
+    ///
+    /// Without a decision log the state is saved before the client is
+    /// answered instead.
+    async fn log_commit(&mut self, phase_id: PhaseId, batch: &CommandBatch, reply: Option<Reply>) {
+        self.logged_phases += 1;
+        self.logged_bytes += batch.commands.iter().map(|c| c.data.len()).sum::<usize>();
+
+        let record = CommitRecord {
+            phase_id,
+            batch: batch.clone(),
+            reply,
+        };
+        let record = match &self.persist_worker {
+            Some(worker) => worker.submit(record).err(),
+            None => Some(record),
         };
 
-        let engine_state = rabia_core::persistence::EngineState::new(
-            current_phase,
-            last_committed_phase,
-            snapshot,
-        );
+        // Without a worker the entry is written before moving on
+        let has_log = self.persistence.has_decision_log();
+        let mut logged = Ok(());
+        if let Some(record) = record {
+            logged = if has_log {
+                self.persistence.append_decision(phase_id, batch).await
+            } else {
+                self.save_snapshot().await
+            };
+            if let Err(e) = &logged {
+                warn!("Failed to log decision for phase {}: {}", phase_id, e);
+            } else {
+                self.engine_state.mark_durable(phase_id);
+            }
+            if let Some((response_tx, response)) = record.reply {
+                let response = match &logged {
+                    Ok(()) => response,
+                    Err(e) => Err(RabiaError::persistence(format!(
+                        "Commit of phase {} is not durable on this node: {}",
+                        phase_id, e
+                    ))),
+                };
+                let _ = response_tx.send(response);
+            }
+        }
 
-        let state_bytes = engine_state.to_bytes()?;
-        self.persistence.save_state(&state_bytes).await?;
+        // An unlogged commit is only covered once a snapshot includes it
+        let due = logged.is_err()
+            || has_log
+                && self.config.snapshot_policy.is_due(
+                    self.logged_phases,
+                    self.logged_bytes,
+                    self.last_snapshot.elapsed(),
+                );
+        if due {
+            self.start_snapshot().await;
+        }
+    }
 
-        debug!(
-            "Saved engine state: phase {}, committed {}",
-            current_phase.value(),
-            last_committed_phase.value()
-        );
+    /// Record a group write reported by the persistence worker.
+    async fn handle_durable_ack(&mut self, ack: DurableAck) {
+        match ack.result {
+            Ok(()) => self.engine_state.mark_durable(ack.through),
+            Err(e) => {
+                warn!(
+                    "Failed to log {} decisions through phase {}: {}",
+                    ack.commits, ack.through, e
+                );
+                // An unlogged commit is only covered once a snapshot includes it
+                self.start_snapshot().await;
+            }
+        }
+    }
 
-        Ok(())
+    /// Write a snapshot in the background, so commits only wait for the
+    /// state machine to be copied or for its delta to be captured.
+    async fn start_snapshot(&mut self) {
+        if let Some(task) = &self.snapshot_task {
+            if !task.is_finished() {
+                debug!("Previous snapshot still running, deferring");
+                return;
+            }
+        }
+        self.wait_for_snapshot().await;
+
+        match self.prepare_snapshot().await {
+            Ok(job) => {
+                let persistence = self.persistence.clone();
+                self.snapshot_task = Some(tokio::spawn(async move {
+                    job.write(persistence.as_ref()).await
+                }));
+            }
+            Err(e) => warn!("Failed to capture snapshot: {}", e),
+        }
+    }
+
+    /// Save a snapshot at the current commit point before returning, for
+    /// persistence layers without a decision log.
+    async fn save_snapshot(&mut self) -> Result<()> {
+        self.wait_for_snapshot().await;
+        let job = self.prepare_snapshot().await?;
+        match job.write(self.persistence.as_ref()).await {
+            Ok(full) => {
+                self.snapshot_saved(full);
+                Ok(())
+            }
+            Err(e) => {
+                self.saved_chain = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Capture what the next snapshot writes at the current commit point.
+    ///
+    /// State machines with [`IncrementalSnapshot`](rabia_core::state_machine::IncrementalSnapshot)
+    /// support add a delta to the chain on top of the last full snapshot until
+    /// the chain reaches `SnapshotPolicy::max_deltas`, and only the deltas not
+    /// yet stored are appended to persistence; otherwise a full snapshot is
+    /// taken from the state machine's snapshot source, or inline without one.
+    async fn prepare_snapshot(&mut self) -> Result<SnapshotJob<SM>> {
+        self.reset_snapshot_progress();
+
+        let mut sm = self.state_machine.lock().await;
+        let max_deltas = self.config.snapshot_policy.max_deltas;
+        let chain_has_room = self.snapshot_deltas.len() < max_deltas
+            && self
+                .saved_chain
+                .is_none_or(|saved| saved.records < max_deltas);
+        let use_delta = match sm.incremental() {
+            Some(incremental) if self.snapshot_base.is_some() && chain_has_room => {
+                if incremental.dirty_count() > 0 {
+                    self.snapshot_deltas.push(incremental.create_delta().await?);
+                }
+                true
+            }
+            Some(incremental) => {
+                // The full snapshot taken from the clone covers these changes
+                incremental.clear_dirty();
+                false
+            }
+            None => false,
+        };
+
+        // Only a failed write resets `saved_chain`, so it can be advanced here
+        let kind = match (&self.snapshot_base, use_delta, self.saved_chain) {
+            (Some(_), true, Some(saved)) => {
+                let unsaved = self.snapshot_deltas[saved.deltas..].to_vec();
+                self.saved_chain = Some(SavedChain {
+                    deltas: self.snapshot_deltas.len(),
+                    records: saved.records + 1,
+                });
+                SnapshotKind::Delta(unsaved)
+            }
+            (Some(base), true, None) => {
+                self.saved_chain = Some(SavedChain {
+                    deltas: self.snapshot_deltas.len(),
+                    records: 0,
+                });
+                SnapshotKind::Chain {
+                    base: base.clone(),
+                    deltas: self.snapshot_deltas.clone(),
+                }
+            }
+            _ => {
+                self.snapshot_base = None;
+                self.snapshot_deltas.clear();
+                self.saved_chain = None;
+                match sm.snapshot_source() {
+                    Some(source) => SnapshotKind::Full(source),
+                    None => SnapshotKind::Taken(sm.create_snapshot().await?),
+                }
+            }
+        };
+
+        Ok(SnapshotJob {
+            current_phase: self.engine_state.current_phase(),
+            last_committed_phase: self.engine_state.last_committed_phase(),
+            sessions: self.sessions.clone(),
+            committed_batches: self.committed_batches.clone(),
+            kind,
+        })
+    }
+
+    /// Wait for the background snapshot, if any, and report its outcome.
+    async fn wait_for_snapshot(&mut self) {
+        let Some(task) = self.snapshot_task.take() else {
+            return;
+        };
+        match task.await {
+            Ok(Ok(snapshot)) => self.snapshot_saved(snapshot),
+            Ok(Err(e)) => {
+                warn!("Background snapshot failed: {}", e);
+                self.saved_chain = None;
+            }
+            Err(e) => {
+                warn!("Background snapshot task failed: {}", e);
+                self.saved_chain = None;
+            }
+        }
+    }
+
+    /// Record a successful snapshot write. A new full snapshot starts an
+    /// empty chain; after a failed write the stored chain is unknown, so the
+    /// next save stores the whole chain again.
+    fn snapshot_saved(&mut self, full: Option<Snapshot>) {
+        if let Some(snapshot) = full {
+            self.snapshot_base = Some(snapshot);
+            self.saved_chain = Some(SavedChain::default());
+        }
+    }
+
+    fn reset_snapshot_progress(&mut self) {
+        self.logged_phases = 0;
+        self.logged_bytes = 0;
+        self.last_snapshot = Instant::now();
     }
 
     pub async fn run(mut self) -> Result<()> {
-        info!("Starting Rabia consensus engine for node {}", self.node_id);
+        // With a registry configured, spans read "node=alpha"
+        let node = self.name(self.node_id).to_string();
+        let span = info_span!("rabia_engine", node = %node);
+        let result = self.run_loop().instrument(span).await;
+        if let Err(e) = &result {
+            error!(
+                "Consensus engine for node {} failed: {}",
+                self.name(self.node_id),
+                e
+            );
+            self.status_tx.send_replace(EngineStatus::Failed);
+        }
+        result
+    }
+
+    async fn run_loop(&mut self) -> Result<()> {
+        info!(
+            "Starting Rabia consensus engine for node {}",
+            self.name(self.node_id)
+        );
 
         let mut cleanup_interval = interval(self.config.cleanup_interval);
         let mut heartbeat_interval = interval(self.config.heartbeat_interval);
-        let mut message_buffer = Vec::new();
 
         self.initialize().await?;
+        self.status_tx.send_replace(EngineStatus::Running);
 
         loop {
-            // Try to receive messages first
-            if let Err(e) = self.receive_messages(&mut message_buffer).await {
-                if !e.to_string().contains("No messages available") {
-                    error!("Error receiving messages: {}", e);
-                }
-            } else {
-                for (from, message) in message_buffer.drain(..) {
-                    if let Err(e) = self.handle_message(from, message).await {
-                        error!("Error handling message from {}: {}", from, e);
-                    }
-                }
+            if self.drain_finished() {
+                break self.finish_shutdown().await;
             }
 
             tokio::select! {
+                // Handle inbound protocol messages
+                received = receive_message(&self.network) => {
+                    match received {
+                        Ok((from, message)) => {
+                            if let Err(e) = self.handle_message(from, message).await {
+                                error!("Error handling message from {}: {}", self.name(from), e);
+                            }
+                        }
+                        Err(e) => {
+                            // Back off so a failing transport doesn't spin the loop
+                            error!("Error receiving messages: {}", e);
+                            tokio::time::sleep(self.config.backoff_base).await;
+                        }
+                    }
+                }
+
                 // Handle incoming commands
                 command_opt = self.command_rx.recv() => {
                     if let Some(command) = command_opt {
@@ -212,11 +607,34 @@ where
                             error!("Error handling command: {}", e);
                         }
                     } else {
-                        // Channel closed, exit loop
-                        break Ok(());
+                        // Channel closed, stop without waiting for in-flight phases
+                        break self.finish_shutdown().await;
+                    }
+                }
+
+                // Handle commands from the engine handle
+                command_opt = recv_control(&mut self.control_rx) => {
+                    if let Some(command) = command_opt {
+                        if let Err(e) = self.handle_command(command).await {
+                            error!("Error handling command: {}", e);
+                        }
+                    } else {
+                        // Handle dropped; keep running on the command channel
+                        self.control_rx = None;
                     }
                 }
 
+                // Handle decision log writes made durable by the worker
+                ack_opt = recv_durable(&mut self.durable_rx) => {
+                    match ack_opt {
+                        Some(ack) => self.handle_durable_ack(ack).await,
+                        None => self.durable_rx = None,
+                    }
+                }
+
+                // Wake up when the shutdown drain timeout expires
+                _ = drain_timer(self.drain_deadline) => {}
+
                 // Cleanup old state
                 _ = cleanup_interval.tick() => {
                     self.cleanup_old_state().await;
@@ -227,21 +645,112 @@ where
                     if let Err(e) = self.send_heartbeat().await {
                         warn!("Failed to send heartbeat: {}", e);
                     }
+                    if let Err(e) = self.reclaim_forwarded_batches().await {
+                        warn!("Failed to re-propose forwarded batches: {}", e);
+                    }
+                    if let Err(e) = self.propose_queued_batches().await {
+                        warn!("Failed to propose queued batches: {}", e);
+                    }
+                    if self.sync_pending {
+                        if let Err(e) = self.initiate_sync().await {
+                            warn!("Failed to request sync: {}", e);
+                        }
+                    }
                 }
+            }
+        }
+    }
+
+    /// Stop accepting new batches and give in-flight phases time to decide.
+    fn begin_shutdown(&mut self) {
+        if self.drain_deadline.is_some() {
+            return;
+        }
 
-                // Prevent busy waiting
-                _ = tokio::time::sleep(Duration::from_millis(1)) => {}
+        info!(
+            "Shutting down consensus engine, draining {} in-flight phases",
+            self.engine_state.in_flight_phases()
+        );
+        self.drain_deadline = Some(Instant::now() + self.config.shutdown_drain_timeout);
+        self.status_tx.send_replace(EngineStatus::Draining);
+    }
+
+    fn drain_finished(&self) -> bool {
+        match self.drain_deadline {
+            Some(deadline) => {
+                self.engine_state.in_flight_phases() == 0 || Instant::now() >= deadline
             }
+            None => false,
+        }
+    }
+
+    /// Fail outstanding requests, persist state and close the network.
+    async fn finish_shutdown(&mut self) -> Result<()> {
+        let in_flight = self.engine_state.in_flight_phases();
+        if in_flight > 0 {
+            warn!("Stopping with {} phases still in flight", in_flight);
+        }
+
+        for (_, response_tx) in self.pending_responses.drain() {
+            let _ = response_tx.send(Err(RabiaError::ShuttingDown));
+        }
+
+        // Flush queued commits so their clients are answered
+        if let Some(worker) = self.persist_worker.take() {
+            worker.close().await;
         }
+        if let Some(mut durable_rx) = self.durable_rx.take() {
+            while let Ok(ack) = durable_rx.try_recv() {
+                if let Err(e) = ack.result {
+                    warn!(
+                        "Failed to log decisions through phase {}: {}",
+                        ack.through, e
+                    );
+                }
+            }
+        }
+
+        self.save_state().await?;
+
+        if let Err(e) = self.network.lock().await.disconnect().await {
+            warn!("Failed to disconnect network during shutdown: {}", e);
+        }
+
+        self.engine_state.set_active(false);
+        self.status_tx.send_replace(EngineStatus::Stopped);
+        info!(
+            "Consensus engine for node {} stopped",
+            self.name(self.node_id)
+        );
+        Ok(())
     }
 
     async fn initialize(&mut self) -> Result<()> {
+        // Missing or malformed keys must stop startup rather than run unsigned
+        if let Some(signing) = &self.config.signing {
+            let signer = Arc::new(signing.load()?);
+            if !self.validator.set_signer(signer.clone()) {
+                warn!("Validator already has a signer; keeping it for inbound messages");
+            }
+            self.signer = Some(signer);
+        }
+
+        // A durable counter keeps restarts ahead of earlier incarnations even
+        // if the clock went backwards; nothing has been sent yet
+        if let Some(incarnation) = self.persistence.next_incarnation().await? {
+            debug!("Starting incarnation {}", incarnation);
+            self.sequencer = SequenceGenerator::with_incarnation(incarnation);
+        }
+
         // Try to restore state from persistence
-        if let Some(persisted_data) = self.persistence.load_state().await? {
-            info!("Restoring state from persistence");
+        if let Some((persisted_data, records)) = self.persistence.load_state_chain().await? {
+            info!(
+                "Restoring state from persistence with {} delta records",
+                records.len()
+            );
 
             let persisted_state =
-                rabia_core::persistence::EngineState::from_bytes(&persisted_data)?;
+                rabia_core::persistence::EngineState::from_chain(&persisted_data, &records)?;
 
             // Restore engine state
             self.engine_state.current_phase.store(
@@ -252,11 +761,37 @@ where
                 persisted_state.last_committed_phase.value(),
                 std::sync::atomic::Ordering::Release,
             );
+            self.engine_state
+                .mark_durable(persisted_state.last_committed_phase);
 
             // Restore state machine if snapshot exists
             if let Some(snapshot) = persisted_state.snapshot {
-                let mut sm = self.state_machine.lock().await;
-                sm.restore_snapshot(&snapshot).await?;
+                let saved_chain = SavedChain {
+                    deltas: persisted_state.deltas.len(),
+                    records: records.len(),
+                };
+                self.restore_state_machine(snapshot, persisted_state.deltas)
+                    .await?;
+                self.saved_chain = Some(saved_chain);
+            }
+            self.sessions = persisted_state.sessions;
+            self.committed_batches = persisted_state.committed_batches;
+        }
+        self.replay_decision_log().await?;
+        self.restored_through = self.engine_state.last_committed_phase();
+
+        if self.persistence.has_decision_log() {
+            let (worker, durable_rx) =
+                PersistenceWorker::spawn(self.persistence.clone(), self.config.group_commit_max);
+            self.persist_worker = Some(worker);
+            self.durable_rx = Some(durable_rx);
+        }
+
+        // Give the other members a full suspect timeout to be heard from
+        // before treating them as failed
+        for node_id in self.cluster_config.all_nodes.clone() {
+            if node_id != self.node_id {
+                self.failure_detector.record_activity(node_id);
             }
         }
 
@@ -264,16 +799,123 @@ where
         let connected_nodes = self.network.lock().await.get_connected_nodes().await?;
         self.engine_state.update_active_nodes(connected_nodes);
 
+        if self.sync_pending {
+            self.initiate_sync().await?;
+        }
+
         info!("Engine initialized successfully");
         Ok(())
     }
 
+    /// Restore the state machine from a full snapshot and the deltas taken on
+    /// top of it, and chain later deltas to them.
+    async fn restore_state_machine(
+        &mut self,
+        snapshot: Snapshot,
+        deltas: Vec<DeltaSnapshot>,
+    ) -> Result<()> {
+        let mut sm = self.state_machine.lock().await;
+        sm.restore_snapshot(&snapshot).await?;
+        if !deltas.is_empty() {
+            let incremental = sm.incremental().ok_or_else(|| {
+                RabiaError::state_machine(
+                    "State has snapshot deltas but the state machine cannot apply them",
+                )
+            })?;
+            for delta in &deltas {
+                incremental.apply_delta(delta).await?;
+            }
+        }
+        drop(sm);
+
+        self.snapshot_base = Some(snapshot);
+        self.snapshot_deltas = deltas;
+        Ok(())
+    }
+
+    /// Apply deltas a sync shipped on top of the current state, and chain
+    /// them after the local deltas.
+    async fn apply_sync_deltas(&mut self, deltas: Vec<DeltaSnapshot>) -> Result<()> {
+        let mut sm = self.state_machine.lock().await;
+        let incremental = sm.incremental().ok_or_else(|| {
+            RabiaError::state_machine(
+                "Sync shipped snapshot deltas but the state machine cannot apply them",
+            )
+        })?;
+        // Changes not in a delta yet go first, so the chain stays complete
+        if self.snapshot_base.is_some() && incremental.dirty_count() > 0 {
+            self.snapshot_deltas.push(incremental.create_delta().await?);
+        }
+        for delta in &deltas {
+            incremental.apply_delta(delta).await?;
+        }
+        drop(sm);
+
+        if self.snapshot_base.is_some() {
+            self.snapshot_deltas.extend(deltas);
+        }
+        Ok(())
+    }
+
+    /// Re-apply commits logged after the restored snapshot.
+    ///
+    /// If the log was truncated past the restored state, e.g. because the
+    /// newest generation was unusable and an older one was loaded, nothing is
+    /// replayed and the missing commits are fetched by a sync instead. With
+    /// no other member to sync from, startup fails.
+    async fn replay_decision_log(&mut self) -> Result<()> {
+        let restored = self.engine_state.last_committed_phase();
+        let truncated = self.persistence.truncated_through().await?;
+        if truncated > restored {
+            let details = format!(
+                "restored state covers phase {} but the decision log was truncated through phase {}",
+                restored, truncated
+            );
+            if !self
+                .cluster_config
+                .all_nodes
+                .iter()
+                .any(|node_id| *node_id != self.node_id)
+            {
+                return Err(RabiaError::StateCorruption { details });
+            }
+            error!(
+                "Not replaying the decision log, syncing instead: {}",
+                details
+            );
+            // Bounded reads and client batches see this replica as behind
+            self.engine_state.observe_cluster_committed(truncated);
+            self.sync_pending = true;
+            return Ok(());
+        }
+
+        let decisions = self.persistence.load_decisions(restored).await?;
+        if decisions.is_empty() {
+            return Ok(());
+        }
+
+        info!("Replaying {} logged decisions", decisions.len());
+        for (phase_id, batch) in decisions {
+            self.apply_batch(phase_id, &batch).await?;
+            self.engine_state.mark_durable(phase_id);
+            self.engine_state
+                .current_phase
+                .fetch_max(phase_id.value(), std::sync::atomic::Ordering::AcqRel);
+            self.engine_state.commit_phase(phase_id)?;
+
+            // Logged entries stay until the next snapshot covers them
+            self.logged_phases += 1;
+            self.logged_bytes += batch.commands.iter().map(|c| c.data.len()).sum::<usize>();
+        }
+        Ok(())
+    }
+
     async fn handle_command(&mut self, command: EngineCommand) -> Result<()> {
         match command {
             EngineCommand::ProcessBatch(request) => self.process_batch_request(request).await,
             EngineCommand::Shutdown => {
-                info!("Shutting down consensus engine");
-                Err(RabiaError::internal("Shutdown requested"))
+                self.begin_shutdown();
+                Ok(())
             }
             EngineCommand::ForcePhaseAdvance => self.advance_to_next_phase().await,
             EngineCommand::TriggerSync => self.initiate_sync().await,
@@ -286,6 +928,27 @@ where
     }
 
     async fn process_batch_request(&mut self, request: CommandRequest) -> Result<()> {
+        if self.drain_deadline.is_some() {
+            let _ = request.response_tx.send(Err(RabiaError::ShuttingDown));
+            return Ok(());
+        }
+
+        if let Err(e) = self.validator.validate_batch(&request.batch) {
+            let _ = request.response_tx.send(Err(e));
+            return Ok(());
+        }
+
+        // Results from a state machine missing commits would be wrong
+        if self.sync_pending {
+            let lag = self.engine_state.replication_lag();
+            let _ = request.response_tx.send(Err(RabiaError::Stale {
+                local_committed: lag.local_committed,
+                cluster_committed: lag.cluster_committed,
+                lag_ms: lag.millis,
+            }));
+            return Ok(());
+        }
+
         if !self.engine_state.has_quorum() {
             let _ = request
                 .response_tx
@@ -301,11 +964,86 @@ where
             .engine_state
             .add_pending_batch(request.batch.clone(), self.node_id);
 
+        // The response is sent once the batch is applied
+        self.pending_responses.insert(batch_id, request.response_tx);
+
+        // In leader-assisted mode followers hand the batch to the leader,
+        // which serializes proposals and avoids conflicting concurrent phases
+        if let Some(leader) = self.forwarding_target() {
+            match self.forward_to_leader(leader, request.batch.clone()).await {
+                Ok(()) => {
+                    self.forwarded_batches.insert(batch_id);
+                    self.engine_state.record_forward();
+                    return Ok(());
+                }
+                Err(e) => warn!(
+                    "Failed to forward batch {} to leader {}, proposing locally: {}",
+                    batch_id,
+                    self.name(leader),
+                    e
+                ),
+            }
+        }
+
         // Start consensus for this batch
-        self.propose_batch(batch_id, request.batch).await?;
+        self.propose_batch(batch_id, request.batch).await
+    }
+
+    /// Leader to forward client batches to, or `None` when this node should
+    /// propose itself: leader-assisted mode is off, this node is the leader, or
+    /// the leader is suspected by the failure detector.
+    fn forwarding_target(&self) -> Option<NodeId> {
+        if !self.config.leader_assisted {
+            return None;
+        }
+
+        let leader = self.leader_selector.get_leader()?;
+        if leader == self.node_id || self.failure_detector.is_suspected(leader) {
+            None
+        } else {
+            Some(leader)
+        }
+    }
+
+    async fn forward_to_leader(&mut self, leader: NodeId, batch: CommandBatch) -> Result<()> {
+        debug!(
+            "Forwarding batch {} to leader {}",
+            batch.id,
+            self.name(leader)
+        );
+
+        let new_batch = NewBatchMessage {
+            batch,
+            originator: self.node_id,
+        };
+        let message =
+            ProtocolMessage::new(self.node_id, Some(leader), MessageType::NewBatch(new_batch));
+        self.send_message(leader, message).await
+    }
+
+    /// Fall back to leaderless proposals for forwarded batches once the
+    /// leader is suspected and they still have not committed.
+    async fn reclaim_forwarded_batches(&mut self) -> Result<()> {
+        let pending_batches = &self.engine_state.pending_batches;
+        self.forwarded_batches
+            .retain(|batch_id| pending_batches.contains_key(batch_id));
+
+        if self.forwarded_batches.is_empty() || self.forwarding_target().is_some() {
+            return Ok(());
+        }
+
+        let reclaimed: Vec<BatchId> = self.forwarded_batches.drain().collect();
+        warn!(
+            "Leader suspected, proposing {} forwarded batches locally",
+            reclaimed.len()
+        );
+
+        for batch_id in reclaimed {
+            if let Some(pending) = self.engine_state.get_pending_batch(&batch_id) {
+                self.propose_batch(batch_id, pending.batch).await?;
+            }
+        }
 
-        // Note: The response will be sent when consensus completes
-        // For now, we'll store the response channel in the pending batch
         Ok(())
     }
 
@@ -313,6 +1051,7 @@ where
         let phase_id = self.engine_state.advance_phase();
 
         debug!("Proposing batch {} in phase {}", batch_id, phase_id);
+        self.engine_state.record_proposal();
 
         // In Rabia protocol, the proposing node suggests committing the batch
         // The StateValue here represents the initial preference (commit = V1)
@@ -320,12 +1059,15 @@ where
         // where randomization occurs based on agreement between nodes
         let proposed_value = StateValue::V1; // This node prefers to commit the batch
 
-        // Update phase with proposal
+        // Update phase with proposal, which is also our round 1 vote
+        let node_id = self.node_id;
         self.engine_state.update_phase(phase_id, |phase| {
             phase.batch_id = Some(batch_id);
             phase.proposed_value = Some(proposed_value);
             phase.batch = Some(batch.clone());
+            phase.add_round1_vote(&self.member_index, node_id, proposed_value);
         })?;
+        self.proposed_batches.insert(batch_id);
 
         // Broadcast proposal containing the actual batch data
         // The key fix is that we're proposing ACTUAL batch data, not random StateValues
@@ -337,30 +1079,43 @@ where
         };
 
         let message = ProtocolMessage::propose(self.node_id, proposal);
-        self.network
-            .lock()
-            .await
-            .broadcast(message, Some(self.node_id))
-            .await?;
+        self.broadcast_message(message).await?;
+
+        // Our own vote is enough when we are the only member
+        self.check_round1(phase_id).await
+    }
+
+    /// Propose the batches queued since the last tick that are still pending.
+    async fn propose_queued_batches(&mut self) -> Result<()> {
+        if self.queued_batches.is_empty() || !self.engine_state.has_quorum() {
+            return Ok(());
+        }
+        // Nodes that lost a contested phase requeue on the same tick, and
+        // proposing again together would only contest the next phase
+        if self.contended && self.rng.gen_bool(0.5) {
+            return Ok(());
+        }
+        self.contended = false;
+
+        let queued: Vec<BatchId> = self.queued_batches.drain(..).collect();
+        for batch_id in queued {
+            if let Some(pending) = self.engine_state.get_pending_batch(&batch_id) {
+                self.propose_batch(batch_id, pending.batch).await?;
+            }
+        }
 
         Ok(())
     }
 
     async fn handle_message(&mut self, from: NodeId, message: ProtocolMessage) -> Result<()> {
-        // Validate incoming message
-        if let Err(e) = message.validate() {
-            warn!("Received invalid message from {}: {}", from, e);
+        // Validate incoming message, including that it comes from who it claims
+        if let Err(e) = self.validator.validate_message(from, &message) {
+            warn!("Received invalid message from {}: {}", self.name(from), e);
             return Err(e);
         }
 
-        // Validate message source
-        if message.from != from {
-            warn!(
-                "Message claims to be from {} but received from {}",
-                message.from, from
-            );
-            return Err(RabiaError::network("Message source mismatch"));
-        }
+        // Any valid message is evidence that the sender is alive
+        self.failure_detector.record_activity(from);
 
         match message.message_type {
             MessageType::Propose(propose) => self.handle_propose(from, propose).await,
@@ -385,7 +1140,8 @@ where
 
         debug!(
             "Received proposal from {} for phase {}",
-            from, propose.phase_id
+            self.name(from),
+            propose.phase_id
         );
 
         // Store the batch if we don't have it
@@ -396,18 +1152,24 @@ where
         // Determine our vote for round 1
         let vote = self.determine_round1_vote(&propose).await;
 
-        // Update phase data
+        // The phase keeps the first batch proposed in it, and our own vote
+        // only counts toward that batch
+        let node_id = self.node_id;
         self.engine_state.update_phase(propose.phase_id, |phase| {
-            phase.batch_id = Some(propose.batch_id);
+            if *phase.batch_id.get_or_insert(propose.batch_id) != propose.batch_id {
+                phase.contested = true;
+                return;
+            }
             if phase.proposed_value.is_none() {
                 phase.proposed_value = Some(propose.value);
             }
             if phase.batch.is_none() {
                 phase.batch = propose.batch.clone();
             }
+            phase.add_round1_vote(&self.member_index, node_id, vote);
         })?;
 
-        // Send round 1 vote
+        // Every node tallies round 1, so the vote goes to all of them
         let vote_msg = VoteRound1Message {
             phase_id: propose.phase_id,
             batch_id: propose.batch_id,
@@ -415,10 +1177,11 @@ where
             voter_id: self.node_id,
         };
 
-        let message = ProtocolMessage::vote_round1(self.node_id, from, vote_msg);
-        self.network.lock().await.send_to(from, message).await?;
+        let message = ProtocolMessage::new(self.node_id, None, MessageType::VoteRound1(vote_msg));
+        self.broadcast_message(message).await?;
 
-        Ok(())
+        // Votes from other nodes may have arrived before the proposal
+        self.check_round1(propose.phase_id).await
     }
 
     async fn determine_round1_vote(&mut self, propose: &ProposeMessage) -> StateValue {
@@ -429,6 +1192,14 @@ where
         let phase = self.engine_state.get_phase(&propose.phase_id);
 
         match phase {
+            Some(existing_phase)
+                if existing_phase
+                    .batch_id
+                    .is_some_and(|batch_id| batch_id != propose.batch_id) =>
+            {
+                // Another batch came first in this phase - vote against this one
+                StateValue::V0
+            }
             Some(existing_phase) => {
                 // If we already have a proposal for this phase
                 if let Some(existing_value) = &existing_phase.proposed_value {
@@ -483,36 +1254,136 @@ where
     async fn handle_vote_round1(&mut self, from: NodeId, vote: VoteRound1Message) -> Result<()> {
         debug!(
             "Received round 1 vote from {} for phase {}",
-            from, vote.phase_id
+            self.name(from),
+            vote.phase_id
         );
 
+        self.ensure_member(from)?;
+
         // Update phase with vote
+        let mut record = None;
         self.engine_state.update_phase(vote.phase_id, |phase| {
-            phase.add_round1_vote(from, vote.vote);
+            if *phase.batch_id.get_or_insert(vote.batch_id) == vote.batch_id {
+                record = Some(phase.add_round1_vote(&self.member_index, from, vote.vote));
+            } else {
+                phase.contested = true;
+            }
         })?;
+        let Some(record) = record else {
+            self.ignore_other_batch(from, vote.phase_id, 1, vote.batch_id);
+            return Ok(());
+        };
+        if !self.accept_vote(from, vote.phase_id, 1, vote.vote, record) {
+            return Ok(());
+        }
 
-        // Check if we have enough votes to proceed to round 2
-        if let Some(phase) = self.engine_state.get_phase(&vote.phase_id) {
-            if let Some(majority_vote) = phase.has_round1_majority(self.engine_state.quorum_size) {
-                // Clear majority - proceed to round 2 with the majority result
-                self.proceed_to_round2(vote.phase_id, majority_vote, phase.round1_votes)
-                    .await?;
-            } else if phase.round1_votes.len() >= self.engine_state.quorum_size {
-                // No clear majority but we have enough votes - proceed with VQuestion
-                // This handles the case where votes are split and no value gets majority
-                self.proceed_to_round2(vote.phase_id, StateValue::VQuestion, phase.round1_votes)
-                    .await?;
-            }
+        self.check_round1(vote.phase_id).await
+    }
+
+    /// Vote in round 2 once a quorum of round 1 votes is in. Each node votes
+    /// in round 2 at most once per phase.
+    async fn check_round1(&mut self, phase_id: PhaseId) -> Result<()> {
+        let Some(phase) = self.engine_state.get_phase(&phase_id) else {
+            return Ok(());
+        };
+        if phase.decision.is_some()
+            || phase
+                .round2_vote(&self.member_index, &self.node_id)
+                .is_some()
+        {
+            return Ok(());
+        }
+
+        if let Some(majority_vote) = phase.has_round1_majority(self.engine_state.quorum_size) {
+            // Clear majority - proceed to round 2 with the majority result
+            self.proceed_to_round2(phase_id, majority_vote, &phase)
+                .await?;
+        } else if phase.round1_tally.total() >= self.engine_state.quorum_size {
+            // No clear majority but we have enough votes - proceed with VQuestion
+            // This handles the case where votes are split and no value gets majority
+            self.proceed_to_round2(phase_id, StateValue::VQuestion, &phase)
+                .await?;
         }
 
         Ok(())
     }
 
+    /// Votes for another batch than the one this node holds for the phase are
+    /// not counted, so replicas that saw concurrent proposals never commit
+    /// different batches.
+    fn ignore_other_batch(&self, from: NodeId, phase_id: PhaseId, round: u8, batch_id: BatchId) {
+        debug!(
+            "Ignoring round {} vote from {} for batch {}: phase {} holds another batch",
+            round,
+            self.name(from),
+            batch_id,
+            phase_id
+        );
+    }
+
+    /// Reject votes from nodes outside the current cluster configuration.
+    fn ensure_member(&self, from: NodeId) -> Result<()> {
+        if self.cluster_config.all_nodes.contains(&from) {
+            return Ok(());
+        }
+
+        warn!(
+            "Ignoring vote from {} which is not a cluster member",
+            self.name(from)
+        );
+        self.validator.count_rejection(&RejectionReason::NotMember);
+        Err(RabiaError::NotMember { node_id: from })
+    }
+
+    /// Decide whether a recorded vote should trigger a tally. Duplicates are
+    /// ignored; conflicting votes are reported as equivocation.
+    fn accept_vote(
+        &self,
+        from: NodeId,
+        phase_id: PhaseId,
+        round: u8,
+        vote: StateValue,
+        record: VoteRecord,
+    ) -> bool {
+        match record {
+            VoteRecord::Recorded => true,
+            VoteRecord::Duplicate => false,
+            VoteRecord::Unindexed => {
+                warn!(
+                    "Dropping round {} vote from {} in phase {}: no index in the current membership",
+                    round,
+                    self.name(from),
+                    phase_id
+                );
+                false
+            }
+            VoteRecord::Conflict { previous } => {
+                warn!(
+                    "Node {} equivocated in round {} of phase {}: voted {:?} after {:?}",
+                    self.name(from),
+                    round,
+                    phase_id,
+                    vote,
+                    previous
+                );
+                self.engine_state.record_equivocation();
+                let _ = self.events_tx.send(EngineEvent::Equivocation {
+                    node_id: from,
+                    phase_id,
+                    round,
+                    first: previous,
+                    second: vote,
+                });
+                false
+            }
+        }
+    }
+
     async fn proceed_to_round2(
         &mut self,
         phase_id: PhaseId,
         round1_result: StateValue,
-        round1_votes: std::collections::HashMap<NodeId, StateValue>,
+        phase: &PhaseData,
     ) -> Result<()> {
         debug!(
             "Proceeding to round 2 for phase {} with result {:?}",
@@ -529,16 +1400,22 @@ where
                 // Round 1 decided V1 - must vote V1 for safety
                 StateValue::V1
             }
+            StateValue::VQuestion if phase.contested => {
+                // Round 1 was inconclusive with other batches in play - the
+                // other nodes may be voting to commit those, so a vote to
+                // commit ours could leave round 2 without a quorum
+                StateValue::V0
+            }
             StateValue::VQuestion => {
                 // Round 1 was inconclusive - Rabia's randomized choice
                 // Bias towards V1 for liveness while maintaining safety
-                self.determine_round2_vote_for_question(&round1_votes)
+                self.determine_round2_vote_for_question(&phase.round1_tally)
             }
         };
 
         // Update our phase with round 2 vote
         self.engine_state.update_phase(phase_id, |phase| {
-            phase.add_round2_vote(self.node_id, round2_vote);
+            phase.add_round2_vote(&self.member_index, self.node_id, round2_vote);
         })?;
 
         // Broadcast round 2 vote
@@ -551,35 +1428,23 @@ where
                 .unwrap_or_default(),
             vote: round2_vote,
             voter_id: self.node_id,
-            round1_votes,
+            round1_votes: phase.round1_votes.clone(),
         };
 
         let message = ProtocolMessage::vote_round2(self.node_id, self.node_id, vote_msg);
-        self.network
-            .lock()
-            .await
-            .broadcast(message, Some(self.node_id))
-            .await?;
+        self.broadcast_message(message).await?;
 
-        Ok(())
+        // Our own vote may complete a round 2 quorum
+        self.check_round2(phase_id).await
     }
 
-    fn determine_round2_vote_for_question(
-        &mut self,
-        round1_votes: &std::collections::HashMap<NodeId, StateValue>,
-    ) -> StateValue {
+    fn determine_round2_vote_for_question(&mut self, round1_tally: &VoteTally) -> StateValue {
         // When round 1 is inconclusive, use Rabia's strategy:
         // 1. Count the non-? votes to see if there's a preference
         // 2. If tied or no clear preference, randomize with bias towards V1
 
-        let v0_count = round1_votes
-            .values()
-            .filter(|&v| *v == StateValue::V0)
-            .count();
-        let v1_count = round1_votes
-            .values()
-            .filter(|&v| *v == StateValue::V1)
-            .count();
+        let v0_count = round1_tally.count(StateValue::V0);
+        let v1_count = round1_tally.count(StateValue::V1);
 
         match v1_count.cmp(&v0_count) {
             std::cmp::Ordering::Greater => {
@@ -613,19 +1478,64 @@ where
     async fn handle_vote_round2(&mut self, from: NodeId, vote: VoteRound2Message) -> Result<()> {
         debug!(
             "Received round 2 vote from {} for phase {}",
-            from, vote.phase_id
+            self.name(from),
+            vote.phase_id
         );
 
+        self.ensure_member(from)?;
+
+        // The justifying votes name members by index, which only means the
+        // same thing under the same configuration
+        if !vote.round1_votes.is_indexed_by(&self.member_index) {
+            warn!(
+                "Ignoring round 2 vote from {} indexed by another configuration ({:#010x})",
+                self.name(from),
+                vote.round1_votes.config_hash()
+            );
+            self.validator
+                .count_rejection(&RejectionReason::ConfigMismatch);
+            return Err(RabiaError::ValidationFailed {
+                reason: RejectionReason::ConfigMismatch,
+                detail: format!("Round 1 votes from {} use another configuration", from),
+            });
+        }
+
         // Update phase with vote
+        // Not committing is the same outcome whichever batch it was voted
+        // on, so only votes to commit are tied to this node's batch
+        let mut record = None;
         self.engine_state.update_phase(vote.phase_id, |phase| {
-            phase.add_round2_vote(from, vote.vote);
+            if vote.vote != StateValue::V1
+                || *phase.batch_id.get_or_insert(vote.batch_id) == vote.batch_id
+            {
+                record = Some(phase.add_round2_vote(&self.member_index, from, vote.vote));
+            } else {
+                phase.contested = true;
+            }
         })?;
+        let Some(record) = record else {
+            self.ignore_other_batch(from, vote.phase_id, 2, vote.batch_id);
+            return Ok(());
+        };
+        if !self.accept_vote(from, vote.phase_id, 2, vote.vote, record) {
+            return Ok(());
+        }
 
-        // Check if we have a decision
-        if let Some(phase) = self.engine_state.get_phase(&vote.phase_id) {
-            if let Some(decision) = phase.has_round2_majority(self.engine_state.quorum_size) {
-                self.make_decision(vote.phase_id, decision).await?;
-            }
+        self.check_round2(vote.phase_id).await
+    }
+
+    /// Decide a phase once a quorum agrees in round 2, unless it is already
+    /// decided here or by another node's decision.
+    async fn check_round2(&mut self, phase_id: PhaseId) -> Result<()> {
+        let Some(phase) = self.engine_state.get_phase(&phase_id) else {
+            return Ok(());
+        };
+        if phase.decision.is_some() {
+            return Ok(());
+        }
+
+        if let Some(decision) = phase.has_round2_majority(self.engine_state.quorum_size) {
+            self.make_decision(phase_id, decision).await?;
         }
 
         Ok(())
@@ -639,49 +1549,60 @@ where
             phase.set_decision(decision);
         })?;
 
-        // Apply the batch if decision is V1 (commit)
-        if decision == StateValue::V1 {
-            if let Some(phase) = self.engine_state.get_phase(&phase_id) {
-                if let Some(batch) = &phase.batch {
-                    self.apply_batch(batch).await?;
-                    if let Err(e) = self.engine_state.commit_phase(phase_id) {
-                        error!("Failed to commit phase {}: {}", phase_id, e);
-                        return Err(e);
-                    }
-
-                    // Save state after successful commit
-                    if let Err(e) = self.save_state().await {
-                        warn!("Failed to save state after commit: {}", e);
-                    }
-                }
-            }
-        }
-
-        // Broadcast decision
         let phase = self.engine_state.get_phase(&phase_id).ok_or_else(|| {
             RabiaError::internal(format!(
                 "Phase {} not found for decision broadcast",
                 phase_id
             ))
         })?;
+
+        // Apply the batch if decision is V1 (commit)
+        let batch_id = phase.batch_id.unwrap_or_default();
+        let batch = self.decided_batch(&phase, batch_id, None);
+        self.apply_decision(&phase, decision, batch_id, batch.as_ref())
+            .await?;
+
+        // Broadcast decision
         let decision_msg = DecisionMessage {
             phase_id,
-            batch_id: phase.batch_id.unwrap_or_default(),
+            batch_id,
             decision,
-            batch: phase.batch,
+            batch,
+            config_hash: self.member_index.config_hash(),
         };
 
         let message = ProtocolMessage::decision(self.node_id, decision_msg);
-        self.network
-            .lock()
-            .await
-            .broadcast(message, Some(self.node_id))
-            .await?;
+        self.broadcast_message(message).await?;
 
         Ok(())
     }
 
-    async fn apply_batch(&mut self, batch: &CommandBatch) -> Result<()> {
+    /// Apply a committed batch, returning the response for a waiting client.
+    ///
+    /// The response is only sent once the commit is logged, see `log_commit`.
+    async fn apply_batch(
+        &mut self,
+        phase_id: PhaseId,
+        batch: &CommandBatch,
+    ) -> Result<Option<Reply>> {
+        // A batch proposed twice, e.g. by the leader and by a follower that
+        // reclaimed it, is applied only in the first phase it commits in
+        if let Some(first) = self.committed_batches.get(&batch.id) {
+            debug!(
+                "Batch {} already committed in phase {}, not applying it again in phase {}",
+                batch.id, first, phase_id
+            );
+            self.engine_state.remove_pending_batch(&batch.id);
+            let reply = self.pending_responses.remove(&batch.id).map(|response_tx| {
+                let detail = format!(
+                    "batch {} was applied in phase {}; its results are not kept",
+                    batch.id, first
+                );
+                (response_tx, Err(RabiaError::OutcomeUnknown { detail }))
+            });
+            return Ok(reply);
+        }
+
         debug!(
             "Applying batch {} with {} commands",
             batch.id,
@@ -689,59 +1610,207 @@ where
         );
 
         // Apply commands without holding the lock for too long
-        let results = {
+        let results = if batch.commands.iter().all(|c| c.session.is_none()) {
             let mut sm = self.state_machine.lock().await;
-            sm.apply_commands(&batch.commands).await?
+            let responses = sm.apply_commands(&batch.commands).await?;
+            responses.into_iter().map(Ok).collect()
+        } else {
+            self.apply_session_commands(phase_id, &batch.commands)
+                .await?
         }; // Lock is released here
 
         // Remove from pending batches after successful application
         self.engine_state.remove_pending_batch(&batch.id);
+        self.committed_batches
+            .record(batch.id, phase_id, &self.config.session_config);
 
         info!(
             "Successfully applied batch {} with {} results",
             batch.id,
             results.len()
         );
-        Ok(())
+
+        let reply = self
+            .pending_responses
+            .remove(&batch.id)
+            .map(|response_tx| (response_tx, Ok(results)));
+
+        Ok(reply)
     }
 
-    async fn handle_decision(&mut self, _from: NodeId, decision: DecisionMessage) -> Result<()> {
+    /// Apply commands that carry client sessions, answering retries from the
+    /// session table instead of re-applying them.
+    ///
+    /// A command older than its client's response window gets an error of its
+    /// own, since it may or may not have been applied; the other commands are
+    /// unaffected.
+    async fn apply_session_commands(
+        &mut self,
+        phase_id: PhaseId,
+        commands: &[Command],
+    ) -> Result<CommandResults> {
+        let state_machine = self.state_machine.clone();
+        let mut sm = state_machine.lock().await;
+
+        let mut results = Vec::with_capacity(commands.len());
+
+        for command in commands {
+            match self.sessions.check(command, &self.config.session_config) {
+                SessionCheck::Apply => {
+                    let response = sm.apply_command(command).await?;
+                    if let Some(session) = command.session {
+                        self.sessions.record(
+                            session,
+                            phase_id,
+                            response.clone(),
+                            &self.config.session_config,
+                        );
+                    }
+                    results.push(Ok(response));
+                }
+                SessionCheck::Duplicate(response) => {
+                    debug!(
+                        "Command {} is a retry, returning cached response",
+                        command.id
+                    );
+                    results.push(Ok(response));
+                }
+                SessionCheck::Expired => {
+                    warn!(
+                        "Command {} is older than its session's response window",
+                        command.id
+                    );
+                    results.push(Err(RabiaError::OutcomeUnknown {
+                        detail: format!(
+                            "command {} is older than its session's response window; it was \
+                             not applied now and may or may not have been applied before",
+                            command.id
+                        ),
+                    }));
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn handle_decision(&mut self, from: NodeId, decision: DecisionMessage) -> Result<()> {
         debug!(
             "Received decision for phase {}: {:?}",
             decision.phase_id, decision.decision
         );
 
-        // Update our phase data with the decision
+        // Applying a batch decided under other shared settings, such as the
+        // session limits, would make this replica diverge
+        if decision.config_hash != self.member_index.config_hash() {
+            warn!(
+                "Ignoring decision from {} made under another configuration ({:#010x})",
+                self.name(from),
+                decision.config_hash
+            );
+            self.validator
+                .count_rejection(&RejectionReason::ConfigMismatch);
+            return Err(RabiaError::ValidationFailed {
+                reason: RejectionReason::ConfigMismatch,
+                detail: format!("Decision from {} uses another configuration", from),
+            });
+        }
+
+        // Each phase is decided once, either here or by the first decision
+        // received for it
+        let phase = self
+            .engine_state
+            .get_phase(&decision.phase_id)
+            .unwrap_or_else(|| PhaseData::new(decision.phase_id));
+        if phase.decision.is_some() {
+            return Ok(());
+        }
+
+        // The phase now holds the batch it decided on, which may not be the
+        // one this node saw proposed first
+        let batch = self.decided_batch(&phase, decision.batch_id, decision.batch.as_ref());
         self.engine_state.update_phase(decision.phase_id, |phase| {
             phase.set_decision(decision.decision);
-            if phase.batch.is_none() {
-                phase.batch = decision.batch.clone();
+            if phase.batch_id != Some(decision.batch_id) {
+                phase.batch_id = Some(decision.batch_id);
+                phase.batch = batch.clone();
             }
         })?;
 
-        // Apply the batch if we haven't already and decision is commit
-        if decision.decision == StateValue::V1 {
-            if let Some(batch) = &decision.batch {
-                // Check if we've already applied this batch
-                let last_committed = self.engine_state.last_committed_phase();
-                if decision.phase_id > last_committed {
-                    self.apply_batch(batch).await?;
-                    if let Err(e) = self.engine_state.commit_phase(decision.phase_id) {
-                        error!(
-                            "Failed to commit phase {} from decision: {}",
-                            decision.phase_id, e
-                        );
-                        return Err(e);
-                    }
+        self.apply_decision(&phase, decision.decision, decision.batch_id, batch.as_ref())
+            .await
+    }
 
-                    // Save state after successful commit
-                    if let Err(e) = self.save_state().await {
-                        warn!("Failed to save state after commit: {}", e);
-                    }
-                }
+    /// The batch named by a decision: the copy it carries, else this phase's
+    /// batch or a pending one with that id.
+    fn decided_batch(
+        &self,
+        phase: &PhaseData,
+        batch_id: BatchId,
+        carried: Option<&CommandBatch>,
+    ) -> Option<CommandBatch> {
+        carried
+            .filter(|batch| batch.id == batch_id)
+            .cloned()
+            .or_else(|| phase.batch.clone().filter(|batch| batch.id == batch_id))
+            .or_else(|| {
+                self.engine_state
+                    .get_pending_batch(&batch_id)
+                    .map(|pending| pending.batch)
+            })
+    }
+
+    /// Act on a phase's decision: apply and log the batch it committed, named
+    /// by `batch_id`, and propose a batch of ours again when its phase did
+    /// not commit it.
+    ///
+    /// `phase` is this node's view of the phase before the decision, so its
+    /// batch is the one this node proposed or saw proposed first.
+    async fn apply_decision(
+        &mut self,
+        phase: &PhaseData,
+        decision: StateValue,
+        batch_id: BatchId,
+        batch: Option<&CommandBatch>,
+    ) -> Result<()> {
+        let phase_id = phase.phase_id;
+        let lost = phase
+            .batch_id
+            .filter(|ours| decision != StateValue::V1 || *ours != batch_id);
+        if let Some(ours) = lost {
+            if self.proposed_batches.remove(&ours) {
+                debug!(
+                    "Phase {} did not commit batch {}, proposing it again",
+                    phase_id, ours
+                );
+                self.queued_batches.push_back(ours);
+                self.contended |= phase.contested;
             }
         }
+        if decision != StateValue::V1 {
+            return Ok(());
+        }
+
+        if phase_id <= self.restored_through {
+            debug!("Phase {} is already part of the restored state", phase_id);
+            return Ok(());
+        }
+        let Some(batch) = batch else {
+            warn!(
+                "Phase {} committed batch {}, which this node does not have",
+                phase_id, batch_id
+            );
+            return self.initiate_sync().await;
+        };
+
+        let reply = self.apply_batch(phase_id, batch).await?;
+        if let Err(e) = self.engine_state.commit_phase(phase_id) {
+            error!("Failed to commit phase {}: {}", phase_id, e);
+            return Err(e);
+        }
+        self.proposed_batches.remove(&batch.id);
 
+        self.log_commit(phase_id, batch, reply).await;
         Ok(())
     }
 
@@ -752,19 +1821,52 @@ where
     ) -> Result<()> {
         debug!(
             "Received sync request from {} (phase: {})",
-            from, request.requester_phase
+            self.name(from),
+            request.requester_phase
         );
 
         // Create sync response with our current state
         let current_phase = self.engine_state.current_phase();
         let state_version = self.engine_state.get_state_version();
 
-        // Create snapshot if we're ahead
-        let snapshot = if current_phase > request.requester_phase {
-            let sm = self.state_machine.lock().await;
-            Some(sm.create_snapshot().await?)
+        // Ship the last full snapshot and its deltas if we're ahead, topped
+        // up with a fresh delta, or else a new full snapshot. A requester
+        // whose state is a point of the delta chain gets only the deltas
+        // after it.
+        let (snapshot, deltas, sessions) = if current_phase > request.requester_phase {
+            let mut sm = self.state_machine.lock().await;
+            match (&self.snapshot_base, sm.incremental()) {
+                (Some(base), Some(incremental)) => {
+                    if incremental.dirty_count() > 0 {
+                        self.snapshot_deltas.push(incremental.create_delta().await?);
+                    }
+                    let requester_position =
+                        request.requester_snapshot_version.and_then(|version| {
+                            self.snapshot_deltas
+                                .iter()
+                                .position(|delta| delta.base_version == version)
+                        });
+                    match requester_position {
+                        Some(from) => (
+                            None,
+                            self.snapshot_deltas[from..].to_vec(),
+                            Some(self.sessions.clone()),
+                        ),
+                        None => (
+                            Some(base.clone()),
+                            self.snapshot_deltas.clone(),
+                            Some(self.sessions.clone()),
+                        ),
+                    }
+                }
+                _ => (
+                    Some(sm.create_snapshot().await?),
+                    Vec::new(),
+                    Some(self.sessions.clone()),
+                ),
+            }
         } else {
-            None
+            (None, Vec::new(), None)
         };
 
         let response = SyncResponseMessage {
@@ -773,10 +1875,13 @@ where
             state_snapshot: snapshot,
             pending_batches: Vec::new(), // Future enhancement: include pending batches for sync
             committed_phases: Vec::new(), // Future enhancement: include recent committed phases
+            committed_batches: sessions.is_some().then(|| self.committed_batches.clone()),
+            sessions,
+            state_deltas: deltas,
         };
 
         let message = ProtocolMessage::sync_response(self.node_id, from, response);
-        self.network.lock().await.send_to(from, message).await?;
+        self.send_message(from, message).await?;
 
         Ok(())
     }
@@ -788,7 +1893,8 @@ where
     ) -> Result<()> {
         debug!(
             "Received sync response from {} (phase: {})",
-            from, response.responder_phase
+            self.name(from),
+            response.responder_phase
         );
 
         // Store the response for sync resolution
@@ -830,10 +1936,36 @@ where
                     std::sync::atomic::Ordering::Release,
                 );
 
-                // Restore state machine if snapshot provided
-                if let Some(snapshot) = latest.state_snapshot {
-                    let mut sm = self.state_machine.lock().await;
-                    sm.restore_snapshot(&snapshot).await?;
+                // Restore state machine if a snapshot or deltas on top of
+                // our state are provided
+                let synced = latest.state_snapshot.is_some() || !latest.state_deltas.is_empty();
+                if synced {
+                    // A snapshot still being written would chain later deltas
+                    // to the replaced state
+                    self.wait_for_snapshot().await;
+                    match latest.state_snapshot {
+                        Some(snapshot) => {
+                            self.restore_state_machine(snapshot, latest.state_deltas)
+                                .await?;
+                            // The received base is not stored yet
+                            self.saved_chain = None;
+                        }
+                        None => self.apply_sync_deltas(latest.state_deltas).await?,
+                    }
+                    if let Some(sessions) = latest.sessions {
+                        self.sessions = sessions;
+                    }
+                    if let Some(committed_batches) = latest.committed_batches {
+                        self.committed_batches = committed_batches;
+                    }
+                    self.restored_through = self.restored_through.max(latest.responder_phase);
+                    self.engine_state.commit_phase(latest.responder_phase)?;
+
+                    // Persist the synced state right away, so a restart does
+                    // not fall back to the state it replaced, and drop the
+                    // log entries it covers
+                    self.save_state().await?;
+                    self.sync_pending = false;
                 }
             }
         }
@@ -844,22 +1976,48 @@ where
     }
 
     async fn handle_new_batch(&mut self, from: NodeId, new_batch: NewBatchMessage) -> Result<()> {
-        debug!("Received new batch from {}", from);
+        debug!("Received new batch from {}", self.name(from));
+
+        // A retransmitted or reclaimed batch must not be proposed twice
+        let batch_id = new_batch.batch.id;
+        if self.proposed_batches.contains(&batch_id)
+            || self.committed_batches.contains(&batch_id)
+            || self.queued_batches.contains(&batch_id)
+        {
+            debug!(
+                "Batch {} from {} is already proposed or committed",
+                batch_id,
+                self.name(from)
+            );
+            return Ok(());
+        }
 
         // Add to pending batches
         self.engine_state
-            .add_pending_batch(new_batch.batch, new_batch.originator);
+            .add_pending_batch(new_batch.batch.clone(), new_batch.originator);
+
+        // As leader in leader-assisted mode, propose batches forwarded by
+        // followers, holding them until quorum returns
+        if self.config.leader_assisted && self.is_leader() {
+            if self.engine_state.has_quorum() {
+                self.propose_batch(batch_id, new_batch.batch).await?;
+            } else {
+                debug!(
+                    "No quorum, queueing batch {} from {}",
+                    batch_id,
+                    self.name(from)
+                );
+                self.queued_batches.push_back(batch_id);
+            }
+        }
 
         Ok(())
     }
 
-    async fn handle_heartbeat(
-        &mut self,
-        _from: NodeId,
-        _heartbeat: HeartBeatMessage,
-    ) -> Result<()> {
-        // Update active nodes based on heartbeat
-        // This is a simplified implementation
+    async fn handle_heartbeat(&mut self, _from: NodeId, heartbeat: HeartBeatMessage) -> Result<()> {
+        // Track how far the cluster has committed so local reads can bound their staleness
+        self.engine_state
+            .observe_cluster_committed(heartbeat.last_committed_phase);
         Ok(())
     }
 
@@ -872,11 +2030,7 @@ where
 
         let message = ProtocolMessage::new(self.node_id, None, MessageType::HeartBeat(heartbeat));
 
-        self.network
-            .lock()
-            .await
-            .broadcast(message, Some(self.node_id))
-            .await?;
+        self.broadcast_message(message).await?;
         Ok(())
     }
 
@@ -892,6 +2046,16 @@ where
         let request = SyncRequestMessage {
             requester_phase: self.engine_state.current_phase(),
             requester_state_version: self.engine_state.get_state_version(),
+            // State still waiting for a sync is not a base deltas can go on
+            requester_snapshot_version: if self.sync_pending {
+                None
+            } else {
+                self.state_machine
+                    .lock()
+                    .await
+                    .incremental()
+                    .map(|incremental| incremental.version())
+            },
         };
 
         // Send sync request to all active nodes
@@ -899,19 +2063,53 @@ where
         for node_id in active_nodes {
             if node_id != self.node_id {
                 let message = ProtocolMessage::sync_request(self.node_id, node_id, request.clone());
-                self.network.lock().await.send_to(node_id, message).await?;
+                self.send_message(node_id, message).await?;
             }
         }
 
         Ok(())
     }
 
+    /// Stamp an outgoing message with the next sequence number and sign it
+    fn seal_message(&self, message: ProtocolMessage) -> Result<ProtocolMessage> {
+        let message = message.with_sequence(self.sequencer.next_sequence());
+        match &self.signer {
+            Some(signer) => signer.sign(message),
+            None => Ok(message),
+        }
+    }
+
+    /// Send a message to one node, stamped with this node's next sequence number
+    async fn send_message(&self, target: NodeId, message: ProtocolMessage) -> Result<()> {
+        let message = self.seal_message(message)?;
+        self.network.lock().await.send_to(target, message).await
+    }
+
+    /// Broadcast a message to all other nodes, stamped with the next sequence number
+    async fn broadcast_message(&self, message: ProtocolMessage) -> Result<()> {
+        let message = self.seal_message(message)?;
+        self.network
+            .lock()
+            .await
+            .broadcast(message, Some(self.node_id))
+            .await
+    }
+
     async fn cleanup_old_state(&mut self) {
         let removed_phases = self
             .engine_state
-            .cleanup_old_phases(self.config.max_phase_history);
+            .cleanup_old_phases(self.config.max_phase_history, self.restored_through);
         let removed_batches = self.engine_state.cleanup_old_pending_batches(300); // 5 minutes
 
+        // Messages for phases that no longer exist can only be stale
+        self.validator
+            .set_compaction_watermark(self.engine_state.compaction_watermark());
+
+        // Drop response channels of batches that were cleaned up without committing
+        let pending_batches = &self.engine_state.pending_batches;
+        self.pending_responses
+            .retain(|batch_id, _| pending_batches.contains_key(batch_id));
+
         if removed_phases > 0 || removed_batches > 0 {
             debug!(
                 "Cleaned up {} old phases and {} old batches",
@@ -919,32 +2117,6 @@ where
             );
         }
     }
-
-    async fn receive_messages(&self, buffer: &mut Vec<(NodeId, ProtocolMessage)>) -> Result<()> {
-        // Try to receive multiple messages in a batch for efficiency
-        let mut network = self.network.lock().await;
-
-        match timeout(Duration::from_millis(10), network.receive()).await {
-            Ok(Ok((from, message))) => {
-                buffer.push((from, message));
-
-                // Try to get more messages without blocking
-                for _ in 0..10 {
-                    // Limit to prevent starvation
-                    match timeout(Duration::from_millis(1), network.receive()).await {
-                        Ok(Ok((from, message))) => buffer.push((from, message)),
-                        _ => break,
-                    }
-                }
-            }
-            Ok(Err(e)) => return Err(e),
-            Err(_) => {
-                // Timeout - no messages available
-            }
-        }
-
-        Ok(())
-    }
 }
 
 #[async_trait::async_trait]
@@ -955,13 +2127,13 @@ where
     PL: PersistenceLayer + 'static,
 {
     async fn on_node_connected(&self, node_id: NodeId) {
-        info!("Node {} connected", node_id);
+        info!("Node {} connected", self.name(node_id));
         // Note: Leadership update would require mutable access
         // In a real implementation, this would trigger a cluster membership update
     }
 
     async fn on_node_disconnected(&self, node_id: NodeId) {
-        warn!("Node {} disconnected", node_id);
+        warn!("Node {} disconnected", self.name(node_id));
         // Note: Leadership update would require mutable access
         // In a real implementation, this would trigger a cluster membership update
     }
@@ -977,7 +2149,10 @@ where
         // In a real implementation, leadership would be updated here
         // For now, we log the cluster change
         let current_leader = self.leader_selector.get_leader();
-        info!("Current leader after partition: {:?}", current_leader);
+        info!(
+            "Current leader after partition: {}",
+            self.leader_name(current_leader)
+        );
     }
 
     async fn on_quorum_lost(&self) {
@@ -993,6 +2168,120 @@ where
         // Note: Leadership update would require mutable access to self
         // In a real implementation, leadership would be updated here
         let current_leader = self.leader_selector.get_leader();
-        info!("Current leader after quorum restore: {:?}", current_leader);
+        info!(
+            "Current leader after quorum restore: {}",
+            self.leader_name(current_leader)
+        );
+    }
+}
+
+/// State captured at a commit point for a snapshot written off the commit path.
+struct SnapshotJob<SM> {
+    current_phase: PhaseId,
+    last_committed_phase: PhaseId,
+    sessions: SessionTable,
+    committed_batches: CommittedBatches,
+    kind: SnapshotKind<SM>,
+}
+
+enum SnapshotKind<SM> {
+    /// Snapshot this copy of the state machine in full
+    Full(SM),
+    /// Save this full snapshot, taken inline from a state machine without a
+    /// snapshot source
+    Taken(Snapshot),
+    /// Save a full snapshot that is not stored yet, e.g. one received in a
+    /// sync, with the deltas taken on top of it
+    Chain {
+        base: Snapshot,
+        deltas: Vec<DeltaSnapshot>,
+    },
+    /// Append the deltas taken since the last save to the stored chain
+    Delta(Vec<DeltaSnapshot>),
+}
+
+/// How much of the snapshot chain the last save left in persistence.
+#[derive(Debug, Clone, Copy, Default)]
+struct SavedChain {
+    /// Deltas of `snapshot_deltas` already stored
+    deltas: usize,
+    /// Delta records appended on top of the stored state
+    records: usize,
+}
+
+impl<SM: StateMachine> SnapshotJob<SM> {
+    /// Save the state with the engine metadata, or append it as a delta
+    /// record, then drop the log entries it now covers. Returns the full
+    /// snapshot, if one was taken.
+    async fn write<PL: PersistenceLayer>(self, persistence: &PL) -> Result<Option<Snapshot>> {
+        let (snapshot, deltas, full) = match self.kind {
+            SnapshotKind::Full(state_machine) => (
+                Some(state_machine.create_snapshot().await?),
+                Vec::new(),
+                true,
+            ),
+            SnapshotKind::Taken(snapshot) => (Some(snapshot), Vec::new(), true),
+            SnapshotKind::Chain { base, deltas } => (Some(base), deltas, false),
+            SnapshotKind::Delta(deltas) => (None, deltas, false),
+        };
+        let append = snapshot.is_none();
+
+        let engine_state = rabia_core::persistence::EngineState::new(
+            self.current_phase,
+            self.last_committed_phase,
+            snapshot.clone(),
+        )
+        .with_sessions(self.sessions)
+        .with_committed_batches(self.committed_batches)
+        .with_deltas(deltas);
+
+        let state_bytes = engine_state.to_bytes()?;
+        if append {
+            persistence.append_state_delta(&state_bytes).await?;
+        } else {
+            persistence.save_state(&state_bytes).await?;
+        }
+        persistence
+            .truncate_decisions(self.last_committed_phase)
+            .await?;
+
+        debug!(
+            "Saved engine state: phase {}, committed {}, {} {}",
+            self.current_phase.value(),
+            self.last_committed_phase.value(),
+            engine_state.deltas.len(),
+            if append { "new deltas" } else { "deltas" }
+        );
+
+        Ok(snapshot.filter(|_| full))
+    }
+}
+
+async fn recv_control(control_rx: &mut Option<EngineCommandReceiver>) -> Option<EngineCommand> {
+    match control_rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_durable(
+    durable_rx: &mut Option<mpsc::UnboundedReceiver<DurableAck>>,
+) -> Option<DurableAck> {
+    match durable_rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn receive_message<NT: NetworkTransport>(
+    network: &tokio::sync::Mutex<NT>,
+) -> Result<(NodeId, ProtocolMessage)> {
+    network.lock().await.receive().await
+}
+
+async fn drain_timer(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
     }
 }
@@ -0,0 +1,25 @@
+//! Notable engine events for monitoring.
+//!
+//! Events are published on a broadcast channel; subscribe through
+//! [`RabiaEngine::subscribe_events`](crate::RabiaEngine::subscribe_events) or
+//! [`EngineHandle::subscribe_events`](crate::EngineHandle::subscribe_events).
+//! Slow subscribers miss events rather than slowing the engine down.
+
+use rabia_core::{NodeId, PhaseId, StateValue};
+
+/// Number of events buffered for each subscriber
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineEvent {
+    /// A node cast conflicting votes in the same round of a phase.
+    /// Only the first vote is counted.
+    Equivocation {
+        node_id: NodeId,
+        phase_id: PhaseId,
+        /// Voting round, 1 or 2
+        round: u8,
+        first: StateValue,
+        second: StateValue,
+    },
+}
@@ -0,0 +1,145 @@
+//! Lifecycle handle for a spawned Rabia engine.
+//!
+//! [`RabiaEngine::spawn`](crate::RabiaEngine::spawn) runs the engine on the
+//! Tokio runtime and returns an [`EngineHandle`]. The handle can report the
+//! engine's status and statistics, request a graceful shutdown, and wait for
+//! the engine task to finish.
+
+use std::fmt;
+use std::sync::Arc;
+
+use rabia_core::{
+    validation::{MessageValidator, RejectionReason},
+    RabiaError, Result,
+};
+use std::collections::HashMap;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::{EngineCommand, EngineEvent, EngineState, EngineStatistics};
+
+/// Lifecycle status of a consensus engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineStatus {
+    /// Restoring persisted state and connecting to peers
+    Starting,
+    /// Participating in consensus
+    Running,
+    /// Shutdown requested; finishing in-flight phases before stopping
+    Draining,
+    /// Stopped cleanly after persisting state
+    Stopped,
+    /// Stopped because of an unrecoverable error
+    Failed,
+}
+
+impl EngineStatus {
+    /// Check if the engine has stopped, cleanly or not
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, EngineStatus::Stopped | EngineStatus::Failed)
+    }
+}
+
+impl fmt::Display for EngineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineStatus::Starting => write!(f, "Starting"),
+            EngineStatus::Running => write!(f, "Running"),
+            EngineStatus::Draining => write!(f, "Draining"),
+            EngineStatus::Stopped => write!(f, "Stopped"),
+            EngineStatus::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+/// Handle to a consensus engine running in the background.
+///
+/// Dropping the handle does not stop the engine.
+pub struct EngineHandle {
+    control_tx: mpsc::UnboundedSender<EngineCommand>,
+    status_rx: watch::Receiver<EngineStatus>,
+    engine_state: Arc<EngineState>,
+    validator: Arc<MessageValidator>,
+    events_tx: broadcast::Sender<EngineEvent>,
+    join_handle: JoinHandle<Result<()>>,
+}
+
+impl EngineHandle {
+    pub(crate) fn new(
+        control_tx: mpsc::UnboundedSender<EngineCommand>,
+        status_rx: watch::Receiver<EngineStatus>,
+        engine_state: Arc<EngineState>,
+        validator: Arc<MessageValidator>,
+        events_tx: broadcast::Sender<EngineEvent>,
+        join_handle: JoinHandle<Result<()>>,
+    ) -> Self {
+        Self {
+            control_tx,
+            status_rx,
+            engine_state,
+            validator,
+            events_tx,
+            join_handle,
+        }
+    }
+
+    /// Current lifecycle status of the engine
+    pub fn status(&self) -> EngineStatus {
+        *self.status_rx.borrow()
+    }
+
+    /// Wait until the engine reaches the given status or stops
+    pub async fn wait_for_status(&self, status: EngineStatus) -> EngineStatus {
+        let mut status_rx = self.status_rx.clone();
+        let result = status_rx
+            .wait_for(|current| *current == status || current.is_terminal())
+            .await
+            .map(|current| *current);
+        match result {
+            Ok(current) => current,
+            // The engine task dropped its sender, so it is no longer running
+            Err(_) => EngineStatus::Stopped,
+        }
+    }
+
+    /// Current consensus statistics, read without going through the engine loop
+    pub fn statistics(&self) -> EngineStatistics {
+        self.engine_state.get_statistics()
+    }
+
+    /// Inbound messages and client batches rejected so far, by reason
+    pub fn rejection_counts(&self) -> HashMap<RejectionReason, u64> {
+        self.validator.rejection_counts()
+    }
+
+    /// Subscribe to engine events such as detected equivocation
+    pub fn subscribe_events(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Ask the engine to shut down without waiting for it to stop
+    pub fn request_shutdown(&self) -> Result<()> {
+        self.control_tx
+            .send(EngineCommand::Shutdown)
+            .map_err(|_| RabiaError::ShuttingDown)
+    }
+
+    /// Shut the engine down gracefully and wait for it to stop.
+    ///
+    /// The engine stops accepting new batches, drains in-flight phases (bounded
+    /// by `RabiaConfig::shutdown_drain_timeout`), persists its state and closes
+    /// the network before this resolves.
+    pub async fn shutdown(self) -> Result<()> {
+        // The engine may already have stopped on its own; join reports how
+        let _ = self.request_shutdown();
+        self.join().await
+    }
+
+    /// Wait for the engine task to finish
+    pub async fn join(self) -> Result<()> {
+        self.join_handle.await.map_err(|e| RabiaError::TaskFailed {
+            task: "engine".to_string(),
+            message: e.to_string(),
+        })?
+    }
+}
@@ -6,10 +6,15 @@
 //!
 //! When the cluster view changes (nodes join/leave), the leader is re-determined based on
 //! the new cluster membership.
+//!
+//! The leader is only an optimisation: in leader-assisted mode followers forward client
+//! batches to it so that proposals are serialized, and the engine falls back to plain
+//! leaderless Rabia whenever the [`FailureDetector`] suspects the leader.
 
 use rabia_core::NodeId;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 use tracing::{debug, info};
 
 /// Simple leader selector that determines leadership based on cluster membership
@@ -145,6 +150,58 @@ impl Default for LeaderSelector {
     }
 }
 
+/// Timeout-based failure detector fed by any message received from a peer
+#[derive(Debug, Clone)]
+pub struct FailureDetector {
+    /// Time without hearing from a node after which it is suspected
+    suspect_timeout: Duration,
+    /// Last time each node was heard from
+    last_seen: HashMap<NodeId, Instant>,
+}
+
+impl FailureDetector {
+    /// Create a new failure detector with the given suspicion timeout
+    pub fn new(suspect_timeout: Duration) -> Self {
+        Self {
+            suspect_timeout,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Record that a node was heard from just now
+    pub fn record_activity(&mut self, node_id: NodeId) {
+        self.record_activity_at(node_id, Instant::now());
+    }
+
+    /// Record that a node was heard from at the given instant
+    pub fn record_activity_at(&mut self, node_id: NodeId, at: Instant) {
+        let entry = self.last_seen.entry(node_id).or_insert(at);
+        if at > *entry {
+            *entry = at;
+        }
+    }
+
+    /// Check if a node is suspected of having failed
+    ///
+    /// Nodes that have never been heard from are suspected.
+    pub fn is_suspected(&self, node_id: NodeId) -> bool {
+        self.is_suspected_at(node_id, Instant::now())
+    }
+
+    /// Check if a node is suspected as of the given instant
+    pub fn is_suspected_at(&self, node_id: NodeId, now: Instant) -> bool {
+        match self.last_seen.get(&node_id) {
+            Some(last_seen) => now.saturating_duration_since(*last_seen) > self.suspect_timeout,
+            None => true,
+        }
+    }
+
+    /// Forget a node, e.g. after it leaves the cluster
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.last_seen.remove(&node_id);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -282,4 +339,28 @@ mod tests {
         assert_eq!(selector1.get_leader(), selector2.get_leader());
         assert_eq!(selector1.get_cluster_view(), selector2.get_cluster_view());
     }
+
+    #[test]
+    fn test_failure_detector_suspects_unknown_nodes() {
+        let detector = FailureDetector::new(Duration::from_secs(1));
+        assert!(detector.is_suspected(NodeId::from(1)));
+    }
+
+    #[test]
+    fn test_failure_detector_timeout() {
+        let node = NodeId::from(1);
+        let start = Instant::now();
+        let mut detector = FailureDetector::new(Duration::from_millis(100));
+
+        detector.record_activity_at(node, start);
+        assert!(!detector.is_suspected_at(node, start + Duration::from_millis(50)));
+        assert!(detector.is_suspected_at(node, start + Duration::from_millis(150)));
+
+        // Hearing from the node again clears the suspicion
+        detector.record_activity_at(node, start + Duration::from_millis(140));
+        assert!(!detector.is_suspected_at(node, start + Duration::from_millis(150)));
+
+        detector.remove_node(node);
+        assert!(detector.is_suspected_at(node, start + Duration::from_millis(150)));
+    }
 }
@@ -13,6 +13,10 @@
 //! - **RabiaConfig**: Configuration for the SMR protocol behavior and performance
 //! - **EngineState**: Internal state management for consensus coordination
 //! - **Operation Submission**: Interface for submitting operations to the SMR system
+//! - **AdaptiveSubmitter**: Batches single commands with sizes tuned to commit latency
+//! - **LocalReader**: Stale and bounded-staleness reads served from the local replica
+//! - **EngineHandle**: Status, graceful shutdown and join for a spawned engine
+//! - **EngineEvent**: Notable protocol events such as detected equivocation
 //!
 //! ## SMR Protocol Usage
 //!
@@ -25,7 +29,6 @@
 //! use bytes::Bytes;
 //!
 //! // Example state machine implementation
-//! #[derive(Clone)]
 //! struct ExampleStateMachine {
 //!     counter: i64,
 //! }
@@ -103,12 +106,21 @@
 
 pub mod config;
 pub mod engine;
+pub mod events;
+pub mod handle;
 pub mod leader;
 pub mod network;
+mod persist;
+pub mod reads;
 pub mod state;
+pub mod submit;
 
 pub use config::*;
 pub use engine::*;
+pub use events::*;
+pub use handle::*;
 pub use leader::*;
 pub use network::*;
+pub use reads::*;
 pub use state::*;
+pub use submit::*;
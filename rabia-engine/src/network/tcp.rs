@@ -24,7 +24,7 @@ use tokio::time::{sleep, timeout};
 use tracing::{debug, error, info, warn};
 
 use rabia_core::{
-    messages::ProtocolMessage, network::NetworkTransport, NodeId, RabiaError, Result,
+    messages::ProtocolMessage, network::NetworkTransport, wire, NodeId, RabiaError, Result,
 };
 
 /// Configuration for TCP networking
@@ -580,7 +580,7 @@ impl TcpNetwork {
                 };
 
                 match frame_result {
-                    Ok(frame) => match bincode::deserialize::<ProtocolMessage>(&frame.payload) {
+                    Ok(frame) => match wire::decode(frame.payload) {
                         Ok(message) => {
                             if let Err(e) = message_tx_clone.send((node_id, message)) {
                                 debug!("Failed to send message to queue: {}", e);
@@ -602,9 +602,9 @@ impl TcpNetwork {
         // Spawn writer task
         let writer_handle = tokio::spawn(async move {
             while let Some(message) = outbound_rx.recv().await {
-                match bincode::serialize(&message) {
+                match wire::encode(&message) {
                     Ok(serialized) => {
-                        let frame = match MessageFrame::new(Bytes::from(serialized)) {
+                        let frame = match MessageFrame::new(serialized) {
                             Ok(frame) => frame,
                             Err(e) => {
                                 warn!("Failed to create frame for message to {}: {}", node_id, e);
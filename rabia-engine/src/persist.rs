@@ -0,0 +1,181 @@
+//! Group commit of decided batches to the persistence layer's decision log.
+//!
+//! The engine hands every commit to a [`PersistenceWorker`] and moves on.
+//! The worker collects the records queued while its previous write was in
+//! flight and makes them durable with one
+//! [`append_decisions`](PersistenceLayer::append_decisions) call, so one
+//! disk sync covers many phases. Client responses travel with their record
+//! and are sent only once it is durable.
+
+use crate::CommandResults;
+use rabia_core::{persistence::PersistenceLayer, CommandBatch, PhaseId, RabiaError, Result};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// Client response held back until its commit is durable
+pub(crate) type Reply = (
+    oneshot::Sender<Result<CommandResults>>,
+    Result<CommandResults>,
+);
+
+/// A committed batch waiting to be logged.
+pub(crate) struct CommitRecord {
+    pub phase_id: PhaseId,
+    pub batch: CommandBatch,
+    pub reply: Option<Reply>,
+}
+
+/// Outcome of one group write, reported back to the engine.
+#[derive(Debug)]
+pub(crate) struct DurableAck {
+    /// Highest phase in the group
+    pub through: PhaseId,
+    /// Number of commits in the group
+    pub commits: usize,
+    pub result: Result<()>,
+}
+
+/// Background task that writes commit records in groups.
+pub(crate) struct PersistenceWorker {
+    records_tx: mpsc::UnboundedSender<CommitRecord>,
+    join_handle: JoinHandle<()>,
+}
+
+impl PersistenceWorker {
+    /// Start the worker; acknowledgements arrive on the returned receiver.
+    pub fn spawn<PL>(
+        persistence: Arc<PL>,
+        max_group: usize,
+    ) -> (Self, mpsc::UnboundedReceiver<DurableAck>)
+    where
+        PL: PersistenceLayer + 'static,
+    {
+        let (records_tx, records_rx) = mpsc::unbounded_channel();
+        let (ack_tx, ack_rx) = mpsc::unbounded_channel();
+        let join_handle = tokio::spawn(run(persistence, records_rx, ack_tx, max_group.max(1)));
+
+        (
+            Self {
+                records_tx,
+                join_handle,
+            },
+            ack_rx,
+        )
+    }
+
+    /// Queue a commit, handing the record back if the worker has stopped.
+    pub fn submit(&self, record: CommitRecord) -> std::result::Result<(), CommitRecord> {
+        self.records_tx.send(record).map_err(|e| e.0)
+    }
+
+    /// Write everything queued so far and stop the worker.
+    pub async fn close(self) {
+        drop(self.records_tx);
+        if let Err(e) = self.join_handle.await {
+            warn!("Persistence worker failed: {}", e);
+        }
+    }
+}
+
+async fn run<PL>(
+    persistence: Arc<PL>,
+    mut records_rx: mpsc::UnboundedReceiver<CommitRecord>,
+    ack_tx: mpsc::UnboundedSender<DurableAck>,
+    max_group: usize,
+) where
+    PL: PersistenceLayer,
+{
+    let mut group = Vec::with_capacity(max_group);
+    while records_rx.recv_many(&mut group, max_group).await > 0 {
+        let ack = write_group(persistence.as_ref(), &mut group).await;
+        // The engine may already be gone during shutdown
+        let _ = ack_tx.send(ack);
+    }
+}
+
+/// Make `group` durable, answer its clients and drain it.
+async fn write_group<PL>(persistence: &PL, group: &mut Vec<CommitRecord>) -> DurableAck
+where
+    PL: PersistenceLayer,
+{
+    let entries: Vec<(PhaseId, CommandBatch)> = group
+        .iter()
+        .map(|record| (record.phase_id, record.batch.clone()))
+        .collect();
+    let through = entries
+        .iter()
+        .map(|(phase_id, _)| *phase_id)
+        .max()
+        .unwrap_or_default();
+
+    let result = persistence.append_decisions(&entries).await;
+    if result.is_ok() {
+        debug!("Logged {} commits through phase {}", entries.len(), through);
+    }
+
+    for record in group.drain(..) {
+        if let Some((response_tx, response)) = record.reply {
+            let response = match &result {
+                Ok(()) => response,
+                Err(e) => Err(RabiaError::persistence(format!(
+                    "Commit of phase {} is not durable on this node: {}",
+                    record.phase_id, e
+                ))),
+            };
+            let _ = response_tx.send(response);
+        }
+    }
+
+    DurableAck {
+        through,
+        commits: entries.len(),
+        result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use rabia_core::Command;
+    use rabia_persistence::InMemoryPersistence;
+
+    #[tokio::test]
+    async fn test_commits_queued_together_share_one_write() {
+        let persistence = Arc::new(InMemoryPersistence::new());
+        let (worker, mut acks) = PersistenceWorker::spawn(persistence.clone(), 8);
+
+        // The worker cannot run before this test yields, so all ten queue up
+        let mut replies = Vec::new();
+        for phase in 1..=10 {
+            let (response_tx, response_rx) = oneshot::channel();
+            let response = Ok(vec![Ok(Bytes::from(format!("r{}", phase)))]);
+            let record = CommitRecord {
+                phase_id: PhaseId::new(phase),
+                batch: CommandBatch::new(vec![Command::new(format!("SET k{} v", phase))]),
+                reply: Some((response_tx, response)),
+            };
+            assert!(worker.submit(record).is_ok());
+            replies.push(response_rx);
+        }
+
+        let first = acks.recv().await.unwrap();
+        assert!(first.result.is_ok());
+        assert_eq!((first.commits, first.through), (8, PhaseId::new(8)));
+        let second = acks.recv().await.unwrap();
+        assert_eq!((second.commits, second.through), (2, PhaseId::new(10)));
+
+        // Replies only arrive once their entries are in the log
+        let logged = persistence.load_decisions(PhaseId::new(0)).await.unwrap();
+        assert_eq!(logged.len(), 10);
+        for (phase, reply) in (1..=10).zip(replies) {
+            let response = reply.await.unwrap().unwrap();
+            assert_eq!(response, vec![Ok(Bytes::from(format!("r{}", phase)))]);
+        }
+
+        worker.close().await;
+        assert!(acks.recv().await.is_none());
+    }
+}
@@ -0,0 +1,198 @@
+//! Local reads against the replicated state machine.
+//!
+//! Linearizable reads go through consensus like any other command. Many callers
+//! (dashboards, caches, monitoring) can tolerate slightly stale data and would
+//! rather read the local replica directly. [`LocalReader`] serves those reads,
+//! optionally bounded by how far this replica trails the cluster's committed
+//! phase as last observed through heartbeats.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rabia_core::{state_machine::StateMachine, RabiaError, Result};
+
+use crate::{EngineState, ReplicationLag};
+
+/// Upper bound on replica staleness accepted by a local read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaxLag {
+    /// Maximum number of committed phases this replica may trail the cluster by
+    Phases(u64),
+    /// Maximum time this replica may have been behind the cluster
+    Time(Duration),
+}
+
+impl MaxLag {
+    /// Check the given lag against this bound.
+    pub fn check(&self, lag: &ReplicationLag) -> Result<()> {
+        let exceeded = match self {
+            MaxLag::Phases(max) => lag.phases > *max,
+            MaxLag::Time(max) => lag.millis > max.as_millis() as u64,
+        };
+
+        if exceeded {
+            Err(RabiaError::Stale {
+                local_committed: lag.local_committed,
+                cluster_committed: lag.cluster_committed,
+                lag_ms: lag.millis,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Cloneable handle for serving reads from the local state machine.
+///
+/// Obtained from [`RabiaEngine::local_reader`](crate::RabiaEngine::local_reader)
+/// before the engine is moved into [`run`](crate::RabiaEngine::run).
+pub struct LocalReader<SM>
+where
+    SM: StateMachine,
+{
+    state_machine: Arc<tokio::sync::Mutex<SM>>,
+    engine_state: Arc<EngineState>,
+}
+
+impl<SM> Clone for LocalReader<SM>
+where
+    SM: StateMachine,
+{
+    fn clone(&self) -> Self {
+        Self {
+            state_machine: self.state_machine.clone(),
+            engine_state: self.engine_state.clone(),
+        }
+    }
+}
+
+impl<SM> LocalReader<SM>
+where
+    SM: StateMachine,
+{
+    pub(crate) fn new(
+        state_machine: Arc<tokio::sync::Mutex<SM>>,
+        engine_state: Arc<EngineState>,
+    ) -> Self {
+        Self {
+            state_machine,
+            engine_state,
+        }
+    }
+
+    /// Current lag of this replica behind the cluster.
+    pub fn lag(&self) -> ReplicationLag {
+        self.engine_state.replication_lag()
+    }
+
+    /// Read the local state, failing with [`RabiaError::Stale`] if the replica
+    /// trails the cluster by more than `max_lag`. `None` accepts any staleness.
+    pub async fn read_state(&self, max_lag: Option<MaxLag>) -> Result<SM::State> {
+        let sm = self.state_machine.lock().await;
+        self.check_lag(max_lag)?;
+        Ok(sm.get_state().await)
+    }
+
+    /// Run `f` against the local state machine under the same staleness rules
+    /// as [`read_state`](Self::read_state).
+    pub async fn read_with<F, R>(&self, max_lag: Option<MaxLag>, f: F) -> Result<R>
+    where
+        F: FnOnce(&SM) -> R,
+    {
+        let sm = self.state_machine.lock().await;
+        self.check_lag(max_lag)?;
+        Ok(f(&sm))
+    }
+
+    fn check_lag(&self, max_lag: Option<MaxLag>) -> Result<()> {
+        match max_lag {
+            Some(bound) => bound.check(&self.engine_state.replication_lag()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rabia_core::{state_machine::InMemoryStateMachine, Command, PhaseId};
+
+    fn reader() -> (LocalReader<InMemoryStateMachine>, Arc<EngineState>) {
+        let engine_state = Arc::new(EngineState::new(2));
+        let sm = Arc::new(tokio::sync::Mutex::new(InMemoryStateMachine::new()));
+        (LocalReader::new(sm, engine_state.clone()), engine_state)
+    }
+
+    #[tokio::test]
+    async fn test_unbounded_read_always_succeeds() {
+        let (reader, engine_state) = reader();
+        engine_state.observe_cluster_committed(PhaseId::new(50));
+
+        let state = reader.read_state(None).await.unwrap();
+        assert!(state.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_phase_bound_rejects_lagging_replica() {
+        let (reader, engine_state) = reader();
+        engine_state.observe_cluster_committed(PhaseId::new(5));
+
+        let lag = reader.lag();
+        assert_eq!(lag.phases, 5);
+
+        assert!(reader.read_state(Some(MaxLag::Phases(10))).await.is_ok());
+        match reader.read_state(Some(MaxLag::Phases(2))).await {
+            Err(RabiaError::Stale {
+                local_committed,
+                cluster_committed,
+                ..
+            }) => {
+                assert_eq!(local_committed, PhaseId::new(0));
+                assert_eq!(cluster_committed, PhaseId::new(5));
+            }
+            other => panic!("expected stale error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catching_up_clears_lag() {
+        let (reader, engine_state) = reader();
+        engine_state.observe_cluster_committed(PhaseId::new(3));
+        assert_ne!(
+            engine_state
+                .behind_since
+                .load(std::sync::atomic::Ordering::Acquire),
+            0
+        );
+
+        for _ in 0..3 {
+            engine_state.advance_phase();
+        }
+        engine_state.commit_phase(PhaseId::new(3)).unwrap();
+
+        let lag = reader.lag();
+        assert_eq!(lag.phases, 0);
+        assert_eq!(lag.millis, 0);
+        assert!(reader
+            .read_state(Some(MaxLag::Time(Duration::ZERO)))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_read_with_closure() {
+        let (reader, _) = reader();
+        {
+            let mut sm = reader.state_machine.lock().await;
+            sm.apply_command(&Command::new("SET key1 value1"))
+                .await
+                .unwrap();
+        }
+
+        let len = reader
+            .read_with(Some(MaxLag::Phases(0)), |sm| sm.state.len())
+            .await
+            .unwrap();
+        assert_eq!(len, 1);
+    }
+}
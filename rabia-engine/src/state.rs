@@ -3,6 +3,8 @@ use dashmap::DashMap;
 use parking_lot::RwLock;
 use rabia_core::{
     messages::{PendingBatch, PhaseData, SyncResponseMessage},
+    registry::NodeRegistry,
+    votes::MemberIndex,
     BatchId, CommandBatch, NodeId, PhaseId, RabiaError, Result,
 };
 use std::collections::HashMap;
@@ -26,6 +28,23 @@ pub struct EngineState {
 
     pub state_version: Arc<AtomicU64>,
     pub last_cleanup: Arc<AtomicU64>,
+
+    /// Highest `last_committed_phase` reported by peers in heartbeats
+    pub cluster_committed_phase: Arc<AtomicU64>,
+    /// Millisecond timestamp at which this replica fell behind the cluster, 0 when caught up
+    pub behind_since: Arc<AtomicU64>,
+    /// Lowest phase retained after cleanup; earlier phases have been compacted
+    pub compaction_watermark: Arc<AtomicU64>,
+    /// Conflicting votes seen from a single node within one round
+    pub equivocations: Arc<AtomicU64>,
+    /// Batches this node has proposed, counting each proposal attempt
+    pub batches_proposed: Arc<AtomicU64>,
+    /// Batches this node has handed to the leader instead of proposing
+    pub batches_forwarded: Arc<AtomicU64>,
+    /// Highest committed phase whose decision log entry is known to be durable
+    pub durable_phase: Arc<AtomicU64>,
+    /// Member names for statistics
+    pub registry: Option<Arc<NodeRegistry>>,
 }
 
 impl EngineState {
@@ -45,9 +64,23 @@ impl EngineState {
 
             state_version: Arc::new(AtomicU64::new(1)),
             last_cleanup: Arc::new(AtomicU64::new(0)),
+
+            cluster_committed_phase: Arc::new(AtomicU64::new(0)),
+            behind_since: Arc::new(AtomicU64::new(0)),
+            compaction_watermark: Arc::new(AtomicU64::new(0)),
+            equivocations: Arc::new(AtomicU64::new(0)),
+            batches_proposed: Arc::new(AtomicU64::new(0)),
+            batches_forwarded: Arc::new(AtomicU64::new(0)),
+            durable_phase: Arc::new(AtomicU64::new(0)),
+            registry: None,
         }
     }
 
+    pub fn with_registry(mut self, registry: Option<Arc<NodeRegistry>>) -> Self {
+        self.registry = registry;
+        self
+    }
+
     pub fn current_phase(&self) -> PhaseId {
         PhaseId::new(self.current_phase.load(Ordering::Acquire))
     }
@@ -86,6 +119,9 @@ impl EngineState {
             ) {
                 Ok(_) => {
                     self.increment_version();
+                    if phase_value >= self.cluster_committed_phase.load(Ordering::Acquire) {
+                        self.behind_since.store(0, Ordering::Release);
+                    }
                     return Ok(true);
                 }
                 Err(actual) => {
@@ -102,6 +138,61 @@ impl EngineState {
         Ok(false)
     }
 
+    pub fn durable_phase(&self) -> PhaseId {
+        PhaseId::new(self.durable_phase.load(Ordering::Acquire))
+    }
+
+    /// Record that decision log entries through `phase_id` are durable.
+    pub fn mark_durable(&self, phase_id: PhaseId) {
+        self.durable_phase
+            .fetch_max(phase_id.value(), Ordering::AcqRel);
+    }
+
+    pub fn cluster_committed_phase(&self) -> PhaseId {
+        PhaseId::new(self.cluster_committed_phase.load(Ordering::Acquire))
+    }
+
+    /// Record a peer's committed phase as observed through a heartbeat.
+    ///
+    /// Starts the lag clock the first time the cluster is seen ahead of this replica.
+    pub fn observe_cluster_committed(&self, phase_id: PhaseId) {
+        let observed = phase_id.value();
+        self.cluster_committed_phase
+            .fetch_max(observed, Ordering::AcqRel);
+
+        if observed > self.last_committed_phase.load(Ordering::Acquire) {
+            let _ = self.behind_since.compare_exchange(
+                0,
+                now_millis(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+        }
+    }
+
+    /// How far this replica trails the highest committed phase seen in heartbeats.
+    pub fn replication_lag(&self) -> ReplicationLag {
+        let local_committed = self.last_committed_phase();
+        let cluster_committed = self.cluster_committed_phase();
+        let phases = cluster_committed
+            .value()
+            .saturating_sub(local_committed.value());
+
+        let behind_since = self.behind_since.load(Ordering::Acquire);
+        let millis = if phases == 0 || behind_since == 0 {
+            0
+        } else {
+            now_millis().saturating_sub(behind_since)
+        };
+
+        ReplicationLag {
+            local_committed,
+            cluster_committed,
+            phases,
+            millis,
+        }
+    }
+
     pub fn is_active(&self) -> bool {
         self.is_active.load(Ordering::Acquire)
     }
@@ -122,6 +213,22 @@ impl EngineState {
         }
     }
 
+    pub fn compaction_watermark(&self) -> PhaseId {
+        PhaseId::new(self.compaction_watermark.load(Ordering::Acquire))
+    }
+
+    pub fn record_equivocation(&self) {
+        self.equivocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_proposal(&self) {
+        self.batches_proposed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_forward(&self) {
+        self.batches_forwarded.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn get_active_nodes(&self) -> std::collections::HashSet<NodeId> {
         self.active_nodes.read().clone()
     }
@@ -173,29 +280,71 @@ impl EngineState {
             .clone()
     }
 
+    /// Update a phase, creating it when this node first hears of it.
+    ///
+    /// The current phase moves up to `phase_id`, so this node never proposes
+    /// in a phase it already knows about.
     pub fn update_phase<F>(&self, phase_id: PhaseId, update_fn: F) -> Result<()>
     where
         F: FnOnce(&mut PhaseData),
     {
-        if let Some(mut entry) = self.phases.get_mut(&phase_id) {
-            update_fn(&mut entry);
-            self.increment_version();
-        }
+        let mut entry = self
+            .phases
+            .entry(phase_id)
+            .or_insert_with(|| PhaseData::new(phase_id));
+        update_fn(&mut entry);
+        drop(entry);
+
+        self.current_phase
+            .fetch_max(phase_id.value(), Ordering::AcqRel);
+        self.increment_version();
         Ok(())
     }
 
+    /// Lowest retained phase after `restored_through` still waiting for its
+    /// decision, if any.
+    fn first_undecided(&self, restored_through: PhaseId) -> Option<PhaseId> {
+        self.phases
+            .iter()
+            .filter(|entry| *entry.key() > restored_through && entry.decision.is_none())
+            .map(|entry| *entry.key())
+            .min()
+    }
+
+    /// Move the votes of every phase from the `from` member index to `to`.
+    pub fn reindex_phases(&self, from: &MemberIndex, to: &MemberIndex) {
+        for mut phase in self.phases.iter_mut() {
+            phase.reindex(from, to);
+        }
+    }
+
     pub fn get_phase(&self, phase_id: &PhaseId) -> Option<PhaseData> {
         self.phases.get(phase_id).map(|entry| entry.value().clone())
     }
 
-    pub fn cleanup_old_phases(&self, max_phase_history: usize) -> usize {
+    /// Drop phases more than `max_phase_history` behind the current one and
+    /// raise the compaction watermark past them.
+    ///
+    /// The watermark stops at the oldest phase still waiting for its
+    /// decision, so a late decision for a phase this node fell behind on is
+    /// still accepted. Phases up to `restored_through` are covered by a
+    /// restored or synced state and do not hold it back.
+    pub fn cleanup_old_phases(&self, max_phase_history: usize, restored_through: PhaseId) -> usize {
         let current_phase = self.current_phase();
-        let cutoff_phase = if current_phase.value() > max_phase_history as u64 {
-            PhaseId::new(current_phase.value() - max_phase_history as u64)
-        } else {
-            PhaseId::new(0)
+        let history_cutoff = PhaseId::new(
+            current_phase
+                .value()
+                .saturating_sub(max_phase_history as u64),
+        );
+        let cutoff_phase = match self.first_undecided(restored_through) {
+            Some(undecided) => history_cutoff.min(undecided),
+            None => history_cutoff,
         };
 
+        self.compaction_watermark
+            .fetch_max(cutoff_phase.value(), Ordering::AcqRel);
+        let cutoff_phase = self.compaction_watermark();
+
         let mut removed_count = 0;
         self.phases.retain(|&phase_id, _| {
             let should_keep = phase_id >= cutoff_phase;
@@ -261,6 +410,14 @@ impl EngineState {
             .collect()
     }
 
+    /// Number of phases that carry a proposed batch but have not decided yet
+    pub fn in_flight_phases(&self) -> usize {
+        self.phases
+            .iter()
+            .filter(|entry| entry.batch.is_some() && entry.decision.is_none())
+            .count()
+    }
+
     pub fn clear_sync_responses(&self) {
         self.sync_responses.clear();
     }
@@ -275,8 +432,46 @@ impl EngineState {
             has_quorum: self.has_quorum(),
             is_active: self.is_active(),
             state_version: self.get_state_version(),
+            cluster_committed_phase: self.cluster_committed_phase(),
+            durable_phase: self.durable_phase(),
+            equivocations_detected: self.equivocations.load(Ordering::Relaxed),
+            batches_proposed: self.batches_proposed.load(Ordering::Relaxed),
+            batches_forwarded: self.batches_forwarded.load(Ordering::Relaxed),
+            active_node_names: self.active_node_names(),
         }
     }
+
+    fn active_node_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .active_nodes
+            .read()
+            .iter()
+            .map(|node_id| match &self.registry {
+                Some(registry) => node_id.display_name(registry),
+                None => node_id.to_string(),
+            })
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Snapshot of how far the local replica trails the rest of the cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicationLag {
+    pub local_committed: PhaseId,
+    pub cluster_committed: PhaseId,
+    /// Committed phases the cluster has that this replica has not applied yet
+    pub phases: u64,
+    /// Time since this replica first fell behind the cluster
+    pub millis: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -289,12 +484,33 @@ pub struct EngineStatistics {
     pub has_quorum: bool,
     pub is_active: bool,
     pub state_version: u64,
+    pub cluster_committed_phase: PhaseId,
+    /// Highest committed phase this node has made durable
+    pub durable_phase: PhaseId,
+    pub equivocations_detected: u64,
+    pub batches_proposed: u64,
+    pub batches_forwarded: u64,
+    /// Display names of the active nodes, sorted; registry names when configured
+    pub active_node_names: Vec<String>,
 }
 
+/// One result per command of a batch, in batch order.
+///
+/// A command can fail on its own, e.g. a retry whose cached response has been
+/// evicted, while the rest of its batch is applied.
+pub type CommandResults = Vec<Result<Bytes>>;
+
+/// A batch submitted to the engine.
+///
+/// **Breaking change:** `response_tx` used to carry `Result<Vec<Bytes>>`. It
+/// now carries [`CommandResults`], one `Result` per command, so callers that
+/// read the responses must unwrap each command's result.
 #[derive(Debug)]
 pub struct CommandRequest {
     pub batch: CommandBatch,
-    pub response_tx: oneshot::Sender<Result<Vec<Bytes>>>,
+    /// Receives the per-command results once the batch is applied, or an
+    /// error when the batch as a whole is rejected
+    pub response_tx: oneshot::Sender<Result<CommandResults>>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,151 @@
+//! Latency-driven batching in front of the engine.
+//!
+//! An [`AdaptiveSubmitter`] accepts single commands, groups them with an
+//! [`AdaptiveBatcher`] and sends each batch to the engine as
+//! [`EngineCommand::ProcessBatch`]. The time from sending a batch to receiving
+//! its responses is the batch's commit latency, which is fed back into the
+//! batcher so batch size and delay track the configured latency target.
+
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+use rabia_core::batching::{AdaptiveBatchConfig, AdaptiveBatcher};
+use rabia_core::{Command, RabiaError, Result};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::{CommandRequest, CommandResults, EngineCommand, EngineCommandSender};
+
+type Submission = (Command, oneshot::Sender<Result<Bytes>>);
+
+/// Outcome of a submitted batch: commit latency, or `None` if it failed.
+type BatchOutcome = Option<Duration>;
+
+/// Submits commands to an engine in adaptively sized batches.
+///
+/// Dropping the submitter stops accepting commands; batches already sent to
+/// the engine still complete.
+pub struct AdaptiveSubmitter {
+    submit_tx: mpsc::UnboundedSender<Submission>,
+}
+
+impl AdaptiveSubmitter {
+    /// Start the batching task, sending batches through `engine_tx`.
+    pub fn spawn(config: AdaptiveBatchConfig, engine_tx: EngineCommandSender) -> Self {
+        let (submit_tx, submit_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_batching(
+            AdaptiveBatcher::new(config),
+            submit_rx,
+            engine_tx,
+        ));
+        Self { submit_tx }
+    }
+
+    /// Submit a command and wait for its result once committed and applied.
+    pub async fn submit(&self, command: Command) -> Result<Bytes> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.submit_tx
+            .send((command, response_tx))
+            .map_err(|_| RabiaError::ShuttingDown)?;
+        response_rx.await.map_err(|_| RabiaError::ShuttingDown)?
+    }
+}
+
+async fn run_batching(
+    mut batcher: AdaptiveBatcher,
+    mut submit_rx: mpsc::UnboundedReceiver<Submission>,
+    engine_tx: EngineCommandSender,
+) {
+    // Response channels in the same FIFO order as the batcher's buffer
+    let mut waiting: VecDeque<oneshot::Sender<Result<Bytes>>> = VecDeque::new();
+    let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel::<BatchOutcome>();
+    let mut input_open = true;
+
+    loop {
+        while let Some(batch) = batcher.next_batch(Instant::now().into_std()) {
+            // The batcher and `waiting` move in step, but a mismatch must not
+            // take the task down; unmatched commands are answered in `deliver`
+            let matched = batch.commands.len().min(waiting.len());
+            if matched < batch.commands.len() {
+                warn!(
+                    "Batch of {} commands has only {} waiting submitters",
+                    batch.commands.len(),
+                    matched
+                );
+            }
+            let responders: Vec<_> = waiting.drain(..matched).collect();
+            let (response_tx, response_rx) = oneshot::channel();
+            let request = CommandRequest { batch, response_tx };
+            if engine_tx
+                .send(EngineCommand::ProcessBatch(request))
+                .is_err()
+            {
+                batcher.record_failure();
+                for responder in responders {
+                    let _ = responder.send(Err(RabiaError::ShuttingDown));
+                }
+                continue;
+            }
+
+            let outcome_tx = outcome_tx.clone();
+            let sent_at = Instant::now();
+            tokio::spawn(async move {
+                // A dropped sender means the engine let go of the batch without
+                // answering, so whether it committed is unknown
+                let result = response_rx.await.unwrap_or_else(|_| {
+                    Err(RabiaError::OutcomeUnknown {
+                        detail: "Engine dropped the batch without answering".to_string(),
+                    })
+                });
+                let outcome = result.as_ref().ok().map(|_| sent_at.elapsed());
+                deliver(responders, result);
+                let _ = outcome_tx.send(outcome);
+            });
+        }
+
+        if !input_open && batcher.buffer_len() == 0 && batcher.in_flight() == 0 {
+            return;
+        }
+
+        let deadline = batcher.next_deadline().map(Instant::from_std);
+        tokio::select! {
+            submission = submit_rx.recv(), if input_open => match submission {
+                Some((command, responder)) => match batcher.add_command(command) {
+                    Ok(()) => waiting.push_back(responder),
+                    Err(e) => {
+                        let _ = responder.send(Err(e));
+                    }
+                },
+                None => input_open = false,
+            },
+            Some(outcome) = outcome_rx.recv() => match outcome {
+                Some(latency) => batcher.record_commit(latency),
+                None => batcher.record_failure(),
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {}
+        }
+    }
+}
+
+/// Hand each command its own response, or the batch error to all of them.
+fn deliver(responders: Vec<oneshot::Sender<Result<Bytes>>>, result: Result<CommandResults>) {
+    match result {
+        Ok(responses) => {
+            let mut responses = responses.into_iter();
+            for responder in responders {
+                let response = responses.next().unwrap_or_else(|| {
+                    Err(RabiaError::OutcomeUnknown {
+                        detail: "Engine returned fewer responses than commands".to_string(),
+                    })
+                });
+                let _ = responder.send(response);
+            }
+        }
+        Err(e) => {
+            for responder in responders {
+                let _ = responder.send(Err(e.clone()));
+            }
+        }
+    }
+}
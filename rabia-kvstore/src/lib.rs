@@ -6,8 +6,9 @@
 //! ## Features
 //!
 //! - **High Performance**: Optimized for high-throughput operations
-//! - **Consensus Integration**: Built-in Rabia consensus for consistency
+//! - **Consensus Integration**: Runs as the engine's `StateMachine`, with JSON-encoded operations as commands
 //! - **Change Notifications**: Event-driven updates via message bus
+//! - **Incremental Snapshots**: Delta snapshots of changed keys via `IncrementalSnapshot`
 //! - **Leader Management**: Automatic leader election and failover
 //! - **Topology Awareness**: Dynamic cluster membership handling
 //! - **Production Ready**: Comprehensive error handling and monitoring
@@ -5,10 +5,14 @@
 
 use crate::notifications::{ChangeNotification, ChangeType, NotificationBus};
 use crate::operations::{KVOperation, KVResult, StoreError};
+use async_trait::async_trait;
+use bytes::Bytes;
 use dashmap::DashMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use rabia_core::state_machine::{DeltaSnapshot, IncrementalSnapshot, Snapshot, StateMachine};
+use rabia_core::{Command, RabiaError};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::watch;
@@ -98,6 +102,23 @@ pub struct StoreSnapshot {
     pub checksum: u64,
 }
 
+/// Keys changed since the last snapshot or delta
+#[derive(Debug, Clone, Default)]
+struct DirtyKeys {
+    keys: HashSet<String>,
+    /// All keys were removed before `keys` changed
+    cleared: bool,
+    /// Store version of the last snapshot or delta
+    since: u64,
+}
+
+/// Changes carried by a delta; deleted keys map to `None`
+#[derive(Debug, Serialize, Deserialize)]
+struct StoreDelta {
+    cleared: bool,
+    changes: HashMap<String, Option<ValueEntry>>,
+}
+
 /// Production-grade key-value store
 pub struct KVStore {
     /// Configuration
@@ -109,9 +130,12 @@ pub struct KVStore {
     /// Store statistics
     stats: Arc<RwLock<StoreStats>>,
 
-    /// Global version counter
+    /// Global version counter, bumped by every change
     version: Arc<std::sync::atomic::AtomicU64>,
 
+    /// Changes not yet covered by a snapshot or delta
+    dirty: Arc<Mutex<DirtyKeys>>,
+
     /// Notification bus for change events
     notification_bus: Arc<NotificationBus>,
 
@@ -131,6 +155,7 @@ impl KVStore {
             data: Arc::new(DashMap::new()),
             stats: Arc::new(RwLock::new(StoreStats::default())),
             version: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            dirty: Arc::new(Mutex::new(DirtyKeys::default())),
             notification_bus: Arc::new(NotificationBus::new()),
             shutdown_tx,
             shutdown_rx,
@@ -158,6 +183,7 @@ impl KVStore {
             None
         };
 
+        let version = self.record_change(key);
         self.increment_operation_count();
 
         // Send notification if enabled
@@ -173,7 +199,7 @@ impl KVStore {
                 change_type,
                 old_value,
                 new_value: Some(value.to_string()),
-                version: self.get_version(),
+                version,
                 timestamp: SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
@@ -218,6 +244,10 @@ impl KVStore {
         self.validate_key(key)?;
 
         let old_value = self.data.remove(key).map(|(_, entry)| entry.value);
+        let version = match old_value {
+            Some(_) => self.record_change(key),
+            None => self.get_version(),
+        };
         self.increment_operation_count();
 
         // Send notification if enabled and key existed
@@ -227,7 +257,7 @@ impl KVStore {
                 change_type: ChangeType::Deleted,
                 old_value: old_value.clone(),
                 new_value: None,
-                version: self.get_version(),
+                version,
                 timestamp: SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
@@ -287,6 +317,12 @@ impl KVStore {
     pub async fn clear(&self) -> Result<KVResult, StoreError> {
         let old_size = self.data.len();
         self.data.clear();
+        let version = {
+            let mut dirty = self.dirty.lock();
+            dirty.keys.clear();
+            dirty.cleared = true;
+            self.next_version()
+        };
         self.increment_operation_count();
 
         // Send bulk notification if enabled
@@ -296,7 +332,7 @@ impl KVStore {
                 change_type: ChangeType::Cleared,
                 old_value: Some(format!("{} keys", old_size)),
                 new_value: None,
-                version: self.get_version(),
+                version,
                 timestamp: SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
@@ -402,6 +438,7 @@ impl KVStore {
 
         self.version
             .store(snapshot.version, std::sync::atomic::Ordering::Release);
+        self.reset_dirty(snapshot.version);
 
         info!(
             "Snapshot restored: version={}, keys={}",
@@ -457,16 +494,39 @@ impl KVStore {
     }
 
     fn get_version(&self) -> u64 {
+        self.version.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    fn next_version(&self) -> u64 {
         self.version
             .fetch_add(1, std::sync::atomic::Ordering::AcqRel)
+            + 1
+    }
+
+    /// Mark `key` as changed and return the new store version.
+    fn record_change(&self, key: &str) -> u64 {
+        let mut dirty = self.dirty.lock();
+        dirty.keys.insert(key.to_string());
+        self.next_version()
+    }
+
+    fn reset_dirty(&self, version: u64) {
+        *self.dirty.lock() = DirtyKeys {
+            since: version,
+            ..Default::default()
+        };
     }
 
     fn calculate_checksum(&self, data: &HashMap<String, ValueEntry>) -> u64 {
         use std::collections::hash_map::DefaultHasher;
         use std::hash::{Hash, Hasher};
 
+        // Sorted, so a snapshot that went through serialization still matches
+        let mut entries: Vec<_> = data.iter().collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+
         let mut hasher = DefaultHasher::new();
-        for (key, value) in data {
+        for (key, value) in entries {
             key.hash(&mut hasher);
             value.value.hash(&mut hasher);
             value.version.hash(&mut hasher);
@@ -485,6 +545,150 @@ impl KVStore {
     }
 }
 
+/// Clones are detached copies of the data, so a snapshot can be taken from a
+/// clone while the store keeps changing. A clone has its own notification bus
+/// and shutdown signal.
+impl Clone for KVStore {
+    fn clone(&self) -> Self {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let data = DashMap::new();
+        for entry in self.data.iter() {
+            data.insert(entry.key().clone(), entry.value().clone());
+        }
+
+        Self {
+            config: self.config.clone(),
+            data: Arc::new(data),
+            stats: Arc::new(RwLock::new(self.stats.read().clone())),
+            version: Arc::new(std::sync::atomic::AtomicU64::new(self.get_version())),
+            dirty: Arc::new(Mutex::new(self.dirty.lock().clone())),
+            notification_bus: Arc::new(NotificationBus::new()),
+            shutdown_tx,
+            shutdown_rx,
+        }
+    }
+}
+
+/// Runs the store as the consensus engine's state machine. Commands carry a
+/// JSON-encoded [`KVOperation`] and yield the JSON-encoded [`KVResult`];
+/// snapshots carry the JSON-encoded [`StoreSnapshot`].
+#[async_trait]
+impl StateMachine for KVStore {
+    type State = HashMap<String, ValueEntry>;
+
+    async fn apply_command(&mut self, command: &Command) -> rabia_core::Result<Bytes> {
+        let result = match serde_json::from_slice::<KVOperation>(&command.data) {
+            Ok(operation) => match self.apply_batch(vec![operation]).await {
+                Ok(mut results) => results.remove(0),
+                Err(e) => KVResult::from(e),
+            },
+            Err(e) => KVResult::Error(format!("Invalid operation: {}", e)),
+        };
+        Ok(Bytes::from(serde_json::to_vec(&result)?))
+    }
+
+    async fn create_snapshot(&self) -> rabia_core::Result<Snapshot> {
+        let snapshot = KVStore::create_snapshot(self)
+            .await
+            .map_err(|e| RabiaError::state_machine(e.to_string()))?;
+        Ok(Snapshot::new(
+            snapshot.version,
+            serde_json::to_vec(&snapshot)?,
+        ))
+    }
+
+    async fn restore_snapshot(&mut self, snapshot: &Snapshot) -> rabia_core::Result<()> {
+        if !snapshot.verify_checksum() {
+            return Err(RabiaError::ChecksumMismatch {
+                expected: snapshot.checksum,
+                actual: crc32fast::hash(&snapshot.data),
+            });
+        }
+
+        let store_snapshot: StoreSnapshot = serde_json::from_slice(&snapshot.data)?;
+        KVStore::restore_snapshot(self, store_snapshot)
+            .await
+            .map_err(|e| RabiaError::state_machine(e.to_string()))
+    }
+
+    async fn get_state(&self) -> Self::State {
+        self.data
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    fn incremental(&mut self) -> Option<&mut dyn IncrementalSnapshot> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl IncrementalSnapshot for KVStore {
+    fn dirty_count(&self) -> usize {
+        self.dirty.lock().keys.len()
+    }
+
+    fn version(&self) -> u64 {
+        self.get_version()
+    }
+
+    async fn create_delta(&mut self) -> rabia_core::Result<DeltaSnapshot> {
+        // Holding the dirty set keeps changes from slipping between capture and reset
+        let mut dirty = self.dirty.lock();
+        let delta = StoreDelta {
+            cleared: dirty.cleared,
+            changes: dirty
+                .keys
+                .iter()
+                .map(|key| (key.clone(), self.data.get(key).map(|entry| entry.clone())))
+                .collect(),
+        };
+        let version = self.get_version();
+        let data = serde_json::to_vec(&delta)?;
+        let snapshot = DeltaSnapshot::new(dirty.since, version, data);
+        *dirty = DirtyKeys {
+            since: version,
+            ..Default::default()
+        };
+
+        debug!(
+            "Delta created: version={}, keys={}",
+            version,
+            delta.changes.len()
+        );
+        Ok(snapshot)
+    }
+
+    async fn apply_delta(&mut self, delta: &DeltaSnapshot) -> rabia_core::Result<()> {
+        delta.check_applies_to(self.get_version())?;
+        let store_delta: StoreDelta = serde_json::from_slice(&delta.data)?;
+
+        if store_delta.cleared {
+            self.data.clear();
+        }
+        for (key, entry) in store_delta.changes {
+            match entry {
+                Some(entry) => {
+                    self.data.insert(key, entry);
+                }
+                None => {
+                    self.data.remove(&key);
+                }
+            }
+        }
+
+        self.version
+            .store(delta.version, std::sync::atomic::Ordering::Release);
+        self.reset_dirty(delta.version);
+        Ok(())
+    }
+
+    fn clear_dirty(&mut self) {
+        self.reset_dirty(self.get_version());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -565,4 +769,65 @@ mod tests {
         let value = store.get("key1").await.unwrap();
         assert_eq!(value.unwrap(), "value1");
     }
+
+    #[tokio::test]
+    async fn test_delta_snapshot_chain() {
+        let mut store = KVStore::new(KVStoreConfig::default()).await.unwrap();
+        store.set("key1", "value1").await.unwrap();
+        store.set("key2", "value2").await.unwrap();
+        let snapshot = store.create_snapshot().await.unwrap();
+        store.clear_dirty();
+
+        store.set("key3", "value3").await.unwrap();
+        store.delete("key1").await.unwrap();
+        assert_eq!(store.dirty_count(), 2);
+        let first = store.create_delta().await.unwrap();
+        assert_eq!(store.dirty_count(), 0);
+
+        store.set("key2", "updated").await.unwrap();
+        let second = store.create_delta().await.unwrap();
+        assert_eq!(second.base_version, first.version);
+
+        let mut replica = KVStore::new(KVStoreConfig::default()).await.unwrap();
+        replica.restore_snapshot(snapshot).await.unwrap();
+        // Deltas only apply in chain order
+        assert!(replica.apply_delta(&second).await.is_err());
+        replica.apply_delta(&first).await.unwrap();
+        replica.apply_delta(&second).await.unwrap();
+
+        assert!(replica.get("key1").await.unwrap().is_none());
+        assert_eq!(replica.get("key2").await.unwrap().unwrap(), "updated");
+        assert_eq!(replica.get("key3").await.unwrap().unwrap(), "value3");
+        assert_eq!(replica.get_version(), store.get_version());
+    }
+
+    #[tokio::test]
+    async fn test_state_machine_commands_and_snapshots() {
+        let mut store = KVStore::new(KVStoreConfig::default()).await.unwrap();
+        let set = Command::new(r#"{"Set":{"key":"key1","value":"value1"}}"#);
+        let result = StateMachine::apply_command(&mut store, &set).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<KVResult>(&result).unwrap(),
+            KVResult::Success
+        );
+        let result = StateMachine::apply_command(&mut store, &Command::new("SET key2"))
+            .await
+            .unwrap();
+        assert!(serde_json::from_slice::<KVResult>(&result)
+            .unwrap()
+            .is_error());
+
+        // A clone is a detached copy
+        let copy = store.clone();
+        store.set("key2", "value2").await.unwrap();
+        assert!(copy.get("key2").await.unwrap().is_none());
+
+        let snapshot = StateMachine::create_snapshot(&store).await.unwrap();
+        let mut replica = KVStore::new(KVStoreConfig::default()).await.unwrap();
+        StateMachine::restore_snapshot(&mut replica, &snapshot)
+            .await
+            .unwrap();
+        assert_eq!(replica.get("key2").await.unwrap().unwrap(), "value2");
+        assert_eq!(IncrementalSnapshot::version(&replica), snapshot.version);
+    }
 }
@@ -0,0 +1,440 @@
+use async_trait::async_trait;
+use rabia_core::{
+    freshness::clock_incarnation,
+    persistence::{EngineState, PersistenceLayer, StateChain},
+    state_machine::Snapshot,
+    CommandBatch, PhaseId, RabiaError, Result,
+};
+use redb::{Database, ReadTransaction, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Engine metadata: the state without its snapshot, or the manifest of an
+/// opaque state
+const META: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+/// Decided batches by phase
+const BATCH_LOG: TableDefinition<u64, &[u8]> = TableDefinition::new("batch_log");
+/// Snapshot data or an opaque state split into chunks, by chunk index
+const SNAPSHOT_CHUNKS: TableDefinition<u32, &[u8]> = TableDefinition::new("snapshot_chunks");
+/// Delta records appended to the saved state, in order
+const STATE_DELTAS: TableDefinition<u32, &[u8]> = TableDefinition::new("state_deltas");
+
+const ENGINE_STATE_KEY: &str = "engine_state";
+const RAW_STATE_KEY: &str = "raw_state";
+/// Manifest of an opaque state stored in [`SNAPSHOT_CHUNKS`]
+const OPAQUE_STATE_KEY: &str = "opaque_state";
+const SNAPSHOT_MANIFEST_KEY: &str = "snapshot_manifest";
+const INCARNATION_KEY: &str = "incarnation";
+
+/// Default size of a snapshot chunk
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Describes the snapshot stored in [`SNAPSHOT_CHUNKS`].
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotManifest {
+    version: u64,
+    checksum: u32,
+    size: u64,
+    chunks: u32,
+}
+
+/// Persistence backed by an embedded [redb](https://docs.rs/redb) database.
+///
+/// Engine metadata, the decided-batch log and snapshot chunks live in
+/// separate tables of a single database file. Every write is one ACID
+/// transaction, so a crash leaves either the old or the new state, never a
+/// mix.
+///
+/// [`save_state`](PersistenceLayer::save_state) splits an [`EngineState`]
+/// into its metadata and snapshot chunks; [`load_state`](PersistenceLayer::load_state)
+/// reassembles it and checks the snapshot checksum. Saving a state whose
+/// snapshot is already stored, e.g. one that only adds deltas, leaves the
+/// chunks untouched. Payloads that are not an `EngineState`, e.g. encrypted
+/// states, are split into chunks as a whole. Delta records are kept in a
+/// table of their own until the next save replaces them.
+///
+/// The decided-batch log also serves as the engine's decision log.
+#[derive(Clone)]
+pub struct EmbeddedPersistence {
+    db: Arc<Database>,
+    chunk_size: usize,
+}
+
+impl std::fmt::Debug for EmbeddedPersistence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddedPersistence")
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl EmbeddedPersistence {
+    /// Open or create the database at `path`.
+    ///
+    /// # Errors
+    /// * Returns error if the file cannot be opened or is not a valid database
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                RabiaError::persistence(format!("Failed to create data directory: {}", e))
+            })?;
+        }
+
+        let db = Database::create(path).map_err(db_error)?;
+
+        // Create the tables up front so readers never see them missing
+        let txn = db.begin_write().map_err(db_error)?;
+        txn.open_table(META).map_err(db_error)?;
+        txn.open_table(BATCH_LOG).map_err(db_error)?;
+        txn.open_table(SNAPSHOT_CHUNKS).map_err(db_error)?;
+        txn.open_table(STATE_DELTAS).map_err(db_error)?;
+        txn.commit().map_err(db_error)?;
+
+        Ok(Self {
+            db: Arc::new(db),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        })
+    }
+
+    /// Set the size snapshots are split into.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Append decided batches to the log in one transaction.
+    ///
+    /// A batch already logged for the same phase is replaced.
+    pub async fn append_decided(&self, entries: Vec<(PhaseId, CommandBatch)>) -> Result<()> {
+        let encoded = entries
+            .iter()
+            .map(|(phase, batch)| Ok((phase.value(), encode(batch)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.blocking(move |db| {
+            let txn = db.begin_write().map_err(db_error)?;
+            {
+                let mut log = txn.open_table(BATCH_LOG).map_err(db_error)?;
+                for (phase, bytes) in &encoded {
+                    log.insert(*phase, bytes.as_slice()).map_err(db_error)?;
+                }
+            }
+            txn.commit().map_err(db_error)
+        })
+        .await
+    }
+
+    /// Decided batches with `from <= phase < to`, in phase order.
+    pub async fn decided_batches(
+        &self,
+        from: PhaseId,
+        to: PhaseId,
+    ) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        self.blocking(move |db| {
+            let txn = db.begin_read().map_err(db_error)?;
+            let log = txn.open_table(BATCH_LOG).map_err(db_error)?;
+            let mut batches = Vec::new();
+            for entry in log.range(from.value()..to.value()).map_err(db_error)? {
+                let (phase, bytes) = entry.map_err(db_error)?;
+                batches.push((PhaseId::new(phase.value()), decode(bytes.value())?));
+            }
+            Ok(batches)
+        })
+        .await
+    }
+
+    /// Drop log entries below `phase`, e.g. once a snapshot covers them.
+    ///
+    /// Returns the number of entries removed.
+    pub async fn truncate_log_before(&self, phase: PhaseId) -> Result<usize> {
+        self.blocking(move |db| {
+            let txn = db.begin_write().map_err(db_error)?;
+            let removed = {
+                let mut log = txn.open_table(BATCH_LOG).map_err(db_error)?;
+                let stale: Vec<u64> = log
+                    .range(..phase.value())
+                    .map_err(db_error)?
+                    .map(|entry| entry.map(|(phase, _)| phase.value()).map_err(db_error))
+                    .collect::<Result<_>>()?;
+                for phase in &stale {
+                    log.remove(*phase).map_err(db_error)?;
+                }
+                stale.len()
+            };
+            txn.commit().map_err(db_error)?;
+            Ok(removed)
+        })
+        .await
+    }
+
+    /// Run a database operation off the async runtime.
+    async fn blocking<T, F>(&self, operation: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || operation(&db))
+            .await
+            .map_err(|e| RabiaError::TaskFailed {
+                task: "persistence".to_string(),
+                message: e.to_string(),
+            })?
+    }
+}
+
+fn write_state(db: &Database, state: Vec<u8>, phase: PhaseId, chunk_size: usize) -> Result<()> {
+    let parsed = EngineState::from_bytes(&state).ok();
+
+    let txn = db.begin_write().map_err(db_error)?;
+    // Delta records chain to the state being replaced
+    txn.delete_table(STATE_DELTAS).map_err(db_error)?;
+    txn.open_table(STATE_DELTAS).map_err(db_error)?;
+    {
+        let mut meta = txn.open_table(META).map_err(db_error)?;
+        meta.remove(RAW_STATE_KEY).map_err(db_error)?;
+
+        // Opaque payloads, e.g. encrypted states, are chunked as a whole
+        let (chunked, manifest_key) = match parsed {
+            Some(mut engine_state) => {
+                let snapshot = engine_state.snapshot.take();
+                meta.insert(ENGINE_STATE_KEY, engine_state.to_bytes()?.as_slice())
+                    .map_err(db_error)?;
+                meta.remove(OPAQUE_STATE_KEY).map_err(db_error)?;
+                (snapshot, SNAPSHOT_MANIFEST_KEY)
+            }
+            None => {
+                meta.remove(ENGINE_STATE_KEY).map_err(db_error)?;
+                meta.remove(SNAPSHOT_MANIFEST_KEY).map_err(db_error)?;
+                (Some(Snapshot::new(phase.value(), state)), OPAQUE_STATE_KEY)
+            }
+        };
+
+        // States that only add deltas keep the stored snapshot as it is
+        let stored = meta
+            .get(manifest_key)
+            .map_err(db_error)?
+            .map(|manifest| decode::<SnapshotManifest>(manifest.value()))
+            .transpose()?;
+        if let (Some(chunked), Some(stored)) = (&chunked, stored) {
+            if stored.version == chunked.version
+                && stored.checksum == chunked.checksum
+                && stored.size == chunked.data.len() as u64
+            {
+                drop(meta);
+                return txn.commit().map_err(db_error);
+            }
+        }
+
+        // Replace the stored snapshot wholesale
+        txn.delete_table(SNAPSHOT_CHUNKS).map_err(db_error)?;
+        match chunked {
+            Some(chunked) => {
+                let mut chunks = txn.open_table(SNAPSHOT_CHUNKS).map_err(db_error)?;
+                let mut count = 0u32;
+                for (index, chunk) in chunked.data.chunks(chunk_size).enumerate() {
+                    chunks.insert(index as u32, chunk).map_err(db_error)?;
+                    count += 1;
+                }
+                let manifest = SnapshotManifest {
+                    version: chunked.version,
+                    checksum: chunked.checksum,
+                    size: chunked.data.len() as u64,
+                    chunks: count,
+                };
+                meta.insert(manifest_key, encode(&manifest)?.as_slice())
+                    .map_err(db_error)?;
+            }
+            None => {
+                meta.remove(SNAPSHOT_MANIFEST_KEY).map_err(db_error)?;
+            }
+        }
+    }
+    txn.commit().map_err(db_error)
+}
+
+/// Advance the stored incarnation counter, starting from the clock.
+fn advance_incarnation(db: &Database) -> Result<u64> {
+    let txn = db.begin_write().map_err(db_error)?;
+    let incarnation = {
+        let mut meta = txn.open_table(META).map_err(db_error)?;
+        let stored: Option<u64> = meta
+            .get(INCARNATION_KEY)
+            .map_err(db_error)?
+            .map(|stored| decode(stored.value()))
+            .transpose()?;
+        let incarnation = match stored {
+            Some(stored) => stored + 1,
+            None => clock_incarnation(),
+        };
+        meta.insert(INCARNATION_KEY, encode(&incarnation)?.as_slice())
+            .map_err(db_error)?;
+        incarnation
+    };
+    txn.commit().map_err(db_error)?;
+    Ok(incarnation)
+}
+
+/// Append a delta record after the stored ones.
+fn write_state_delta(db: &Database, record: Vec<u8>) -> Result<()> {
+    let txn = db.begin_write().map_err(db_error)?;
+    {
+        let meta = txn.open_table(META).map_err(db_error)?;
+        let mut saved = false;
+        for key in [ENGINE_STATE_KEY, OPAQUE_STATE_KEY, RAW_STATE_KEY] {
+            saved |= meta.get(key).map_err(db_error)?.is_some();
+        }
+        if !saved {
+            return Err(RabiaError::persistence(
+                "Cannot append a delta record without a saved state",
+            ));
+        }
+
+        let mut deltas = txn.open_table(STATE_DELTAS).map_err(db_error)?;
+        let next = match deltas.last().map_err(db_error)? {
+            Some((index, _)) => index.value() + 1,
+            None => 0,
+        };
+        deltas.insert(next, record.as_slice()).map_err(db_error)?;
+    }
+    txn.commit().map_err(db_error)
+}
+
+fn read_state_chain(db: &Database) -> Result<Option<StateChain>> {
+    let txn = db.begin_read().map_err(db_error)?;
+    let Some(state) = read_state_in(&txn)? else {
+        return Ok(None);
+    };
+    let deltas = txn.open_table(STATE_DELTAS).map_err(db_error)?;
+    let records = deltas
+        .iter()
+        .map_err(db_error)?
+        .map(|entry| {
+            entry
+                .map(|(_, record)| record.value().to_vec())
+                .map_err(db_error)
+        })
+        .collect::<Result<_>>()?;
+    Ok(Some((state, records)))
+}
+
+fn read_state(db: &Database) -> Result<Option<Vec<u8>>> {
+    read_state_in(&db.begin_read().map_err(db_error)?)
+}
+
+fn read_state_in(txn: &ReadTransaction) -> Result<Option<Vec<u8>>> {
+    let meta = txn.open_table(META).map_err(db_error)?;
+
+    // Written by versions that kept opaque payloads in one value
+    if let Some(raw) = meta.get(RAW_STATE_KEY).map_err(db_error)? {
+        return Ok(Some(raw.value().to_vec()));
+    }
+    if let Some(manifest) = meta.get(OPAQUE_STATE_KEY).map_err(db_error)? {
+        let opaque = read_chunks(txn, decode(manifest.value())?)?;
+        return Ok(Some(opaque.data.to_vec()));
+    }
+    let Some(stored) = meta.get(ENGINE_STATE_KEY).map_err(db_error)? else {
+        return Ok(None);
+    };
+    let mut engine_state = EngineState::from_bytes(stored.value())?;
+
+    if let Some(manifest) = meta.get(SNAPSHOT_MANIFEST_KEY).map_err(db_error)? {
+        engine_state.snapshot = Some(read_chunks(txn, decode(manifest.value())?)?);
+    }
+
+    engine_state.to_bytes().map(Some)
+}
+
+/// Reassemble the chunks `manifest` describes.
+fn read_chunks(txn: &ReadTransaction, manifest: SnapshotManifest) -> Result<Snapshot> {
+    let chunks = txn.open_table(SNAPSHOT_CHUNKS).map_err(db_error)?;
+    let mut data = Vec::with_capacity(manifest.size as usize);
+    for index in 0..manifest.chunks {
+        let chunk =
+            chunks
+                .get(index)
+                .map_err(db_error)?
+                .ok_or_else(|| RabiaError::StateCorruption {
+                    details: format!("Snapshot chunk {} is missing", index),
+                })?;
+        data.extend_from_slice(chunk.value());
+    }
+
+    let snapshot = Snapshot::new(manifest.version, data);
+    if snapshot.checksum != manifest.checksum || snapshot.data.len() as u64 != manifest.size {
+        return Err(RabiaError::StateCorruption {
+            details: format!(
+                "Reassembled snapshot {} does not match its manifest",
+                manifest.version
+            ),
+        });
+    }
+    Ok(snapshot)
+}
+
+#[async_trait]
+impl PersistenceLayer for EmbeddedPersistence {
+    async fn save_state(&self, state: &[u8]) -> Result<()> {
+        let phase = EngineState::peek_last_committed_phase(state).unwrap_or_default();
+        self.save_state_through(phase, state).await
+    }
+
+    async fn save_state_through(&self, phase: PhaseId, state: &[u8]) -> Result<()> {
+        let state = state.to_vec();
+        let chunk_size = self.chunk_size;
+        self.blocking(move |db| write_state(db, state, phase, chunk_size))
+            .await
+    }
+
+    async fn load_state(&self) -> Result<Option<Vec<u8>>> {
+        self.blocking(read_state).await
+    }
+
+    async fn append_state_delta(&self, record: &[u8]) -> Result<()> {
+        let record = record.to_vec();
+        self.blocking(move |db| write_state_delta(db, record)).await
+    }
+
+    async fn load_state_chain(&self) -> Result<Option<StateChain>> {
+        self.blocking(read_state_chain).await
+    }
+
+    fn has_decision_log(&self) -> bool {
+        true
+    }
+
+    async fn append_decision(&self, phase: PhaseId, batch: &CommandBatch) -> Result<()> {
+        self.append_decided(vec![(phase, batch.clone())]).await
+    }
+
+    async fn append_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        self.append_decided(entries.to_vec()).await
+    }
+
+    async fn load_decisions(&self, after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        self.decided_batches(after.next(), PhaseId::new(u64::MAX))
+            .await
+    }
+
+    async fn truncate_decisions(&self, through: PhaseId) -> Result<()> {
+        self.truncate_log_before(through.next()).await.map(|_| ())
+    }
+
+    async fn next_incarnation(&self) -> Result<Option<u64>> {
+        self.blocking(advance_incarnation).await.map(Some)
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| RabiaError::serialization(e.to_string()))
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T> {
+    bincode::deserialize(bytes).map_err(|e| RabiaError::serialization(e.to_string()))
+}
+
+fn db_error(e: impl Into<redb::Error>) -> RabiaError {
+    RabiaError::persistence(format!("Embedded database error: {}", e.into()))
+}
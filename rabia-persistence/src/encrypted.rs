@@ -0,0 +1,336 @@
+use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rabia_core::{
+    persistence::{EngineState, PersistenceLayer, StateChain},
+    signing::read_key32,
+    Command, CommandBatch, PhaseId, RabiaError, Result,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Magic bytes at the start of every encrypted value
+const ENVELOPE_MAGIC: &[u8; 4] = b"RBEN";
+/// Current envelope format version
+const ENVELOPE_VERSION: u8 = 1;
+/// Magic, version and key id
+const ENVELOPE_HEADER_LEN: usize = 4 + 1 + 4;
+const NONCE_LEN: usize = 12;
+
+/// Keys used by [`EncryptedPersistence`], each identified by a key id.
+///
+/// New values are always encrypted with the active key. Retired keys are only
+/// used to decrypt values written before a rotation; they can be dropped once
+/// a snapshot has been written with the new key and the decision log entries
+/// sealed with the old one have been truncated.
+#[derive(Clone)]
+pub struct EncryptionKeys {
+    active: u32,
+    ciphers: HashMap<u32, ChaCha20Poly1305>,
+}
+
+impl fmt::Debug for EncryptionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print key material
+        let mut key_ids: Vec<_> = self.ciphers.keys().collect();
+        key_ids.sort();
+        f.debug_struct("EncryptionKeys")
+            .field("active", &self.active)
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+impl EncryptionKeys {
+    /// Encrypt with `key`, known by `key_id`.
+    pub fn new(key_id: u32, key: [u8; 32]) -> Self {
+        let mut ciphers = HashMap::new();
+        ciphers.insert(key_id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        Self {
+            active: key_id,
+            ciphers,
+        }
+    }
+
+    /// Encrypt with the hex-encoded 32-byte key in `path`.
+    pub fn from_key_file(key_id: u32, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(key_id, read_key32(path.as_ref())?))
+    }
+
+    /// Also decrypt values written with an older key.
+    pub fn with_retired_key(mut self, key_id: u32, key: [u8; 32]) -> Result<Self> {
+        if self.ciphers.contains_key(&key_id) {
+            return Err(RabiaError::key(format!(
+                "Encryption key id {} is already in use",
+                key_id
+            )));
+        }
+        self.ciphers
+            .insert(key_id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        Ok(self)
+    }
+
+    /// Also decrypt values written with the older key in `path`.
+    pub fn with_retired_key_file(self, key_id: u32, path: impl AsRef<Path>) -> Result<Self> {
+        let key = read_key32(path.as_ref())?;
+        self.with_retired_key(key_id, key)
+    }
+
+    /// Id of the key new values are encrypted with.
+    pub fn active_key_id(&self) -> u32 {
+        self.active
+    }
+
+    /// Encrypt `plaintext`, binding it to `context` so it cannot be passed off
+    /// as a different value.
+    fn seal(&self, plaintext: &[u8], context: &[u8]) -> Result<Vec<u8>> {
+        let cipher = &self.ciphers[&self.active];
+        let header = envelope_header(self.active);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = [header.as_slice(), context].concat();
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| RabiaError::persistence("Failed to encrypt persisted value"))?;
+
+        let mut envelope = Vec::with_capacity(header.len() + NONCE_LEN + ciphertext.len());
+        envelope.extend_from_slice(&header);
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+        Ok(envelope)
+    }
+
+    fn open(&self, envelope: &[u8], context: &[u8]) -> Result<Vec<u8>> {
+        if envelope.len() < ENVELOPE_HEADER_LEN + NONCE_LEN || !is_encrypted(envelope) {
+            return Err(corruption("value is not an encryption envelope"));
+        }
+        let version = envelope[4];
+        if version != ENVELOPE_VERSION {
+            return Err(corruption(format!(
+                "unsupported encryption envelope version {}",
+                version
+            )));
+        }
+        let key_id = u32::from_be_bytes(envelope[5..ENVELOPE_HEADER_LEN].try_into().unwrap());
+        let cipher = self.ciphers.get(&key_id).ok_or_else(|| {
+            corruption(format!("value was encrypted with unknown key {}", key_id))
+        })?;
+
+        let (header, rest) = envelope.split_at(ENVELOPE_HEADER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let aad = [header, context].concat();
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| corruption(format!("authentication failed with key {}", key_id)))
+    }
+}
+
+/// Encrypts everything an inner [`PersistenceLayer`] stores.
+///
+/// The saved state, its delta records and every decision log entry are sealed with
+/// ChaCha20-Poly1305 under the active key of an [`EncryptionKeys`]. Each value
+/// carries the id of the key it was written with, so keys can be rotated while
+/// older values stay readable. Values that fail authentication, were written
+/// with an unknown key or are not encrypted at all are reported as
+/// [`RabiaError::StateCorruption`].
+///
+/// States are saved under the phase they cover, which the wrapped backend
+/// cannot read from the sealed value, so a file system backend keeps its
+/// generations in order.
+///
+/// Decision log entries are stored as a batch with the original batch id and
+/// timestamp and a single command holding the sealed batch, so any backend can
+/// hold them. Entries are bound to their phase and cannot be swapped.
+///
+/// ```rust
+/// use rabia_core::persistence::PersistenceLayer;
+/// use rabia_persistence::{EncryptedPersistence, EncryptionKeys, InMemoryPersistence};
+///
+/// # tokio_test::block_on(async {
+/// let inner = InMemoryPersistence::new();
+/// let persistence = EncryptedPersistence::new(inner.clone(), EncryptionKeys::new(1, [7; 32]));
+/// persistence.save_state(b"balances").await.unwrap();
+///
+/// assert_eq!(persistence.load_state().await.unwrap(), Some(b"balances".to_vec()));
+/// assert_ne!(inner.load_state().await.unwrap(), Some(b"balances".to_vec()));
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct EncryptedPersistence<P> {
+    inner: P,
+    keys: EncryptionKeys,
+    accept_plaintext: bool,
+}
+
+impl<P: PersistenceLayer> EncryptedPersistence<P> {
+    pub fn new(inner: P, keys: EncryptionKeys) -> Self {
+        Self {
+            inner,
+            keys,
+            accept_plaintext: false,
+        }
+    }
+
+    /// Read values stored before encryption was enabled instead of rejecting
+    /// them. Writes are always encrypted, so this is only needed until the
+    /// first snapshot after enabling encryption.
+    pub fn with_plaintext_reads(mut self, accept: bool) -> Self {
+        self.accept_plaintext = accept;
+        self
+    }
+
+    /// The wrapped backend, e.g. to manage file system generations.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    fn open_state(&self, data: Vec<u8>, context: &[u8]) -> Result<Vec<u8>> {
+        if self.accept_plaintext && !is_encrypted(&data) {
+            return Ok(data);
+        }
+        self.keys.open(&data, context)
+    }
+
+    fn seal_batch(&self, phase: PhaseId, batch: &CommandBatch) -> Result<CommandBatch> {
+        let plaintext =
+            bincode::serialize(batch).map_err(|e| RabiaError::serialization(e.to_string()))?;
+        let envelope = self.keys.seal(&plaintext, &decision_context(phase))?;
+        Ok(CommandBatch {
+            id: batch.id,
+            commands: vec![Command::new(envelope)],
+            timestamp: batch.timestamp,
+        })
+    }
+
+    fn open_batch(&self, phase: PhaseId, sealed: CommandBatch) -> Result<CommandBatch> {
+        let envelope = match sealed.commands.as_slice() {
+            [command] if is_encrypted(&command.data) => &command.data,
+            _ if self.accept_plaintext => return Ok(sealed),
+            _ => {
+                return Err(corruption(format!(
+                    "decision log entry for phase {} is not encrypted",
+                    phase
+                )))
+            }
+        };
+        let plaintext = self.keys.open(envelope, &decision_context(phase))?;
+        bincode::deserialize(&plaintext).map_err(|e| {
+            corruption(format!(
+                "decision log entry for phase {} is malformed: {}",
+                phase, e
+            ))
+        })
+    }
+}
+
+#[async_trait]
+impl<P: PersistenceLayer> PersistenceLayer for EncryptedPersistence<P> {
+    async fn save_state(&self, state: &[u8]) -> Result<()> {
+        // The backend cannot read the phase from the sealed state
+        let phase = EngineState::peek_last_committed_phase(state).unwrap_or_default();
+        self.save_state_through(phase, state).await
+    }
+
+    async fn save_state_through(&self, phase: PhaseId, state: &[u8]) -> Result<()> {
+        let sealed = self.keys.seal(state, STATE_CONTEXT)?;
+        self.inner.save_state_through(phase, &sealed).await
+    }
+
+    async fn load_state(&self) -> Result<Option<Vec<u8>>> {
+        match self.inner.load_state().await? {
+            Some(data) => self.open_state(data, STATE_CONTEXT).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn append_state_delta(&self, record: &[u8]) -> Result<()> {
+        let sealed = self.keys.seal(record, STATE_DELTA_CONTEXT)?;
+        self.inner.append_state_delta(&sealed).await
+    }
+
+    async fn load_state_chain(&self) -> Result<Option<StateChain>> {
+        let Some((state, records)) = self.inner.load_state_chain().await? else {
+            return Ok(None);
+        };
+        let records = records
+            .into_iter()
+            .map(|record| self.open_state(record, STATE_DELTA_CONTEXT))
+            .collect::<Result<_>>()?;
+        Ok(Some((self.open_state(state, STATE_CONTEXT)?, records)))
+    }
+
+    fn has_decision_log(&self) -> bool {
+        self.inner.has_decision_log()
+    }
+
+    async fn append_decision(&self, phase: PhaseId, batch: &CommandBatch) -> Result<()> {
+        let sealed = self.seal_batch(phase, batch)?;
+        self.inner.append_decision(phase, &sealed).await
+    }
+
+    async fn append_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        let sealed = entries
+            .iter()
+            .map(|(phase, batch)| Ok((*phase, self.seal_batch(*phase, batch)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.append_decisions(&sealed).await
+    }
+
+    async fn load_decisions(&self, after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        self.inner
+            .load_decisions(after)
+            .await?
+            .into_iter()
+            .map(|(phase, sealed)| Ok((phase, self.open_batch(phase, sealed)?)))
+            .collect()
+    }
+
+    async fn truncate_decisions(&self, through: PhaseId) -> Result<()> {
+        self.inner.truncate_decisions(through).await
+    }
+
+    async fn truncated_through(&self) -> Result<PhaseId> {
+        self.inner.truncated_through().await
+    }
+
+    async fn next_incarnation(&self) -> Result<Option<u64>> {
+        self.inner.next_incarnation().await
+    }
+}
+
+const STATE_CONTEXT: &[u8] = b"rabia-state";
+const STATE_DELTA_CONTEXT: &[u8] = b"rabia-state-delta";
+
+fn decision_context(phase: PhaseId) -> Vec<u8> {
+    [b"rabia-decision".as_slice(), &phase.value().to_be_bytes()].concat()
+}
+
+fn envelope_header(key_id: u32) -> [u8; ENVELOPE_HEADER_LEN] {
+    let mut header = [0; ENVELOPE_HEADER_LEN];
+    header[..4].copy_from_slice(ENVELOPE_MAGIC);
+    header[4] = ENVELOPE_VERSION;
+    header[5..].copy_from_slice(&key_id.to_be_bytes());
+    header
+}
+
+fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(ENVELOPE_MAGIC)
+}
+
+fn corruption(details: impl fmt::Display) -> RabiaError {
+    RabiaError::StateCorruption {
+        details: format!("Encrypted persistence: {}", details),
+    }
+}
@@ -1,22 +1,148 @@
 use async_trait::async_trait;
-use rabia_core::{persistence::PersistenceLayer, RabiaError, Result};
+use rabia_core::{
+    freshness::clock_incarnation,
+    persistence::{EngineState, PersistenceLayer, StateChain},
+    CommandBatch, PhaseId, RabiaError, Result,
+};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// Magic bytes at the start of every state file
+const STATE_MAGIC: &[u8; 4] = b"RBST";
+/// Current state file format version
+const STATE_FORMAT_VERSION: u16 = 1;
+/// Magic, version, reserved, payload length and payload CRC32
+const STATE_HEADER_LEN: usize = 4 + 2 + 2 + 8 + 4;
+
+/// State file written before generations, possibly without a header
+const LEGACY_STATE_FILE: &str = "state.dat";
+const GENERATION_PREFIX: &str = "state-";
+const GENERATION_EXTENSION: &str = "dat";
+
+const DECISION_LOG_FILE: &str = "decisions.log";
+/// Incarnation counter, a big-endian u64 followed by its CRC32
+const INCARNATION_FILE: &str = "incarnation";
+/// Highest phase the decision log was truncated through, stored like the
+/// incarnation counter
+const TRUNCATED_THROUGH_FILE: &str = "truncated-through";
+/// A big-endian u64 followed by its CRC32
+const COUNTER_FILE_LEN: usize = 8 + 4;
+/// Payload length and payload CRC32 before every decision log record
+const RECORD_HEADER_LEN: usize = 4 + 4;
+
+/// Which state generations [`FileSystemPersistence`] keeps on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Number of most recent generations always kept; at least one
+    pub keep_last: usize,
+    /// Also keep generations whose phase is a multiple of this, as backups
+    pub backup_every_phases: Option<u64>,
+    /// Maximum number of backup generations beyond `keep_last`
+    pub max_backups: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 2,
+            backup_every_phases: None,
+            max_backups: 0,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Generations to delete from `phases`, which must be sorted newest first.
+    fn expired(&self, phases: &[PhaseId]) -> Vec<PhaseId> {
+        let keep_last = self.keep_last.max(1);
+        let mut backups = 0;
+        phases
+            .iter()
+            .skip(keep_last)
+            .filter(|phase| {
+                let is_backup = self
+                    .backup_every_phases
+                    .is_some_and(|every| every > 0 && phase.value().is_multiple_of(every));
+                if is_backup && backups < self.max_backups {
+                    backups += 1;
+                    false
+                } else {
+                    true
+                }
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// A stored state generation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    /// `last_committed_phase` of the stored state
+    pub phase: PhaseId,
+    pub path: PathBuf,
+    /// File size including the header
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
 
 /// Simple file-based persistence implementation.
 ///
-/// This implementation stores the state in a single file on disk. It provides
+/// This implementation stores the state in files on disk. It provides
 /// persistent storage that survives process restarts.
+///
+/// Each save is a generation named by the state's `last_committed_phase`
+/// (`state-<phase>.dat`); saving again at the same phase replaces that
+/// generation. Wrappers that store the state in another form, e.g.
+/// [`EncryptedPersistence`](crate::EncryptedPersistence), pass its phase with
+/// [`save_state_through`](PersistenceLayer::save_state_through); other
+/// payloads that are not an [`EngineState`] are stored as phase 0.
+/// Older generations are kept according to a [`RetentionPolicy`], and can be
+/// listed, loaded, or restored to roll the node back to a known-good phase.
+///
+/// Writes are durable: the new state is written to a temporary file and
+/// fsynced, renamed into place, and the directory is fsynced. Each file
+/// starts with a header holding a magic number, format version, payload
+/// length and CRC32, so a torn or corrupted generation is detected on load,
+/// moved aside with a `.corrupt` extension, and the next older one is used
+/// instead. A generation in a format this version cannot read, e.g. one
+/// written by a newer version, fails the load with
+/// [`RabiaError::UnsupportedFormat`] and is left in place.
+///
+/// The decision log is a single append-only file of length-prefixed,
+/// checksummed records, synced after every append. A record torn by a crash
+/// is cut off on the next load. Delta records appended to the newest
+/// generation go to a log of the same format next to it,
+/// `state-<phase>.dat.deltas`, which is pruned, rolled back and moved aside
+/// with its generation. Truncation never drops entries newer than the
+/// oldest retained generation, so any generation load falls back to can be
+/// brought up to date from the log.
+///
+/// The node's incarnation counter is kept in an `incarnation` file, replaced
+/// atomically on every start.
+///
+/// A `state.dat` left by an older version, with or without a header, is
+/// migrated into a generation when the directory is opened with
+/// [`new`](FileSystemPersistence::new), and then moved aside with a
+/// `.migrated` extension.
 #[derive(Debug, Clone)]
 pub struct FileSystemPersistence {
-    state_file_path: PathBuf,
+    data_dir: PathBuf,
+    retention: RetentionPolicy,
+    /// Serializes appends with truncation, which rewrites the log
+    log_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl FileSystemPersistence {
     /// Create a new file-based persistence instance.
     ///
     /// # Arguments
-    /// * `data_dir` - Directory path where the state file will be stored
+    /// * `data_dir` - Directory path where the state files will be stored
     ///
     /// # Returns
     /// * A new `FileSystemPersistence` instance
@@ -33,9 +159,36 @@ impl FileSystemPersistence {
             })?;
         }
 
-        let state_file_path = data_dir.join("state.dat");
+        let persistence = Self {
+            data_dir: data_dir.to_path_buf(),
+            retention: RetentionPolicy::default(),
+            log_lock: Arc::new(tokio::sync::Mutex::new(())),
+        };
+        persistence.migrate_legacy_state().await?;
+        Ok(persistence)
+    }
+
+    /// Open an existing data directory for inspection.
+    ///
+    /// Unlike [`new`](Self::new), nothing is created and a legacy `state.dat`
+    /// is left unmigrated, so opening does not modify the directory.
+    ///
+    /// # Errors
+    /// * Returns error if the data directory does not exist
+    pub async fn open_existing<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        if !data_dir.is_dir() {
+            return Err(RabiaError::persistence(format!(
+                "Data directory {} does not exist",
+                data_dir.display()
+            )));
+        }
 
-        Ok(Self { state_file_path })
+        Ok(Self {
+            data_dir: data_dir.to_path_buf(),
+            retention: RetentionPolicy::default(),
+            log_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
     }
 
     /// Create a new file-based persistence instance (synchronous).
@@ -43,7 +196,7 @@ impl FileSystemPersistence {
     /// This is a convenience method that blocks on the async `new` method.
     ///
     /// # Arguments
-    /// * `data_dir` - Directory path where the state file will be stored
+    /// * `data_dir` - Directory path where the state files will be stored
     ///
     /// # Returns
     /// * A new `FileSystemPersistence` instance
@@ -55,40 +208,840 @@ impl FileSystemPersistence {
             .map_err(|e| RabiaError::internal(format!("Failed to create runtime: {}", e)))?;
         runtime.block_on(Self::new(data_dir))
     }
+
+    /// Set which generations are kept after each save.
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    /// Stored generations, newest first.
+    pub async fn list_generations(&self) -> Result<Vec<Generation>> {
+        let mut entries = fs::read_dir(&self.data_dir).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to list data directory: {}", e))
+        })?;
+
+        let mut generations = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| RabiaError::persistence(format!("Failed to list data directory: {}", e)))?
+        {
+            let path = entry.path();
+            let Some(phase) = parse_generation_name(&path) else {
+                continue;
+            };
+            let metadata = entry.metadata().await.map_err(|e| {
+                RabiaError::persistence(format!("Failed to stat {}: {}", path.display(), e))
+            })?;
+            generations.push(Generation {
+                phase,
+                path,
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+
+        generations.sort_by_key(|generation| std::cmp::Reverse(generation.phase));
+        Ok(generations)
+    }
+
+    /// Load and verify the generation stored for `phase`, without moving it
+    /// aside if it does not verify.
+    pub async fn load_generation(&self, phase: PhaseId) -> Result<Vec<u8>> {
+        Self::read_state_file(&self.generation_path(phase))
+            .await?
+            .ok_or_else(|| {
+                RabiaError::persistence(format!("No state generation for phase {}", phase))
+            })
+    }
+
+    /// Make the generation for `phase` the latest, rolling the node back.
+    ///
+    /// Newer generations are not deleted but renamed with a timestamp and a
+    /// `.rolled-back` extension, so they are ignored on load and can be
+    /// inspected later, even after several rollbacks.
+    /// The decision log and its truncation record are moved aside the same
+    /// way, so its newer commits are not replayed on top of the restored state.
+    pub async fn restore_generation(&self, phase: PhaseId) -> Result<()> {
+        // Refuse to roll back onto a generation that does not verify
+        self.load_generation(phase).await?;
+        self.roll_back_after(Some(phase)).await
+    }
+
+    /// Roll the node back to an empty data directory.
+    ///
+    /// Like [`restore_generation`](Self::restore_generation), every generation
+    /// and the decision log are renamed with a timestamp and a `.rolled-back`
+    /// extension rather than deleted.
+    pub async fn reset(&self) -> Result<()> {
+        self.roll_back_after(None).await
+    }
+
+    /// Move aside the decision log, its truncation record and the generations
+    /// newer than `phase`, or all of them when `phase` is `None`.
+    async fn roll_back_after(&self, phase: Option<PhaseId>) -> Result<()> {
+        let _log = self.log_lock.lock().await;
+        let stamp = aside_stamp();
+        // The truncation record describes the log it goes with, and left
+        // behind it would make the restored state look like it missed commits
+        for path in [
+            self.decision_log_path(),
+            self.data_dir.join(TRUNCATED_THROUGH_FILE),
+        ] {
+            match fs::rename(&path, aside_path(&path, stamp, "rolled-back")).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(RabiaError::persistence(format!(
+                        "Failed to move aside {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+
+        for generation in self.list_generations().await? {
+            if phase.is_some_and(|phase| generation.phase <= phase) {
+                break;
+            }
+            let aside = aside_path(&generation.path, stamp, "rolled-back");
+            fs::rename(&generation.path, &aside).await.map_err(|e| {
+                RabiaError::persistence(format!(
+                    "Failed to move aside generation {}: {}",
+                    generation.phase, e
+                ))
+            })?;
+            let delta_log = delta_log_path(&generation.path);
+            match fs::rename(&delta_log, aside_path(&delta_log, stamp, "rolled-back")).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(RabiaError::persistence(format!(
+                        "Failed to move aside delta log of generation {}: {}",
+                        generation.phase, e
+                    )))
+                }
+            }
+            info!(
+                "Rolled back state generation {} to {}",
+                generation.phase,
+                aside.display()
+            );
+        }
+
+        self.sync_data_dir().await
+    }
+
+    /// Path of the generation holding the state at `phase`.
+    pub fn generation_path(&self, phase: PhaseId) -> PathBuf {
+        self.data_dir.join(format!(
+            "{}{:020}.{}",
+            GENERATION_PREFIX,
+            phase.value(),
+            GENERATION_EXTENSION
+        ))
+    }
+
+    /// Path of the decision log.
+    pub fn decision_log_path(&self) -> PathBuf {
+        self.data_dir.join(DECISION_LOG_FILE)
+    }
+
+    /// Whether a `state.dat` of an older version is waiting to be migrated.
+    pub fn has_legacy_state(&self) -> bool {
+        self.data_dir.join(LEGACY_STATE_FILE).exists()
+    }
+
+    /// Decision log entries after `after`, like
+    /// [`load_decisions`](PersistenceLayer::load_decisions), but a torn record
+    /// at the end of the log is left in place.
+    pub async fn read_decisions(&self, after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        let _log = self.log_lock.lock().await;
+        let entries = self.read_decision_log(false).await?;
+        Ok(decisions_after(entries, after))
+    }
+
+    /// Append encoded records to the decision log and sync them.
+    async fn append_decision_records(&self, records: &[u8]) -> Result<()> {
+        let _log = self.log_lock.lock().await;
+        let path = self.decision_log_path();
+        let created = !fs::try_exists(&path).await.unwrap_or(false);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| RabiaError::persistence(format!("Failed to open decision log: {}", e)))?;
+        file.write_all(records).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to append to decision log: {}", e))
+        })?;
+        file.sync_data()
+            .await
+            .map_err(|e| RabiaError::persistence(format!("Failed to sync decision log: {}", e)))?;
+
+        if created {
+            self.sync_data_dir().await?;
+        }
+        Ok(())
+    }
+
+    /// Read a counter file, e.g. the incarnation counter; `Ok(None)` if
+    /// there is none.
+    async fn read_counter(&self, file_name: &str) -> Result<Option<u64>> {
+        let path = self.data_dir.join(file_name);
+        let data = match fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(RabiaError::persistence(format!(
+                    "Failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        let valid = data.len() == COUNTER_FILE_LEN
+            && crc32fast::hash(&data[..8]) == u32::from_be_bytes(data[8..].try_into().unwrap());
+        if !valid {
+            return Err(RabiaError::StateCorruption {
+                details: format!("{}: unreadable counter", path.display()),
+            });
+        }
+        Ok(Some(u64::from_be_bytes(data[..8].try_into().unwrap())))
+    }
+
+    /// Durably replace a counter file.
+    async fn write_counter(&self, file_name: &str, value: u64) -> Result<()> {
+        let path = self.data_dir.join(file_name);
+        let temp_path = path.with_extension("tmp");
+        let bytes = value.to_be_bytes();
+        let mut data = bytes.to_vec();
+        data.extend_from_slice(&crc32fast::hash(&bytes).to_be_bytes());
+
+        let mut file = fs::File::create(&temp_path).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to create {}: {}", temp_path.display(), e))
+        })?;
+        file.write_all(&data).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to write {}: {}", temp_path.display(), e))
+        })?;
+        file.sync_all().await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to sync {}: {}", temp_path.display(), e))
+        })?;
+        drop(file);
+
+        fs::rename(&temp_path, &path).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to replace {}: {}", path.display(), e))
+        })?;
+        self.sync_data_dir().await
+    }
+
+    /// Read all decision log records, cutting off a torn record at the end
+    /// if `cut_torn` is set.
+    ///
+    /// Callers must hold `log_lock`.
+    async fn read_decision_log(&self, cut_torn: bool) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        let path = self.decision_log_path();
+        let data = match fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(RabiaError::persistence(format!(
+                    "Failed to read decision log: {}",
+                    e
+                )))
+            }
+        };
+
+        let (entries, valid_len) =
+            decode_decision_log(&data).map_err(|details| RabiaError::StateCorruption {
+                details: format!("{}: {}", path.display(), details),
+            })?;
+
+        if cut_torn && valid_len < data.len() {
+            cut_torn_record(&path, valid_len, data.len()).await?;
+        }
+
+        Ok(entries)
+    }
+
+    /// Read the delta records appended to the generation at
+    /// `generation_path`, cutting off a torn record at the end.
+    async fn read_delta_log(generation_path: &Path) -> Result<Vec<Vec<u8>>> {
+        let path = delta_log_path(generation_path);
+        let data = match fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(RabiaError::persistence(format!(
+                    "Failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        let (records, valid_len) =
+            decode_records(&data).map_err(|details| RabiaError::StateCorruption {
+                details: format!("{}: {}", path.display(), details),
+            })?;
+        let records = records
+            .into_iter()
+            .map(|(_, record)| record.to_vec())
+            .collect();
+
+        if valid_len < data.len() {
+            cut_torn_record(&path, valid_len, data.len()).await?;
+        }
+        Ok(records)
+    }
+
+    /// Load the newest generation that verifies, with its delta records if
+    /// `with_deltas` is set.
+    ///
+    /// Damaged generations are moved aside along with their delta logs.
+    async fn load_newest(&self, with_deltas: bool) -> Result<Option<StateChain>> {
+        let mut first_error = None;
+        for generation in self.list_generations().await? {
+            let loaded = match Self::read_state_file(&generation.path).await {
+                Ok(Some(state)) if with_deltas => Self::read_delta_log(&generation.path)
+                    .await
+                    .map(|records| Some((state, records))),
+                Ok(state) => Ok(state.map(|state| (state, Vec::new()))),
+                Err(e) => Err(e),
+            };
+            match loaded {
+                Ok(Some(chain)) => return Ok(Some(chain)),
+                // Removed since listing, e.g. by a concurrent prune
+                Ok(None) => continue,
+                Err(e @ RabiaError::StateCorruption { .. }) => {
+                    warn!(
+                        "State generation {} is unusable, trying older: {}",
+                        generation.phase, e
+                    );
+                    Self::quarantine(&generation.path).await;
+                    Self::quarantine(&delta_log_path(&generation.path)).await;
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Read and verify one state file; `Ok(None)` if it does not exist.
+    async fn read_state_file(path: &Path) -> Result<Option<Vec<u8>>> {
+        let data = match fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(RabiaError::persistence(format!(
+                    "Failed to read state file {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        decode_state_file(&data)
+            .map(Some)
+            .map_err(|e| e.into_error(path))
+    }
+
+    /// Turn the `state.dat` of an older version into a generation.
+    ///
+    /// The generation is written before the old file is moved aside, so a
+    /// crash in between only repeats the migration on the next open. An
+    /// unreadable `state.dat` is an error rather than a silent empty start.
+    async fn migrate_legacy_state(&self) -> Result<()> {
+        let path = self.data_dir.join(LEGACY_STATE_FILE);
+        let Some(state) = Self::read_legacy_state_file(&path).await? else {
+            return Ok(());
+        };
+
+        self.save_state(&state).await?;
+        let aside = aside_path(&path, aside_stamp(), "migrated");
+        fs::rename(&path, &aside).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to move aside {}: {}", path.display(), e))
+        })?;
+        self.sync_data_dir().await?;
+        info!("Migrated {} into a state generation", path.display());
+        Ok(())
+    }
+
+    /// Read the `state.dat` of an older version; `Ok(None)` if there is none.
+    async fn read_legacy_state_file(path: &Path) -> Result<Option<Vec<u8>>> {
+        let data = match fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(RabiaError::persistence(format!(
+                    "Failed to read state file {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        decode_legacy_state_file(&data)
+            .map(Some)
+            .map_err(|e| e.into_error(path))
+    }
+
+    /// Move a corrupt generation aside, so it is neither loaded nor counted
+    /// towards retention again.
+    async fn quarantine(path: &Path) {
+        let corrupt_path = path.with_extension("corrupt");
+        match fs::rename(path, &corrupt_path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to move corrupt state file aside: {}", e),
+        }
+    }
+
+    /// Delete generations the retention policy no longer keeps.
+    async fn prune(&self) -> Result<()> {
+        let phases: Vec<PhaseId> = self
+            .list_generations()
+            .await?
+            .into_iter()
+            .map(|generation| generation.phase)
+            .collect();
+
+        for phase in self.retention.expired(&phases) {
+            let path = self.generation_path(phase);
+            remove_if_present(&path).await?;
+            remove_if_present(&delta_log_path(&path)).await?;
+        }
+        Ok(())
+    }
+
+    /// Flush directory entries so renames survive a power loss.
+    async fn sync_data_dir(&self) -> Result<()> {
+        // Directories cannot be opened for syncing on every platform
+        #[cfg(unix)]
+        {
+            let dir = fs::File::open(&self.data_dir).await.map_err(|e| {
+                RabiaError::persistence(format!("Failed to open data directory: {}", e))
+            })?;
+            dir.sync_all().await.map_err(|e| {
+                RabiaError::persistence(format!("Failed to sync data directory: {}", e))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_generation_name(path: &Path) -> Option<PhaseId> {
+    if path.extension()? != GENERATION_EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    stem.strip_prefix(GENERATION_PREFIX)?
+        .parse()
+        .ok()
+        .map(PhaseId::new)
+}
+
+/// Delta records appended to a generation: `state-<phase>.dat.deltas`.
+fn delta_log_path(generation_path: &Path) -> PathBuf {
+    let mut path = generation_path.as_os_str().to_owned();
+    path.push(".deltas");
+    PathBuf::from(path)
+}
+
+async fn remove_if_present(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(RabiaError::persistence(format!(
+            "Failed to remove {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// The logged decisions after `after`, in phase order.
+fn decisions_after(
+    entries: Vec<(PhaseId, CommandBatch)>,
+    after: PhaseId,
+) -> Vec<(PhaseId, CommandBatch)> {
+    // Collecting by phase lets a phase logged again replace its earlier record
+    let decisions: BTreeMap<PhaseId, CommandBatch> = entries
+        .into_iter()
+        .filter(|(phase, _)| *phase > after)
+        .collect();
+    decisions.into_iter().collect()
+}
+
+/// Cut a torn final record off the file at `path`.
+async fn cut_torn_record(path: &Path, valid_len: usize, len: usize) -> Result<()> {
+    warn!(
+        "{} ends with a torn record, dropping its last {} bytes",
+        path.display(),
+        len - valid_len
+    );
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|e| {
+            RabiaError::persistence(format!("Failed to open {}: {}", path.display(), e))
+        })?;
+    file.set_len(valid_len as u64).await.map_err(|e| {
+        RabiaError::persistence(format!(
+            "Failed to cut torn record off {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    file.sync_all()
+        .await
+        .map_err(|e| RabiaError::persistence(format!("Failed to sync {}: {}", path.display(), e)))
+}
+
+/// Current time in microseconds, to keep files moved aside apart.
+fn aside_stamp() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros())
+        .unwrap_or_default()
+}
+
+/// `<file name>.<stamp>.<extension>` next to `path`.
+fn aside_path(path: &Path, stamp: u128, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.{}", stamp, extension));
+    path.with_file_name(name)
+}
+
+/// Prefix `state` with the state file header.
+fn encode_state_file(state: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(STATE_HEADER_LEN + state.len());
+    data.extend_from_slice(STATE_MAGIC);
+    data.extend_from_slice(&STATE_FORMAT_VERSION.to_be_bytes());
+    data.extend_from_slice(&0u16.to_be_bytes());
+    data.extend_from_slice(&(state.len() as u64).to_be_bytes());
+    data.extend_from_slice(&crc32fast::hash(state).to_be_bytes());
+    data.extend_from_slice(state);
+    data
+}
+
+/// Frame one decision log record.
+fn encode_decision_record(phase: PhaseId, batch: &CommandBatch) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(&(phase, batch))
+        .map_err(|e| RabiaError::serialization(format!("Failed to serialize decision: {}", e)))?;
+    Ok(frame_record(&payload))
+}
+
+/// Prefix a payload with its length and CRC32.
+fn frame_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    record.extend_from_slice(&crc32fast::hash(payload).to_be_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Decode decision log records, returning them with the length of the
+/// complete records. Anything after that length is a torn final record.
+fn decode_decision_log(
+    data: &[u8],
+) -> std::result::Result<(Vec<(PhaseId, CommandBatch)>, usize), String> {
+    let (payloads, valid_len) = decode_records(data)?;
+    let entries = payloads
+        .into_iter()
+        .map(|(position, payload)| {
+            bincode::deserialize(payload)
+                .map_err(|e| format!("undecodable record at offset {}: {}", position, e))
+        })
+        .collect::<std::result::Result<_, _>>()?;
+    Ok((entries, valid_len))
+}
+
+/// Offsets and payloads of framed records, with the length of the complete
+/// records.
+type DecodedRecords<'a> = (Vec<(usize, &'a [u8])>, usize);
+
+/// Split framed records into their offsets and payloads.
+fn decode_records(data: &[u8]) -> std::result::Result<DecodedRecords<'_>, String> {
+    let mut records = Vec::new();
+    let mut position = 0;
+
+    while data.len() - position >= RECORD_HEADER_LEN {
+        let header = &data[position..position + RECORD_HEADER_LEN];
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let expected = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let start = position + RECORD_HEADER_LEN;
+        let Some(payload) = data.get(start..start + length) else {
+            break;
+        };
+
+        let actual = crc32fast::hash(payload);
+        if expected != actual {
+            return Err(format!(
+                "CRC32 mismatch in record at offset {}: expected {:08x}, found {:08x}",
+                position, expected, actual
+            ));
+        }
+        records.push((position, payload));
+        position = start + length;
+    }
+
+    Ok((records, position))
+}
+
+/// Why a state file could not be decoded.
+#[derive(Debug)]
+enum StateFileError {
+    /// Torn or corrupted: the length or CRC32 does not match the header
+    Damaged(String),
+    /// Not a state file this version can read, e.g. a newer format
+    Unsupported(String),
+}
+
+impl StateFileError {
+    fn into_error(self, path: &Path) -> RabiaError {
+        match self {
+            Self::Damaged(details) => RabiaError::StateCorruption {
+                details: format!("{}: {}", path.display(), details),
+            },
+            Self::Unsupported(details) => RabiaError::UnsupportedFormat {
+                details: format!("{}: {}", path.display(), details),
+            },
+        }
+    }
+}
+
+/// Check the header of a state file and return its payload.
+fn decode_state_file(data: &[u8]) -> std::result::Result<Vec<u8>, StateFileError> {
+    if data.len() < STATE_HEADER_LEN {
+        return Err(StateFileError::Damaged(format!(
+            "file is {} bytes, shorter than the {} byte header",
+            data.len(),
+            STATE_HEADER_LEN
+        )));
+    }
+    let (header, payload) = data.split_at(STATE_HEADER_LEN);
+
+    if &header[0..4] != STATE_MAGIC {
+        return Err(StateFileError::Unsupported("bad magic number".to_string()));
+    }
+    let version = u16::from_be_bytes([header[4], header[5]]);
+    if version != STATE_FORMAT_VERSION {
+        return Err(StateFileError::Unsupported(format!(
+            "unsupported format version {}",
+            version
+        )));
+    }
+    let length = u64::from_be_bytes(header[8..16].try_into().unwrap());
+    if length != payload.len() as u64 {
+        return Err(StateFileError::Damaged(format!(
+            "header declares {} bytes but file holds {}",
+            length,
+            payload.len()
+        )));
+    }
+    let expected = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    let actual = crc32fast::hash(payload);
+    if expected != actual {
+        return Err(StateFileError::Damaged(format!(
+            "CRC32 mismatch: expected {:08x}, found {:08x}",
+            expected, actual
+        )));
+    }
+    Ok(payload.to_vec())
+}
+
+/// Return the payload of a legacy state file.
+///
+/// Files written before the header was introduced hold the bare payload;
+/// later ones are checked like any generation.
+fn decode_legacy_state_file(data: &[u8]) -> std::result::Result<Vec<u8>, StateFileError> {
+    if data.starts_with(STATE_MAGIC) {
+        decode_state_file(data)
+    } else {
+        Ok(data.to_vec())
+    }
 }
 
 #[async_trait]
 impl PersistenceLayer for FileSystemPersistence {
     async fn save_state(&self, state: &[u8]) -> Result<()> {
+        let phase = EngineState::peek_last_committed_phase(state).unwrap_or_default();
+        self.save_state_through(phase, state).await
+    }
+
+    async fn save_state_through(&self, phase: PhaseId, state: &[u8]) -> Result<()> {
+        let generation_path = self.generation_path(phase);
+
         // Write to a temporary file first, then atomically move to final location
-        let temp_file_path = self.state_file_path.with_extension("tmp");
+        let temp_file_path = generation_path.with_extension("tmp");
 
-        fs::write(&temp_file_path, state).await.map_err(|e| {
-            RabiaError::persistence(format!("Failed to write state to temp file: {}", e))
+        let mut file = fs::File::create(&temp_file_path).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to create temp state file: {}", e))
+        })?;
+        file.write_all(&encode_state_file(state))
+            .await
+            .map_err(|e| {
+                RabiaError::persistence(format!("Failed to write state to temp file: {}", e))
+            })?;
+        file.sync_all().await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to sync temp state file: {}", e))
         })?;
+        drop(file);
+
+        // Delta records of a generation saved again at this phase chain to
+        // the state being replaced. Losing them first is safe, since the
+        // decision log still holds everything after the generation's phase
+        remove_if_present(&delta_log_path(&generation_path)).await?;
 
-        // Atomically replace the old file with the new one
-        fs::rename(&temp_file_path, &self.state_file_path)
+        fs::rename(&temp_file_path, &generation_path)
             .await
             .map_err(|e| {
                 RabiaError::persistence(format!("Failed to rename temp file to state file: {}", e))
             })?;
+        self.sync_data_dir().await?;
 
-        Ok(())
+        self.prune().await
     }
 
     async fn load_state(&self) -> Result<Option<Vec<u8>>> {
-        if !self.state_file_path.exists() {
-            return Ok(None);
+        Ok(self.load_newest(false).await?.map(|(state, _)| state))
+    }
+
+    async fn append_state_delta(&self, record: &[u8]) -> Result<()> {
+        let Some(newest) = self.list_generations().await?.into_iter().next() else {
+            return Err(RabiaError::persistence(
+                "Cannot append a delta record without a saved state",
+            ));
+        };
+        let path = delta_log_path(&newest.path);
+        let created = !fs::try_exists(&path).await.unwrap_or(false);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| {
+                RabiaError::persistence(format!("Failed to open {}: {}", path.display(), e))
+            })?;
+        file.write_all(&frame_record(record)).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to append to {}: {}", path.display(), e))
+        })?;
+        file.sync_data().await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to sync {}: {}", path.display(), e))
+        })?;
+
+        if created {
+            self.sync_data_dir().await?;
+        }
+        Ok(())
+    }
+
+    async fn load_state_chain(&self) -> Result<Option<StateChain>> {
+        self.load_newest(true).await
+    }
+
+    fn has_decision_log(&self) -> bool {
+        true
+    }
+
+    async fn append_decision(&self, phase: PhaseId, batch: &CommandBatch) -> Result<()> {
+        let record = encode_decision_record(phase, batch)?;
+        self.append_decision_records(&record).await
+    }
+
+    async fn append_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut records = Vec::new();
+        for (phase, batch) in entries {
+            records.extend_from_slice(&encode_decision_record(*phase, batch)?);
+        }
+        self.append_decision_records(&records).await
+    }
+
+    async fn load_decisions(&self, after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        let _log = self.log_lock.lock().await;
+        let entries = self.read_decision_log(true).await?;
+        Ok(decisions_after(entries, after))
+    }
+
+    async fn truncate_decisions(&self, through: PhaseId) -> Result<()> {
+        // Falling back to an older generation replays the log from its phase,
+        // so keep the entries every retained generation still needs
+        let through = match self.list_generations().await?.last() {
+            Some(oldest) => through.min(oldest.phase),
+            None => through,
+        };
+
+        let _log = self.log_lock.lock().await;
+        let entries = self.read_decision_log(true).await?;
+        if entries.iter().all(|(phase, _)| *phase > through) {
+            return Ok(());
+        }
+
+        // Recorded first, so a crash during the rewrite errs on the side of
+        // reporting entries as dropped
+        if self.read_counter(TRUNCATED_THROUGH_FILE).await? < Some(through.value()) {
+            self.write_counter(TRUNCATED_THROUGH_FILE, through.value())
+                .await?;
         }
 
-        match fs::read(&self.state_file_path).await {
-            Ok(data) => Ok(Some(data)),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(RabiaError::persistence(format!(
-                "Failed to read state file: {}",
-                e
-            ))),
+        let mut data = Vec::new();
+        for (phase, batch) in entries.iter().filter(|(phase, _)| *phase > through) {
+            data.extend_from_slice(&encode_decision_record(*phase, batch)?);
         }
+
+        let path = self.decision_log_path();
+        let temp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&temp_path).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to create temp decision log: {}", e))
+        })?;
+        file.write_all(&data).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to write temp decision log: {}", e))
+        })?;
+        file.sync_all().await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to sync temp decision log: {}", e))
+        })?;
+        drop(file);
+
+        fs::rename(&temp_path, &path).await.map_err(|e| {
+            RabiaError::persistence(format!("Failed to replace decision log: {}", e))
+        })?;
+        self.sync_data_dir().await
+    }
+
+    async fn truncated_through(&self) -> Result<PhaseId> {
+        let _log = self.log_lock.lock().await;
+        Ok(PhaseId::new(
+            self.read_counter(TRUNCATED_THROUGH_FILE)
+                .await?
+                .unwrap_or_default(),
+        ))
+    }
+
+    async fn next_incarnation(&self) -> Result<Option<u64>> {
+        // The first counter starts from the clock, above the clock-based
+        // incarnations this node may have used before
+        let incarnation = match self.read_counter(INCARNATION_FILE).await? {
+            Some(stored) => stored + 1,
+            None => clock_incarnation(),
+        };
+        self.write_counter(INCARNATION_FILE, incarnation).await?;
+        Ok(Some(incarnation))
     }
 }
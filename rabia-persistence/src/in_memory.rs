@@ -1,16 +1,23 @@
 use async_trait::async_trait;
 use parking_lot::RwLock;
-use rabia_core::{persistence::PersistenceLayer, Result};
+use rabia_core::{
+    persistence::{PersistenceLayer, StateChain},
+    CommandBatch, PhaseId, RabiaError, Result,
+};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 /// Simple in-memory persistence implementation.
 ///
-/// This implementation stores a single state value in memory. It's suitable for
+/// This implementation stores a single state value, the delta records appended
+/// to it and the decision log in memory. It's suitable for
 /// testing and non-persistent scenarios where state doesn't need to survive
 /// process restarts.
 #[derive(Debug, Clone)]
 pub struct InMemoryPersistence {
     state: Arc<RwLock<Option<Vec<u8>>>>,
+    state_deltas: Arc<RwLock<Vec<Vec<u8>>>>,
+    decisions: Arc<RwLock<BTreeMap<PhaseId, CommandBatch>>>,
 }
 
 impl InMemoryPersistence {
@@ -18,6 +25,8 @@ impl InMemoryPersistence {
     pub fn new() -> Self {
         Self {
             state: Arc::new(RwLock::new(None)),
+            state_deltas: Arc::new(RwLock::new(Vec::new())),
+            decisions: Arc::new(RwLock::new(BTreeMap::new())),
         }
     }
 }
@@ -33,6 +42,7 @@ impl PersistenceLayer for InMemoryPersistence {
     async fn save_state(&self, state: &[u8]) -> Result<()> {
         let mut current_state = self.state.write();
         *current_state = Some(state.to_vec());
+        self.state_deltas.write().clear();
         Ok(())
     }
 
@@ -40,4 +50,52 @@ impl PersistenceLayer for InMemoryPersistence {
         let state = self.state.read();
         Ok(state.clone())
     }
+
+    async fn append_state_delta(&self, record: &[u8]) -> Result<()> {
+        let state = self.state.read();
+        if state.is_none() {
+            return Err(RabiaError::persistence(
+                "Cannot append a delta record without a saved state",
+            ));
+        }
+        self.state_deltas.write().push(record.to_vec());
+        Ok(())
+    }
+
+    async fn load_state_chain(&self) -> Result<Option<StateChain>> {
+        let state = self.state.read();
+        Ok(state
+            .clone()
+            .map(|state| (state, self.state_deltas.read().clone())))
+    }
+
+    fn has_decision_log(&self) -> bool {
+        true
+    }
+
+    async fn append_decision(&self, phase: PhaseId, batch: &CommandBatch) -> Result<()> {
+        self.decisions.write().insert(phase, batch.clone());
+        Ok(())
+    }
+
+    async fn append_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        let mut decisions = self.decisions.write();
+        for (phase, batch) in entries {
+            decisions.insert(*phase, batch.clone());
+        }
+        Ok(())
+    }
+
+    async fn load_decisions(&self, after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        let decisions = self.decisions.read();
+        Ok(decisions
+            .range(after.next()..)
+            .map(|(phase, batch)| (*phase, batch.clone()))
+            .collect())
+    }
+
+    async fn truncate_decisions(&self, through: PhaseId) -> Result<()> {
+        self.decisions.write().retain(|phase, _| *phase > through);
+        Ok(())
+    }
 }
@@ -8,7 +8,12 @@
 //! ## Implementations
 //!
 //! - [`InMemoryPersistence`] - State stored in memory (testing/non-persistent)
-//! - [`FileSystemPersistence`] - State stored in a file (persistent across restarts)
+//! - [`EmbeddedPersistence`] - State, decided-batch log and snapshot chunks in an embedded
+//!   transactional database
+//! - [`FileSystemPersistence`] - State stored in checksummed, fsynced files (persistent across restarts),
+//!   with retained generations per committed phase for point-in-time restore
+//! - [`EncryptedPersistence`] - Wraps any of the above and encrypts state and decision log entries at
+//!   rest, with key ids for rotation
 //!
 //! ## Example
 //!
@@ -31,9 +36,13 @@
 //! # });
 //! ```
 
+pub mod embedded;
+pub mod encrypted;
 pub mod file_system;
 pub mod in_memory;
 mod tests;
 
-pub use file_system::FileSystemPersistence;
+pub use embedded::EmbeddedPersistence;
+pub use encrypted::{EncryptedPersistence, EncryptionKeys};
+pub use file_system::{FileSystemPersistence, Generation, RetentionPolicy};
 pub use in_memory::InMemoryPersistence;
@@ -1,7 +1,8 @@
 #[cfg(test)]
 mod unit_tests {
-    use crate::{FileSystemPersistence, InMemoryPersistence};
+    use crate::{EncryptedPersistence, EncryptionKeys, FileSystemPersistence, InMemoryPersistence};
     use rabia_core::persistence::PersistenceLayer;
+    use rabia_core::{Command, CommandBatch, PhaseId, RabiaError};
 
     #[tokio::test]
     async fn test_in_memory_persistence() {
@@ -59,6 +60,750 @@ mod unit_tests {
         assert_eq!(loaded3, Some(new_data.to_vec()));
     }
 
+    fn state_at(phase: u64) -> Vec<u8> {
+        use rabia_core::{persistence::EngineState, PhaseId};
+
+        EngineState::new(PhaseId::new(phase + 1), PhaseId::new(phase), None)
+            .to_bytes()
+            .unwrap()
+    }
+
+    fn generation_file(dir: &std::path::Path, phase: u64) -> std::path::PathBuf {
+        dir.join(format!("state-{:020}.dat", phase))
+    }
+
+    /// Files in `dir` whose name starts with `prefix` and ends with `suffix`.
+    fn files_named(dir: &std::path::Path, prefix: &str, suffix: &str) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.unwrap().file_name().into_string().ok())
+            .filter(|name| name.starts_with(prefix) && name.ends_with(suffix))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_file_system_persistence_writes_header() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        persistence.save_state(b"state").await.unwrap();
+
+        // Payloads that are not an engine state are stored as phase 0
+        let raw = std::fs::read(generation_file(temp_dir.path(), 0)).unwrap();
+        assert_eq!(&raw[..4], b"RBST");
+        assert!(raw.ends_with(b"state"));
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_headerless_legacy_state_is_loaded_and_rewritten() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("state.dat"), state_at(4)).unwrap();
+
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        assert_eq!(persistence.load_state().await.unwrap(), Some(state_at(4)));
+
+        // It is rewritten as a headered generation
+        let raw = std::fs::read(generation_file(temp_dir.path(), 4)).unwrap();
+        assert_eq!(&raw[..4], b"RBST");
+        persistence.save_state(&state_at(5)).await.unwrap();
+        assert_eq!(persistence.load_state().await.unwrap(), Some(state_at(5)));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_state_file_is_migrated_on_open() {
+        use rabia_core::PhaseId;
+        use tempfile::TempDir;
+
+        // Save with the single-file layout: one headered state.dat
+        let temp_dir = TempDir::new().unwrap();
+        let logged = decisions(7..=7);
+        {
+            let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+            persistence.save_state(&state_at(6)).await.unwrap();
+            persistence.append_decisions(&logged).await.unwrap();
+        }
+        std::fs::rename(
+            generation_file(temp_dir.path(), 6),
+            temp_dir.path().join("state.dat"),
+        )
+        .unwrap();
+
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        assert_eq!(persistence.load_state().await.unwrap(), Some(state_at(6)));
+        assert_eq!(
+            persistence.load_decisions(PhaseId::new(6)).await.unwrap(),
+            logged
+        );
+        let phases: Vec<u64> = persistence
+            .list_generations()
+            .await
+            .unwrap()
+            .iter()
+            .map(|generation| generation.phase.value())
+            .collect();
+        assert_eq!(phases, vec![6]);
+        assert!(!temp_dir.path().join("state.dat").exists());
+        assert_eq!(files_named(temp_dir.path(), "state.dat.", ".migrated"), 1);
+
+        // A corrupt legacy file refuses to open instead of starting empty
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("state.dat"), b"RBST-torn").unwrap();
+        assert!(matches!(
+            FileSystemPersistence::new(temp_dir.path()).await,
+            Err(RabiaError::StateCorruption { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_state_falls_back_to_previous_generation() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        persistence.save_state(&state_at(1)).await.unwrap();
+        persistence.save_state(&state_at(2)).await.unwrap();
+
+        // Flip a payload bit in the latest generation
+        let latest = generation_file(temp_dir.path(), 2);
+        let mut raw = std::fs::read(&latest).unwrap();
+        let last = raw.len() - 1;
+        raw[last] ^= 0x01;
+        std::fs::write(&latest, &raw).unwrap();
+
+        let loaded = persistence.load_state().await.unwrap();
+        assert_eq!(loaded, Some(state_at(1)));
+        assert!(latest.with_extension("corrupt").exists());
+
+        // The good generation survives the next save
+        persistence.save_state(&state_at(3)).await.unwrap();
+        std::fs::write(generation_file(temp_dir.path(), 3), b"garbage").unwrap();
+        let loaded = persistence.load_state().await.unwrap();
+        assert_eq!(loaded, Some(state_at(1)));
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_state_without_fallback_is_an_error() {
+        use rabia_core::RabiaError;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        persistence.save_state(&state_at(5)).await.unwrap();
+
+        // Simulate a torn write
+        let state_path = generation_file(temp_dir.path(), 5);
+        let raw = std::fs::read(&state_path).unwrap();
+        std::fs::write(&state_path, &raw[..raw.len() / 2]).unwrap();
+
+        let result = persistence.load_state().await;
+        assert!(matches!(result, Err(RabiaError::StateCorruption { .. })));
+
+        // An empty file left by a power loss is detected too
+        std::fs::write(&state_path, b"").unwrap();
+        let result = persistence.load_state().await;
+        assert!(matches!(result, Err(RabiaError::StateCorruption { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_state_version_is_left_in_place() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        persistence.save_state(&state_at(1)).await.unwrap();
+        persistence.save_state(&state_at(2)).await.unwrap();
+
+        // As if written by a newer version
+        let latest = generation_file(temp_dir.path(), 2);
+        let mut raw = std::fs::read(&latest).unwrap();
+        raw[4..6].copy_from_slice(&2u16.to_be_bytes());
+        std::fs::write(&latest, &raw).unwrap();
+
+        let result = persistence.load_state().await;
+        assert!(matches!(result, Err(RabiaError::UnsupportedFormat { .. })));
+        // Neither quarantined nor skipped in favour of the older generation
+        assert!(latest.exists());
+        assert!(!latest.with_extension("corrupt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_retention_keeps_recent_generations_and_backups() {
+        use crate::file_system::RetentionPolicy;
+        use rabia_core::PhaseId;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path())
+            .await
+            .unwrap()
+            .with_retention(RetentionPolicy {
+                keep_last: 2,
+                backup_every_phases: Some(10),
+                max_backups: 2,
+            });
+
+        for phase in 1..=35 {
+            persistence.save_state(&state_at(phase)).await.unwrap();
+        }
+
+        let phases: Vec<u64> = persistence
+            .list_generations()
+            .await
+            .unwrap()
+            .iter()
+            .map(|generation| generation.phase.value())
+            .collect();
+        assert_eq!(phases, vec![35, 34, 30, 20]);
+
+        let older = persistence.load_generation(PhaseId::new(20)).await.unwrap();
+        assert_eq!(older, state_at(20));
+        assert!(persistence.load_generation(PhaseId::new(10)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restore_generation_rolls_back() {
+        use rabia_core::PhaseId;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        persistence.save_state(&state_at(7)).await.unwrap();
+        persistence.save_state(&state_at(8)).await.unwrap();
+        let (phase, batch) = decisions(9..=9).remove(0);
+        persistence.append_decision(phase, &batch).await.unwrap();
+
+        persistence
+            .restore_generation(PhaseId::new(7))
+            .await
+            .unwrap();
+        assert_eq!(persistence.load_state().await.unwrap(), Some(state_at(7)));
+        // Commits after the restored generation are not replayed
+        assert!(persistence
+            .load_decisions(PhaseId::new(7))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            files_named(
+                temp_dir.path(),
+                "state-00000000000000000008.dat.",
+                ".rolled-back"
+            ),
+            1
+        );
+
+        // Restoring a phase that was never saved fails without changes
+        assert!(persistence
+            .restore_generation(PhaseId::new(3))
+            .await
+            .is_err());
+        assert_eq!(persistence.load_state().await.unwrap(), Some(state_at(7)));
+
+        // Resetting moves aside everything that is left, without replacing
+        // what earlier rollbacks moved aside
+        persistence.append_decision(phase, &batch).await.unwrap();
+        persistence.reset().await.unwrap();
+        assert!(persistence.load_state().await.unwrap().is_none());
+        assert!(persistence.list_generations().await.unwrap().is_empty());
+        assert_eq!(
+            files_named(
+                temp_dir.path(),
+                "state-00000000000000000007.dat.",
+                ".rolled-back"
+            ),
+            1
+        );
+        assert_eq!(
+            files_named(temp_dir.path(), "decisions.log.", ".rolled-back"),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embedded_persistence_splits_and_restores_state() {
+        use crate::EmbeddedPersistence;
+        use rabia_core::{persistence::EngineState, state_machine::Snapshot, PhaseId};
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("rabia.redb");
+        let snapshot = Snapshot::new(4, (0..=255u8).cycle().take(10_000).collect::<Vec<_>>());
+        let state = EngineState::new(PhaseId::new(9), PhaseId::new(8), Some(snapshot))
+            .to_bytes()
+            .unwrap();
+
+        {
+            let persistence = EmbeddedPersistence::open(&path)
+                .unwrap()
+                .with_chunk_size(1024);
+            assert!(persistence.load_state().await.unwrap().is_none());
+            persistence.save_state(&state).await.unwrap();
+        }
+
+        // Reopen as after a restart
+        let persistence = EmbeddedPersistence::open(&path).unwrap();
+        let loaded = persistence.load_state().await.unwrap().unwrap();
+        let loaded = EngineState::from_bytes(&loaded).unwrap();
+        let expected = EngineState::from_bytes(&state).unwrap();
+        assert_eq!(loaded.last_committed_phase, expected.last_committed_phase);
+        let (loaded, expected) = (loaded.snapshot.unwrap(), expected.snapshot.unwrap());
+        assert_eq!(loaded.version, expected.version);
+        assert_eq!(loaded.data, expected.data);
+        assert!(loaded.verify_checksum());
+
+        // A state without a snapshot drops the stored chunks
+        let bare = EngineState::new(PhaseId::new(10), PhaseId::new(9), None)
+            .to_bytes()
+            .unwrap();
+        persistence.save_state(&bare).await.unwrap();
+        let loaded = persistence.load_state().await.unwrap().unwrap();
+        assert!(EngineState::from_bytes(&loaded).unwrap().snapshot.is_none());
+
+        // Opaque payloads are stored as they are
+        persistence.save_state(b"opaque").await.unwrap();
+        assert_eq!(
+            persistence.load_state().await.unwrap(),
+            Some(b"opaque".to_vec())
+        );
+
+        // Encrypted states are opaque too, and chunked as a whole
+        let encrypted = EncryptedPersistence::new(
+            persistence.with_chunk_size(1024),
+            EncryptionKeys::new(1, [7; 32]),
+        );
+        encrypted.save_state(&state).await.unwrap();
+        assert_eq!(encrypted.load_state().await.unwrap(), Some(state));
+    }
+
+    #[tokio::test]
+    async fn test_embedded_persistence_batch_log() {
+        use crate::EmbeddedPersistence;
+        use rabia_core::{Command, CommandBatch, PhaseId};
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = EmbeddedPersistence::open(temp_dir.path().join("rabia.redb")).unwrap();
+
+        let entries: Vec<(PhaseId, CommandBatch)> = (1..=5)
+            .map(|phase| {
+                let batch = CommandBatch::new(vec![Command::new(format!("SET k{} v", phase))]);
+                (PhaseId::new(phase), batch)
+            })
+            .collect();
+        persistence.append_decided(entries.clone()).await.unwrap();
+
+        let middle = persistence
+            .decided_batches(PhaseId::new(2), PhaseId::new(4))
+            .await
+            .unwrap();
+        assert_eq!(middle, entries[1..3].to_vec());
+
+        let removed = persistence
+            .truncate_log_before(PhaseId::new(4))
+            .await
+            .unwrap();
+        assert_eq!(removed, 3);
+        let remaining = persistence
+            .decided_batches(PhaseId::new(0), PhaseId::new(u64::MAX))
+            .await
+            .unwrap();
+        assert_eq!(remaining, entries[3..].to_vec());
+    }
+
+    fn decisions(phases: std::ops::RangeInclusive<u64>) -> Vec<(PhaseId, CommandBatch)> {
+        phases
+            .map(|phase| {
+                let batch = CommandBatch::new(vec![Command::new(format!("SET k{} v", phase))]);
+                (PhaseId::new(phase), batch)
+            })
+            .collect()
+    }
+
+    async fn check_decision_log<P: PersistenceLayer>(persistence: &P) {
+        let entries = decisions(1..=5);
+        for (phase, batch) in &entries {
+            persistence.append_decision(*phase, batch).await.unwrap();
+        }
+
+        let after = persistence.load_decisions(PhaseId::new(2)).await.unwrap();
+        assert_eq!(after, entries[2..].to_vec());
+
+        persistence
+            .truncate_decisions(PhaseId::new(3))
+            .await
+            .unwrap();
+        let remaining = persistence.load_decisions(PhaseId::new(0)).await.unwrap();
+        assert_eq!(remaining, entries[3..].to_vec());
+
+        // Logging a phase again replaces its entry
+        let (phase, _) = entries[4].clone();
+        let replacement = CommandBatch::new(vec![Command::new("DEL k5")]);
+        persistence
+            .append_decision(phase, &replacement)
+            .await
+            .unwrap();
+        let remaining = persistence.load_decisions(PhaseId::new(4)).await.unwrap();
+        assert_eq!(remaining, vec![(phase, replacement.clone())]);
+
+        // A group append lands every entry
+        let group = decisions(6..=9);
+        persistence.append_decisions(&group).await.unwrap();
+        persistence.append_decisions(&[]).await.unwrap();
+        let mut expected = vec![(phase, replacement)];
+        expected.extend(group);
+        let remaining = persistence.load_decisions(PhaseId::new(4)).await.unwrap();
+        assert_eq!(remaining, expected);
+    }
+
+    #[tokio::test]
+    async fn test_decision_log() {
+        use crate::EmbeddedPersistence;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        check_decision_log(&InMemoryPersistence::new()).await;
+        check_decision_log(
+            &FileSystemPersistence::new(temp_dir.path().join("fs"))
+                .await
+                .unwrap(),
+        )
+        .await;
+        check_decision_log(&EmbeddedPersistence::open(temp_dir.path().join("rabia.redb")).unwrap())
+            .await;
+        check_decision_log(&EncryptedPersistence::new(
+            InMemoryPersistence::new(),
+            EncryptionKeys::new(1, [7; 32]),
+        ))
+        .await;
+    }
+
+    async fn check_state_chain<P: PersistenceLayer>(persistence: &P) {
+        // A delta record needs a saved state to go on
+        assert!(persistence.append_state_delta(&state_at(1)).await.is_err());
+        assert!(persistence.load_state_chain().await.unwrap().is_none());
+
+        persistence.save_state(&state_at(1)).await.unwrap();
+        persistence.append_state_delta(&state_at(2)).await.unwrap();
+        persistence.append_state_delta(&state_at(3)).await.unwrap();
+        let chain = persistence.load_state_chain().await.unwrap();
+        assert_eq!(chain, Some((state_at(1), vec![state_at(2), state_at(3)])));
+
+        // A full save starts a new chain
+        persistence.save_state(&state_at(4)).await.unwrap();
+        let chain = persistence.load_state_chain().await.unwrap();
+        assert_eq!(chain, Some((state_at(4), Vec::new())));
+    }
+
+    #[tokio::test]
+    async fn test_state_chain() {
+        use crate::EmbeddedPersistence;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        check_state_chain(&InMemoryPersistence::new()).await;
+        let fs_dir = temp_dir.path().join("fs");
+        check_state_chain(&FileSystemPersistence::new(&fs_dir).await.unwrap()).await;
+        check_state_chain(&EmbeddedPersistence::open(temp_dir.path().join("rabia.redb")).unwrap())
+            .await;
+        check_state_chain(&EncryptedPersistence::new(
+            InMemoryPersistence::new(),
+            EncryptionKeys::new(1, [7; 32]),
+        ))
+        .await;
+
+        // Records sit next to the generation they go on
+        let delta_log = |phase| {
+            let mut log = generation_file(&fs_dir, phase).into_os_string();
+            log.push(".deltas");
+            std::path::PathBuf::from(log)
+        };
+        assert!(delta_log(1).exists());
+        assert!(!delta_log(4).exists());
+        let persistence = FileSystemPersistence::new(&fs_dir).await.unwrap();
+        persistence.append_state_delta(&state_at(5)).await.unwrap();
+        assert!(delta_log(4).exists());
+        let chain = persistence.load_state_chain().await.unwrap();
+        assert_eq!(chain, Some((state_at(4), vec![state_at(5)])));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_persistence_hides_and_authenticates_state() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = EncryptedPersistence::new(
+            FileSystemPersistence::new(temp_dir.path()).await.unwrap(),
+            EncryptionKeys::new(1, [7; 32]),
+        );
+        persistence.save_state(b"alice=100").await.unwrap();
+        let entries = decisions(1..=1);
+        persistence.append_decisions(&entries).await.unwrap();
+
+        // Nothing on disk holds the plaintext
+        for entry in std::fs::read_dir(temp_dir.path()).unwrap() {
+            let data = std::fs::read(entry.unwrap().path()).unwrap();
+            for secret in [&b"alice=100"[..], b"SET k1 v"] {
+                assert!(!data.windows(secret.len()).any(|w| w == secret));
+            }
+        }
+        assert_eq!(
+            persistence.load_state().await.unwrap(),
+            Some(b"alice=100".to_vec())
+        );
+        assert_eq!(
+            persistence.load_decisions(PhaseId::new(0)).await.unwrap(),
+            entries
+        );
+
+        // A wrong key fails authentication
+        let wrong_key = EncryptedPersistence::new(
+            FileSystemPersistence::new(temp_dir.path()).await.unwrap(),
+            EncryptionKeys::new(1, [8; 32]),
+        );
+        assert!(matches!(
+            wrong_key.load_state().await,
+            Err(RabiaError::StateCorruption { .. })
+        ));
+
+        // A tampered value fails authentication
+        let inner = InMemoryPersistence::new();
+        let persistence = EncryptedPersistence::new(inner.clone(), EncryptionKeys::new(1, [7; 32]));
+        persistence.save_state(b"alice=100").await.unwrap();
+        let mut sealed = inner.load_state().await.unwrap().unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        inner.save_state(&sealed).await.unwrap();
+        assert!(matches!(
+            persistence.load_state().await,
+            Err(RabiaError::StateCorruption { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_persistence_key_rotation() {
+        let inner = InMemoryPersistence::new();
+        let old = EncryptedPersistence::new(inner.clone(), EncryptionKeys::new(1, [1; 32]));
+        old.save_state(b"written with key 1").await.unwrap();
+        let entries = decisions(1..=2);
+        old.append_decisions(&entries).await.unwrap();
+
+        // Only the active key is used for new values
+        let rotated = EncryptedPersistence::new(
+            inner.clone(),
+            EncryptionKeys::new(2, [2; 32])
+                .with_retired_key(1, [1; 32])
+                .unwrap(),
+        );
+        assert_eq!(
+            rotated.load_state().await.unwrap(),
+            Some(b"written with key 1".to_vec())
+        );
+        assert_eq!(
+            rotated.load_decisions(PhaseId::new(0)).await.unwrap(),
+            entries
+        );
+        rotated.save_state(b"written with key 2").await.unwrap();
+
+        let new_only = EncryptedPersistence::new(inner.clone(), EncryptionKeys::new(2, [2; 32]));
+        assert_eq!(
+            new_only.load_state().await.unwrap(),
+            Some(b"written with key 2".to_vec())
+        );
+        assert!(matches!(
+            new_only.load_decisions(PhaseId::new(0)).await,
+            Err(RabiaError::StateCorruption { .. })
+        ));
+        assert!(EncryptionKeys::new(2, [2; 32])
+            .with_retired_key(2, [1; 32])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_persistence_plaintext_reads() {
+        let inner = InMemoryPersistence::new();
+        inner.save_state(b"from before encryption").await.unwrap();
+
+        let strict = EncryptedPersistence::new(inner.clone(), EncryptionKeys::new(1, [7; 32]));
+        assert!(matches!(
+            strict.load_state().await,
+            Err(RabiaError::StateCorruption { .. })
+        ));
+
+        let migrating = strict.with_plaintext_reads(true);
+        assert_eq!(
+            migrating.load_state().await.unwrap(),
+            Some(b"from before encryption".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_file_system_keeps_generations_by_phase() {
+        use rabia_core::{persistence::EngineState, state_machine::Snapshot};
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let keys = EncryptionKeys::new(1, [7; 32]);
+        let engine_state = |phase: u64| {
+            let snapshot = Snapshot::new(phase, format!("balances at {}", phase).into_bytes());
+            EngineState::new(PhaseId::new(phase + 1), PhaseId::new(phase), Some(snapshot))
+                .to_bytes()
+                .unwrap()
+        };
+
+        // A plaintext generation from before encryption was enabled
+        let plaintext = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        plaintext.save_state(&state_at(5)).await.unwrap();
+
+        let persistence = EncryptedPersistence::new(
+            FileSystemPersistence::new(temp_dir.path()).await.unwrap(),
+            keys.clone(),
+        )
+        .with_plaintext_reads(true);
+        assert_eq!(persistence.load_state().await.unwrap(), Some(state_at(5)));
+        persistence.save_state(&engine_state(7)).await.unwrap();
+        persistence.save_state(&engine_state(9)).await.unwrap();
+
+        let phases: Vec<_> = persistence
+            .inner()
+            .list_generations()
+            .await
+            .unwrap()
+            .iter()
+            .map(|generation| generation.phase.value())
+            .collect();
+        // Retention pruned the plaintext generation
+        assert_eq!(phases, vec![9, 7]);
+        let stored = std::fs::read(generation_file(temp_dir.path(), 9)).unwrap();
+        assert!(!stored
+            .windows(b"balances".len())
+            .any(|window| window == b"balances"));
+
+        // Reopen as after a restart: the newest encrypted save is loaded
+        let reopened = EncryptedPersistence::new(
+            FileSystemPersistence::new(temp_dir.path()).await.unwrap(),
+            keys,
+        );
+        assert_eq!(reopened.load_state().await.unwrap(), Some(engine_state(9)));
+    }
+
+    #[tokio::test]
+    async fn test_file_system_decision_log_cuts_torn_record() {
+        use std::io::Write;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        let entries = decisions(1..=2);
+        for (phase, batch) in &entries {
+            persistence.append_decision(*phase, batch).await.unwrap();
+        }
+
+        // Simulate a crash halfway through an append
+        let log_path = temp_dir.path().join("decisions.log");
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .unwrap()
+            .write_all(&[0, 0, 1, 0, 0xde, 0xad])
+            .unwrap();
+
+        let loaded = persistence.load_decisions(PhaseId::new(0)).await.unwrap();
+        assert_eq!(loaded, entries);
+
+        // Appends after the cut are readable again
+        let (phase, batch) = decisions(3..=3).remove(0);
+        persistence.append_decision(phase, &batch).await.unwrap();
+        let loaded = persistence.load_decisions(PhaseId::new(2)).await.unwrap();
+        assert_eq!(loaded, vec![(phase, batch)]);
+    }
+
+    #[tokio::test]
+    async fn test_file_system_truncation_keeps_entries_of_retained_generations() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        let entries = decisions(1..=6);
+        persistence.append_decisions(&entries).await.unwrap();
+        assert_eq!(
+            persistence.truncated_through().await.unwrap(),
+            PhaseId::new(0)
+        );
+
+        // Generation 2 is still kept, so a fallback to it needs phases 3 and 4
+        persistence.save_state(&state_at(2)).await.unwrap();
+        persistence.save_state(&state_at(4)).await.unwrap();
+        persistence
+            .truncate_decisions(PhaseId::new(4))
+            .await
+            .unwrap();
+        let remaining = persistence.load_decisions(PhaseId::new(0)).await.unwrap();
+        assert_eq!(remaining, entries[2..].to_vec());
+        assert_eq!(
+            persistence.truncated_through().await.unwrap(),
+            PhaseId::new(2)
+        );
+
+        // Saving generation 6 prunes generation 2
+        persistence.save_state(&state_at(6)).await.unwrap();
+        persistence
+            .truncate_decisions(PhaseId::new(6))
+            .await
+            .unwrap();
+        let remaining = persistence.load_decisions(PhaseId::new(0)).await.unwrap();
+        assert_eq!(remaining, entries[4..].to_vec());
+        assert_eq!(
+            persistence.truncated_through().await.unwrap(),
+            PhaseId::new(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incarnation_counter_survives_reopen() {
+        use crate::EmbeddedPersistence;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs_dir = temp_dir.path().join("fs");
+        let db_path = temp_dir.path().join("rabia.redb");
+
+        let first = FileSystemPersistence::new(&fs_dir)
+            .await
+            .unwrap()
+            .next_incarnation()
+            .await
+            .unwrap()
+            .unwrap();
+        // Counts on from the stored value rather than reading the clock again
+        let reopened = FileSystemPersistence::new(&fs_dir).await.unwrap();
+        assert_eq!(reopened.next_incarnation().await.unwrap(), Some(first + 1));
+        assert_eq!(reopened.next_incarnation().await.unwrap(), Some(first + 2));
+
+        std::fs::write(fs_dir.join("incarnation"), b"garbage").unwrap();
+        assert!(matches!(
+            reopened.next_incarnation().await,
+            Err(RabiaError::StateCorruption { .. })
+        ));
+
+        let first = EmbeddedPersistence::open(&db_path)
+            .unwrap()
+            .next_incarnation()
+            .await
+            .unwrap()
+            .unwrap();
+        let reopened = EmbeddedPersistence::open(&db_path).unwrap();
+        assert_eq!(reopened.next_incarnation().await.unwrap(), Some(first + 1));
+
+        // Backends that do not survive restarts leave it to the clock
+        assert_eq!(
+            InMemoryPersistence::new().next_incarnation().await.unwrap(),
+            None
+        );
+    }
+
     #[tokio::test]
     async fn test_empty_data() {
         let persistence = InMemoryPersistence::new();
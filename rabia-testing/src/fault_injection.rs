@@ -1,16 +1,24 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tempfile::TempDir;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tracing::{error, info, warn};
 
 use rabia_core::{
-    network::ClusterConfig, state_machine::InMemoryStateMachine, Command, CommandBatch, NodeId,
+    network::ClusterConfig,
+    state_machine::{InMemoryStateMachine, StateMachine},
+    Command, CommandBatch, NodeId,
 };
-use rabia_engine::{EngineCommand, EngineCommandSender, RabiaConfig, RabiaEngine};
-use rabia_persistence::InMemoryPersistence;
+use rabia_engine::{
+    EngineCommand, EngineCommandSender, EngineStatistics, LocalReader, RabiaConfig, RabiaEngine,
+};
+use rabia_persistence::FileSystemPersistence;
 
+use crate::faulty_persistence::{FaultyPersistence, StorageFault, StorageFaultController};
 use crate::network_sim::{NetworkConditions, NetworkSimulator, SimulatedNetwork};
 
 #[derive(Debug, Clone)]
@@ -41,6 +49,13 @@ pub enum FaultType {
         probability: f64,
         max_delay: Duration,
     },
+    /// Apply a storage fault to every write of one node's persistence, then
+    /// restart the node's engine over what its storage kept
+    StorageFault {
+        node_id: NodeId,
+        fault: StorageFault,
+        duration: Duration,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -69,19 +84,104 @@ pub struct ConsensusTestHarness {
     test_duration: Duration,
     #[allow(dead_code)]
     start_time: Instant,
+    /// Holds every node's storage; removed with the harness
+    _data_dir: TempDir,
 }
 
 struct ConsensusNode {
-    #[allow(dead_code)]
     node_id: NodeId,
+    config: RabiaConfig,
+    all_node_ids: HashSet<NodeId>,
+    data_dir: PathBuf,
+    simulator: Arc<NetworkSimulator>,
+    storage: StorageFaultController,
+    engine: Mutex<RunningEngine>,
+}
+
+struct RunningEngine {
     engine_tx: EngineCommandSender,
-    #[allow(dead_code)]
-    engine_handle: tokio::task::JoinHandle<()>,
+    reader: Option<LocalReader<InMemoryStateMachine>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ConsensusNode {
+    /// Start an engine over the node's storage, recovering whatever it kept.
+    async fn start_engine(&self) -> RunningEngine {
+        let cluster_config = ClusterConfig::new(self.node_id, self.all_node_ids.clone());
+        let network = SimulatedNetwork::new(self.node_id, self.simulator.clone()).await;
+        network.connect_to_nodes(self.all_node_ids.clone()).await;
+        let persistence = FaultyPersistence::with_controller(
+            FileSystemPersistence::new(&self.data_dir)
+                .await
+                .expect("Failed to open node storage"),
+            self.storage.clone(),
+        );
+
+        let (engine_tx, engine_rx) = mpsc::unbounded_channel();
+        let engine = RabiaEngine::new(
+            self.node_id,
+            self.config.clone(),
+            cluster_config,
+            InMemoryStateMachine::new(),
+            network,
+            persistence,
+            engine_rx,
+        );
+
+        let reader = engine.local_reader();
+        let node_id = self.node_id;
+        let handle = tokio::spawn(async move {
+            if let Err(e) = engine.run().await {
+                error!("Engine for node {} failed: {}", node_id, e);
+            }
+        });
+
+        RunningEngine {
+            engine_tx,
+            reader: Some(reader),
+            handle: Some(handle),
+        }
+    }
+
+    fn engine_tx(&self) -> EngineCommandSender {
+        self.engine.lock().unwrap().engine_tx.clone()
+    }
+
+    /// Shut the node's engine down, killing it if it does not stop in time.
+    async fn stop_engine(&self) {
+        let handle = {
+            let mut engine = self.engine.lock().unwrap();
+            let _ = engine.engine_tx.send(EngineCommand::Shutdown);
+            engine.reader = None;
+            engine.handle.take()
+        };
+        if let Some(mut handle) = handle {
+            // An engine stuck on its faulted storage is killed instead
+            if tokio::time::timeout(Duration::from_secs(1), &mut handle)
+                .await
+                .is_err()
+            {
+                handle.abort();
+            }
+        }
+    }
+
+    /// Stop the node's engine, bring its storage back and start a new engine
+    /// over it, which then catches up with the cluster.
+    async fn restart(&self) {
+        self.stop_engine().await;
+        self.storage.restart();
+        let running = self.start_engine().await;
+        let _ = running.engine_tx.send(EngineCommand::TriggerSync);
+        *self.engine.lock().unwrap() = running;
+        info!("Restarted node {} over its storage", self.node_id);
+    }
 }
 
 impl ConsensusTestHarness {
     pub async fn new(node_count: usize, config: RabiaConfig) -> Self {
         let simulator = Arc::new(NetworkSimulator::new());
+        let data_dir = TempDir::new().expect("Failed to create harness data directory");
         let mut nodes = HashMap::new();
         let mut all_node_ids = HashSet::new();
 
@@ -90,41 +190,25 @@ impl ConsensusTestHarness {
             all_node_ids.insert(NodeId::new());
         }
 
-        // Create nodes
+        // Create nodes, each storing to its own directory
         for &node_id in &all_node_ids {
-            let cluster_config = ClusterConfig::new(node_id, all_node_ids.clone());
-            let state_machine = InMemoryStateMachine::new();
-            let network = SimulatedNetwork::new(node_id, simulator.clone()).await;
-            let persistence = InMemoryPersistence::new();
-
-            // Connect network to all other nodes
-            network.connect_to_nodes(all_node_ids.clone()).await;
-
-            let (engine_tx, engine_rx) = mpsc::unbounded_channel();
-
-            let engine = RabiaEngine::new(
+            let node = ConsensusNode {
                 node_id,
-                config.clone(),
-                cluster_config,
-                state_machine,
-                network,
-                persistence,
-                engine_rx,
-            );
-
-            let engine_handle = tokio::spawn(async move {
-                if let Err(e) = engine.run().await {
-                    error!("Engine for node {} failed: {}", node_id, e);
-                }
-            });
-
-            let consensus_node = Arc::new(ConsensusNode {
-                node_id,
-                engine_tx,
-                engine_handle,
-            });
-
-            nodes.insert(node_id, consensus_node);
+                config: config.clone(),
+                all_node_ids: all_node_ids.clone(),
+                data_dir: data_dir.path().join(node_id.to_string()),
+                simulator: simulator.clone(),
+                storage: StorageFaultController::new(),
+                engine: Mutex::new(RunningEngine {
+                    engine_tx: mpsc::unbounded_channel().0,
+                    reader: None,
+                    handle: None,
+                }),
+            };
+            let running = node.start_engine().await;
+            *node.engine.lock().unwrap() = running;
+
+            nodes.insert(node_id, Arc::new(node));
         }
 
         // Start network simulation
@@ -138,7 +222,80 @@ impl ConsensusTestHarness {
             nodes,
             test_duration: Duration::from_secs(30),
             start_time: Instant::now(),
+            _data_dir: data_dir,
+        }
+    }
+
+    /// Ids of the harness's nodes, sorted.
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        let mut node_ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        node_ids.sort();
+        node_ids
+    }
+
+    /// Command channel of a node's running engine, for submitting batches
+    /// directly. A restart of the node closes it.
+    pub fn engine_sender(&self, node_id: NodeId) -> Option<EngineCommandSender> {
+        self.nodes.get(&node_id).map(|node| node.engine_tx())
+    }
+
+    /// Statistics of a node's running engine, if it answers in time.
+    pub async fn node_statistics(&self, node_id: NodeId) -> Option<EngineStatistics> {
+        let node = self.nodes.get(&node_id)?;
+        let (stats_tx, stats_rx) = tokio::sync::oneshot::channel();
+        node.engine_tx()
+            .send(EngineCommand::GetStatistics(stats_tx))
+            .ok()?;
+        tokio::time::timeout(Duration::from_millis(100), stats_rx)
+            .await
+            .ok()?
+            .ok()
+    }
+
+    /// Storage fault controller of a node, for scheduling faults directly.
+    pub fn storage_faults(&self, node_id: NodeId) -> Option<StorageFaultController> {
+        self.nodes.get(&node_id).map(|node| node.storage.clone())
+    }
+
+    /// State machine contents of a node's running engine.
+    pub async fn node_state(
+        &self,
+        node_id: NodeId,
+    ) -> Option<<InMemoryStateMachine as StateMachine>::State> {
+        let reader = self
+            .nodes
+            .get(&node_id)?
+            .engine
+            .lock()
+            .unwrap()
+            .reader
+            .clone()?;
+        reader.read_state(None).await.ok()
+    }
+
+    /// Wait until every node has committed the same phase, past phase 0,
+    /// and holds the same state. Returns whether they did within `deadline`.
+    pub async fn wait_for_convergence(&self, deadline: Duration) -> bool {
+        let started = Instant::now();
+        while started.elapsed() < deadline {
+            let mut nodes = Vec::new();
+            for node_id in self.node_ids() {
+                let stats = self.node_statistics(node_id).await;
+                let state = self.node_state(node_id).await;
+                match stats.zip(state) {
+                    Some((stats, state)) => nodes.push((stats.last_committed_phase, state)),
+                    None => break,
+                }
+            }
+            if nodes.len() == self.nodes.len()
+                && nodes[0].0.value() > 0
+                && nodes.iter().all(|node| *node == nodes[0])
+            {
+                return true;
+            }
+            sleep(Duration::from_millis(50)).await;
         }
+        false
     }
 
     pub async fn run_scenario(&mut self, scenario: TestScenario) -> TestResult {
@@ -146,18 +303,20 @@ impl ConsensusTestHarness {
         let start_time = Instant::now();
 
         // Submit initial commands
+        let mut responses = Vec::new();
         for (i, command) in scenario.initial_commands.iter().enumerate() {
             let node_id = self.nodes.keys().nth(i % self.nodes.len()).unwrap();
             if let Some(node) = self.nodes.get(node_id) {
                 let batch = CommandBatch::new(vec![command.clone()]);
-                let (response_tx, _response_rx) = tokio::sync::oneshot::channel();
+                let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+                responses.push(response_rx);
 
                 let cmd = EngineCommand::ProcessBatch(rabia_engine::CommandRequest {
                     batch,
                     response_tx,
                 });
 
-                if let Err(e) = node.engine_tx.send(cmd) {
+                if let Err(e) = node.engine_tx().send(cmd) {
                     warn!("Failed to send command to node {}: {}", node_id, e);
                 }
             }
@@ -177,7 +336,13 @@ impl ConsensusTestHarness {
         let network_stats = self.simulator.get_stats().await;
 
         // Analyze outcome
-        let actual_outcome = self.analyze_outcome().await;
+        let mut actual_outcome = self.analyze_outcome().await;
+        actual_outcome.submitted_batches = responses.len();
+        actual_outcome.committed_batches = responses
+            .into_iter()
+            .filter_map(|mut response_rx| response_rx.try_recv().ok())
+            .filter(Result::is_ok)
+            .count();
         let success = self
             .check_expected_outcome(&scenario.expected_outcome, &actual_outcome)
             .await;
@@ -285,6 +450,27 @@ impl ConsensusTestHarness {
                 info!("Injecting message reordering");
                 // Implementation would involve delaying random messages
             }
+
+            FaultType::StorageFault {
+                node_id,
+                fault,
+                duration,
+            } => {
+                let Some(node) = self.nodes.get(&node_id).cloned() else {
+                    warn!("Cannot inject storage fault into unknown node {}", node_id);
+                    return;
+                };
+                info!(
+                    "Injecting storage fault {:?} for {} (duration: {:?})",
+                    fault, node_id, duration
+                );
+                node.storage.inject(fault, None);
+
+                tokio::spawn(async move {
+                    sleep(duration).await;
+                    node.restart().await;
+                });
+            }
         }
     }
 
@@ -292,18 +478,9 @@ impl ConsensusTestHarness {
         // Get statistics from all nodes
         let mut node_stats = HashMap::new();
 
-        for (&node_id, node) in &self.nodes {
-            let (stats_tx, stats_rx) = tokio::sync::oneshot::channel();
-            if node
-                .engine_tx
-                .send(EngineCommand::GetStatistics(stats_tx))
-                .is_ok()
-            {
-                if let Ok(Ok(stats)) =
-                    tokio::time::timeout(Duration::from_millis(100), stats_rx).await
-                {
-                    node_stats.insert(node_id, stats);
-                }
+        for &node_id in self.nodes.keys() {
+            if let Some(stats) = self.node_statistics(node_id).await {
+                node_stats.insert(node_id, stats);
             }
         }
 
@@ -317,6 +494,10 @@ impl ConsensusTestHarness {
                 .map(|s| s.current_phase.value())
                 .collect(),
             nodes_with_progress: node_stats.len(),
+            batches_proposed: node_stats.values().map(|s| s.batches_proposed).collect(),
+            batches_forwarded: node_stats.values().map(|s| s.batches_forwarded).collect(),
+            submitted_batches: 0,
+            committed_batches: 0,
         }
     }
 
@@ -354,9 +535,9 @@ impl ConsensusTestHarness {
     pub async fn shutdown(&self) {
         self.simulator.shutdown().await;
 
-        // Send shutdown to all nodes
+        // Stop all nodes before their storage is removed
         for node in self.nodes.values() {
-            let _ = node.engine_tx.send(EngineCommand::Shutdown);
+            node.stop_engine().await;
         }
     }
 }
@@ -376,6 +557,13 @@ pub struct ActualOutcome {
     pub committed_phases: Vec<u64>,
     pub current_phases: Vec<u64>,
     pub nodes_with_progress: usize,
+    /// Proposals made by each node, in the same order as `committed_phases`
+    pub batches_proposed: Vec<u64>,
+    /// Batches each node forwarded to the leader
+    pub batches_forwarded: Vec<u64>,
+    pub submitted_batches: usize,
+    /// Submitted batches whose response reported them applied
+    pub committed_batches: usize,
 }
 
 pub fn create_test_scenarios() -> Vec<TestScenario> {
@@ -498,6 +686,48 @@ pub fn create_test_scenarios() -> Vec<TestScenario> {
     ]
 }
 
+/// Storage fault scenarios for the harness nodes `node_ids`: each faults one
+/// node's writes while commands commit, then restarts it over what its
+/// storage kept.
+pub fn create_storage_scenarios(node_ids: &[NodeId]) -> Vec<TestScenario> {
+    let scenario = |name: &str, description: &str, at: Duration, fault: FaultType| TestScenario {
+        name: name.to_string(),
+        description: description.to_string(),
+        node_count: node_ids.len(),
+        initial_commands: vec![
+            Command::new("SET key1 value1"),
+            Command::new("SET key2 value2"),
+            Command::new("SET key3 value3"),
+        ],
+        faults: vec![(at, fault)],
+        expected_outcome: ExpectedOutcome::EventualConsistency,
+        timeout: Duration::from_secs(4),
+    };
+
+    vec![
+        scenario(
+            "Storage Write Failures",
+            "One node's storage fails writes until the node restarts",
+            Duration::ZERO,
+            FaultType::StorageFault {
+                node_id: node_ids[0],
+                fault: StorageFault::FailWrites,
+                duration: Duration::from_secs(1),
+            },
+        ),
+        scenario(
+            "Storage Crash Before Rename",
+            "One node crashes partway through a write and restarts",
+            Duration::ZERO,
+            FaultType::StorageFault {
+                node_id: node_ids[1 % node_ids.len()],
+                fault: StorageFault::CrashBeforeRename,
+                duration: Duration::from_secs(1),
+            },
+        ),
+    ]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -524,6 +754,47 @@ mod tests {
         harness.shutdown().await;
     }
 
+    #[tokio::test]
+    async fn test_storage_fault_is_applied_and_lifted() {
+        let harness = ConsensusTestHarness::new(3, RabiaConfig::default()).await;
+        let node_ids = harness.node_ids();
+        let storage = harness.storage_faults(node_ids[0]).unwrap();
+
+        harness
+            .inject_fault(FaultType::StorageFault {
+                node_id: node_ids[0],
+                fault: StorageFault::CrashBeforeRename,
+                duration: Duration::from_millis(500),
+            })
+            .await;
+        let engine_tx = harness.engine_sender(node_ids[1]).unwrap();
+        for i in 0..3 {
+            let (response_tx, _response_rx) = tokio::sync::oneshot::channel();
+            let batch = CommandBatch::new(vec![Command::new(format!("SET k{} v", i))]);
+            engine_tx
+                .send(EngineCommand::ProcessBatch(rabia_engine::CommandRequest {
+                    batch,
+                    response_tx,
+                }))
+                .unwrap();
+        }
+
+        // The crashed node lost its writes, and catches up once restarted
+        sleep(Duration::from_millis(200)).await;
+        assert!(storage.is_crashed());
+        assert!(storage.stats().faulted_writes > 0);
+        timeout(Duration::from_secs(5), async {
+            while storage.is_crashed() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("Node was not restarted");
+        assert!(harness.wait_for_convergence(Duration::from_secs(5)).await);
+
+        harness.shutdown().await;
+    }
+
     #[tokio::test]
     async fn test_packet_loss_scenario() {
         let config = RabiaConfig::default();
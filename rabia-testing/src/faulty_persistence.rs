@@ -0,0 +1,535 @@
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+use rabia_core::{
+    persistence::{EngineState, PersistenceLayer, StateChain},
+    CommandBatch, PhaseId, RabiaError, Result,
+};
+use rabia_persistence::{EmbeddedPersistence, FileSystemPersistence, InMemoryPersistence};
+
+/// A storage fault injected by [`FaultyPersistence`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageFault {
+    /// Writes fail without touching the wrapped backend
+    FailWrites,
+    /// Writes complete only after `delay`, like a slow fsync
+    DelaySync { delay: Duration },
+    /// Writes are cut short and fail with [`RabiaError::PartialWrite`],
+    /// leaving in the backend what a crash partway through leaves there; see
+    /// [`InterruptedWrites::tear_state`]
+    TornWrite,
+    /// The process dies after writing a temp file but before renaming it over
+    /// the old one: the temp file is left behind, the write is lost and every
+    /// later call fails until [`StorageFaultController::restart`]
+    CrashBeforeRename,
+}
+
+/// Counters kept by a [`StorageFaultController`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageFaultStats {
+    /// Writes that reached the wrapper
+    pub writes: u64,
+    /// Writes a fault was applied to
+    pub faulted_writes: u64,
+    /// Calls rejected because the storage had crashed
+    pub rejected_after_crash: u64,
+}
+
+#[derive(Debug)]
+struct ScheduledFault {
+    fault: StorageFault,
+    /// Writes left to apply the fault to; `None` until cleared
+    remaining: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    faults: VecDeque<ScheduledFault>,
+    crashed: bool,
+    stats: StorageFaultStats,
+}
+
+/// Schedules faults for a [`FaultyPersistence`], shared with test code.
+#[derive(Debug, Clone, Default)]
+pub struct StorageFaultController {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl StorageFaultController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `fault` to the next `writes` writes, or to every write until
+    /// [`clear`](Self::clear) when `writes` is `None`.
+    ///
+    /// Faults apply in the order they were injected; each write uses the
+    /// oldest one still pending.
+    pub fn inject(&self, fault: StorageFault, writes: Option<usize>) {
+        info!(
+            "Injecting storage fault {:?} for {:?} writes",
+            fault, writes
+        );
+        self.state.lock().unwrap().faults.push_back(ScheduledFault {
+            fault,
+            remaining: writes,
+        });
+    }
+
+    /// Drop all pending faults.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// Bring crashed storage back, as a restarted process would see it, and
+    /// drop all pending faults.
+    pub fn restart(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.faults.clear();
+        state.crashed = false;
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    pub fn stats(&self) -> StorageFaultStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    /// Fail if the storage has crashed.
+    fn check_alive(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.crashed {
+            state.stats.rejected_after_crash += 1;
+            return Err(RabiaError::persistence(
+                "Injected fault: storage has crashed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Count a write and take the fault that applies to it, if any.
+    fn next_write_fault(&self) -> Result<Option<StorageFault>> {
+        self.check_alive()?;
+
+        let mut state = self.state.lock().unwrap();
+        state.stats.writes += 1;
+        let Some(scheduled) = state.faults.front_mut() else {
+            return Ok(None);
+        };
+        let fault = scheduled.fault.clone();
+        if let Some(remaining) = &mut scheduled.remaining {
+            *remaining -= 1;
+            if *remaining == 0 {
+                state.faults.pop_front();
+            }
+        }
+        state.stats.faulted_writes += 1;
+        if fault == StorageFault::CrashBeforeRename {
+            state.crashed = true;
+        }
+        Ok(Some(fault))
+    }
+}
+
+/// What an interrupted write leaves in a backend's storage, reproduced by
+/// [`FaultyPersistence`].
+///
+/// The defaults leave nothing behind, as in a backend that commits every
+/// write atomically.
+#[async_trait]
+pub trait InterruptedWrites: PersistenceLayer {
+    /// Leave `state`, saved as covering `phase`, cut off partway.
+    async fn tear_state(&self, _phase: PhaseId, _state: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Leave the delta `record` cut off partway.
+    async fn tear_state_delta(&self, _record: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Leave `entries` in the decision log with the last one cut off partway.
+    async fn tear_decisions(&self, _entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Leave the temp file of a save of `state` that was never moved into
+    /// place.
+    async fn leave_temp_state(&self, _phase: PhaseId, _state: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl InterruptedWrites for InMemoryPersistence {}
+
+impl InterruptedWrites for EmbeddedPersistence {}
+
+/// Files are written in full and then cut, so the length and CRC checks of
+/// [`FileSystemPersistence`] see what a torn write leaves.
+#[async_trait]
+impl InterruptedWrites for FileSystemPersistence {
+    async fn tear_state(&self, phase: PhaseId, state: &[u8]) -> Result<()> {
+        self.save_state_through(phase, state).await?;
+        let path = self.generation_path(phase);
+        let len = file_len(&path).await?;
+        cut_file(&path, len / 2).await
+    }
+
+    async fn tear_state_delta(&self, record: &[u8]) -> Result<()> {
+        self.append_state_delta(record).await?;
+        let Some(newest) = self.list_generations().await?.into_iter().next() else {
+            return Ok(());
+        };
+        // Named like `FileSystemPersistence` names delta logs
+        let mut path = newest.path.into_os_string();
+        path.push(".deltas");
+        let path = PathBuf::from(path);
+        let len = file_len(&path).await?;
+        cut_file(&path, len - 1).await
+    }
+
+    async fn tear_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.append_decisions(entries).await?;
+        let path = self.decision_log_path();
+        let len = file_len(&path).await?;
+        cut_file(&path, len - 1).await
+    }
+
+    async fn leave_temp_state(&self, phase: PhaseId, state: &[u8]) -> Result<()> {
+        // Named like the temp file `FileSystemPersistence` renames into place
+        let path = self.generation_path(phase).with_extension("tmp");
+        tokio::fs::write(&path, &state[..state.len() / 2])
+            .await
+            .map_err(|e| RabiaError::persistence(format!("Failed to write temp file: {}", e)))
+    }
+}
+
+async fn file_len(path: &Path) -> Result<u64> {
+    tokio::fs::metadata(path)
+        .await
+        .map(|metadata| metadata.len())
+        .map_err(|e| RabiaError::persistence(format!("Failed to stat {}: {}", path.display(), e)))
+}
+
+/// Cut the file at `path` to `len` bytes, as a write torn there leaves it.
+async fn cut_file(path: &Path, len: u64) -> Result<()> {
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|e| {
+            RabiaError::persistence(format!("Failed to open {}: {}", path.display(), e))
+        })?;
+    file.set_len(len)
+        .await
+        .map_err(|e| RabiaError::persistence(format!("Failed to cut {}: {}", path.display(), e)))
+}
+
+/// Wraps a [`PersistenceLayer`] and injects the storage faults scheduled
+/// through its [`StorageFaultController`], for crash-consistency tests.
+///
+/// Reads pass through unless the storage has crashed. Torn writes and
+/// crashes leave behind what the wrapped backend would keep of the write, as
+/// described by its [`InterruptedWrites`] implementation. Truncating the
+/// decision log counts as a write, but only `FailWrites`, `DelaySync` and
+/// `CrashBeforeRename` apply to it.
+#[derive(Debug, Clone)]
+pub struct FaultyPersistence<P> {
+    inner: P,
+    controller: StorageFaultController,
+}
+
+impl<P: InterruptedWrites> FaultyPersistence<P> {
+    pub fn new(inner: P) -> Self {
+        Self::with_controller(inner, StorageFaultController::new())
+    }
+
+    /// Wrap `inner`, taking faults from an existing controller.
+    pub fn with_controller(inner: P, controller: StorageFaultController) -> Self {
+        Self { inner, controller }
+    }
+
+    pub fn controller(&self) -> StorageFaultController {
+        self.controller.clone()
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Save through the inner backend, with the phase if one was given.
+    async fn save_inner(&self, phase: Option<PhaseId>, state: &[u8]) -> Result<()> {
+        match phase {
+            Some(phase) => self.inner.save_state_through(phase, state).await,
+            None => self.inner.save_state(state).await,
+        }
+    }
+
+    async fn write_state(&self, phase: Option<PhaseId>, state: &[u8]) -> Result<()> {
+        let fault = self.controller.next_write_fault()?;
+        let phase_or_peeked = || {
+            phase.unwrap_or_else(|| {
+                EngineState::peek_last_committed_phase(state).unwrap_or_default()
+            })
+        };
+        match fault {
+            None => self.save_inner(phase, state).await,
+            Some(StorageFault::FailWrites) => Err(injected_failure("save_state")),
+            Some(StorageFault::DelaySync { delay }) => {
+                sleep(delay).await;
+                self.save_inner(phase, state).await
+            }
+            Some(StorageFault::TornWrite) => {
+                self.inner.tear_state(phase_or_peeked(), state).await?;
+                Err(RabiaError::PartialWrite {
+                    details: format!("Injected fault: tore a {} byte state", state.len()),
+                })
+            }
+            Some(StorageFault::CrashBeforeRename) => {
+                debug!("Injected crash before the state file was renamed into place");
+                self.inner
+                    .leave_temp_state(phase_or_peeked(), state)
+                    .await?;
+                Err(injected_crash("save_state"))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<P: InterruptedWrites> PersistenceLayer for FaultyPersistence<P> {
+    async fn save_state(&self, state: &[u8]) -> Result<()> {
+        self.write_state(None, state).await
+    }
+
+    async fn save_state_through(&self, phase: PhaseId, state: &[u8]) -> Result<()> {
+        self.write_state(Some(phase), state).await
+    }
+
+    async fn load_state(&self) -> Result<Option<Vec<u8>>> {
+        self.controller.check_alive()?;
+        self.inner.load_state().await
+    }
+
+    async fn append_state_delta(&self, record: &[u8]) -> Result<()> {
+        match self.controller.next_write_fault()? {
+            None => self.inner.append_state_delta(record).await,
+            Some(StorageFault::FailWrites) => Err(injected_failure("append_state_delta")),
+            Some(StorageFault::DelaySync { delay }) => {
+                sleep(delay).await;
+                self.inner.append_state_delta(record).await
+            }
+            Some(StorageFault::TornWrite) => {
+                self.inner.tear_state_delta(record).await?;
+                Err(RabiaError::PartialWrite {
+                    details: format!("Injected fault: tore a {} byte delta record", record.len()),
+                })
+            }
+            Some(StorageFault::CrashBeforeRename) => Err(injected_crash("append_state_delta")),
+        }
+    }
+
+    async fn load_state_chain(&self) -> Result<Option<StateChain>> {
+        self.controller.check_alive()?;
+        self.inner.load_state_chain().await
+    }
+
+    fn has_decision_log(&self) -> bool {
+        self.inner.has_decision_log()
+    }
+
+    async fn append_decision(&self, phase: PhaseId, batch: &CommandBatch) -> Result<()> {
+        self.append_decisions(&[(phase, batch.clone())]).await
+    }
+
+    async fn append_decisions(&self, entries: &[(PhaseId, CommandBatch)]) -> Result<()> {
+        match self.controller.next_write_fault()? {
+            None => self.inner.append_decisions(entries).await,
+            Some(StorageFault::FailWrites) => Err(injected_failure("append_decisions")),
+            Some(StorageFault::DelaySync { delay }) => {
+                sleep(delay).await;
+                self.inner.append_decisions(entries).await
+            }
+            Some(StorageFault::TornWrite) => {
+                self.inner.tear_decisions(entries).await?;
+                Err(RabiaError::PartialWrite {
+                    details: format!(
+                        "Injected fault: tore the last of {} decisions",
+                        entries.len()
+                    ),
+                })
+            }
+            Some(StorageFault::CrashBeforeRename) => Err(injected_crash("append_decisions")),
+        }
+    }
+
+    async fn load_decisions(&self, after: PhaseId) -> Result<Vec<(PhaseId, CommandBatch)>> {
+        self.controller.check_alive()?;
+        self.inner.load_decisions(after).await
+    }
+
+    async fn truncate_decisions(&self, through: PhaseId) -> Result<()> {
+        match self.controller.next_write_fault()? {
+            Some(StorageFault::FailWrites) => Err(injected_failure("truncate_decisions")),
+            Some(StorageFault::CrashBeforeRename) => Err(injected_crash("truncate_decisions")),
+            Some(StorageFault::DelaySync { delay }) => {
+                sleep(delay).await;
+                self.inner.truncate_decisions(through).await
+            }
+            Some(StorageFault::TornWrite) | None => self.inner.truncate_decisions(through).await,
+        }
+    }
+
+    async fn truncated_through(&self) -> Result<PhaseId> {
+        self.controller.check_alive()?;
+        self.inner.truncated_through().await
+    }
+
+    async fn next_incarnation(&self) -> Result<Option<u64>> {
+        self.controller.check_alive()?;
+        self.inner.next_incarnation().await
+    }
+}
+
+fn injected_failure(operation: &str) -> RabiaError {
+    RabiaError::persistence(format!("Injected fault: {} failed", operation))
+}
+
+fn injected_crash(operation: &str) -> RabiaError {
+    RabiaError::persistence(format!("Injected fault: crashed during {}", operation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::decisions;
+    use tempfile::TempDir;
+
+    fn engine_state(phase: u64) -> Vec<u8> {
+        EngineState::new(PhaseId::new(phase), PhaseId::new(phase), None)
+            .to_bytes()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_leave_state_untouched() {
+        let persistence = FaultyPersistence::new(InMemoryPersistence::new());
+        let controller = persistence.controller();
+        persistence.save_state(b"v1").await.unwrap();
+
+        controller.inject(StorageFault::FailWrites, Some(2));
+        assert!(persistence.save_state(b"v2").await.is_err());
+        assert!(persistence
+            .append_decisions(&decisions(1..=1))
+            .await
+            .is_err());
+        persistence.save_state(b"v3").await.unwrap();
+
+        assert_eq!(
+            persistence.load_state().await.unwrap(),
+            Some(b"v3".to_vec())
+        );
+        assert!(persistence
+            .load_decisions(PhaseId::new(0))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            controller.stats(),
+            StorageFaultStats {
+                writes: 4,
+                faulted_writes: 2,
+                rejected_after_crash: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_torn_writes_are_caught_on_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let persistence =
+            FaultyPersistence::new(FileSystemPersistence::new(temp_dir.path()).await.unwrap());
+        let controller = persistence.controller();
+        persistence.save_state(&engine_state(3)).await.unwrap();
+
+        controller.inject(StorageFault::TornWrite, None);
+        let entries = decisions(1..=4);
+        assert!(matches!(
+            persistence.append_decisions(&entries).await,
+            Err(RabiaError::PartialWrite { .. })
+        ));
+        assert!(matches!(
+            persistence.save_state(&engine_state(5)).await,
+            Err(RabiaError::PartialWrite { .. })
+        ));
+        controller.clear();
+
+        // The torn generation is on disk, but a reopened backend skips it
+        // and the torn log record
+        let torn = persistence.inner().generation_path(PhaseId::new(5));
+        assert!(torn.exists());
+        let reopened = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+        assert_eq!(reopened.load_state().await.unwrap(), Some(engine_state(3)));
+        assert_eq!(
+            reopened.load_decisions(PhaseId::new(0)).await.unwrap(),
+            entries[..3].to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delayed_sync_still_writes() {
+        let persistence = FaultyPersistence::new(InMemoryPersistence::new());
+        let delay = Duration::from_millis(20);
+        persistence
+            .controller()
+            .inject(StorageFault::DelaySync { delay }, Some(1));
+
+        let started = std::time::Instant::now();
+        persistence.save_state(b"slow").await.unwrap();
+        assert!(started.elapsed() >= delay);
+        assert_eq!(
+            persistence.load_state().await.unwrap(),
+            Some(b"slow".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crash_before_rename_keeps_previous_state() {
+        let temp_dir = TempDir::new().unwrap();
+        let persistence =
+            FaultyPersistence::new(FileSystemPersistence::new(temp_dir.path()).await.unwrap());
+        let controller = persistence.controller();
+        persistence.save_state(&engine_state(3)).await.unwrap();
+
+        controller.inject(StorageFault::CrashBeforeRename, Some(1));
+        assert!(persistence.save_state(&engine_state(5)).await.is_err());
+        assert!(controller.is_crashed());
+        assert!(persistence.load_state().await.is_err());
+        assert!(persistence.save_state(&engine_state(7)).await.is_err());
+
+        // The temp file is left behind, and a restarted node reads what was
+        // durable before the crash
+        let temp = persistence
+            .inner()
+            .generation_path(PhaseId::new(5))
+            .with_extension("tmp");
+        assert!(temp.exists());
+        controller.restart();
+        let restarted = FaultyPersistence::with_controller(
+            FileSystemPersistence::new(temp_dir.path()).await.unwrap(),
+            controller.clone(),
+        );
+        assert_eq!(restarted.load_state().await.unwrap(), Some(engine_state(3)));
+        assert_eq!(controller.stats().rejected_after_crash, 2);
+    }
+}
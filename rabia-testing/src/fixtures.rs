@@ -0,0 +1,14 @@
+//! Data shared by the storage tests.
+
+use rabia_core::{Command, CommandBatch, PhaseId};
+use std::ops::RangeInclusive;
+
+/// One single-command batch decided in each of `phases`.
+pub fn decisions(phases: RangeInclusive<u64>) -> Vec<(PhaseId, CommandBatch)> {
+    phases
+        .map(|phase| {
+            let batch = CommandBatch::new(vec![Command::new(format!("SET k{} v", phase))]);
+            (PhaseId::new(phase), batch)
+        })
+        .collect()
+}
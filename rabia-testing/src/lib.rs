@@ -1,10 +1,16 @@
 pub mod fault_injection;
+pub mod faulty_persistence;
+pub mod fixtures;
 pub mod network;
 pub mod network_sim;
 pub mod scenarios;
 
 pub use fault_injection::{
-    create_test_scenarios, ConsensusTestHarness, FaultType, TestResult, TestScenario,
+    create_storage_scenarios, create_test_scenarios, ConsensusTestHarness, FaultType, TestResult,
+    TestScenario,
+};
+pub use faulty_persistence::{
+    FaultyPersistence, InterruptedWrites, StorageFault, StorageFaultController, StorageFaultStats,
 };
 pub use network::{InMemoryNetwork, InMemoryNetworkSimulator};
 pub use network_sim::{NetworkConditions, NetworkSimulator, NetworkStats, SimulatedNetwork};
@@ -4,12 +4,13 @@ use rabia_core::{
 };
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, Notify};
 
 #[derive(Debug)]
 pub struct InMemoryNetwork {
     node_id: NodeId,
     message_queue: Arc<Mutex<VecDeque<(NodeId, ProtocolMessage)>>>,
+    message_available: Arc<Notify>,
     connected_nodes: Arc<Mutex<HashSet<NodeId>>>,
     #[allow(clippy::type_complexity)]
     network_bus: Arc<Mutex<Option<mpsc::UnboundedSender<(NodeId, NodeId, ProtocolMessage)>>>>,
@@ -20,6 +21,7 @@ impl InMemoryNetwork {
         Self {
             node_id,
             message_queue: Arc::new(Mutex::new(VecDeque::new())),
+            message_available: Arc::new(Notify::new()),
             connected_nodes: Arc::new(Mutex::new(HashSet::new())),
             network_bus: Arc::new(Mutex::new(None)),
         }
@@ -36,6 +38,7 @@ impl InMemoryNetwork {
     pub async fn deliver_message(&self, from: NodeId, message: ProtocolMessage) {
         let mut queue = self.message_queue.lock().await;
         queue.push_back((from, message));
+        self.message_available.notify_one();
     }
 
     pub async fn set_connected_nodes(&self, nodes: HashSet<NodeId>) {
@@ -71,13 +74,12 @@ impl NetworkTransport for InMemoryNetwork {
     }
 
     async fn receive(&mut self) -> Result<(NodeId, ProtocolMessage)> {
-        let mut queue = self.message_queue.lock().await;
-        if let Some((from, message)) = queue.pop_front() {
-            Ok((from, message))
-        } else {
-            // In a real implementation, this would block waiting for messages
-            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-            Err(RabiaError::network("No messages available"))
+        loop {
+            if let Some(entry) = self.message_queue.lock().await.pop_front() {
+                return Ok(entry);
+            }
+            // A delivery between the check and this wait leaves a permit behind
+            self.message_available.notified().await;
         }
     }
 
@@ -139,3 +141,51 @@ impl InMemoryNetworkSimulator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rabia_core::{messages::HeartBeatMessage, messages::MessageType, PhaseId};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_receive_waits_for_delivery() {
+        let node1 = NodeId::new();
+        let node2 = NodeId::new();
+        let mut network = InMemoryNetwork::new(node1);
+
+        let idle = tokio::time::timeout(Duration::from_millis(20), network.receive()).await;
+        assert!(
+            idle.is_err(),
+            "receive should block while no message is queued"
+        );
+
+        let sender = InMemoryNetwork {
+            node_id: node1,
+            message_queue: network.message_queue.clone(),
+            message_available: network.message_available.clone(),
+            connected_nodes: network.connected_nodes.clone(),
+            network_bus: network.network_bus.clone(),
+        };
+        let delivery = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let message = ProtocolMessage::new(
+                node2,
+                Some(node1),
+                MessageType::HeartBeat(HeartBeatMessage {
+                    current_phase: PhaseId::new(1),
+                    last_committed_phase: PhaseId::new(0),
+                    active: true,
+                }),
+            );
+            sender.deliver_message(node2, message).await;
+        });
+
+        let (from, _) = tokio::time::timeout(Duration::from_secs(1), network.receive())
+            .await
+            .expect("delivery should wake the receiver")
+            .unwrap();
+        assert_eq!(from, node2);
+        delivery.await.unwrap();
+    }
+}
@@ -218,7 +218,7 @@ impl NetworkSimulator {
             }
             rabia_core::messages::MessageType::VoteRound1(_) => 32,
             rabia_core::messages::MessageType::VoteRound2(vote) => {
-                32 + vote.round1_votes.len() * 16
+                32 + vote.round1_votes.as_packed().len()
             }
             rabia_core::messages::MessageType::Decision(decision) => {
                 let batch_size = decision
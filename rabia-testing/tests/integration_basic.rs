@@ -11,7 +11,7 @@ use tokio::time::timeout;
 use rabia_core::{
     network::ClusterConfig, state_machine::InMemoryStateMachine, Command, CommandBatch, NodeId,
 };
-use rabia_engine::{EngineCommand, RabiaConfig, RabiaEngine};
+use rabia_engine::{EngineCommand, EngineStatus, RabiaConfig, RabiaEngine};
 use rabia_persistence::InMemoryPersistence;
 use rabia_testing::InMemoryNetwork;
 
@@ -332,3 +332,506 @@ async fn test_engine_lifecycle() {
         }
     }
 }
+
+/// Test graceful shutdown through the engine handle
+#[tokio::test]
+async fn test_engine_handle_graceful_shutdown() {
+    use rabia_core::persistence::PersistenceLayer;
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let cluster_config = ClusterConfig::new(node_id, node_ids);
+    let persistence = InMemoryPersistence::new();
+    let config = RabiaConfig::default().with_shutdown_drain_timeout(Duration::from_millis(500));
+
+    let (_cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+    let engine = RabiaEngine::new(
+        node_id,
+        config,
+        cluster_config,
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        persistence.clone(),
+        cmd_rx,
+    );
+
+    let handle = engine.spawn();
+    let status = timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+    assert_eq!(status, EngineStatus::Running);
+    assert_eq!(handle.statistics().last_committed_phase.value(), 0);
+
+    timeout(Duration::from_secs(5), handle.shutdown())
+        .await
+        .expect("Engine shutdown timed out")
+        .expect("Engine shutdown returned an error");
+
+    let saved = persistence.load_state().await.unwrap();
+    assert!(
+        saved.is_some(),
+        "Engine state should be persisted on shutdown"
+    );
+}
+
+/// Test that client batches are checked against the configured limits
+#[tokio::test]
+async fn test_configured_validation_rejects_oversized_batch() {
+    use rabia_core::validation::{RejectionReason, ValidationConfig};
+    use rabia_engine::CommandRequest;
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let config = RabiaConfig::default().with_validation_config(ValidationConfig {
+        max_batch_size: 2,
+        ..Default::default()
+    });
+    let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+    let engine = RabiaEngine::new(
+        node_id,
+        config,
+        ClusterConfig::new(node_id, node_ids),
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        InMemoryPersistence::new(),
+        cmd_rx,
+    );
+    let handle = engine.spawn();
+
+    let batch = CommandBatch::new(vec![Command::new("SET a 1"); 3]);
+    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+    cmd_tx
+        .send(EngineCommand::ProcessBatch(CommandRequest {
+            batch,
+            response_tx,
+        }))
+        .unwrap();
+
+    let response = timeout(Duration::from_secs(5), response_rx)
+        .await
+        .expect("Engine did not respond")
+        .unwrap();
+    assert!(matches!(
+        response,
+        Err(rabia_core::RabiaError::ValidationFailed {
+            reason: RejectionReason::BatchTooLarge,
+            ..
+        })
+    ));
+    assert_eq!(
+        handle
+            .rejection_counts()
+            .get(&RejectionReason::BatchTooLarge),
+        Some(&1)
+    );
+
+    handle.shutdown().await.unwrap();
+}
+
+/// An engine configured for signing refuses to start without its keys
+#[tokio::test]
+async fn test_missing_signing_key_fails_startup() {
+    use rabia_core::signing::SigningConfig;
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let config = RabiaConfig::default().with_signing(SigningConfig::Hmac {
+        key_file: std::env::temp_dir().join(format!("rabia-missing-{}.key", node_id)),
+    });
+    let (_cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+    let engine = RabiaEngine::new(
+        node_id,
+        config,
+        ClusterConfig::new(node_id, node_ids),
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        InMemoryPersistence::new(),
+        cmd_rx,
+    );
+    let handle = engine.spawn();
+
+    let result = timeout(Duration::from_secs(5), handle.join())
+        .await
+        .expect("Engine did not stop");
+    assert!(result.is_err());
+}
+
+/// Every command submitted through the adaptive batcher gets its batch's outcome
+#[tokio::test]
+async fn test_adaptive_submitter_answers_every_command() {
+    use rabia_core::batching::AdaptiveBatchConfig;
+    use rabia_engine::AdaptiveSubmitter;
+    use std::sync::Arc;
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        RabiaConfig::default(),
+        ClusterConfig::new(node_id, node_ids),
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        InMemoryPersistence::new(),
+        cmd_rx,
+    );
+    let handle = engine.spawn();
+    timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+
+    let submitter = Arc::new(AdaptiveSubmitter::spawn(
+        AdaptiveBatchConfig::default(),
+        cmd_tx,
+    ));
+    let tasks: Vec<_> = (0..20)
+        .map(|i| {
+            let submitter = submitter.clone();
+            tokio::spawn(async move {
+                submitter
+                    .submit(Command::new(format!("SET k{} {}", i, i)))
+                    .await
+            })
+        })
+        .collect();
+
+    // A lone in-memory node has no connected peers, so each batch fails on
+    // quorum and that error must reach every command in it
+    for task in tasks {
+        let response = timeout(Duration::from_secs(10), task)
+            .await
+            .expect("Command was not answered in time")
+            .unwrap();
+        assert!(matches!(
+            response,
+            Err(rabia_core::RabiaError::QuorumNotAvailable { .. })
+        ));
+    }
+
+    handle.shutdown().await.unwrap();
+}
+
+/// A batch the engine drops without answering is not reported as a shutdown
+#[tokio::test]
+async fn test_adaptive_submitter_reports_dropped_batches() {
+    use rabia_core::batching::AdaptiveBatchConfig;
+    use rabia_engine::AdaptiveSubmitter;
+
+    let (cmd_tx, mut cmd_rx) = mpsc::unbounded_channel();
+    let submitter = AdaptiveSubmitter::spawn(AdaptiveBatchConfig::default(), cmd_tx);
+
+    // Stand in for an engine that loses every request
+    tokio::spawn(async move {
+        while let Some(command) = cmd_rx.recv().await {
+            drop(command);
+        }
+    });
+
+    let response = timeout(
+        Duration::from_secs(5),
+        submitter.submit(Command::new("SET a 1")),
+    )
+    .await
+    .expect("Command was not answered in time");
+    assert!(matches!(
+        response,
+        Err(rabia_core::RabiaError::OutcomeUnknown { .. })
+    ));
+}
+
+/// Commits logged since the last snapshot are replayed on startup, and the
+/// shutdown snapshot then covers them
+#[tokio::test]
+async fn test_engine_replays_decision_log() {
+    use rabia_core::{persistence::PersistenceLayer, PhaseId};
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let persistence = InMemoryPersistence::new();
+    for (phase, command) in [(1, "SET a 1"), (2, "SET b 2")] {
+        let batch = CommandBatch::new(vec![Command::new(command)]);
+        persistence
+            .append_decision(PhaseId::new(phase), &batch)
+            .await
+            .unwrap();
+    }
+
+    let (_cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        RabiaConfig::default(),
+        ClusterConfig::new(node_id, node_ids),
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        persistence.clone(),
+        cmd_rx,
+    );
+    let reader = engine.local_reader();
+    let handle = engine.spawn();
+    timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+
+    assert_eq!(handle.statistics().last_committed_phase.value(), 2);
+    let state = reader.read_state(None).await.unwrap();
+    assert_eq!(state.get("a").map(|v| v.as_ref()), Some(&b"1"[..]));
+    assert_eq!(state.get("b").map(|v| v.as_ref()), Some(&b"2"[..]));
+
+    handle.shutdown().await.unwrap();
+    assert!(persistence.load_state().await.unwrap().is_some());
+    assert!(persistence
+        .load_decisions(PhaseId::new(0))
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+/// A persisted delta chain is applied on startup and kept as the base for
+/// later deltas
+#[tokio::test]
+async fn test_engine_restores_snapshot_deltas() {
+    use rabia_core::{
+        persistence::{EngineState, PersistenceLayer},
+        state_machine::{IncrementalSnapshot, StateMachine},
+        PhaseId,
+    };
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let mut source = InMemoryStateMachine::new();
+    source
+        .apply_command(&Command::new("SET a 1"))
+        .await
+        .unwrap();
+    let snapshot = source.create_snapshot().await.unwrap();
+    source.clear_dirty();
+    source
+        .apply_command(&Command::new("SET b 2"))
+        .await
+        .unwrap();
+    let delta = source.create_delta().await.unwrap();
+
+    let persistence = InMemoryPersistence::new();
+    let state = EngineState::new(PhaseId::new(2), PhaseId::new(2), Some(snapshot))
+        .with_deltas(vec![delta.clone()]);
+    persistence
+        .save_state(&state.to_bytes().unwrap())
+        .await
+        .unwrap();
+
+    let (_cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        RabiaConfig::default(),
+        ClusterConfig::new(node_id, node_ids),
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        persistence.clone(),
+        cmd_rx,
+    );
+    let reader = engine.local_reader();
+    let handle = engine.spawn();
+    timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+
+    assert_eq!(reader.read_state(None).await.unwrap(), source.state);
+
+    // Nothing changed, so the shutdown save keeps the chain as it was
+    handle.shutdown().await.unwrap();
+    let saved = persistence.load_state().await.unwrap().unwrap();
+    let saved = EngineState::from_bytes(&saved).unwrap();
+    assert_eq!(saved.deltas, vec![delta]);
+}
+
+/// An engine running a KVStore appends only the new delta after a full
+/// snapshot, and the next start restores the state from the chain
+#[tokio::test]
+async fn test_engine_appends_kvstore_deltas() {
+    use rabia_core::{
+        persistence::{EngineState, PersistenceLayer},
+        PhaseId,
+    };
+    use rabia_engine::SnapshotPolicy;
+    use rabia_kvstore::{KVStore, KVStoreConfig};
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+    let persistence = InMemoryPersistence::new();
+
+    // Each start replays the logged command and saves on shutdown
+    let mut states = Vec::new();
+    for (phase, command) in [
+        (1, Some(r#"{"Set":{"key":"a","value":"1"}}"#)),
+        (2, Some(r#"{"Set":{"key":"b","value":"2"}}"#)),
+        (2, None),
+    ] {
+        if let Some(command) = command {
+            let batch = CommandBatch::new(vec![Command::new(command)]);
+            persistence
+                .append_decision(PhaseId::new(phase), &batch)
+                .await
+                .unwrap();
+        }
+
+        let (_cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+        let engine = RabiaEngine::new(
+            node_id,
+            RabiaConfig::default().with_snapshot_policy(SnapshotPolicy::on_shutdown_only()),
+            ClusterConfig::new(node_id, node_ids.clone()),
+            KVStore::new(KVStoreConfig::default()).await.unwrap(),
+            InMemoryNetwork::new(node_id),
+            persistence.clone(),
+            cmd_rx,
+        );
+        let reader = engine.local_reader();
+        let handle = engine.spawn();
+        timeout(
+            Duration::from_secs(5),
+            handle.wait_for_status(EngineStatus::Running),
+        )
+        .await
+        .expect("Engine did not start in time");
+        assert_eq!(handle.statistics().last_committed_phase.value(), phase);
+
+        let state = reader.read_state(None).await.unwrap();
+        states.push(
+            state
+                .into_iter()
+                .map(|(key, entry)| (key, entry.value))
+                .collect::<std::collections::BTreeMap<_, _>>(),
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    // The full snapshot of the first start stays as it was
+    let (state, records) = persistence.load_state_chain().await.unwrap().unwrap();
+    let base = EngineState::from_bytes(&state).unwrap();
+    assert!(base.deltas.is_empty());
+    assert_eq!(records.len(), 2);
+    let record = EngineState::from_bytes(&records[0]).unwrap();
+    assert!(record.snapshot.is_none());
+    assert_eq!(record.deltas.len(), 1);
+
+    let expected: std::collections::BTreeMap<_, _> = [
+        ("a".to_string(), "1".to_string()),
+        ("b".to_string(), "2".to_string()),
+    ]
+    .into();
+    assert_eq!(states[1], expected);
+    assert_eq!(states[2], expected);
+}
+
+/// A lone node whose decision log was truncated past the state it loads has
+/// no member to sync the missing commits from, so it refuses to start
+#[tokio::test]
+async fn test_engine_refuses_log_truncated_past_its_state() {
+    use rabia_core::{
+        persistence::{EngineState, PersistenceLayer},
+        PhaseId,
+    };
+    use rabia_persistence::FileSystemPersistence;
+
+    let node_id = NodeId::new();
+    let mut node_ids = HashSet::new();
+    node_ids.insert(node_id);
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let persistence = FileSystemPersistence::new(temp_dir.path()).await.unwrap();
+    for phase in 1..=5 {
+        let batch = CommandBatch::new(vec![Command::new(format!("SET k{} v", phase))]);
+        persistence
+            .append_decision(PhaseId::new(phase), &batch)
+            .await
+            .unwrap();
+    }
+    let state = EngineState::new(PhaseId::new(3), PhaseId::new(3), None);
+    persistence
+        .save_state(&state.to_bytes().unwrap())
+        .await
+        .unwrap();
+    persistence
+        .truncate_decisions(PhaseId::new(3))
+        .await
+        .unwrap();
+
+    // Lose the only generation; replaying phases 4 and 5 onto an empty
+    // state would skip the commits of phases 1 to 3
+    for generation in persistence.list_generations().await.unwrap() {
+        std::fs::remove_file(generation.path).unwrap();
+    }
+
+    let (_cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        RabiaConfig::default(),
+        ClusterConfig::new(node_id, node_ids),
+        InMemoryStateMachine::new(),
+        InMemoryNetwork::new(node_id),
+        persistence,
+        cmd_rx,
+    );
+    let handle = engine.spawn();
+
+    let result = timeout(Duration::from_secs(5), handle.join())
+        .await
+        .expect("Engine did not stop");
+    assert!(matches!(
+        result,
+        Err(rabia_core::RabiaError::StateCorruption { .. })
+    ));
+}
+
+/// Compaction keeps a phase still waiting for its decision, however far
+/// behind, so its late decision is not rejected below the watermark
+#[test]
+fn test_compaction_stops_at_undecided_phase() {
+    use rabia_core::{PhaseId, StateValue};
+
+    let state = rabia_engine::EngineState::new(2);
+    for phase in 1..=20 {
+        state
+            .update_phase(PhaseId::new(phase), |data| {
+                if phase != 3 {
+                    data.set_decision(StateValue::V1);
+                }
+            })
+            .unwrap();
+    }
+
+    assert_eq!(state.cleanup_old_phases(5, PhaseId::new(0)), 2);
+    assert_eq!(state.compaction_watermark(), PhaseId::new(3));
+    assert!(state.get_phase(&PhaseId::new(3)).is_some());
+
+    // Once covered by a synced state it no longer holds compaction back
+    assert_eq!(state.cleanup_old_phases(5, PhaseId::new(3)), 12);
+    assert_eq!(state.compaction_watermark(), PhaseId::new(15));
+}
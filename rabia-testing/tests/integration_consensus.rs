@@ -477,3 +477,1121 @@ async fn test_randomization_during_voting_only() {
 
     harness.shutdown().await;
 }
+
+/// Test that in leader-assisted mode followers forward their batches and the
+/// leader alone proposes, committing every batch
+#[tokio::test]
+async fn test_leader_assisted_consensus() {
+    // Initialize logging for tests
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .try_init();
+
+    let config = RabiaConfig::default().with_leader_assisted(true);
+    let mut harness = ConsensusTestHarness::new(3, config).await;
+
+    let scenario = TestScenario {
+        name: "Leader Assisted Consensus".to_string(),
+        description: "Followers forward batches to the deterministic leader".to_string(),
+        node_count: 3,
+        initial_commands: vec![
+            rabia_core::Command::new("SET key1 value1"),
+            rabia_core::Command::new("SET key2 value2"),
+            rabia_core::Command::new("SET key3 value3"),
+        ],
+        faults: vec![],
+        expected_outcome: ExpectedOutcome::AllCommitted,
+        timeout: Duration::from_secs(3),
+    };
+
+    let result = timeout(Duration::from_secs(10), harness.run_scenario(scenario)).await;
+    assert!(result.is_ok(), "Leader-assisted test timed out");
+
+    let test_result = result.unwrap();
+    assert!(test_result.success, "{}", test_result.details);
+
+    let outcome = &test_result.actual_outcome;
+    assert_eq!(outcome.submitted_batches, 3);
+    assert_eq!(outcome.committed_batches, 3, "{}", test_result.details);
+
+    // Commands are submitted round-robin, so the two followers each forward
+    // one batch and only the leader proposes
+    assert_eq!(
+        outcome.batches_forwarded.iter().sum::<u64>(),
+        2,
+        "{}",
+        test_result.details
+    );
+    let proposers = outcome
+        .batches_proposed
+        .iter()
+        .filter(|&&proposed| proposed > 0)
+        .count();
+    assert_eq!(proposers, 1, "{}", test_result.details);
+    assert!(
+        outcome
+            .batches_proposed
+            .iter()
+            .any(|&proposed| proposed >= 3),
+        "{}",
+        test_result.details
+    );
+
+    harness.shutdown().await;
+}
+
+/// Retried session commands are answered from the session table instead of
+/// being applied again, and an evicted retry fails without failing its batch
+#[tokio::test]
+async fn test_retried_commands_are_not_applied_twice() {
+    use rabia_core::{sessions::SessionConfig, ClientId, Command, CommandBatch};
+    use rabia_engine::{CommandRequest, CommandResults, EngineCommand};
+
+    // Only the latest response per client is cached
+    let config = RabiaConfig::default().with_session_config(SessionConfig {
+        response_window: 1,
+        ..SessionConfig::default()
+    });
+    let harness = ConsensusTestHarness::new(3, config).await;
+    let engine_tx = harness.engine_sender(harness.node_ids()[0]).unwrap();
+
+    let submit = |commands: Vec<Command>| {
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        engine_tx
+            .send(EngineCommand::ProcessBatch(CommandRequest {
+                batch: CommandBatch::new(commands),
+                response_tx,
+            }))
+            .unwrap();
+        async move {
+            let results: CommandResults = timeout(Duration::from_secs(5), response_rx)
+                .await
+                .expect("Engine did not respond")
+                .unwrap()
+                .expect("Batch failed");
+            results
+        }
+    };
+
+    let client = ClientId::new();
+    let first = Command::with_session("SET a 1", client, 1);
+    let second = Command::with_session("SET b 2", client, 2);
+    submit(vec![first.clone()]).await;
+    submit(vec![second.clone()]).await;
+    submit(vec![Command::new("SET a 8"), Command::new("SET b 9")]).await;
+
+    let results = submit(vec![second, first, Command::new("SET c 3")]).await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().as_ref(), b"OK");
+    assert!(
+        matches!(
+            results[1],
+            Err(rabia_core::RabiaError::OutcomeUnknown { .. })
+        ),
+        "Evicted retry must not be re-applied"
+    );
+    assert_eq!(
+        results[1].as_ref().unwrap_err().code(),
+        rabia_core::ErrorCode::OutcomeUnknown
+    );
+    assert_eq!(results[2].as_ref().unwrap().as_ref(), b"OK");
+
+    // Neither retry overwrote the later values
+    let results = submit(vec![
+        Command::new("GET a"),
+        Command::new("GET b"),
+        Command::new("GET c"),
+    ])
+    .await;
+    let values: Vec<&[u8]> = results
+        .iter()
+        .map(|result| result.as_ref().unwrap().as_ref())
+        .collect();
+    assert_eq!(values, vec![&b"8"[..], &b"9"[..], &b"3"[..]]);
+
+    harness.shutdown().await;
+}
+
+/// Votes from outside the cluster are rejected, and a member voting both
+/// ways in one round is reported as equivocating
+#[tokio::test]
+async fn test_engine_rejects_non_member_and_reports_equivocation() {
+    use rabia_core::{
+        messages::{ProtocolMessage, VoteRound1Message},
+        network::{ClusterConfig, NetworkTransport},
+        state_machine::InMemoryStateMachine,
+        validation::RejectionReason,
+        BatchId, NodeId, PhaseId, StateValue,
+    };
+    use rabia_engine::{EngineEvent, EngineStatus, RabiaEngine};
+    use rabia_persistence::InMemoryPersistence;
+    use rabia_testing::network_sim::{NetworkSimulator, SimulatedNetwork};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    let simulator = Arc::new(NetworkSimulator::new());
+    let (node_id, peer_id, outsider_id) = (NodeId::new(), NodeId::new(), NodeId::new());
+    let members: HashSet<NodeId> = [node_id, peer_id, NodeId::new()].into_iter().collect();
+
+    let network = SimulatedNetwork::new(node_id, simulator.clone()).await;
+    network.connect_to_nodes(members.clone()).await;
+    let peer = SimulatedNetwork::new(peer_id, simulator.clone()).await;
+    let outsider = SimulatedNetwork::new(outsider_id, simulator.clone()).await;
+    let sim = simulator.clone();
+    tokio::spawn(async move { sim.run_simulation().await });
+
+    let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        RabiaConfig::default(),
+        ClusterConfig::new(node_id, members),
+        InMemoryStateMachine::new(),
+        network,
+        InMemoryPersistence::new(),
+        cmd_rx,
+    );
+    let mut events = engine.subscribe_events();
+    let handle = engine.spawn();
+    timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+
+    let phase_id = PhaseId::new(1);
+    let batch_id = BatchId::new();
+    let vote = |voter_id: NodeId, vote: StateValue| {
+        ProtocolMessage::vote_round1(
+            voter_id,
+            node_id,
+            VoteRound1Message {
+                phase_id,
+                batch_id,
+                vote,
+                voter_id,
+            },
+        )
+    };
+
+    outsider
+        .send_to(node_id, vote(outsider_id, StateValue::V1))
+        .await
+        .unwrap();
+    peer.send_to(node_id, vote(peer_id, StateValue::V1))
+        .await
+        .unwrap();
+    peer.send_to(node_id, vote(peer_id, StateValue::V0))
+        .await
+        .unwrap();
+
+    let event = timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("No equivocation reported")
+        .unwrap();
+    match event {
+        EngineEvent::Equivocation {
+            node_id: equivocator,
+            phase_id: event_phase,
+            round,
+            first,
+            second,
+        } => {
+            assert_eq!(equivocator, peer_id);
+            assert_eq!(event_phase, phase_id);
+            assert_eq!(round, 1);
+            assert_ne!(first, second);
+        }
+    }
+    assert_eq!(handle.statistics().equivocations_detected, 1);
+
+    // Delivery order is not fixed, so wait for the outsider's vote as well
+    timeout(Duration::from_secs(5), async {
+        while !handle
+            .rejection_counts()
+            .contains_key(&RejectionReason::NotMember)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Vote from a non-member was not rejected");
+    assert_eq!(
+        handle.rejection_counts().get(&RejectionReason::NotMember),
+        Some(&1)
+    );
+
+    handle.shutdown().await.unwrap();
+    simulator.shutdown().await;
+}
+
+/// Round 2 votes whose round 1 votes are indexed by another cluster
+/// configuration are rejected
+#[tokio::test]
+async fn test_engine_rejects_votes_from_other_configuration() {
+    use rabia_core::{
+        messages::{ProtocolMessage, VoteRound2Message},
+        network::{ClusterConfig, NetworkTransport},
+        state_machine::InMemoryStateMachine,
+        validation::RejectionReason,
+        votes::{MemberIndex, VoteVector},
+        BatchId, NodeId, PhaseId, StateValue,
+    };
+    use rabia_engine::{EngineStatus, RabiaEngine};
+    use rabia_persistence::InMemoryPersistence;
+    use rabia_testing::network_sim::{NetworkSimulator, SimulatedNetwork};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    let simulator = Arc::new(NetworkSimulator::new());
+    let (node_id, peer_id) = (NodeId::new(), NodeId::new());
+    let members: HashSet<NodeId> = [node_id, peer_id, NodeId::new()].into_iter().collect();
+
+    let network = SimulatedNetwork::new(node_id, simulator.clone()).await;
+    network.connect_to_nodes(members.clone()).await;
+    let peer = SimulatedNetwork::new(peer_id, simulator.clone()).await;
+    let sim = simulator.clone();
+    tokio::spawn(async move { sim.run_simulation().await });
+
+    let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        RabiaConfig::default(),
+        ClusterConfig::new(node_id, members.clone()),
+        InMemoryStateMachine::new(),
+        network,
+        InMemoryPersistence::new(),
+        cmd_rx,
+    );
+    let handle = engine.spawn();
+    timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+
+    // The peer still indexes votes by a configuration with a fourth member
+    let stale_members = MemberIndex::new(members.iter().copied().chain([NodeId::new()]));
+    let votes: HashMap<NodeId, StateValue> = [(peer_id, StateValue::V1)].into_iter().collect();
+    let message = ProtocolMessage::vote_round2(
+        peer_id,
+        node_id,
+        VoteRound2Message {
+            phase_id: PhaseId::new(1),
+            batch_id: BatchId::new(),
+            vote: StateValue::V1,
+            voter_id: peer_id,
+            round1_votes: VoteVector::from_votes(&votes, &stale_members),
+        },
+    );
+    peer.send_to(node_id, message).await.unwrap();
+
+    timeout(Duration::from_secs(5), async {
+        while !handle
+            .rejection_counts()
+            .contains_key(&RejectionReason::ConfigMismatch)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Vote from another configuration was not rejected");
+
+    handle.shutdown().await.unwrap();
+    simulator.shutdown().await;
+}
+
+/// A decision made under other session limits is rejected rather than
+/// applied, since the session table is replicated state
+#[tokio::test]
+async fn test_engine_rejects_decisions_under_other_session_limits() {
+    use rabia_core::{
+        messages::{DecisionMessage, ProtocolMessage},
+        network::{ClusterConfig, NetworkTransport},
+        sessions::SessionConfig,
+        state_machine::InMemoryStateMachine,
+        validation::RejectionReason,
+        votes::MemberIndex,
+        Command, CommandBatch, NodeId, PhaseId, StateValue,
+    };
+    use rabia_engine::{EngineStatus, RabiaEngine};
+    use rabia_persistence::InMemoryPersistence;
+    use rabia_testing::network_sim::{NetworkSimulator, SimulatedNetwork};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    let simulator = Arc::new(NetworkSimulator::new());
+    let (node_id, peer_id) = (NodeId::new(), NodeId::new());
+    let members: HashSet<NodeId> = [node_id, peer_id, NodeId::new()].into_iter().collect();
+
+    let network = SimulatedNetwork::new(node_id, simulator.clone()).await;
+    network.connect_to_nodes(members.clone()).await;
+    let peer = SimulatedNetwork::new(peer_id, simulator.clone()).await;
+    let sim = simulator.clone();
+    tokio::spawn(async move { sim.run_simulation().await });
+
+    let config = RabiaConfig::default();
+    let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+    let engine = RabiaEngine::new(
+        node_id,
+        config.clone(),
+        ClusterConfig::new(node_id, members.clone()),
+        InMemoryStateMachine::new(),
+        network,
+        InMemoryPersistence::new(),
+        cmd_rx,
+    );
+    let handle = engine.spawn();
+    timeout(
+        Duration::from_secs(5),
+        handle.wait_for_status(EngineStatus::Running),
+    )
+    .await
+    .expect("Engine did not start in time");
+
+    let decision = |phase: u64, config: &RabiaConfig| {
+        let batch = CommandBatch::new(vec![Command::new("SET a 1")]);
+        let config_hash = MemberIndex::new(members.iter().copied())
+            .with_settings(&config.replicated_settings())
+            .config_hash();
+        ProtocolMessage::decision(
+            peer_id,
+            DecisionMessage {
+                phase_id: PhaseId::new(phase),
+                batch_id: batch.id,
+                decision: StateValue::V1,
+                batch: Some(batch),
+                config_hash,
+            },
+        )
+    };
+
+    // The peer caches a different number of responses per client
+    let other = config.clone().with_session_config(SessionConfig {
+        response_window: 1,
+        ..SessionConfig::default()
+    });
+    peer.send_to(node_id, decision(1, &other)).await.unwrap();
+    timeout(Duration::from_secs(5), async {
+        while !handle
+            .rejection_counts()
+            .contains_key(&RejectionReason::ConfigMismatch)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Decision under other session limits was not rejected");
+    assert_eq!(handle.statistics().last_committed_phase, PhaseId::new(0));
+
+    // The same decision under matching limits is applied
+    peer.send_to(node_id, decision(1, &config)).await.unwrap();
+    timeout(Duration::from_secs(5), async {
+        while handle.statistics().last_committed_phase != PhaseId::new(1) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Decision under matching session limits was not applied");
+
+    handle.shutdown().await.unwrap();
+    simulator.shutdown().await;
+}
+
+/// One engine in a three member cluster whose other two members are played
+/// by the test through their simulated networks.
+struct ScriptedNode {
+    handle: rabia_engine::EngineHandle,
+    cmd_tx: rabia_engine::EngineCommandSender,
+    node_id: rabia_core::NodeId,
+    members: std::collections::HashSet<rabia_core::NodeId>,
+    peer_ids: [rabia_core::NodeId; 2],
+    peers: [rabia_testing::network_sim::SimulatedNetwork; 2],
+    simulator: std::sync::Arc<rabia_testing::network_sim::NetworkSimulator>,
+}
+
+impl ScriptedNode {
+    async fn start<SM, PL>(config: RabiaConfig, state_machine: SM, persistence: PL) -> Self
+    where
+        SM: rabia_core::state_machine::StateMachine + 'static,
+        PL: rabia_core::persistence::PersistenceLayer + 'static,
+    {
+        use rabia_core::{network::ClusterConfig, NodeId};
+        use rabia_engine::{EngineStatus, RabiaEngine};
+        use rabia_testing::network_sim::{NetworkSimulator, SimulatedNetwork};
+        use std::sync::Arc;
+
+        let simulator = Arc::new(NetworkSimulator::new());
+        let node_id = NodeId::new();
+        let peer_ids = [NodeId::new(), NodeId::new()];
+        let members = [node_id, peer_ids[0], peer_ids[1]].into_iter().collect();
+
+        let network = SimulatedNetwork::new(node_id, simulator.clone()).await;
+        network.connect_to_nodes(Clone::clone(&members)).await;
+        let peers = [
+            SimulatedNetwork::new(peer_ids[0], simulator.clone()).await,
+            SimulatedNetwork::new(peer_ids[1], simulator.clone()).await,
+        ];
+        let sim = simulator.clone();
+        tokio::spawn(async move { sim.run_simulation().await });
+
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let engine = RabiaEngine::new(
+            node_id,
+            config,
+            ClusterConfig::new(node_id, Clone::clone(&members)),
+            state_machine,
+            network,
+            persistence,
+            cmd_rx,
+        );
+        let handle = engine.spawn();
+        timeout(
+            Duration::from_secs(5),
+            handle.wait_for_status(EngineStatus::Running),
+        )
+        .await
+        .expect("Engine did not start in time");
+
+        Self {
+            handle,
+            cmd_tx,
+            node_id,
+            members,
+            peer_ids,
+            peers,
+            simulator,
+        }
+    }
+
+    /// Send `message` from peer `peer` to the engine.
+    async fn send(&self, peer: usize, message: rabia_core::messages::ProtocolMessage) {
+        use rabia_core::network::NetworkTransport;
+
+        self.peers[peer]
+            .send_to(self.node_id, message)
+            .await
+            .unwrap();
+    }
+
+    /// Next message peer `peer` receives that `wanted` accepts, skipping
+    /// heartbeats and anything else.
+    async fn next_message<F>(
+        &mut self,
+        peer: usize,
+        wanted: F,
+    ) -> rabia_core::messages::ProtocolMessage
+    where
+        F: Fn(&rabia_core::messages::MessageType) -> bool,
+    {
+        use rabia_core::network::NetworkTransport;
+
+        let network = &mut self.peers[peer];
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let (_, message) = network.receive().await.unwrap();
+                if wanted(&message.message_type) {
+                    return message;
+                }
+            }
+        })
+        .await
+        .expect("Expected message did not arrive")
+    }
+
+    /// Next proposal peer `peer` receives.
+    async fn next_proposal(&mut self, peer: usize) -> rabia_core::messages::ProposeMessage {
+        use rabia_core::messages::MessageType;
+
+        let message = self
+            .next_message(peer, |m| matches!(m, MessageType::Propose(_)))
+            .await;
+        let MessageType::Propose(proposal) = message.message_type else {
+            unreachable!()
+        };
+        proposal
+    }
+
+    /// Hand a client batch to the engine, returning the receiver of its results.
+    fn submit(
+        &self,
+        batch: rabia_core::CommandBatch,
+    ) -> tokio::sync::oneshot::Receiver<rabia_core::Result<rabia_engine::CommandResults>> {
+        use rabia_engine::{CommandRequest, EngineCommand};
+
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        self.cmd_tx
+            .send(EngineCommand::ProcessBatch(CommandRequest {
+                batch,
+                response_tx,
+            }))
+            .unwrap();
+        response_rx
+    }
+
+    /// Configuration hash the engine stamps on its decisions.
+    fn config_hash(&self, config: &RabiaConfig) -> u32 {
+        rabia_core::votes::MemberIndex::new(self.members.iter().copied())
+            .with_settings(&config.replicated_settings())
+            .config_hash()
+    }
+
+    async fn shutdown(self) {
+        self.handle.shutdown().await.unwrap();
+        self.simulator.shutdown().await;
+    }
+}
+
+/// Start a node of a three member cluster and have a peer send it the
+/// decision committing `SET a 1` in phase 1.
+async fn commit_one_batch<SM, PL>(
+    config: RabiaConfig,
+    state_machine: SM,
+    persistence: PL,
+) -> ScriptedNode
+where
+    SM: rabia_core::state_machine::StateMachine + 'static,
+    PL: rabia_core::persistence::PersistenceLayer + 'static,
+{
+    use rabia_core::{
+        messages::{DecisionMessage, ProtocolMessage},
+        Command, CommandBatch, PhaseId, StateValue,
+    };
+
+    let node = ScriptedNode::start(config.clone(), state_machine, persistence).await;
+    let batch = CommandBatch::new(vec![Command::new("SET a 1")]);
+    let decision = DecisionMessage {
+        phase_id: PhaseId::new(1),
+        batch_id: batch.id,
+        decision: StateValue::V1,
+        batch: Some(batch),
+        config_hash: node.config_hash(&config),
+    };
+    node.send(0, ProtocolMessage::decision(node.peer_ids[0], decision))
+        .await;
+    node
+}
+
+/// Persistence without a decision log, so the engine saves its state on
+/// every commit.
+#[derive(Clone)]
+struct StateOnly(rabia_persistence::InMemoryPersistence);
+
+#[async_trait::async_trait]
+impl rabia_core::persistence::PersistenceLayer for StateOnly {
+    async fn save_state(&self, state: &[u8]) -> rabia_core::Result<()> {
+        self.0.save_state(state).await
+    }
+
+    async fn load_state(&self) -> rabia_core::Result<Option<Vec<u8>>> {
+        self.0.load_state().await
+    }
+}
+
+/// Wait for a state to be saved and check it holds the batch committed by
+/// [`commit_one_batch`].
+async fn expect_saved_batch(persistence: &impl rabia_core::persistence::PersistenceLayer) {
+    use rabia_core::{
+        persistence::EngineState,
+        state_machine::{InMemoryStateMachine, StateMachine},
+        PhaseId,
+    };
+
+    let state = timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(bytes) = persistence.load_state().await.unwrap() {
+                let state = EngineState::from_bytes(&bytes).unwrap();
+                if state.last_committed_phase == PhaseId::new(1) {
+                    break state;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("No state covering the commit was saved");
+    let mut restored = InMemoryStateMachine::new();
+    restored
+        .restore_snapshot(&state.snapshot.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        restored.get_state().await.get("a").map(|v| v.as_ref()),
+        Some(&b"1"[..])
+    );
+}
+
+/// A state machine that cannot be cloned runs in the engine, which takes its
+/// full snapshots inline instead of from a copy
+#[tokio::test]
+async fn test_engine_snapshots_state_machine_without_clone() {
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use rabia_core::{
+        state_machine::{InMemoryStateMachine, Snapshot, StateMachine},
+        Command,
+    };
+    use rabia_engine::SnapshotPolicy;
+    use rabia_persistence::InMemoryPersistence;
+    use std::collections::HashMap;
+
+    struct Uncloned(InMemoryStateMachine);
+
+    #[async_trait]
+    impl StateMachine for Uncloned {
+        type State = HashMap<String, Bytes>;
+
+        async fn apply_command(&mut self, command: &Command) -> rabia_core::Result<Bytes> {
+            self.0.apply_command(command).await
+        }
+
+        async fn create_snapshot(&self) -> rabia_core::Result<Snapshot> {
+            self.0.create_snapshot().await
+        }
+
+        async fn restore_snapshot(&mut self, snapshot: &Snapshot) -> rabia_core::Result<()> {
+            self.0.restore_snapshot(snapshot).await
+        }
+
+        async fn get_state(&self) -> Self::State {
+            self.0.get_state().await
+        }
+    }
+
+    let config = RabiaConfig::default().with_snapshot_policy(SnapshotPolicy {
+        every_phases: Some(1),
+        ..SnapshotPolicy::default()
+    });
+    let persistence = InMemoryPersistence::new();
+    let node = commit_one_batch(
+        config,
+        Uncloned(InMemoryStateMachine::new()),
+        persistence.clone(),
+    )
+    .await;
+
+    // The policy snapshots after the commit, before any shutdown snapshot
+    expect_saved_batch(&persistence).await;
+
+    node.shutdown().await;
+}
+
+/// A persistence layer without a decision log has the state saved on every
+/// commit instead, well before the snapshot policy is due
+#[tokio::test]
+async fn test_engine_saves_state_on_commit_without_decision_log() {
+    use rabia_core::state_machine::InMemoryStateMachine;
+    use rabia_persistence::InMemoryPersistence;
+
+    let persistence = StateOnly(InMemoryPersistence::new());
+    let node = commit_one_batch(
+        RabiaConfig::default(),
+        InMemoryStateMachine::new(),
+        persistence.clone(),
+    )
+    .await;
+
+    expect_saved_batch(&persistence).await;
+
+    node.shutdown().await;
+}
+
+/// A batch that commits a second time, as when the leader proposes a batch
+/// that the follower who reclaimed it has already committed, is applied only
+/// the first time
+#[tokio::test]
+async fn test_batch_committed_again_is_applied_once() {
+    use rabia_core::{
+        messages::{DecisionMessage, ProtocolMessage},
+        persistence::{EngineState, PersistenceLayer},
+        state_machine::{InMemoryStateMachine, StateMachine},
+        Command, CommandBatch, PhaseId, StateValue,
+    };
+    use rabia_engine::SnapshotPolicy;
+    use rabia_persistence::InMemoryPersistence;
+
+    // Full snapshots only, so the saved state can be read back directly
+    let config = RabiaConfig::default().with_snapshot_policy(SnapshotPolicy {
+        max_deltas: 0,
+        ..SnapshotPolicy::default()
+    });
+    let persistence = StateOnly(InMemoryPersistence::new());
+    let node = ScriptedNode::start(
+        config.clone(),
+        InMemoryStateMachine::new(),
+        persistence.clone(),
+    )
+    .await;
+
+    // The follower's proposal of the reclaimed batch commits before the
+    // leader's, with another write in between
+    let reclaimed = CommandBatch::new(vec![Command::new("SET a 1")]);
+    let later = CommandBatch::new(vec![Command::new("SET a 2")]);
+    for (phase, batch) in [(1, &reclaimed), (2, &later), (3, &reclaimed)] {
+        let decision = DecisionMessage {
+            phase_id: PhaseId::new(phase),
+            batch_id: batch.id,
+            decision: StateValue::V1,
+            batch: Some(batch.clone()),
+            config_hash: node.config_hash(&config),
+        };
+        node.send(0, ProtocolMessage::decision(node.peer_ids[0], decision))
+            .await;
+    }
+
+    let state = timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(bytes) = persistence.load_state().await.unwrap() {
+                let state = EngineState::from_bytes(&bytes).unwrap();
+                if state.last_committed_phase == PhaseId::new(3) {
+                    break state;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("No state covering the commits was saved");
+
+    let mut restored = InMemoryStateMachine::new();
+    restored
+        .restore_snapshot(&state.snapshot.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        restored.get_state().await.get("a").map(|v| v.as_ref()),
+        Some(&b"2"[..])
+    );
+    assert_eq!(
+        state.committed_batches.get(&reclaimed.id),
+        Some(PhaseId::new(1))
+    );
+
+    node.shutdown().await;
+}
+
+/// Each node counts its own round 1 vote, the proposer's being its proposal,
+/// so one peer's vote makes a quorum of two in a three member cluster
+#[tokio::test]
+async fn test_own_round1_vote_counts() {
+    use rabia_core::{
+        messages::{MessageType, ProposeMessage, ProtocolMessage, VoteRound1Message},
+        state_machine::InMemoryStateMachine,
+        Command, CommandBatch, PhaseId, StateValue,
+    };
+    use rabia_persistence::InMemoryPersistence;
+
+    // The proposed batch never commits, so shutdown need not wait for it
+    let config = RabiaConfig {
+        randomization_seed: Some(7),
+        ..RabiaConfig::default()
+    }
+    .with_shutdown_drain_timeout(Duration::from_millis(100));
+    let mut node = ScriptedNode::start(
+        config,
+        InMemoryStateMachine::new(),
+        InMemoryPersistence::new(),
+    )
+    .await;
+    let is_propose = |m: &MessageType| matches!(m, MessageType::Propose(_));
+    let is_round2 = |m: &MessageType| matches!(m, MessageType::VoteRound2(_));
+    let node_id = node.node_id;
+    let round1_vote = |voter_id, phase_id, batch_id| {
+        ProtocolMessage::vote_round1(
+            voter_id,
+            node_id,
+            VoteRound1Message {
+                phase_id,
+                batch_id,
+                vote: StateValue::V1,
+                voter_id,
+            },
+        )
+    };
+
+    // As a follower, its vote and the other follower's make a quorum
+    let batch = CommandBatch::new(vec![Command::new("SET a 1")]);
+    let batch_id = batch.id;
+    let proposal = ProposeMessage {
+        phase_id: PhaseId::new(1),
+        batch_id,
+        value: StateValue::V1,
+        batch: Some(batch),
+    };
+    node.send(0, ProtocolMessage::propose(node.peer_ids[0], proposal))
+        .await;
+    node.send(1, round1_vote(node.peer_ids[1], PhaseId::new(1), batch_id))
+        .await;
+    let vote = node.next_message(0, is_round2).await;
+    assert_eq!(vote.from, node.node_id);
+
+    // As the proposer, its proposal and one follower's vote make a quorum
+    let _response = node.submit(CommandBatch::new(vec![Command::new("SET b 2")]));
+    let MessageType::Propose(proposal) = node.next_message(0, is_propose).await.message_type else {
+        unreachable!()
+    };
+    node.send(
+        0,
+        round1_vote(node.peer_ids[0], proposal.phase_id, proposal.batch_id),
+    )
+    .await;
+    let vote = node
+        .next_message(
+            1,
+            |m| matches!(m, MessageType::VoteRound2(vote) if vote.phase_id == proposal.phase_id),
+        )
+        .await;
+    assert_eq!(vote.from, node.node_id);
+
+    node.shutdown().await;
+}
+
+/// A follower's round 1 vote goes to every member, so the other follower
+/// reaches round 2 without the proposer relaying it
+#[tokio::test]
+async fn test_round1_votes_are_broadcast() {
+    use rabia_core::{
+        messages::{MessageType, ProposeMessage, ProtocolMessage},
+        state_machine::InMemoryStateMachine,
+        Command, CommandBatch, PhaseId, StateValue,
+    };
+    use rabia_persistence::InMemoryPersistence;
+
+    // The proposed batch never commits, so shutdown need not wait for it
+    let config = RabiaConfig::default().with_shutdown_drain_timeout(Duration::from_millis(100));
+    let mut node = ScriptedNode::start(
+        config,
+        InMemoryStateMachine::new(),
+        InMemoryPersistence::new(),
+    )
+    .await;
+
+    let batch = CommandBatch::new(vec![Command::new("SET a 1")]);
+    let proposal = ProposeMessage {
+        phase_id: PhaseId::new(1),
+        batch_id: batch.id,
+        value: StateValue::V1,
+        batch: Some(batch),
+    };
+    node.send(0, ProtocolMessage::propose(node.peer_ids[0], proposal))
+        .await;
+
+    let is_round1 = |m: &MessageType| matches!(m, MessageType::VoteRound1(_));
+    for peer in 0..2 {
+        let vote = node.next_message(peer, is_round1).await;
+        assert_eq!(vote.from, node.node_id);
+        assert_eq!(vote.to, None);
+    }
+
+    node.shutdown().await;
+}
+
+/// A second batch proposed in a phase that already holds one gets a vote
+/// against it
+#[tokio::test]
+async fn test_conflicting_proposal_gets_v0_vote() {
+    use rabia_core::{
+        messages::{MessageType, ProposeMessage, ProtocolMessage},
+        state_machine::InMemoryStateMachine,
+        Command, CommandBatch, PhaseId, StateValue,
+    };
+    use rabia_persistence::InMemoryPersistence;
+
+    // Neither batch commits, so shutdown need not wait for them
+    let config = RabiaConfig::default().with_shutdown_drain_timeout(Duration::from_millis(100));
+    let mut node = ScriptedNode::start(
+        config,
+        InMemoryStateMachine::new(),
+        InMemoryPersistence::new(),
+    )
+    .await;
+
+    let first = CommandBatch::new(vec![Command::new("SET a 1")]);
+    let second = CommandBatch::new(vec![Command::new("SET a 2")]);
+    let (first_id, second_id) = (first.id, second.id);
+    for (peer, batch) in [(0, first), (1, second)] {
+        let proposal = ProposeMessage {
+            phase_id: PhaseId::new(1),
+            batch_id: batch.id,
+            value: StateValue::V1,
+            batch: Some(batch),
+        };
+        node.send(
+            peer,
+            ProtocolMessage::propose(node.peer_ids[peer], proposal),
+        )
+        .await;
+    }
+
+    let is_round1 = |m: &MessageType| matches!(m, MessageType::VoteRound1(_));
+    let mut votes = Vec::new();
+    for _ in 0..2 {
+        let MessageType::VoteRound1(vote) = node.next_message(0, is_round1).await.message_type
+        else {
+            unreachable!()
+        };
+        votes.push(vote);
+    }
+    assert_eq!(votes[0].batch_id, first_id);
+    assert_eq!(votes[1].batch_id, second_id);
+    assert_eq!(votes[1].vote, StateValue::V0);
+
+    node.shutdown().await;
+}
+
+/// A batch whose phase decides not to commit it is proposed again in a later
+/// phase, and commits there
+#[tokio::test]
+async fn test_batch_decided_v0_is_proposed_again() {
+    use rabia_core::{
+        messages::{DecisionMessage, ProtocolMessage},
+        state_machine::InMemoryStateMachine,
+        Command, CommandBatch, StateValue,
+    };
+    use rabia_persistence::InMemoryPersistence;
+
+    let config = RabiaConfig::default();
+    let mut node = ScriptedNode::start(
+        config.clone(),
+        InMemoryStateMachine::new(),
+        InMemoryPersistence::new(),
+    )
+    .await;
+
+    let response = node.submit(CommandBatch::new(vec![Command::new("SET a 1")]));
+    let first = node.next_proposal(0).await;
+
+    let decide = |node: &ScriptedNode, phase_id, batch_id, decision, batch| {
+        let decision = DecisionMessage {
+            phase_id,
+            batch_id,
+            decision,
+            batch,
+            config_hash: node.config_hash(&config),
+        };
+        ProtocolMessage::decision(node.peer_ids[0], decision)
+    };
+    node.send(
+        0,
+        decide(&node, first.phase_id, first.batch_id, StateValue::V0, None),
+    )
+    .await;
+
+    let second = node.next_proposal(0).await;
+    assert_eq!(second.batch_id, first.batch_id);
+    assert!(second.phase_id > first.phase_id);
+
+    node.send(
+        0,
+        decide(
+            &node,
+            second.phase_id,
+            second.batch_id,
+            StateValue::V1,
+            second.batch,
+        ),
+    )
+    .await;
+    let results = timeout(Duration::from_secs(5), response)
+        .await
+        .expect("Batch was not answered")
+        .unwrap()
+        .expect("Batch failed");
+    assert_eq!(results[0].as_ref().unwrap().as_ref(), b"OK");
+
+    node.shutdown().await;
+}
+
+/// Batches proposed concurrently by different nodes land in the same phase;
+/// every replica commits the same one there and the others in later phases
+#[tokio::test]
+async fn test_concurrent_proposals_commit_the_same_batch() {
+    use rabia_core::{Command, CommandBatch};
+    use rabia_engine::{CommandRequest, EngineCommand};
+
+    // Losing proposers propose again on a heartbeat tick
+    let config = RabiaConfig {
+        heartbeat_interval: Duration::from_millis(100),
+        ..RabiaConfig::default()
+    };
+    let harness = ConsensusTestHarness::new(3, config).await;
+    let node_ids = harness.node_ids();
+
+    for round in 0..3 {
+        let responses: Vec<_> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, node_id)| {
+                let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+                let batch = CommandBatch::new(vec![Command::new(format!("SET k{}{} v", round, i))]);
+                harness
+                    .engine_sender(*node_id)
+                    .unwrap()
+                    .send(EngineCommand::ProcessBatch(CommandRequest {
+                        batch,
+                        response_tx,
+                    }))
+                    .unwrap();
+                response_rx
+            })
+            .collect();
+
+        for response in responses {
+            timeout(Duration::from_secs(10), response)
+                .await
+                .expect("Batch was not answered")
+                .unwrap()
+                .expect("Batch failed");
+        }
+    }
+
+    assert!(
+        harness.wait_for_convergence(Duration::from_secs(5)).await,
+        "Replicas committed different batches"
+    );
+    let state = harness.node_state(node_ids[0]).await.unwrap();
+    assert_eq!(state.len(), 9);
+
+    harness.shutdown().await;
+}
+
+/// A node whose storage fails writes or crashes while commands commit is
+/// restarted over what its storage kept, and catches up with the others
+#[tokio::test]
+async fn test_storage_fault_scenarios_converge() {
+    use rabia_testing::create_storage_scenarios;
+
+    let scenario_count = create_storage_scenarios(&[rabia_core::NodeId::new()]).len();
+    for index in 0..scenario_count {
+        let config = RabiaConfig::default().with_leader_assisted(true);
+        let mut harness = ConsensusTestHarness::new(3, config).await;
+        let scenario = create_storage_scenarios(&harness.node_ids()).remove(index);
+        let faulted = match &scenario.faults[0].1 {
+            FaultType::StorageFault { node_id, .. } => *node_id,
+            fault => panic!("Unexpected fault {:?}", fault),
+        };
+        let storage = harness.storage_faults(faulted).unwrap();
+
+        let result = timeout(
+            scenario.timeout + Duration::from_secs(5),
+            harness.run_scenario(scenario.clone()),
+        )
+        .await
+        .unwrap_or_else(|_| panic!("Scenario '{}' timed out", scenario.name));
+
+        assert!(result.success, "{}", result.details);
+        assert!(
+            storage.stats().faulted_writes > 0,
+            "Scenario '{}' faulted no writes",
+            scenario.name
+        );
+        assert!(
+            harness.wait_for_convergence(Duration::from_secs(5)).await,
+            "Scenario '{}' did not converge",
+            scenario.name
+        );
+
+        harness.shutdown().await;
+    }
+}